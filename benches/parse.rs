@@ -0,0 +1,88 @@
+//! Parses generated multi-megabyte HTML and CSS inputs and reports throughput.
+//! Both parsers should scale linearly, so the 4 MB runs should take about
+//! four times as long as the 1 MB ones.
+//!
+//! Run with `cargo bench --bench parse`.
+
+use axolotl::css::parser::CssParser;
+use axolotl::html::Parser;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const MB: usize = 1024 * 1024;
+const ROUNDS: usize = 5;
+
+// Mixes markup the tree builder has to work for (tables, misnested
+// formatting, implied end tags) with non-ASCII text and character references.
+const HTML_CHUNK: &str = r#"<div class="post" id="p{n}" data-index="{n}">
+  <h2>Überschrift {n} &mdash; 見出し &#x1F600;</h2>
+  <p>Lorem ipsum <b>dolor <i>sit</b> amet</i>, consectetur &amp; adipiscing
+  <a href="/posts/{n}?a=1&b=2">élit</a>. Ça marche — да, это работает.
+  <ul><li>one<li>two<li>three</ul>
+  <table><tr><td>{n}<td>cell</table>
+  <!-- comment {n} -->
+  <pre>
+  preformatted   text</pre>
+</div>
+"#;
+
+const CSS_CHUNK: &str = r#"/* rule {n} */
+.post-{n} > h2, #p{n} .title, a[href^="/posts"] {
+  color: #ff8800;
+  margin: 0 auto;
+  font-family: "Noto Sans", sans-serif;
+  content: "→ ünïcödé {n}";
+}
+@media screen and (max-width: 600px) {
+  .post-{n} { padding: 4px !important; }
+}
+"#;
+
+fn generate(chunk: &str, size: usize) -> String {
+    let mut output = String::with_capacity(size + chunk.len() * 2);
+    let mut n = 0;
+    while output.len() < size {
+        output.push_str(&chunk.replace("{n}", &n.to_string()));
+        n += 1;
+    }
+    output
+}
+
+// Best of several rounds, to keep scheduling noise out of the numbers.
+fn measure(mut run: impl FnMut()) -> Duration {
+    (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            run();
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+fn report(name: &str, size: usize, elapsed: Duration) {
+    let megabytes = size as f64 / MB as f64;
+    println!(
+        "{:<5} {:>5.1} MB  {:>9.2?}  {:>7.1} MB/s",
+        name,
+        megabytes,
+        elapsed,
+        megabytes / elapsed.as_secs_f64()
+    );
+}
+
+fn main() {
+    for size in [MB, 4 * MB] {
+        let html = generate(HTML_CHUNK, size);
+        let elapsed = measure(|| {
+            black_box(Parser::new(html.clone()).parse());
+        });
+        report("html", html.len(), elapsed);
+
+        let css = generate(CSS_CHUNK, size);
+        let elapsed = measure(|| {
+            black_box(CssParser::new(&css).parse_stylesheet());
+        });
+        report("css", css.len(), elapsed);
+    }
+}
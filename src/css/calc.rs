@@ -0,0 +1,434 @@
+use super::values::{Unit, Value};
+use std::fmt;
+
+/// The expression of a math function (`calc()`, `min()`, `max()` or
+/// `clamp()`), as a calculation tree from CSS Values and Units Level 4.
+/// Subtraction is a sum with a negated term, division a product with an
+/// inverted factor.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Calc {
+    Number(f32),
+    Dimension(f32, Unit),
+    Percentage(f32),
+    Sum(Vec<Calc>),
+    Product(Vec<Calc>),
+    Negate(Box<Calc>),
+    Invert(Box<Calc>),
+    Min(Vec<Calc>),
+    Max(Vec<Calc>),
+    // The minimum, the preferred value and the maximum.
+    Clamp(Box<Calc>, Box<Calc>, Box<Calc>),
+}
+
+/// What a calculation resolves to. A sum of lengths and percentages is a
+/// length-percentage, whose percentages resolve to lengths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalcType {
+    Number,
+    Percentage,
+    Length,
+    LengthPercentage,
+    Angle,
+    Time,
+    Frequency,
+    Resolution,
+}
+
+/// What relative lengths and percentages in a calculation are resolved
+/// against. Missing parts leave calculations that need them unresolved.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalcContext {
+    pub font_size: f32,
+    pub root_font_size: f32,
+    // The width and height of the viewport, in pixels.
+    pub viewport: Option<(f32, f32)>,
+    // What 100% is, in the canonical unit of the calculation's type.
+    pub percentage_basis: Option<f32>,
+}
+
+impl Calc {
+    /// The type the calculation resolves to, or None when it mixes types
+    /// that cannot be added, like `1px + 2s`, or multiplies or divides two
+    /// dimensions.
+    pub fn kind(&self) -> Option<CalcType> {
+        match self {
+            Calc::Number(_) => Some(CalcType::Number),
+            Calc::Percentage(_) => Some(CalcType::Percentage),
+            Calc::Dimension(_, unit) => unit_type(unit),
+            Calc::Negate(calc) => calc.kind(),
+            Calc::Invert(calc) => (calc.kind()? == CalcType::Number).then_some(CalcType::Number),
+            Calc::Product(factors) => factors.iter().try_fold(CalcType::Number, |kind, factor| {
+                match (kind, factor.kind()?) {
+                    (CalcType::Number, kind) | (kind, CalcType::Number) => Some(kind),
+                    _ => None,
+                }
+            }),
+            Calc::Sum(calcs) | Calc::Min(calcs) | Calc::Max(calcs) => {
+                let (first, rest) = calcs.split_first()?;
+                rest.iter()
+                    .try_fold(first.kind()?, |kind, calc| add_types(kind, calc.kind()?))
+            }
+            Calc::Clamp(min, value, max) => {
+                add_types(add_types(min.kind()?, value.kind()?)?, max.kind()?)
+            }
+        }
+    }
+
+    /// Simplifies the tree as far as it can be without a context: nested
+    /// sums and products are flattened, absolute units are converted to
+    /// their canonical unit (px, deg, s, Hz and dppx), terms of the same unit
+    /// are added together and numbers are multiplied out.
+    pub fn simplify(self) -> Calc {
+        match self {
+            Calc::Dimension(n, unit) => canonical(n, unit),
+            Calc::Number(_) | Calc::Percentage(_) => self,
+            Calc::Negate(calc) => match calc.simplify() {
+                Calc::Negate(calc) => *calc,
+                calc => match calc.scale(-1.0) {
+                    Some(negated) => negated,
+                    None => Calc::Negate(Box::new(calc)),
+                },
+            },
+            Calc::Invert(calc) => match calc.simplify() {
+                Calc::Number(n) => Calc::Number(1.0 / n),
+                Calc::Invert(calc) => *calc,
+                calc => Calc::Invert(Box::new(calc)),
+            },
+            Calc::Sum(terms) => {
+                let mut sum: Vec<Calc> = Vec::new();
+                for term in terms.into_iter().map(Calc::simplify) {
+                    let terms = match term {
+                        Calc::Sum(terms) => terms,
+                        term => vec![term],
+                    };
+                    for term in terms {
+                        match sum.iter_mut().find(|other| other.same_unit(&term)) {
+                            Some(other) => other.add(&term),
+                            None => sum.push(term),
+                        }
+                    }
+                }
+                single_or(sum, Calc::Sum)
+            }
+            Calc::Product(factors) => {
+                let mut number = 1.0;
+                let mut rest = Vec::new();
+                for factor in factors.into_iter().map(Calc::simplify) {
+                    let factors = match factor {
+                        Calc::Product(factors) => factors,
+                        factor => vec![factor],
+                    };
+                    for factor in factors {
+                        match factor {
+                            Calc::Number(n) => number *= n,
+                            factor => rest.push(factor),
+                        }
+                    }
+                }
+                match rest.as_slice() {
+                    [] => return Calc::Number(number),
+                    [factor] => {
+                        if let Some(scaled) = factor.scale(number) {
+                            return scaled;
+                        }
+                    }
+                    _ => {}
+                }
+                if number != 1.0 {
+                    rest.insert(0, Calc::Number(number));
+                }
+                single_or(rest, Calc::Product)
+            }
+            Calc::Min(calcs) => fold(calcs, Calc::Min, f32::min),
+            Calc::Max(calcs) => fold(calcs, Calc::Max, f32::max),
+            Calc::Clamp(min, value, max) => {
+                let (min, value, max) = (min.simplify(), value.simplify(), max.simplify());
+                match (min.leaf(), value.leaf(), max.leaf()) {
+                    (Some(lower), Some(n), Some(upper))
+                        if min.same_unit(&value) && value.same_unit(&max) =>
+                    {
+                        value.with_number(n.min(upper).max(lower))
+                    }
+                    _ => Calc::Clamp(Box::new(min), Box::new(value), Box::new(max)),
+                }
+            }
+        }
+    }
+
+    /// Converts font-relative lengths, and viewport units when the context
+    /// has a viewport, to pixels and simplifies again, as for the computed
+    /// value. Percentages are left for layout.
+    pub fn absolutize(&self, context: &CalcContext) -> Calc {
+        self.map_leaves(&|calc| match calc {
+            Calc::Dimension(n, unit) => match unit {
+                Unit::Em => Calc::Dimension(n * context.font_size, Unit::Px),
+                Unit::Rem => Calc::Dimension(n * context.root_font_size, Unit::Px),
+                // Without font metrics, ex and ch are taken as half an em.
+                Unit::Ex | Unit::Ch => Calc::Dimension(n * context.font_size / 2.0, Unit::Px),
+                unit => match context
+                    .viewport
+                    .and_then(|viewport| unit.viewport_length(n, viewport))
+                {
+                    Some(n) => Calc::Dimension(n, Unit::Px),
+                    None => Calc::Dimension(n, unit),
+                },
+            },
+            calc => calc,
+        })
+        .simplify()
+    }
+
+    /// The value of the calculation in the canonical unit of its type, or
+    /// None if it needs something the context does not have.
+    pub fn resolve(&self, context: &CalcContext) -> Option<f32> {
+        let all = |calcs: &[Calc]| -> Option<Vec<f32>> {
+            calcs.iter().map(|calc| calc.resolve(context)).collect()
+        };
+        let value = match self {
+            Calc::Number(n) => *n,
+            Calc::Percentage(p) => p / 100.0 * context.percentage_basis?,
+            Calc::Dimension(n, unit) => match unit {
+                Unit::Em => n * context.font_size,
+                Unit::Rem => n * context.root_font_size,
+                Unit::Ex | Unit::Ch => n * context.font_size / 2.0,
+                unit if unit.is_viewport_relative() => {
+                    unit.viewport_length(*n, context.viewport?)?
+                }
+                unit => match canonical(*n, unit.clone()) {
+                    Calc::Dimension(n, _) => n,
+                    _ => return None,
+                },
+            },
+            Calc::Sum(terms) => all(terms)?.into_iter().sum(),
+            Calc::Product(factors) => all(factors)?.into_iter().product(),
+            Calc::Negate(calc) => -calc.resolve(context)?,
+            Calc::Invert(calc) => 1.0 / calc.resolve(context)?,
+            Calc::Min(calcs) => all(calcs)?.into_iter().reduce(f32::min)?,
+            Calc::Max(calcs) => all(calcs)?.into_iter().reduce(f32::max)?,
+            Calc::Clamp(min, value, max) => value
+                .resolve(context)?
+                .min(max.resolve(context)?)
+                .max(min.resolve(context)?),
+        };
+        Some(value)
+    }
+
+    /// The plain value a calculation simplified to a single number,
+    /// percentage or dimension stands for, or else the calculation itself.
+    pub fn into_value(self) -> Value {
+        match self {
+            Calc::Number(n) => Value::Number(n),
+            Calc::Percentage(p) => Value::Percentage(p),
+            Calc::Dimension(n, unit) => Value::Length(n, unit),
+            calc => Value::Calc(Box::new(calc)),
+        }
+    }
+
+    fn map_leaves(&self, map: &impl Fn(Calc) -> Calc) -> Calc {
+        let all = |calcs: &[Calc]| calcs.iter().map(|calc| calc.map_leaves(map)).collect();
+        match self {
+            Calc::Number(_) | Calc::Dimension(..) | Calc::Percentage(_) => map(self.clone()),
+            Calc::Sum(terms) => Calc::Sum(all(terms)),
+            Calc::Product(factors) => Calc::Product(all(factors)),
+            Calc::Negate(calc) => Calc::Negate(Box::new(calc.map_leaves(map))),
+            Calc::Invert(calc) => Calc::Invert(Box::new(calc.map_leaves(map))),
+            Calc::Min(calcs) => Calc::Min(all(calcs)),
+            Calc::Max(calcs) => Calc::Max(all(calcs)),
+            Calc::Clamp(min, value, max) => Calc::Clamp(
+                Box::new(min.map_leaves(map)),
+                Box::new(value.map_leaves(map)),
+                Box::new(max.map_leaves(map)),
+            ),
+        }
+    }
+
+    // The number of a number, percentage or dimension.
+    fn leaf(&self) -> Option<f32> {
+        match self {
+            Calc::Number(n) | Calc::Percentage(n) | Calc::Dimension(n, _) => Some(*n),
+            _ => None,
+        }
+    }
+
+    fn with_number(&self, n: f32) -> Calc {
+        match self {
+            Calc::Number(_) => Calc::Number(n),
+            Calc::Percentage(_) => Calc::Percentage(n),
+            Calc::Dimension(_, unit) => Calc::Dimension(n, unit.clone()),
+            calc => calc.clone(),
+        }
+    }
+
+    // Whether both are numbers, percentages or dimensions in one unit.
+    fn same_unit(&self, other: &Calc) -> bool {
+        match (self, other) {
+            (Calc::Number(_), Calc::Number(_)) => true,
+            (Calc::Percentage(_), Calc::Percentage(_)) => true,
+            (Calc::Dimension(_, a), Calc::Dimension(_, b)) => a == b,
+            _ => false,
+        }
+    }
+
+    fn add(&mut self, other: &Calc) {
+        if let (Some(a), Some(b)) = (self.leaf(), other.leaf()) {
+            *self = self.with_number(a + b);
+        }
+    }
+
+    // Multiplies a number, percentage or dimension, or a sum of them, by
+    // `factor`. None for anything else.
+    fn scale(&self, factor: f32) -> Option<Calc> {
+        match self {
+            Calc::Sum(terms) => terms
+                .iter()
+                .map(|term| term.leaf().map(|n| term.with_number(n * factor)))
+                .collect::<Option<_>>()
+                .map(Calc::Sum),
+            calc => calc.leaf().map(|n| calc.with_number(n * factor)),
+        }
+    }
+}
+
+// Whether `name` is a math function this module parses.
+pub fn is_math_function(name: &str) -> bool {
+    matches!(
+        name.to_ascii_lowercase().as_str(),
+        "calc" | "min" | "max" | "clamp"
+    )
+}
+
+fn unit_type(unit: &Unit) -> Option<CalcType> {
+    let kind = match unit {
+        Unit::Deg | Unit::Rad | Unit::Grad | Unit::Turn => CalcType::Angle,
+        Unit::S | Unit::Ms => CalcType::Time,
+        Unit::Hz | Unit::Khz => CalcType::Frequency,
+        Unit::Dpi | Unit::Dpcm | Unit::Dppx => CalcType::Resolution,
+        Unit::Percent => CalcType::Percentage,
+        // Flexible lengths are not allowed in calculations.
+        Unit::Fr => return None,
+        _ => CalcType::Length,
+    };
+    Some(kind)
+}
+
+fn add_types(a: CalcType, b: CalcType) -> Option<CalcType> {
+    use CalcType::*;
+    match (a, b) {
+        (a, b) if a == b => Some(a),
+        (Length | Percentage | LengthPercentage, Length | Percentage | LengthPercentage) => {
+            Some(LengthPercentage)
+        }
+        _ => None,
+    }
+}
+
+// Converts a dimension in an absolute unit to the canonical unit of its
+// type; relative units stay as they are.
+fn canonical(n: f32, unit: Unit) -> Calc {
+    let (n, unit) = match unit {
+        Unit::In => (n * 96.0, Unit::Px),
+        Unit::Cm => (n * 96.0 / 2.54, Unit::Px),
+        Unit::Mm => (n * 96.0 / 25.4, Unit::Px),
+        Unit::Q => (n * 96.0 / 101.6, Unit::Px),
+        Unit::Pt => (n * 96.0 / 72.0, Unit::Px),
+        Unit::Pc => (n * 16.0, Unit::Px),
+        Unit::Rad => (n.to_degrees(), Unit::Deg),
+        Unit::Grad => (n * 0.9, Unit::Deg),
+        Unit::Turn => (n * 360.0, Unit::Deg),
+        Unit::Ms => (n / 1000.0, Unit::S),
+        Unit::Khz => (n * 1000.0, Unit::Hz),
+        Unit::Dpi => (n / 96.0, Unit::Dppx),
+        Unit::Dpcm => (n * 2.54 / 96.0, Unit::Dppx),
+        unit => (n, unit),
+    };
+    Calc::Dimension(n, unit)
+}
+
+fn single_or(mut calcs: Vec<Calc>, wrap: fn(Vec<Calc>) -> Calc) -> Calc {
+    if calcs.len() == 1 {
+        calcs.remove(0)
+    } else {
+        wrap(calcs)
+    }
+}
+
+// Simplifies the arguments of `min()` or `max()`, which come down to one
+// value when they are all in the same unit.
+fn fold(calcs: Vec<Calc>, wrap: fn(Vec<Calc>) -> Calc, pick: fn(f32, f32) -> f32) -> Calc {
+    let calcs: Vec<Calc> = calcs.into_iter().map(Calc::simplify).collect();
+    let folded = calcs.split_first().and_then(|(first, rest)| {
+        rest.iter().try_fold(first.leaf()?, |n, calc| {
+            first.same_unit(calc).then_some(pick(n, calc.leaf()?))
+        })
+    });
+    match folded {
+        Some(n) => calcs[0].with_number(n),
+        None => single_or(calcs, wrap),
+    }
+}
+
+// Serializes as the arguments of a `calc()`, so the outermost sum has no
+// parentheses.
+impl fmt::Display for Calc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Calc::Number(n) => write!(f, "{}", n),
+            Calc::Percentage(p) => write!(f, "{}%", p),
+            Calc::Dimension(n, unit) => write!(f, "{}{}", n, unit),
+            Calc::Sum(terms) => {
+                for (i, term) in terms.iter().enumerate() {
+                    match (i, term) {
+                        (0, term) => write!(f, "{}", term)?,
+                        (_, Calc::Negate(term)) => write!(f, " - {}", Nested(term))?,
+                        (_, term) if term.leaf().is_some_and(|n| n < 0.0) => {
+                            write!(f, " - {}", term.scale(-1.0).unwrap())?
+                        }
+                        (_, term) => write!(f, " + {}", term)?,
+                    }
+                }
+                Ok(())
+            }
+            Calc::Product(factors) => {
+                for (i, factor) in factors.iter().enumerate() {
+                    match (i, factor) {
+                        (0, Calc::Invert(factor)) => write!(f, "1 / {}", Nested(factor))?,
+                        (0, factor) => write!(f, "{}", Nested(factor))?,
+                        (_, Calc::Invert(factor)) => write!(f, " / {}", Nested(factor))?,
+                        (_, factor) => write!(f, " * {}", Nested(factor))?,
+                    }
+                }
+                Ok(())
+            }
+            Calc::Negate(calc) => write!(f, "-1 * {}", Nested(calc)),
+            Calc::Invert(calc) => write!(f, "1 / {}", Nested(calc)),
+            Calc::Min(calcs) => write_function(f, "min", calcs),
+            Calc::Max(calcs) => write_function(f, "max", calcs),
+            Calc::Clamp(min, value, max) => write!(f, "clamp({}, {}, {})", min, value, max),
+        }
+    }
+}
+
+// A sum or product inside another expression, which is parenthesized.
+struct Nested<'a>(&'a Calc);
+
+impl fmt::Display for Nested<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            calc @ (Calc::Sum(_) | Calc::Product(_) | Calc::Negate(_) | Calc::Invert(_)) => {
+                write!(f, "({})", calc)
+            }
+            calc => write!(f, "{}", calc),
+        }
+    }
+}
+
+fn write_function(f: &mut fmt::Formatter, name: &str, calcs: &[Calc]) -> fmt::Result {
+    write!(f, "{}(", name)?;
+    for (i, calc) in calcs.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", calc)?;
+    }
+    write!(f, ")")
+}
@@ -0,0 +1,705 @@
+use super::calc::CalcContext;
+use super::matching::matching_specificity;
+use super::media::MediaEnvironment;
+use super::parser::CssParser;
+use super::properties::Property;
+use super::rules::{Declaration, Rule, Specificity, Stylesheet};
+use super::tokenizer::{self, Token};
+use super::user_agent::user_agent_stylesheet;
+use super::values::{Unit, Unparsed, Value};
+use super::variables::{self, is_custom_property};
+use crate::html::arena::{Dom, NodeId};
+use crate::html::dom::NodeType;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// The font size of the root element's parent, i.e. the initial `medium`.
+const INITIAL_FONT_SIZE: f32 = 16.0;
+
+/// Where a stylesheet comes from. For normal declarations later origins
+/// win; for `!important` ones the order is reversed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Origin {
+    UserAgent,
+    User,
+    Author,
+}
+
+/// The stylesheets that apply to a document, in the order they appear,
+/// after the built-in user agent stylesheet.
+#[derive(Debug)]
+pub struct Cascade {
+    user_agent: &'static Stylesheet,
+    stylesheets: Vec<(Origin, Stylesheet)>,
+    // What @media rules are evaluated against.
+    media: MediaEnvironment,
+}
+
+/// A node with its computed style, and its styled children. Only elements
+/// and text are styled; text takes the inherited style of its parent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyledNode {
+    pub node: NodeId,
+    pub style: ComputedStyle,
+    pub children: Vec<StyledNode>,
+}
+
+/// The computed value of every property of one node. Properties that are
+/// neither set by the cascade nor inherited have their initial value.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ComputedStyle {
+    values: BTreeMap<String, Value>,
+    // None until the root element has been styled.
+    root_font_size: Option<f32>,
+}
+
+// A longhand declaration that applies to the element being styled, with
+// what it is sorted by: origin and importance, then whether it is from the
+// `style` attribute, then cascade layer, then specificity, then source
+// order.
+struct Candidate {
+    precedence: u8,
+    inline: bool,
+    layer: Vec<usize>,
+    specificity: Specificity,
+    order: usize,
+    name: String,
+    value: Value,
+}
+
+// Gathers the declarations that apply to one element from the rules of
+// each stylesheet in turn.
+struct Collector<'a> {
+    media: &'a MediaEnvironment,
+    dom: &'a Dom,
+    id: NodeId,
+    // The layers declared so far in each origin; origins don't share them.
+    layers: BTreeMap<Origin, Layers>,
+    order: usize,
+    candidates: Vec<Candidate>,
+}
+
+// Cascade layers in the order they are first declared, each with its own
+// sublayers. Anonymous layers have no name, so they are never declared
+// again.
+#[derive(Default)]
+struct Layers {
+    sublayers: Vec<(Option<String>, Layers)>,
+}
+
+impl Cascade {
+    pub fn new() -> Self {
+        Cascade {
+            user_agent: user_agent_stylesheet(),
+            stylesheets: Vec::new(),
+            media: MediaEnvironment::default(),
+        }
+    }
+
+    /// Sets the device and preferences @media rules are evaluated against,
+    /// in place of a desktop screen's.
+    pub fn set_media_environment(&mut self, media: MediaEnvironment) {
+        self.media = media;
+    }
+
+    /// Adds a stylesheet after the ones already added, so it wins ties in
+    /// source order against them. Nested rules are flattened first.
+    pub fn add_stylesheet(&mut self, origin: Origin, stylesheet: Stylesheet) {
+        self.stylesheets.push((origin, stylesheet.flatten()));
+    }
+
+    /// Styles every element and text node under the root of `dom`.
+    pub fn style_tree(&self, dom: &Dom) -> StyledNode {
+        self.style_node(dom, dom.root(), &ComputedStyle::default())
+    }
+
+    fn style_node(&self, dom: &Dom, id: NodeId, parent: &ComputedStyle) -> StyledNode {
+        let style = match dom[id].node_type {
+            NodeType::Element(_) => self.computed_style(dom, id, parent),
+            _ => parent.inherit(),
+        };
+        let children = dom
+            .children(id)
+            .filter(|&child| {
+                matches!(
+                    dom[child].node_type,
+                    NodeType::Element(_) | NodeType::Text(_)
+                )
+            })
+            .map(|child| self.style_node(dom, child, &style))
+            .collect();
+        StyledNode {
+            node: id,
+            style,
+            children,
+        }
+    }
+
+    /// The computed style of the element `id`, given its parent's.
+    pub fn computed_style(&self, dom: &Dom, id: NodeId, parent: &ComputedStyle) -> ComputedStyle {
+        let viewport = (self.media.width, self.media.height);
+        ComputedStyle::compute(self.cascaded_values(dom, id), parent, viewport)
+    }
+
+    /// The winning declared value of each property on the element `id`.
+    pub fn cascaded_values(&self, dom: &Dom, id: NodeId) -> BTreeMap<String, Value> {
+        let mut collector = Collector {
+            media: &self.media,
+            dom,
+            id,
+            layers: BTreeMap::new(),
+            order: 0,
+            candidates: Vec::new(),
+        };
+        let user_agent = std::iter::once((Origin::UserAgent, self.user_agent));
+        let stylesheets = self
+            .stylesheets
+            .iter()
+            .map(|(origin, stylesheet)| (*origin, stylesheet));
+        for (origin, stylesheet) in user_agent.chain(stylesheets) {
+            collector.collect(&stylesheet.rules, origin, &[]);
+        }
+        let Collector {
+            mut order,
+            mut candidates,
+            ..
+        } = collector;
+
+        // Declarations in the style attribute are unlayered author
+        // declarations that beat any selector.
+        let inline = inline_declarations(dom, id);
+        for declaration in &inline {
+            order += 1;
+            let precedence = precedence(Origin::Author, declaration.important);
+            for (name, value) in longhands(declaration) {
+                candidates.push(Candidate {
+                    precedence,
+                    inline: true,
+                    layer: layer_key(&[], declaration.important),
+                    specificity: Specificity::default(),
+                    order,
+                    name,
+                    value,
+                });
+            }
+        }
+
+        candidates.sort_by_cached_key(|candidate| {
+            (
+                candidate.precedence,
+                candidate.inline,
+                candidate.layer.clone(),
+                candidate.specificity,
+                candidate.order,
+            )
+        });
+        candidates
+            .into_iter()
+            .map(|candidate| (candidate.name, candidate.value))
+            .collect()
+    }
+}
+
+impl Collector<'_> {
+    // Adds the declarations of the rules that apply to the element, which
+    // are in the layer at `layer`, or unlayered when it is empty.
+    fn collect(&mut self, rules: &[Rule], origin: Origin, layer: &[usize]) {
+        for rule in rules {
+            let style_rule = match rule {
+                Rule::Style(style_rule) => style_rule,
+                Rule::Media { query, rules, .. } => {
+                    if query.matches(self.media) {
+                        self.collect(rules, origin, layer);
+                    }
+                    continue;
+                }
+                Rule::Supports {
+                    condition, rules, ..
+                } => {
+                    if condition.evaluate() {
+                        self.collect(rules, origin, layer);
+                    }
+                    continue;
+                }
+                Rule::Layer { name, rules, .. } => {
+                    let path = self.declare_layer(origin, layer, name.as_deref());
+                    self.collect(rules, origin, &path);
+                    continue;
+                }
+                Rule::LayerStatement { names, .. } => {
+                    for name in names {
+                        self.declare_layer(origin, layer, Some(name));
+                    }
+                    continue;
+                }
+                // The imported stylesheet is not fetched, but its layer is
+                // declared all the same.
+                Rule::Import {
+                    layer: Some(Some(name)),
+                    ..
+                } => {
+                    self.declare_layer(origin, layer, Some(name));
+                    continue;
+                }
+                // Container queries need a layout to be evaluated against.
+                // Keyframes only apply through animations, and the other
+                // rules hold no properties of elements.
+                Rule::Container { .. }
+                | Rule::Import { .. }
+                | Rule::Keyframes { .. }
+                | Rule::FontFace { .. }
+                | Rule::Page { .. }
+                | Rule::Namespace { .. } => continue,
+            };
+            let Some(specificity) = matching_specificity(&style_rule.selectors, self.dom, self.id)
+            else {
+                self.order += style_rule.declarations.len();
+                continue;
+            };
+            for declaration in &style_rule.declarations {
+                self.order += 1;
+                let precedence = precedence(origin, declaration.important);
+                for (name, value) in longhands(declaration) {
+                    self.candidates.push(Candidate {
+                        precedence,
+                        inline: false,
+                        layer: layer_key(layer, declaration.important),
+                        specificity,
+                        order: self.order,
+                        name,
+                        value,
+                    });
+                }
+            }
+        }
+    }
+
+    // Declares the layer `name`, which may be dotted, inside the layer at
+    // `parent`, along with each layer on the way to it, and gives back its
+    // path. Without a name the layer is a new anonymous one.
+    fn declare_layer(
+        &mut self,
+        origin: Origin,
+        parent: &[usize],
+        name: Option<&str>,
+    ) -> Vec<usize> {
+        let layers = self.layers.entry(origin).or_default();
+        let mut path = parent.to_vec();
+        match name {
+            Some(name) => {
+                for part in name.split('.') {
+                    path.push(layers.get(&path).declare(Some(part)));
+                }
+            }
+            None => path.push(layers.get(&path).declare(None)),
+        }
+        path
+    }
+}
+
+impl Layers {
+    // The layer at `path`, which must have been declared.
+    fn get(&mut self, path: &[usize]) -> &mut Layers {
+        path.iter()
+            .fold(self, |layers, &index| &mut layers.sublayers[index].1)
+    }
+
+    // The position of the sublayer `name`, declaring it after the others if
+    // it is new. An anonymous sublayer is always new.
+    fn declare(&mut self, name: Option<&str>) -> usize {
+        let existing = name.and_then(|name| {
+            self.sublayers
+                .iter()
+                .position(|(sublayer, _)| sublayer.as_deref() == Some(name))
+        });
+        existing.unwrap_or_else(|| {
+            self.sublayers
+                .push((name.map(str::to_string), Layers::default()));
+            self.sublayers.len() - 1
+        })
+    }
+}
+
+// What a declaration in the layer at `path` sorts by. Declarations directly
+// in a layer come after those of its sublayers, as unlayered declarations
+// come after those of every layer. The order is reversed for `!important`
+// declarations; as no key is a prefix of another, complementing each
+// position does that.
+fn layer_key(path: &[usize], important: bool) -> Vec<usize> {
+    path.iter()
+        .copied()
+        .chain(std::iter::once(usize::MAX))
+        .map(|index| if important { usize::MAX - index } else { index })
+        .collect()
+}
+
+// The longhands a declaration sets, or the custom property it is. Unknown
+// properties and shorthands with invalid values set nothing.
+fn longhands(declaration: &Declaration) -> Vec<(String, Value)> {
+    if is_custom_property(&declaration.name) {
+        return vec![(declaration.name.clone(), declaration.value.clone())];
+    }
+    Property::parse(&declaration.name, declaration.value.clone())
+        .and_then(|property| property.expand().ok())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|property| (property.name().to_string(), property.value().clone()))
+        .collect()
+}
+
+// The declarations of the element's `style` attribute. Errors in it are
+// ignored here, as they are when the attribute is applied.
+fn inline_declarations(dom: &Dom, id: NodeId) -> Vec<Declaration> {
+    let Some(element) = dom.element(id) else {
+        return Vec::new();
+    };
+    element
+        .attributes
+        .iter()
+        .find(|attribute| attribute.name == "style")
+        .map(|attribute| CssParser::new(&attribute.value).parse_declaration_list().0)
+        .unwrap_or_default()
+}
+
+// Normal user agent < normal user < normal author < important author
+// < important user < important user agent.
+fn precedence(origin: Origin, important: bool) -> u8 {
+    match (important, origin) {
+        (false, Origin::UserAgent) => 0,
+        (false, Origin::User) => 1,
+        (false, Origin::Author) => 2,
+        (true, Origin::Author) => 3,
+        (true, Origin::User) => 4,
+        (true, Origin::UserAgent) => 5,
+    }
+}
+
+impl Default for Cascade {
+    fn default() -> Self {
+        Cascade::new()
+    }
+}
+
+impl ComputedStyle {
+    /// Resolves cascaded values against the parent's style: `var()` is
+    /// substituted, CSS-wide keywords are applied, inherited properties are
+    /// filled in, and relative lengths become pixels, with viewport units
+    /// relative to a viewport of the given width and height.
+    pub fn compute(
+        cascaded: BTreeMap<String, Value>,
+        parent: &ComputedStyle,
+        viewport: (f32, f32),
+    ) -> ComputedStyle {
+        let mut style = parent.inherit();
+        let parent_font_size = parent.font_size();
+        let root_font_size = parent.root_font_size.unwrap_or(INITIAL_FONT_SIZE);
+
+        // Custom properties come first, as `var()` in the other values
+        // refers to their computed values.
+        let mut custom_properties = CustomProperties {
+            cascaded: &cascaded,
+            parent,
+            resolved: HashMap::new(),
+            stack: Vec::new(),
+            cyclic: HashSet::new(),
+        };
+        for name in cascaded.keys().filter(|name| is_custom_property(name)) {
+            match custom_properties.resolve(name) {
+                Some(tokens) => {
+                    let value = Value::Unparsed(Unparsed {
+                        tokens,
+                        shorthand: None,
+                    });
+                    style.values.insert(name.clone(), value);
+                }
+                // The guaranteed-invalid value, which is not inherited
+                // from the parent either.
+                None => {
+                    style.values.remove(name);
+                }
+            }
+        }
+        let cascaded: BTreeMap<String, Value> = cascaded
+            .iter()
+            .filter(|(name, _)| !is_custom_property(name))
+            .map(|(name, value)| {
+                let value = match value {
+                    Value::Unparsed(unparsed) => substitute_var(name, unparsed, &style),
+                    value => value.clone(),
+                };
+                (name.clone(), value)
+            })
+            .collect();
+
+        let specified = |name: &str, value: Value| -> Value {
+            let inherit = || parent.value(name).cloned().unwrap_or(Value::Initial);
+            match value {
+                Value::Inherit => inherit(),
+                Value::Unset if Property::is_inherited(name) => inherit(),
+                Value::Initial | Value::Unset => Property::initial_value(name)
+                    .cloned()
+                    .unwrap_or(Value::Initial),
+                // `color: currentcolor` means the parent's color.
+                Value::CurrentColor if name == "color" => inherit(),
+                Value::Keyword(keyword)
+                    if name == "color" && keyword.eq_ignore_ascii_case("currentcolor") =>
+                {
+                    inherit()
+                }
+                value => value,
+            }
+        };
+
+        // Everything else in em is relative to this element's font size.
+        let mut font_size = parent_font_size;
+        if let Some(value) = cascaded.get("font-size") {
+            let value = specified("font-size", value.clone());
+            // Relative lengths in font-size refer to the parent's.
+            let context = CalcContext {
+                font_size: parent_font_size,
+                root_font_size,
+                viewport: Some(viewport),
+                percentage_basis: Some(parent_font_size),
+            };
+            font_size = resolve_font_size(&value, &context).unwrap_or(parent_font_size);
+            style
+                .values
+                .insert("font-size".to_string(), Value::Length(font_size, Unit::Px));
+        }
+
+        let context = CalcContext {
+            font_size,
+            root_font_size,
+            viewport: Some(viewport),
+            percentage_basis: None,
+        };
+        for (name, value) in cascaded {
+            if name == "font-size" {
+                continue;
+            }
+            let value = specified(&name, value);
+            let mut value = absolute_lengths(&value, &context);
+            if Property::zero_is_length(&name) {
+                value = zero_lengths(value);
+            }
+            style.values.insert(name, value);
+        }
+
+        style.root_font_size = Some(root_font_size);
+        if parent.root_font_size.is_none() {
+            style.root_font_size = Some(font_size);
+        }
+        style
+    }
+
+    /// The style a child with no declarations of its own would get: the
+    /// inherited properties, with everything else back to initial.
+    pub fn inherit(&self) -> ComputedStyle {
+        ComputedStyle {
+            values: self
+                .values
+                .iter()
+                .filter(|(name, _)| Property::is_inherited(name))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            root_font_size: self.root_font_size,
+        }
+    }
+
+    /// The computed value of a property, or None for unknown properties.
+    pub fn value(&self, name: &str) -> Option<&Value> {
+        self.values
+            .get(name)
+            .or_else(|| Property::initial_value(name))
+    }
+
+    /// The computed value of a property as a declaration. Shorthands are
+    /// put back together from their longhands, when that is possible.
+    pub fn get(&self, name: &str) -> Option<Property> {
+        if let Some(longhands) = Property::longhands(name) {
+            let longhands: Vec<Property> = longhands
+                .iter()
+                .filter_map(|longhand| self.get(longhand))
+                .collect();
+            return Property::shorthand(name, &longhands);
+        }
+        Property::parse(name, self.value(name)?.clone())
+    }
+
+    /// The properties set by the cascade or inherited, in name order.
+    pub fn properties(&self) -> impl Iterator<Item = Property> + '_ {
+        self.values
+            .iter()
+            .filter_map(|(name, value)| Property::parse(name, value.clone()))
+    }
+
+    /// The custom properties set by the cascade or inherited, in name
+    /// order, with their `var()` references substituted.
+    pub fn custom_properties(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.values
+            .iter()
+            .filter(|(name, _)| is_custom_property(name))
+            .map(|(name, value)| (name.as_str(), value))
+    }
+
+    pub fn font_size(&self) -> f32 {
+        match self.value("font-size") {
+            Some(Value::Length(size, Unit::Px)) => *size,
+            _ => INITIAL_FONT_SIZE,
+        }
+    }
+}
+
+// Resolves the `var()` references between the custom properties of one
+// element, finding those in reference cycles.
+struct CustomProperties<'a> {
+    cascaded: &'a BTreeMap<String, Value>,
+    parent: &'a ComputedStyle,
+    resolved: HashMap<String, Option<Vec<Token>>>,
+    // The properties being resolved, innermost last.
+    stack: Vec<String>,
+    cyclic: HashSet<String>,
+}
+
+impl CustomProperties<'_> {
+    // The computed value of the custom property `name`, or None for the
+    // guaranteed-invalid value.
+    fn resolve(&mut self, name: &str) -> Option<Vec<Token>> {
+        if let Some(position) = self.stack.iter().position(|other| other == name) {
+            // Every property in a cycle is invalid, fallbacks or not.
+            self.cyclic.extend(self.stack[position..].iter().cloned());
+            return None;
+        }
+        if let Some(resolved) = self.resolved.get(name) {
+            return resolved.clone();
+        }
+
+        let cascaded = self.cascaded;
+        let resolved = match cascaded.get(name) {
+            None | Some(Value::Inherit | Value::Unset) => match self.parent.values.get(name) {
+                Some(Value::Unparsed(inherited)) => Some(inherited.tokens.clone()),
+                _ => None,
+            },
+            Some(Value::Unparsed(unparsed)) => {
+                self.stack.push(name.to_string());
+                let substituted =
+                    variables::substitute(&unparsed.tokens, &mut |other| self.resolve(other));
+                self.stack.pop();
+                substituted
+                    .filter(|_| !self.cyclic.contains(name))
+                    .map(|tokens| variables::trim(&tokens).to_vec())
+            }
+            // `initial`
+            Some(_) => None,
+        };
+        self.resolved.insert(name.to_string(), resolved.clone());
+        resolved
+    }
+}
+
+// Substitutes the element's custom properties into a value using `var()`
+// and parses the result as a value of the property `name`. A value that is
+// invalid then acts as `unset`.
+fn substitute_var(name: &str, unparsed: &Unparsed, style: &ComputedStyle) -> Value {
+    let mut lookup = |reference: &str| match style.values.get(reference) {
+        Some(Value::Unparsed(value)) => Some(value.tokens.clone()),
+        _ => None,
+    };
+    let Some(tokens) = variables::substitute(&unparsed.tokens, &mut lookup) else {
+        return Value::Unset;
+    };
+    let value = CssParser::new(&tokenizer::serialize(&tokens)).parse_property_value();
+    let property = match &unparsed.shorthand {
+        None => Property::parse(name, value),
+        Some(shorthand) => Property::parse(shorthand, value)
+            .and_then(|shorthand| shorthand.expand().ok())
+            .and_then(|longhands| {
+                longhands
+                    .into_iter()
+                    .find(|longhand| longhand.name() == name)
+            }),
+    };
+    property.map_or(Value::Unset, |property| property.value().clone())
+}
+
+fn resolve_font_size(value: &Value, context: &CalcContext) -> Option<f32> {
+    let parent = context.font_size;
+    // The absolute-size keywords, from the CSS Fonts scale for a 16px medium.
+    let size = match value {
+        Value::Keyword(keyword) => match keyword.to_ascii_lowercase().as_str() {
+            "xx-small" => 9.0,
+            "x-small" => 10.0,
+            "small" => 13.0,
+            "medium" => 16.0,
+            "large" => 18.0,
+            "x-large" => 24.0,
+            "xx-large" => 32.0,
+            "xxx-large" => 48.0,
+            "larger" => parent * 1.2,
+            "smaller" => parent / 1.2,
+            _ => return None,
+        },
+        // Percentages of font-size refer to the parent's font size.
+        Value::Percentage(percentage) => parent * percentage / 100.0,
+        Value::Length(..) => match absolute_lengths(value, context) {
+            Value::Length(size, Unit::Px) => size,
+            _ => return None,
+        },
+        Value::Calc(calc) => calc.resolve(context)?,
+        _ => return None,
+    };
+    Some(size)
+}
+
+// Unitless zeros as the lengths they stand for, so a computed length is
+// always in pixels.
+fn zero_lengths(value: Value) -> Value {
+    match value {
+        Value::Number(0.0) => Value::Length(0.0, Unit::Px),
+        Value::List(values) => Value::List(values.into_iter().map(zero_lengths).collect()),
+        value => value,
+    }
+}
+
+// Converts relative and absolute lengths to pixels, leaving percentages
+// and everything else alone.
+fn absolute_lengths(value: &Value, context: &CalcContext) -> Value {
+    let px = |size: f32| Value::Length(size, Unit::Px);
+    match value {
+        Value::Length(n, unit) => match unit {
+            Unit::Em => px(n * context.font_size),
+            Unit::Rem => px(n * context.root_font_size),
+            // Without font metrics, ex and ch are taken as half an em.
+            Unit::Ex | Unit::Ch => px(n * context.font_size / 2.0),
+            Unit::In => px(n * 96.0),
+            Unit::Cm => px(n * 96.0 / 2.54),
+            Unit::Mm => px(n * 96.0 / 25.4),
+            Unit::Q => px(n * 96.0 / 101.6),
+            Unit::Pt => px(n * 96.0 / 72.0),
+            Unit::Pc => px(n * 16.0),
+            unit => match context
+                .viewport
+                .and_then(|viewport| unit.viewport_length(*n, viewport))
+            {
+                Some(size) => px(size),
+                None => value.clone(),
+            },
+        },
+        // What is left of a calculation once it is in pixels may be a
+        // plain length.
+        Value::Calc(calc) => calc.absolutize(context).into_value(),
+        Value::List(values) => Value::List(
+            values
+                .iter()
+                .map(|value| absolute_lengths(value, context))
+                .collect(),
+        ),
+        Value::Function(name, arguments) => Value::Function(
+            name.clone(),
+            arguments
+                .iter()
+                .map(|value| absolute_lengths(value, context))
+                .collect(),
+        ),
+        _ => value.clone(),
+    }
+}
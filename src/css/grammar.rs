@@ -0,0 +1,731 @@
+use super::calc::{Calc, CalcType};
+use super::properties::Property;
+use super::values::{Color, Unit, Value};
+
+// What one space-separated component of a value may be.
+#[derive(Debug, Clone, Copy)]
+enum Type {
+    Length,
+    Percentage,
+    Number,
+    Integer,
+    Time,
+    Angle,
+    Color,
+    Image,
+    Url,
+    Quoted,
+    Easing,
+    // Any identifier, like a font family or a counter style name.
+    Ident,
+    Keywords(&'static [&'static str]),
+}
+
+// A value of one to `max` components, each of one of the `types`. A list
+// grammar takes any number of such values, separated by commas.
+#[derive(Debug, Clone, Copy)]
+struct Grammar {
+    types: &'static [Type],
+    max: usize,
+    non_negative: bool,
+    list: bool,
+}
+
+impl Grammar {
+    const fn one(types: &'static [Type]) -> Self {
+        Grammar {
+            types,
+            max: 1,
+            non_negative: false,
+            list: false,
+        }
+    }
+
+    const fn up_to(max: usize, types: &'static [Type]) -> Self {
+        Grammar {
+            types,
+            max,
+            non_negative: false,
+            list: false,
+        }
+    }
+
+    const fn non_negative(self) -> Self {
+        Grammar {
+            non_negative: true,
+            ..self
+        }
+    }
+
+    const fn list(self) -> Self {
+        Grammar { list: true, ..self }
+    }
+
+    fn matches(&self, value: &Value) -> bool {
+        if let Value::Calc(calc) = value {
+            return self.matches_calc(calc);
+        }
+        if self.non_negative && is_negative(value) {
+            return false;
+        }
+        self.types.iter().any(|kind| match kind {
+            Type::Length => is_length(value),
+            Type::Percentage => matches!(value, Value::Percentage(_)),
+            Type::Number => matches!(value, Value::Number(_)),
+            Type::Integer => matches!(value, Value::Number(n) if n.fract() == 0.0),
+            Type::Time => is_time(value),
+            Type::Angle => {
+                matches!(
+                    value,
+                    Value::Length(_, Unit::Deg | Unit::Rad | Unit::Grad | Unit::Turn)
+                ) || matches!(value, Value::Number(n) if *n == 0.0)
+            }
+            Type::Color => is_color(value),
+            Type::Image => is_image(value),
+            Type::Url => matches!(value, Value::Url(_)),
+            Type::Quoted => matches!(value, Value::String(_)),
+            Type::Easing => is_easing(value),
+            Type::Ident => is_ident(value),
+            Type::Keywords(keywords) => is_keyword(value, keywords),
+        })
+    }
+
+    // A calculation matches by the type it resolves to. Its range is not
+    // checked, as results out of range are clamped rather than invalid.
+    fn matches_calc(&self, calc: &Calc) -> bool {
+        let accepts = |wanted: fn(&Type) -> bool| self.types.iter().any(wanted);
+        match calc.kind() {
+            Some(CalcType::Number) => accepts(|kind| matches!(kind, Type::Number | Type::Integer)),
+            Some(CalcType::Percentage) => accepts(|kind| matches!(kind, Type::Percentage)),
+            Some(CalcType::Length) => accepts(|kind| matches!(kind, Type::Length)),
+            Some(CalcType::LengthPercentage) => {
+                accepts(|kind| matches!(kind, Type::Length))
+                    && accepts(|kind| matches!(kind, Type::Percentage))
+            }
+            Some(CalcType::Angle) => accepts(|kind| matches!(kind, Type::Angle)),
+            Some(CalcType::Time) => accepts(|kind| matches!(kind, Type::Time)),
+            _ => false,
+        }
+    }
+}
+
+// The grammar of each longhand that is checked. Shorthands are checked by
+// expanding them; the rest, like the grid placement properties, take
+// anything for now.
+fn grammar(name: &str) -> Option<Grammar> {
+    const LENGTH_PERCENTAGE: &[Type] = &[Type::Length, Type::Percentage];
+    const SIZE: &[Type] = &[
+        Type::Length,
+        Type::Percentage,
+        Type::Keywords(&["auto", "min-content", "max-content", "fit-content"]),
+    ];
+    const MAX_SIZE: &[Type] = &[
+        Type::Length,
+        Type::Percentage,
+        Type::Keywords(&["none", "min-content", "max-content", "fit-content"]),
+    ];
+    const INSET: &[Type] = &[Type::Length, Type::Percentage, Type::Keywords(&["auto"])];
+    const LINE_WIDTH: &[Type] = &[Type::Length, Type::Keywords(LINE_WIDTHS)];
+    const LINE_STYLE: &[Type] = &[Type::Keywords(LINE_STYLES)];
+    const COLOR: &[Type] = &[Type::Color];
+    const EASING: &[Type] = &[Type::Easing];
+    const TIME: &[Type] = &[Type::Time];
+    const SPACING: &[Type] = &[Type::Length, Type::Keywords(&["normal"])];
+    const GAP: &[Type] = &[Type::Length, Type::Percentage, Type::Keywords(&["normal"])];
+    const ALIGNMENT: &[&str] = &[
+        "normal",
+        "stretch",
+        "baseline",
+        "first",
+        "last",
+        "center",
+        "start",
+        "end",
+        "flex-start",
+        "flex-end",
+        "self-start",
+        "self-end",
+        "left",
+        "right",
+        "space-between",
+        "space-around",
+        "space-evenly",
+        "safe",
+        "unsafe",
+    ];
+
+    let grammar = match name {
+        // Box Model
+        "width" | "height" | "min-width" | "min-height" => Grammar::one(SIZE).non_negative(),
+        "max-width" | "max-height" => Grammar::one(MAX_SIZE).non_negative(),
+        "margin-top" | "margin-right" | "margin-bottom" | "margin-left" => Grammar::one(INSET),
+        "padding-top" | "padding-right" | "padding-bottom" | "padding-left" => {
+            Grammar::one(LENGTH_PERCENTAGE).non_negative()
+        }
+        "border-top-width" | "border-right-width" | "border-bottom-width" | "border-left-width" => {
+            Grammar::one(LINE_WIDTH).non_negative()
+        }
+        "border-top-style" | "border-right-style" | "border-bottom-style" | "border-left-style" => {
+            Grammar::one(LINE_STYLE)
+        }
+        "border-top-color" | "border-right-color" | "border-bottom-color" | "border-left-color" => {
+            Grammar::one(COLOR)
+        }
+        "outline-width" => Grammar::one(LINE_WIDTH).non_negative(),
+        "outline-style" => Grammar::one(&[Type::Keywords(LINE_STYLES), Type::Keywords(&["auto"])]),
+        "outline-color" => Grammar::one(COLOR),
+        "border-radius" => {
+            Grammar::up_to(9, &[Type::Length, Type::Percentage, Type::Keywords(&["/"])])
+                .non_negative()
+        }
+        "box-sizing" => Grammar::one(&[Type::Keywords(&["content-box", "border-box"])]),
+        "display" => Grammar::up_to(
+            3,
+            &[Type::Keywords(&[
+                "none",
+                "contents",
+                "block",
+                "inline",
+                "inline-block",
+                "run-in",
+                "flow",
+                "flow-root",
+                "list-item",
+                "table",
+                "inline-table",
+                "table-row-group",
+                "table-header-group",
+                "table-footer-group",
+                "table-row",
+                "table-cell",
+                "table-column-group",
+                "table-column",
+                "table-caption",
+                "flex",
+                "inline-flex",
+                "grid",
+                "inline-grid",
+                "ruby",
+                "ruby-base",
+                "ruby-text",
+                "ruby-base-container",
+                "ruby-text-container",
+            ])],
+        ),
+
+        // Positioning
+        "position" => Grammar::one(&[Type::Keywords(&[
+            "static", "relative", "absolute", "fixed", "sticky",
+        ])]),
+        "top" | "right" | "bottom" | "left" => Grammar::one(INSET),
+        "z-index" => Grammar::one(&[Type::Integer, Type::Keywords(&["auto"])]),
+        "float" => Grammar::one(&[Type::Keywords(&[
+            "none",
+            "left",
+            "right",
+            "inline-start",
+            "inline-end",
+        ])]),
+        "clear" => Grammar::one(&[Type::Keywords(&[
+            "none",
+            "left",
+            "right",
+            "both",
+            "inline-start",
+            "inline-end",
+        ])]),
+
+        // Typography
+        "color" => Grammar::one(COLOR),
+        "font-family" => Grammar::up_to(usize::MAX, &[Type::Ident, Type::Quoted]).list(),
+        "font-size" => Grammar::one(&[Type::Length, Type::Percentage, Type::Keywords(FONT_SIZES)])
+            .non_negative(),
+        "font-style" => Grammar::up_to(
+            2,
+            &[
+                Type::Keywords(&["normal", "italic", "oblique"]),
+                Type::Angle,
+            ],
+        ),
+        "font-variant" => Grammar::up_to(
+            8,
+            &[Type::Keywords(&[
+                "normal",
+                "none",
+                "small-caps",
+                "all-small-caps",
+                "petite-caps",
+                "all-petite-caps",
+                "unicase",
+                "titling-caps",
+            ])],
+        ),
+        "font-weight" => Grammar::one(&[
+            Type::Number,
+            Type::Keywords(&["normal", "bold", "bolder", "lighter"]),
+        ]),
+        "line-height" => Grammar::one(&[
+            Type::Number,
+            Type::Length,
+            Type::Percentage,
+            Type::Keywords(&["normal"]),
+        ])
+        .non_negative(),
+        "text-align" => Grammar::one(&[Type::Keywords(&[
+            "start",
+            "end",
+            "left",
+            "right",
+            "center",
+            "justify",
+            "justify-all",
+            "match-parent",
+        ])]),
+        "text-decoration" => Grammar::up_to(
+            6,
+            &[
+                Type::Keywords(&[
+                    "none",
+                    "underline",
+                    "overline",
+                    "line-through",
+                    "blink",
+                    "solid",
+                    "double",
+                    "dotted",
+                    "dashed",
+                    "wavy",
+                    "auto",
+                    "from-font",
+                ]),
+                Type::Length,
+                Type::Percentage,
+                Type::Color,
+            ],
+        ),
+        "text-transform" => Grammar::up_to(
+            3,
+            &[Type::Keywords(&[
+                "none",
+                "capitalize",
+                "uppercase",
+                "lowercase",
+                "full-width",
+                "full-size-kana",
+            ])],
+        ),
+        "letter-spacing" => Grammar::one(SPACING),
+        "word-spacing" => {
+            Grammar::one(&[Type::Length, Type::Percentage, Type::Keywords(&["normal"])])
+        }
+        "white-space" => Grammar::one(&[Type::Keywords(&[
+            "normal",
+            "pre",
+            "nowrap",
+            "pre-wrap",
+            "pre-line",
+            "break-spaces",
+        ])]),
+        "vertical-align" => Grammar::one(&[
+            Type::Length,
+            Type::Percentage,
+            Type::Keywords(&[
+                "baseline",
+                "sub",
+                "super",
+                "text-top",
+                "text-bottom",
+                "middle",
+                "top",
+                "bottom",
+            ]),
+        ]),
+
+        // Lists
+        "list-style-type" => Grammar::one(&[Type::Ident, Type::Quoted]),
+        "list-style-position" => Grammar::one(&[Type::Keywords(LIST_POSITIONS)]),
+        "list-style-image" => Grammar::one(&[Type::Image]),
+
+        // Visual
+        "background-color" => Grammar::one(COLOR),
+        "background-image" => Grammar::one(&[Type::Image]).list(),
+        "background-position" => Grammar::up_to(
+            4,
+            &[Type::Length, Type::Percentage, Type::Keywords(POSITIONS)],
+        )
+        .list(),
+        "background-repeat" => Grammar::up_to(
+            2,
+            &[Type::Keywords(&[
+                "repeat",
+                "no-repeat",
+                "space",
+                "round",
+                "repeat-x",
+                "repeat-y",
+            ])],
+        )
+        .list(),
+        "background-size" => Grammar::up_to(
+            2,
+            &[
+                Type::Length,
+                Type::Percentage,
+                Type::Keywords(&["auto", "cover", "contain"]),
+            ],
+        )
+        .non_negative()
+        .list(),
+        "background-attachment" => Grammar::one(&[Type::Keywords(ATTACHMENTS)]).list(),
+        "opacity" => Grammar::one(&[Type::Number, Type::Percentage]),
+        "visibility" => Grammar::one(&[Type::Keywords(&["visible", "hidden", "collapse"])]),
+
+        // Flexbox
+        "flex-direction" => Grammar::one(&[Type::Keywords(&[
+            "row",
+            "row-reverse",
+            "column",
+            "column-reverse",
+        ])]),
+        "flex-wrap" => Grammar::one(&[Type::Keywords(&["nowrap", "wrap", "wrap-reverse"])]),
+        "flex-grow" | "flex-shrink" => Grammar::one(&[Type::Number]).non_negative(),
+        "flex-basis" => Grammar::one(&[
+            Type::Length,
+            Type::Percentage,
+            Type::Keywords(&[
+                "auto",
+                "content",
+                "min-content",
+                "max-content",
+                "fit-content",
+            ]),
+        ])
+        .non_negative(),
+        "justify-content" | "align-items" | "align-content" => {
+            Grammar::up_to(2, &[Type::Keywords(ALIGNMENT)])
+        }
+        "align-self" => Grammar::up_to(2, &[Type::Keywords(ALIGNMENT), Type::Keywords(&["auto"])]),
+
+        // Grid
+        "grid-column-gap" | "grid-row-gap" => Grammar::one(GAP).non_negative(),
+
+        // Animation
+        "transition-property" => Grammar::one(&[Type::Ident]).list(),
+        "transition-duration" => Grammar::one(TIME).non_negative().list(),
+        "transition-delay" | "animation-delay" => Grammar::one(TIME).list(),
+        "transition-timing-function" | "animation-timing-function" => Grammar::one(EASING).list(),
+        "animation-name" => Grammar::one(&[Type::Ident, Type::Quoted]).list(),
+        "animation-duration" => Grammar::one(&[Type::Time, Type::Keywords(&["auto"])])
+            .non_negative()
+            .list(),
+        "animation-iteration-count" => Grammar::one(&[Type::Number, Type::Keywords(&["infinite"])])
+            .non_negative()
+            .list(),
+        "animation-direction" => Grammar::one(&[Type::Keywords(DIRECTIONS)]).list(),
+        "animation-fill-mode" => Grammar::one(&[Type::Keywords(FILL_MODES)]).list(),
+        "animation-play-state" => Grammar::one(&[Type::Keywords(PLAY_STATES)]).list(),
+
+        // Other
+        "cursor" => Grammar::up_to(3, &[Type::Url, Type::Number, Type::Ident]),
+        "overflow" => Grammar::up_to(
+            2,
+            &[Type::Keywords(&[
+                "visible", "hidden", "clip", "scroll", "auto",
+            ])],
+        ),
+        "pointer-events" => Grammar::one(&[Type::Ident]),
+        "user-select" => {
+            Grammar::one(&[Type::Keywords(&["auto", "text", "none", "contain", "all"])])
+        }
+        _ => return None,
+    };
+    Some(grammar)
+}
+
+impl Property {
+    /// Checks a value against the grammar of the property `name`, giving
+    /// back the first component that doesn't fit. Unknown properties, and
+    /// properties whose grammar isn't checked, take any value.
+    pub fn validate(name: &str, value: &Value) -> Result<(), Value> {
+        // Values using `var()` are checked once it is substituted.
+        if matches!(
+            value,
+            Value::Initial | Value::Inherit | Value::Unset | Value::Unparsed(_)
+        ) {
+            return Ok(());
+        }
+        if Property::longhands(name).is_some() {
+            return match Property::new(name, value.clone()) {
+                Some(shorthand) => shorthand.expand().map(|_| ()),
+                None => Ok(()),
+            };
+        }
+
+        let Some(grammar) = grammar(name) else {
+            return Ok(());
+        };
+        let components = components(value);
+        if components.is_empty() {
+            return Err(value.clone());
+        }
+        let items = match grammar.list {
+            true => comma_separated(components),
+            false => vec![components],
+        };
+        for item in items {
+            // An empty item is a stray comma.
+            if item.is_empty() {
+                return Err(Value::Keyword(",".to_string()));
+            }
+            if let Some(extra) = item.get(grammar.max) {
+                return Err(extra.clone());
+            }
+            if let Some(invalid) = item.iter().find(|component| !grammar.matches(component)) {
+                return Err(invalid.clone());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Property {
+    /// Whether a unitless zero in the value of `name` stands for a length,
+    /// as it does for properties that take lengths but not numbers.
+    pub fn zero_is_length(name: &str) -> bool {
+        grammar(name).is_some_and(|grammar| {
+            grammar
+                .types
+                .iter()
+                .any(|kind| matches!(kind, Type::Length))
+                && !grammar
+                    .types
+                    .iter()
+                    .any(|kind| matches!(kind, Type::Number | Type::Integer))
+        })
+    }
+}
+
+// The space-separated parts of a value.
+pub(super) fn components(value: &Value) -> &[Value] {
+    match value {
+        Value::List(values) => values,
+        value => std::slice::from_ref(value),
+    }
+}
+
+// The items of a comma-separated list, each as its space-separated parts.
+// Commas inside functions are not in `components`, so only top-level ones
+// separate items.
+pub(super) fn comma_separated(components: &[Value]) -> Vec<&[Value]> {
+    components
+        .split(|value| matches!(value, Value::Keyword(keyword) if keyword == ","))
+        .collect()
+}
+
+pub(super) const LINE_STYLES: &[&str] = &[
+    "none", "hidden", "dotted", "dashed", "solid", "double", "groove", "ridge", "inset", "outset",
+];
+pub(super) const LINE_WIDTHS: &[&str] = &["thin", "medium", "thick"];
+pub(super) const LIST_POSITIONS: &[&str] = &["inside", "outside"];
+pub(super) const REPEATS: &[&str] = &["repeat", "no-repeat", "space", "round"];
+pub(super) const ATTACHMENTS: &[&str] = &["scroll", "fixed", "local"];
+pub(super) const POSITIONS: &[&str] = &["left", "center", "right", "top", "bottom"];
+pub(super) const FONT_STYLES: &[&str] = &["italic", "oblique"];
+pub(super) const FONT_VARIANTS: &[&str] = &["small-caps"];
+pub(super) const FONT_WEIGHTS: &[&str] = &["bold", "bolder", "lighter"];
+pub(super) const FONT_SIZES: &[&str] = &[
+    "xx-small",
+    "x-small",
+    "small",
+    "medium",
+    "large",
+    "x-large",
+    "xx-large",
+    "xxx-large",
+    "larger",
+    "smaller",
+];
+pub(super) const EASINGS: &[&str] = &[
+    "ease",
+    "linear",
+    "ease-in",
+    "ease-out",
+    "ease-in-out",
+    "step-start",
+    "step-end",
+];
+pub(super) const DIRECTIONS: &[&str] = &["normal", "reverse", "alternate", "alternate-reverse"];
+pub(super) const FILL_MODES: &[&str] = &["none", "forwards", "backwards", "both"];
+pub(super) const PLAY_STATES: &[&str] = &["running", "paused"];
+
+// The CSS Color system colors, including the deprecated ones.
+const SYSTEM_COLORS: &[&str] = &[
+    "accentcolor",
+    "accentcolortext",
+    "activetext",
+    "buttonborder",
+    "buttonface",
+    "buttontext",
+    "canvas",
+    "canvastext",
+    "field",
+    "fieldtext",
+    "graytext",
+    "highlight",
+    "highlighttext",
+    "linktext",
+    "mark",
+    "marktext",
+    "selecteditem",
+    "selecteditemtext",
+    "visitedtext",
+    "activeborder",
+    "activecaption",
+    "appworkspace",
+    "background",
+    "buttonhighlight",
+    "buttonshadow",
+    "captiontext",
+    "inactiveborder",
+    "inactivecaption",
+    "inactivecaptiontext",
+    "infobackground",
+    "infotext",
+    "menu",
+    "menutext",
+    "scrollbar",
+    "threeddarkshadow",
+    "threedface",
+    "threedhighlight",
+    "threedlightshadow",
+    "threedshadow",
+    "window",
+    "windowframe",
+    "windowtext",
+];
+
+// The keyword a value is, lowercased, if it is one.
+pub(super) fn keyword(value: &Value) -> Option<String> {
+    match value {
+        Value::Keyword(keyword) => Some(keyword.to_ascii_lowercase()),
+        Value::Auto => Some("auto".to_string()),
+        Value::None => Some("none".to_string()),
+        Value::CurrentColor => Some("currentcolor".to_string()),
+        _ => None,
+    }
+}
+
+pub(super) fn is_keyword(value: &Value, keywords: &[&str]) -> bool {
+    keyword(value).is_some_and(|keyword| keywords.contains(&keyword.as_str()))
+}
+
+// A zero without a unit is a length too.
+pub(super) fn is_length(value: &Value) -> bool {
+    match value {
+        Value::Length(_, unit) => !matches!(
+            unit,
+            Unit::Deg
+                | Unit::Rad
+                | Unit::Grad
+                | Unit::Turn
+                | Unit::S
+                | Unit::Ms
+                | Unit::Hz
+                | Unit::Khz
+                | Unit::Dpi
+                | Unit::Dpcm
+                | Unit::Dppx
+                | Unit::Fr
+                | Unit::Percent
+        ),
+        Value::Number(n) => *n == 0.0,
+        Value::Calc(calc) => calc.kind() == Some(CalcType::Length),
+        _ => false,
+    }
+}
+
+pub(super) fn is_length_percentage(value: &Value) -> bool {
+    match value {
+        Value::Calc(calc) => matches!(
+            calc.kind(),
+            Some(CalcType::Length | CalcType::Percentage | CalcType::LengthPercentage)
+        ),
+        value => is_length(value) || matches!(value, Value::Percentage(_)),
+    }
+}
+
+pub(super) fn is_time(value: &Value) -> bool {
+    match value {
+        Value::Calc(calc) => calc.kind() == Some(CalcType::Time),
+        value => matches!(value, Value::Length(_, Unit::S | Unit::Ms)),
+    }
+}
+
+pub(super) fn is_color(value: &Value) -> bool {
+    match value {
+        // Valid color functions are parsed as colors.
+        Value::Color(_) | Value::CurrentColor => true,
+        Value::Keyword(keyword) => {
+            let keyword = keyword.to_ascii_lowercase();
+            keyword == "currentcolor"
+                || SYSTEM_COLORS.contains(&keyword.as_str())
+                || Color::parse_named(&keyword).is_some()
+        }
+        _ => false,
+    }
+}
+
+pub(super) fn is_image(value: &Value) -> bool {
+    match value {
+        Value::Url(_) | Value::LinearGradient(_) => true,
+        Value::Function(name, _) => name.to_ascii_lowercase().ends_with("gradient"),
+        value => is_keyword(value, &["none"]),
+    }
+}
+
+pub(super) fn is_line_style(value: &Value) -> bool {
+    is_keyword(value, LINE_STYLES)
+}
+
+pub(super) fn is_line_width(value: &Value) -> bool {
+    is_length(value) || is_keyword(value, LINE_WIDTHS)
+}
+
+pub(super) fn is_position(value: &Value) -> bool {
+    is_length_percentage(value) || is_keyword(value, POSITIONS)
+}
+
+pub(super) fn is_size(value: &Value) -> bool {
+    is_length_percentage(value) || is_keyword(value, &["auto", "cover", "contain"])
+}
+
+pub(super) fn is_font_weight(value: &Value) -> bool {
+    match value {
+        Value::Number(n) => (1.0..=1000.0).contains(n),
+        value => is_keyword(value, FONT_WEIGHTS),
+    }
+}
+
+pub(super) fn is_easing(value: &Value) -> bool {
+    match value {
+        Value::Function(name, _) => {
+            matches!(name.to_ascii_lowercase().as_str(), "cubic-bezier" | "steps")
+        }
+        value => is_keyword(value, EASINGS),
+    }
+}
+
+pub(super) fn is_iteration_count(value: &Value) -> bool {
+    matches!(value, Value::Number(n) if *n >= 0.0) || is_keyword(value, &["infinite"])
+}
+
+// Keywords kept as written, like `/`, `,` or a malformed `#ggg`, are not
+// identifiers.
+fn is_ident(value: &Value) -> bool {
+    let starts_ident = |c: char| c.is_ascii_alphabetic() || matches!(c, '-' | '_') || !c.is_ascii();
+    matches!(value, Value::Keyword(keyword) if keyword.starts_with(starts_ident))
+}
+
+fn is_negative(value: &Value) -> bool {
+    matches!(value, Value::Number(n) | Value::Length(n, _) | Value::Percentage(n) if *n < 0.0)
+}
@@ -0,0 +1,414 @@
+use super::rules::{
+    AttributeOperator, AttributeSelector, Combinator, CompoundSelector, NamespaceSelector, Nth,
+    PseudoClass, Selector, Specificity,
+};
+use crate::html::arena::{Dom, NodeId};
+use crate::html::dom::{ElementData, NodeType};
+use crate::html::foreign;
+
+/// Whether the node `id` is an element matched by `selector`. A selector
+/// with a pseudo-element matches part of an element, never the element.
+pub fn matches(selector: &Selector, dom: &Dom, id: NodeId) -> bool {
+    matches_in_scope(selector, dom, id, None)
+}
+
+/// Like `matches`, with `:scope` meaning `scope` instead of the root element,
+/// as for `querySelector` on an element.
+pub fn matches_in_scope(selector: &Selector, dom: &Dom, id: NodeId, scope: Option<NodeId>) -> bool {
+    let context = Context { dom, scope };
+    selector.pseudo_element().is_none() && context.matches_compounds(&selector.compounds, id, None)
+}
+
+/// Whether any selector in the list matches the node `id`.
+pub fn matches_any(selectors: &[Selector], dom: &Dom, id: NodeId) -> bool {
+    selectors.iter().any(|selector| matches(selector, dom, id))
+}
+
+/// The specificity a rule with these selectors applies to `id` with, which
+/// is that of its most specific selector that matches. None if none match.
+/// The cascade orders declarations of equal origin and importance by this.
+pub fn matching_specificity(selectors: &[Selector], dom: &Dom, id: NodeId) -> Option<Specificity> {
+    selectors
+        .iter()
+        .filter(|selector| matches(selector, dom, id))
+        .map(Selector::specificity)
+        .max()
+}
+
+struct Context<'a> {
+    dom: &'a Dom,
+    scope: Option<NodeId>,
+}
+
+impl Context<'_> {
+    fn matches_list(&self, selectors: &[Selector], id: NodeId) -> bool {
+        selectors.iter().any(|selector| {
+            selector.pseudo_element().is_none()
+                && self.matches_compounds(&selector.compounds, id, None)
+        })
+    }
+
+    // Matches right to left: the last compound against `id`, then the rest
+    // against the nodes its combinator reaches. `anchor` is the element a
+    // relative selector in `:has()` is relative to.
+    fn matches_compounds(
+        &self,
+        compounds: &[CompoundSelector],
+        id: NodeId,
+        anchor: Option<NodeId>,
+    ) -> bool {
+        let Some((last, rest)) = compounds.split_last() else {
+            return false;
+        };
+        if !self.matches_compound(last, id) {
+            return false;
+        }
+        let Some(combinator) = last.combinator else {
+            return rest.is_empty();
+        };
+        let mut candidates = self.related(id, combinator);
+        if rest.is_empty() {
+            anchor.is_some_and(|anchor| candidates.any(|node| node == anchor))
+        } else {
+            candidates.any(|node| self.matches_compounds(rest, node, anchor))
+        }
+    }
+
+    // The elements that `combinator` can connect to `id` from the left.
+    fn related(&self, id: NodeId, combinator: Combinator) -> Box<dyn Iterator<Item = NodeId> + '_> {
+        let dom = self.dom;
+        match combinator {
+            Combinator::Child => Box::new(dom.parent(id).into_iter()),
+            Combinator::Descendant => Box::new(dom.ancestors(id)),
+            Combinator::NextSibling => Box::new(self.previous_elements(id).take(1)),
+            Combinator::SubsequentSibling => Box::new(self.previous_elements(id)),
+        }
+    }
+
+    fn previous_elements(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(self.dom.previous_sibling(id), |&node| {
+            self.dom.previous_sibling(node)
+        })
+        .filter(|&node| self.dom.element(node).is_some())
+    }
+
+    fn next_elements(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(self.dom.next_sibling(id), |&node| {
+            self.dom.next_sibling(node)
+        })
+        .filter(|&node| self.dom.element(node).is_some())
+    }
+
+    fn matches_compound(&self, selector: &CompoundSelector, id: NodeId) -> bool {
+        let Some(element) = self.dom.element(id) else {
+            return false;
+        };
+        // Type selectors are ASCII case-insensitive for HTML documents.
+        if let Some(tag_name) = &selector.tag_name {
+            if !tag_name.eq_ignore_ascii_case(&element.tag_name) {
+                return false;
+            }
+        }
+        // Every element is in a namespace; no prefix matches any.
+        let in_namespace = match &selector.namespace {
+            None | Some(NamespaceSelector::Any) => true,
+            Some(NamespaceSelector::None) => false,
+            Some(NamespaceSelector::Named { url, .. }) => url == element.namespace.url(),
+        };
+        if !in_namespace {
+            return false;
+        }
+        if selector
+            .ids
+            .iter()
+            .any(|id| attribute(element, "id") != Some(id))
+        {
+            return false;
+        }
+        if !selector.classes.is_empty() {
+            let classes = attribute(element, "class").unwrap_or("");
+            if !selector
+                .classes
+                .iter()
+                .all(|class| classes.split_ascii_whitespace().any(|c| c == class))
+            {
+                return false;
+            }
+        }
+        // Outside a flattened stylesheet, `&` is `:scope`.
+        if selector.nesting && !self.matches_pseudo_class(&PseudoClass::Scope, id, element) {
+            return false;
+        }
+        selector
+            .attributes
+            .iter()
+            .all(|attribute| matches_attribute(attribute, element))
+            && selector
+                .pseudo_classes
+                .iter()
+                .all(|pseudo_class| self.matches_pseudo_class(pseudo_class, id, element))
+    }
+
+    fn matches_pseudo_class(
+        &self,
+        pseudo_class: &PseudoClass,
+        id: NodeId,
+        element: &ElementData,
+    ) -> bool {
+        let dom = self.dom;
+        let same_type = |node: NodeId| {
+            dom.element(node)
+                .is_some_and(|other| other.tag_name == element.tag_name)
+        };
+
+        match pseudo_class {
+            PseudoClass::Root => self.is_root(id),
+            // Scoped to the document, `:scope` is the root element.
+            PseudoClass::Scope => match self.scope {
+                Some(scope) if dom.element(scope).is_some() => id == scope,
+                _ => self.is_root(id),
+            },
+            PseudoClass::Empty => dom.children(id).all(|child| match &dom[child].node_type {
+                NodeType::Element(_) => false,
+                NodeType::Text(text) => text.is_empty(),
+                _ => true,
+            }),
+            PseudoClass::FirstChild => self.previous_elements(id).next().is_none(),
+            PseudoClass::LastChild => self.next_elements(id).next().is_none(),
+            PseudoClass::OnlyChild => {
+                self.previous_elements(id).next().is_none()
+                    && self.next_elements(id).next().is_none()
+            }
+            PseudoClass::FirstOfType => !self.previous_elements(id).any(same_type),
+            PseudoClass::LastOfType => !self.next_elements(id).any(same_type),
+            PseudoClass::OnlyOfType => {
+                !self.previous_elements(id).any(same_type) && !self.next_elements(id).any(same_type)
+            }
+            PseudoClass::NthChild(nth, of) => {
+                self.matches_nth(nth, of, id, self.previous_elements(id))
+            }
+            PseudoClass::NthLastChild(nth, of) => {
+                self.matches_nth(nth, of, id, self.next_elements(id))
+            }
+            PseudoClass::NthOfType(nth) => nth.matches(
+                self.previous_elements(id)
+                    .filter(|&node| same_type(node))
+                    .count() as i32
+                    + 1,
+            ),
+            PseudoClass::NthLastOfType(nth) => nth.matches(
+                self.next_elements(id)
+                    .filter(|&node| same_type(node))
+                    .count() as i32
+                    + 1,
+            ),
+
+            PseudoClass::Not(selectors) => !self.matches_list(selectors, id),
+            PseudoClass::Is(selectors) | PseudoClass::Where(selectors) => {
+                self.matches_list(selectors, id)
+            }
+            PseudoClass::Has(selectors) => {
+                // Relative selectors can reach later siblings and their
+                // subtrees, so search everything under the parent.
+                let root = dom.parent(id).unwrap_or(id);
+                dom.descendants(root).any(|node| {
+                    selectors
+                        .iter()
+                        .any(|selector| self.matches_compounds(&selector.compounds, node, Some(id)))
+                })
+            }
+
+            PseudoClass::Lang(ranges) => {
+                let Some(lang) = self.inherited_attribute(id, "lang") else {
+                    return false;
+                };
+                ranges.iter().any(|range| {
+                    if range == "*" {
+                        return !lang.is_empty();
+                    }
+                    lang.eq_ignore_ascii_case(range)
+                        || (lang.len() > range.len()
+                            && lang.as_bytes()[range.len()] == b'-'
+                            && lang[..range.len()].eq_ignore_ascii_case(range))
+                })
+            }
+            PseudoClass::Dir(direction) => {
+                let actual = match self.inherited_attribute(id, "dir") {
+                    Some(dir) if dir.eq_ignore_ascii_case("rtl") => "rtl",
+                    _ => "ltr",
+                };
+                direction.eq_ignore_ascii_case(actual)
+            }
+
+            PseudoClass::AnyLink | PseudoClass::Link => {
+                matches!(element.tag_name.as_str(), "a" | "area") && has_attribute(element, "href")
+            }
+
+            PseudoClass::Enabled => is_form_control(element) && !self.is_disabled(id, element),
+            PseudoClass::Disabled => is_form_control(element) && self.is_disabled(id, element),
+            PseudoClass::ReadWrite => self.is_read_write(id, element),
+            PseudoClass::ReadOnly => !self.is_read_write(id, element),
+            PseudoClass::PlaceholderShown => {
+                matches!(element.tag_name.as_str(), "input" | "textarea")
+                    && has_attribute(element, "placeholder")
+                    && attribute(element, "value").unwrap_or("").is_empty()
+                    && dom.first_child(id).is_none()
+            }
+            PseudoClass::Checked | PseudoClass::Default => match element.tag_name.as_str() {
+                "input" => {
+                    matches!(attribute(element, "type"), Some(t) if t.eq_ignore_ascii_case("checkbox") || t.eq_ignore_ascii_case("radio"))
+                        && has_attribute(element, "checked")
+                }
+                "option" => has_attribute(element, "selected"),
+                _ => false,
+            },
+            PseudoClass::Required => is_input_field(element) && has_attribute(element, "required"),
+            PseudoClass::Optional => is_input_field(element) && !has_attribute(element, "required"),
+            PseudoClass::Defined => true,
+
+            // These depend on user interaction, navigation or validation
+            // state that a parsed document does not have.
+            PseudoClass::Visited
+            | PseudoClass::LocalLink
+            | PseudoClass::Target
+            | PseudoClass::TargetWithin
+            | PseudoClass::Hover
+            | PseudoClass::Active
+            | PseudoClass::Focus
+            | PseudoClass::FocusVisible
+            | PseudoClass::FocusWithin
+            | PseudoClass::Indeterminate
+            | PseudoClass::Valid
+            | PseudoClass::Invalid
+            | PseudoClass::InRange
+            | PseudoClass::OutOfRange
+            | PseudoClass::UserValid
+            | PseudoClass::UserInvalid
+            | PseudoClass::Blank
+            | PseudoClass::Playing
+            | PseudoClass::Paused
+            | PseudoClass::Vendor(_) => false,
+        }
+    }
+
+    // `siblings` are the element's siblings on the side the count starts
+    // from. With `of S`, only siblings matching S count and the element
+    // itself must match S.
+    fn matches_nth(
+        &self,
+        nth: &Nth,
+        of: &[Selector],
+        id: NodeId,
+        siblings: impl Iterator<Item = NodeId>,
+    ) -> bool {
+        if of.is_empty() {
+            return nth.matches(siblings.count() as i32 + 1);
+        }
+        self.matches_list(of, id)
+            && nth.matches(siblings.filter(|&node| self.matches_list(of, node)).count() as i32 + 1)
+    }
+
+    fn is_root(&self, id: NodeId) -> bool {
+        self.dom
+            .parent(id)
+            .is_some_and(|parent| self.dom[parent].node_type == NodeType::Document)
+    }
+
+    // The value of `name` on the element or its nearest ancestor with it.
+    fn inherited_attribute(&self, id: NodeId, name: &str) -> Option<&str> {
+        std::iter::once(id)
+            .chain(self.dom.ancestors(id))
+            .filter_map(|node| self.dom.element(node))
+            .find_map(|element| attribute(element, name))
+    }
+
+    fn is_disabled(&self, id: NodeId, element: &ElementData) -> bool {
+        has_attribute(element, "disabled")
+            || self.dom.ancestors(id).any(|node| {
+                self.dom.element(node).is_some_and(|ancestor| {
+                    ancestor.tag_name == "fieldset" && has_attribute(ancestor, "disabled")
+                })
+            })
+    }
+
+    fn is_read_write(&self, id: NodeId, element: &ElementData) -> bool {
+        if matches!(element.tag_name.as_str(), "input" | "textarea") {
+            return !has_attribute(element, "readonly") && !self.is_disabled(id, element);
+        }
+        self.inherited_attribute(id, "contenteditable")
+            .is_some_and(|value| value.is_empty() || value.eq_ignore_ascii_case("true"))
+    }
+}
+
+// Without a prefix, only attributes in no namespace match.
+fn matches_attribute(selector: &AttributeSelector, element: &ElementData) -> bool {
+    let Some(actual) = element
+        .attributes
+        .iter()
+        .find(|attribute| {
+            let (namespace, name) =
+                foreign::attribute_namespace(element.namespace, &attribute.name);
+            let in_namespace = match &selector.namespace {
+                None | Some(NamespaceSelector::None) => namespace.is_none(),
+                Some(NamespaceSelector::Any) => true,
+                Some(NamespaceSelector::Named { url, .. }) => namespace == Some(url.as_str()),
+            };
+            in_namespace && name.eq_ignore_ascii_case(&selector.name)
+        })
+        .map(|attribute| attribute.value.as_str())
+    else {
+        return false;
+    };
+    let (Some(op), Some(expected)) = (&selector.op, &selector.value) else {
+        return true;
+    };
+    let (actual, expected) = if selector.case_insensitive {
+        (actual.to_ascii_lowercase(), expected.to_ascii_lowercase())
+    } else {
+        (actual.to_string(), expected.clone())
+    };
+    let (actual, expected) = (actual.as_str(), expected.as_str());
+
+    match op {
+        AttributeOperator::Equal => actual == expected,
+        // A value with whitespace can never be one of the listed words.
+        AttributeOperator::Includes => {
+            !expected.is_empty()
+                && !expected.contains(|c: char| c.is_ascii_whitespace())
+                && actual.split_ascii_whitespace().any(|word| word == expected)
+        }
+        AttributeOperator::DashMatch => {
+            actual == expected
+                || actual
+                    .strip_prefix(expected)
+                    .is_some_and(|rest| rest.starts_with('-'))
+        }
+        // Per the spec, an empty value never matches for these three.
+        AttributeOperator::Prefix => !expected.is_empty() && actual.starts_with(expected),
+        AttributeOperator::Suffix => !expected.is_empty() && actual.ends_with(expected),
+        AttributeOperator::Substring => !expected.is_empty() && actual.contains(expected),
+    }
+}
+
+fn attribute<'a>(element: &'a ElementData, name: &str) -> Option<&'a str> {
+    element
+        .attributes
+        .iter()
+        .find(|attribute| attribute.name == name)
+        .map(|attribute| attribute.value.as_str())
+}
+
+fn has_attribute(element: &ElementData, name: &str) -> bool {
+    attribute(element, name).is_some()
+}
+
+fn is_form_control(element: &ElementData) -> bool {
+    matches!(
+        element.tag_name.as_str(),
+        "button" | "input" | "select" | "textarea" | "optgroup" | "option" | "fieldset"
+    )
+}
+
+fn is_input_field(element: &ElementData) -> bool {
+    matches!(element.tag_name.as_str(), "input" | "select" | "textarea")
+}
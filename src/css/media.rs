@@ -0,0 +1,435 @@
+use super::calc::{Calc, CalcContext};
+use super::grammar::is_length;
+use super::values::{Unit, Value};
+use std::fmt;
+
+/// A comma-separated list of media queries, after Media Queries Level 4.
+/// It matches when any of its queries does; an empty list matches always.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaQueryList {
+    pub queries: Vec<MediaQuery>,
+}
+
+/// One media query, like `only screen and (min-width: 600px)`. A query that
+/// does not parse is kept as `not all`, which matches nothing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaQuery {
+    pub qualifier: Option<Qualifier>,
+    // Lowercased. None for a query that is only a condition, which is the
+    // same as `all`.
+    pub media_type: Option<String>,
+    pub condition: Option<MediaCondition>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Qualifier {
+    Not,
+    Only,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MediaCondition {
+    Feature(MediaFeature),
+    Not(Box<MediaCondition>),
+    And(Vec<MediaCondition>),
+    Or(Vec<MediaCondition>),
+    // Anything else in parentheses, or a function, as written. It is
+    // neither true nor false.
+    GeneralEnclosed(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MediaFeature {
+    // `(hover)`: whether the feature is anything but zero or `none`.
+    Boolean(String),
+    // `(orientation: landscape)`, or `(min-width: 600px)`, which the prefix
+    // makes a comparison.
+    Plain(String, Value),
+    // `(400px <= width < 800px)`, as the comparisons of the feature with
+    // each value: `width >= 400px` and `width < 800px`.
+    Range(String, Vec<(Comparison, Value)>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorScheme {
+    Light,
+    Dark,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pointer {
+    None,
+    Coarse,
+    Fine,
+}
+
+/// The device and user preferences media queries are evaluated against.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaEnvironment {
+    // Lowercased, like `screen` or `print`.
+    pub media_type: String,
+    // The size of the viewport, in CSS pixels.
+    pub width: f32,
+    pub height: f32,
+    // Device pixels per CSS pixel.
+    pub resolution: f32,
+    pub color_scheme: ColorScheme,
+    pub reduced_motion: bool,
+    pub hover: bool,
+    pub pointer: Pointer,
+}
+
+// What a feature of the environment is, to compare with a query's value.
+enum FeatureValue {
+    // A length in px, a resolution in dppx, a ratio or an integer, which
+    // features of the same kind compare as numbers.
+    Range(f32, RangeKind),
+    Discrete(&'static str),
+}
+
+#[derive(PartialEq)]
+enum RangeKind {
+    Length,
+    Ratio,
+    Resolution,
+    Integer,
+}
+
+// Lengths in queries are relative to the initial font size.
+const INITIAL_FONT_SIZE: f32 = 16.0;
+
+impl MediaQueryList {
+    pub fn matches(&self, environment: &MediaEnvironment) -> bool {
+        self.queries.is_empty() || self.queries.iter().any(|query| query.matches(environment))
+    }
+}
+
+impl MediaQuery {
+    /// The query that never matches, which one that does not parse becomes.
+    pub fn not_all() -> Self {
+        MediaQuery {
+            qualifier: Some(Qualifier::Not),
+            media_type: Some("all".to_string()),
+            condition: None,
+        }
+    }
+
+    /// Whether the query is true. A condition that is unknown, as one using
+    /// an unknown feature is, makes the query false, even after `not`.
+    pub fn matches(&self, environment: &MediaEnvironment) -> bool {
+        let type_matches = match self.media_type.as_deref() {
+            None | Some("all") => true,
+            Some(media_type) => media_type == environment.media_type,
+        };
+        let result = match &self.condition {
+            _ if !type_matches => Some(false),
+            Some(condition) => condition.evaluate(environment),
+            None => Some(true),
+        };
+        let result = match self.qualifier {
+            Some(Qualifier::Not) => result.map(|result| !result),
+            _ => result,
+        };
+        result == Some(true)
+    }
+}
+
+impl MediaCondition {
+    /// Three-valued: None when the result is unknown.
+    pub fn evaluate(&self, environment: &MediaEnvironment) -> Option<bool> {
+        match self {
+            MediaCondition::Feature(feature) => feature.evaluate(environment),
+            MediaCondition::Not(condition) => condition.evaluate(environment).map(|result| !result),
+            MediaCondition::And(conditions) => {
+                let results: Vec<Option<bool>> = conditions
+                    .iter()
+                    .map(|condition| condition.evaluate(environment))
+                    .collect();
+                if results.contains(&Some(false)) {
+                    Some(false)
+                } else if results.contains(&None) {
+                    None
+                } else {
+                    Some(true)
+                }
+            }
+            MediaCondition::Or(conditions) => {
+                let results: Vec<Option<bool>> = conditions
+                    .iter()
+                    .map(|condition| condition.evaluate(environment))
+                    .collect();
+                if results.contains(&Some(true)) {
+                    Some(true)
+                } else if results.contains(&None) {
+                    None
+                } else {
+                    Some(false)
+                }
+            }
+            MediaCondition::GeneralEnclosed(_) => None,
+        }
+    }
+}
+
+impl MediaFeature {
+    /// Three-valued: None for unknown features and values of the wrong type.
+    pub fn evaluate(&self, environment: &MediaEnvironment) -> Option<bool> {
+        match self {
+            MediaFeature::Boolean(name) => match environment.feature(name)? {
+                FeatureValue::Range(value, _) => Some(value != 0.0),
+                FeatureValue::Discrete(value) => Some(value != "none"),
+            },
+            MediaFeature::Plain(name, value) => {
+                let (name, comparison) = if let Some(name) = name.strip_prefix("min-") {
+                    (name, Comparison::GreaterOrEqual)
+                } else if let Some(name) = name.strip_prefix("max-") {
+                    (name, Comparison::LessOrEqual)
+                } else {
+                    (name.as_str(), Comparison::Equal)
+                };
+                match environment.feature(name)? {
+                    FeatureValue::Discrete(actual) if comparison == Comparison::Equal => {
+                        match value {
+                            Value::Keyword(keyword) => Some(keyword.eq_ignore_ascii_case(actual)),
+                            _ => None,
+                        }
+                    }
+                    FeatureValue::Range(actual, kind) => {
+                        let value = environment.range_value(value, &kind)?;
+                        Some(comparison.holds(actual, value))
+                    }
+                    _ => None,
+                }
+            }
+            MediaFeature::Range(name, comparisons) => {
+                let FeatureValue::Range(actual, kind) = environment.feature(name)? else {
+                    return None;
+                };
+                let mut result = true;
+                for (comparison, value) in comparisons {
+                    let value = environment.range_value(value, &kind)?;
+                    result &= comparison.holds(actual, value);
+                }
+                Some(result)
+            }
+        }
+    }
+}
+
+impl Comparison {
+    /// The comparison with its operands swapped, so `400px < width` is
+    /// `width > 400px`.
+    pub fn flip(self) -> Self {
+        match self {
+            Comparison::Less => Comparison::Greater,
+            Comparison::LessOrEqual => Comparison::GreaterOrEqual,
+            Comparison::Greater => Comparison::Less,
+            Comparison::GreaterOrEqual => Comparison::LessOrEqual,
+            Comparison::Equal => Comparison::Equal,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+            Comparison::Equal => "=",
+        }
+    }
+
+    fn holds(self, a: f32, b: f32) -> bool {
+        match self {
+            Comparison::Less => a < b,
+            Comparison::LessOrEqual => a <= b,
+            Comparison::Greater => a > b,
+            Comparison::GreaterOrEqual => a >= b,
+            Comparison::Equal => a == b,
+        }
+    }
+}
+
+impl Default for MediaEnvironment {
+    /// A desktop screen with a mouse and no preferences.
+    fn default() -> Self {
+        MediaEnvironment {
+            media_type: "screen".to_string(),
+            width: 1024.0,
+            height: 768.0,
+            resolution: 1.0,
+            color_scheme: ColorScheme::Light,
+            reduced_motion: false,
+            hover: true,
+            pointer: Pointer::Fine,
+        }
+    }
+}
+
+impl MediaEnvironment {
+    fn feature(&self, name: &str) -> Option<FeatureValue> {
+        let range = |value: f32, kind: RangeKind| Some(FeatureValue::Range(value, kind));
+        let discrete = |value: &'static str| Some(FeatureValue::Discrete(value));
+        match name {
+            "width" | "device-width" => range(self.width, RangeKind::Length),
+            "height" | "device-height" => range(self.height, RangeKind::Length),
+            "aspect-ratio" | "device-aspect-ratio" => {
+                range(self.width / self.height, RangeKind::Ratio)
+            }
+            "resolution" => range(self.resolution, RangeKind::Resolution),
+            // Eight bits per color component, and no palette.
+            "color" => range(8.0, RangeKind::Integer),
+            "color-index" | "monochrome" => range(0.0, RangeKind::Integer),
+            "grid" => discrete("0"),
+            "orientation" if self.height >= self.width => discrete("portrait"),
+            "orientation" => discrete("landscape"),
+            "prefers-color-scheme" => match self.color_scheme {
+                ColorScheme::Light => discrete("light"),
+                ColorScheme::Dark => discrete("dark"),
+            },
+            "prefers-reduced-motion" if self.reduced_motion => discrete("reduce"),
+            "prefers-reduced-motion" => discrete("no-preference"),
+            "hover" | "any-hover" if self.hover => discrete("hover"),
+            "hover" | "any-hover" => discrete("none"),
+            "pointer" | "any-pointer" => match self.pointer {
+                Pointer::None => discrete("none"),
+                Pointer::Coarse => discrete("coarse"),
+                Pointer::Fine => discrete("fine"),
+            },
+            "update" if self.media_type == "print" => discrete("none"),
+            "update" => discrete("fast"),
+            // Documents are not scripted here.
+            "scripting" => discrete("none"),
+            _ => None,
+        }
+    }
+
+    // A query's value as a number to compare with a feature of `kind`, or
+    // None when it is not of that kind.
+    fn range_value(&self, value: &Value, kind: &RangeKind) -> Option<f32> {
+        let context = CalcContext {
+            font_size: INITIAL_FONT_SIZE,
+            root_font_size: INITIAL_FONT_SIZE,
+            viewport: Some((self.width, self.height)),
+            percentage_basis: None,
+        };
+        match (kind, value) {
+            (RangeKind::Length, Value::Length(n, unit)) if is_length(value) => {
+                Calc::Dimension(*n, unit.clone()).resolve(&context)
+            }
+            (RangeKind::Length, Value::Number(n)) if *n == 0.0 => Some(0.0),
+            (
+                RangeKind::Resolution,
+                Value::Length(n, unit @ (Unit::Dpi | Unit::Dpcm | Unit::Dppx)),
+            ) => Calc::Dimension(*n, unit.clone()).resolve(&context),
+            (RangeKind::Ratio, Value::Number(n)) => Some(*n),
+            (RangeKind::Ratio, Value::List(ratio)) => match ratio.as_slice() {
+                [Value::Number(a), Value::Keyword(slash), Value::Number(b)] if slash == "/" => {
+                    Some(a / b)
+                }
+                _ => None,
+            },
+            (RangeKind::Integer, Value::Number(n)) if n.fract() == 0.0 => Some(*n),
+            (_, Value::Calc(calc)) => calc.resolve(&context),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for MediaQueryList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, query) in self.queries.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", query)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for MediaQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.qualifier {
+            Some(Qualifier::Not) => write!(f, "not ")?,
+            Some(Qualifier::Only) => write!(f, "only ")?,
+            None => {}
+        }
+        match (&self.media_type, &self.condition) {
+            (Some(media_type), Some(condition)) => write!(f, "{} and {}", media_type, condition),
+            (Some(media_type), None) => write!(f, "{}", media_type),
+            (None, Some(condition)) => write!(f, "{}", condition),
+            (None, None) => write!(f, "all"),
+        }
+    }
+}
+
+impl fmt::Display for MediaCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |f: &mut fmt::Formatter, conditions: &[MediaCondition], operator: &str| {
+            for (i, condition) in conditions.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " {} ", operator)?;
+                }
+                write_in_parens(f, condition)?;
+            }
+            Ok(())
+        };
+        match self {
+            MediaCondition::Feature(feature) => write!(f, "({})", feature),
+            MediaCondition::Not(condition) => {
+                write!(f, "not ")?;
+                write_in_parens(f, condition)
+            }
+            MediaCondition::And(conditions) => join(f, conditions, "and"),
+            MediaCondition::Or(conditions) => join(f, conditions, "or"),
+            MediaCondition::GeneralEnclosed(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+// Features and general-enclosed conditions bring their own parentheses.
+fn write_in_parens(f: &mut fmt::Formatter, condition: &MediaCondition) -> fmt::Result {
+    match condition {
+        MediaCondition::Feature(_) | MediaCondition::GeneralEnclosed(_) => {
+            write!(f, "{}", condition)
+        }
+        condition => write!(f, "({})", condition),
+    }
+}
+
+impl fmt::Display for MediaFeature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MediaFeature::Boolean(name) => write!(f, "{}", name),
+            MediaFeature::Plain(name, value) => write!(f, "{}: {}", name, value),
+            MediaFeature::Range(name, comparisons) => match comparisons.as_slice() {
+                [(lower, low), (upper, high)] => write!(
+                    f,
+                    "{} {} {} {} {}",
+                    low,
+                    lower.flip().as_str(),
+                    name,
+                    upper.as_str(),
+                    high
+                ),
+                comparisons => {
+                    write!(f, "{}", name)?;
+                    for (comparison, value) in comparisons {
+                        write!(f, " {} {}", comparison.as_str(), value)?;
+                    }
+                    Ok(())
+                }
+            },
+        }
+    }
+}
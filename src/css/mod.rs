@@ -1,7 +1,18 @@
+pub mod calc;
+pub mod cascade;
+pub mod grammar;
+pub mod matching;
+pub mod media;
+pub mod nesting;
 pub mod parser;
 pub mod properties;
 pub mod rules;
+pub mod shorthands;
+pub mod supports;
+pub mod tokenizer;
+pub mod user_agent;
 pub mod values;
+pub mod variables;
 
 // pub use parser::*;
 // pub use rules::*;
@@ -0,0 +1,150 @@
+use super::rules::{CompoundSelector, PseudoClass, Rule, Selector, StyleRule, Stylesheet};
+
+impl Stylesheet {
+    /// The stylesheet as it would be written without nesting, after CSS
+    /// Nesting: each nested style rule follows its parent, with `&` replaced
+    /// by the parent's selectors, and conditional rules nested in a style
+    /// rule hold the rules nested in them. Top-level `&` becomes `:scope`.
+    pub fn flatten(&self) -> Stylesheet {
+        let mut rules = Vec::new();
+        for rule in &self.rules {
+            flatten_rule(rule, None, &mut rules);
+        }
+        Stylesheet { rules }
+    }
+}
+
+// Adds `rule`, nested in a style rule with the selectors `parent` if any,
+// to `flat`, followed by the style rules nested in it.
+fn flatten_rule(rule: &Rule, parent: Option<&[Selector]>, flat: &mut Vec<Rule>) {
+    let flatten_all = |rules: &[Rule]| {
+        let mut flat = Vec::new();
+        for rule in rules {
+            flatten_rule(rule, parent, &mut flat);
+        }
+        flat
+    };
+
+    match rule {
+        Rule::Style(style_rule) => {
+            let selectors: Vec<Selector> = style_rule
+                .selectors
+                .iter()
+                .map(|selector| resolve_nesting(selector, parent))
+                .collect();
+            // A rule that only holds nested rules adds nothing itself.
+            if !style_rule.declarations.is_empty() || style_rule.rules.is_empty() {
+                flat.push(Rule::Style(StyleRule {
+                    selectors: selectors.clone(),
+                    declarations: style_rule.declarations.clone(),
+                    rules: Vec::new(),
+                    span: style_rule.span,
+                }));
+            }
+            for nested in &style_rule.rules {
+                flatten_rule(nested, Some(&selectors), flat);
+            }
+        }
+        Rule::Media { query, rules, span } => flat.push(Rule::Media {
+            query: query.clone(),
+            rules: flatten_all(rules),
+            span: *span,
+        }),
+        Rule::Supports {
+            condition,
+            rules,
+            span,
+        } => flat.push(Rule::Supports {
+            condition: condition.clone(),
+            rules: flatten_all(rules),
+            span: *span,
+        }),
+        Rule::Layer { name, rules, span } => flat.push(Rule::Layer {
+            name: name.clone(),
+            rules: flatten_all(rules),
+            span: *span,
+        }),
+        Rule::Container {
+            name,
+            condition,
+            rules,
+            span,
+        } => flat.push(Rule::Container {
+            name: name.clone(),
+            condition: condition.clone(),
+            rules: flatten_all(rules),
+            span: *span,
+        }),
+        rule => flat.push(rule.clone()),
+    }
+}
+
+// The selector with each `&` standing for `parent`. When the only `&` starts
+// the selector and there is a single parent selector, the parent's
+// compounds take its place, as in `.card > h2` for `& > h2` in `.card`;
+// otherwise `&` becomes `:is(parent)`, which matches and weighs the same.
+fn resolve_nesting(selector: &Selector, parent: Option<&[Selector]>) -> Selector {
+    if !selector.compounds.iter().any(|compound| compound.nesting) {
+        return selector.clone();
+    }
+    let Some(parent) = parent else {
+        return replace_nesting(selector, PseudoClass::Scope);
+    };
+
+    if let ([parent], [first, rest @ ..]) = (parent, selector.compounds.as_slice()) {
+        if first.nesting && !rest.iter().any(|compound| compound.nesting) {
+            if let Some(merged) = merge_compounds(parent.subject(), first) {
+                let mut compounds = parent.compounds.clone();
+                *compounds.last_mut().unwrap() = merged;
+                compounds.extend(rest.iter().cloned());
+                return Selector { compounds };
+            }
+        }
+    }
+    replace_nesting(selector, PseudoClass::Is(parent.to_vec()))
+}
+
+fn replace_nesting(selector: &Selector, replacement: PseudoClass) -> Selector {
+    let compounds = selector
+        .compounds
+        .iter()
+        .map(|compound| {
+            let mut compound = compound.clone();
+            if compound.nesting {
+                compound.nesting = false;
+                compound.pseudo_classes.insert(0, replacement.clone());
+            }
+            compound
+        })
+        .collect();
+    Selector { compounds }
+}
+
+// The parent's subject with the rest of the `&` compound, like `.card:hover`
+// for `&:hover`. None when both have a type selector, a namespace or a
+// pseudo-element.
+fn merge_compounds(
+    subject: &CompoundSelector,
+    nesting: &CompoundSelector,
+) -> Option<CompoundSelector> {
+    if (subject.tag_name.is_some() && nesting.tag_name.is_some())
+        || (subject.namespace.is_some() && nesting.namespace.is_some())
+        || (subject.pseudo_element.is_some() && nesting.pseudo_element.is_some())
+    {
+        return None;
+    }
+    let mut merged = subject.clone();
+    merged.namespace = merged.namespace.or_else(|| nesting.namespace.clone());
+    merged.tag_name = merged.tag_name.or_else(|| nesting.tag_name.clone());
+    merged.universal |= nesting.universal;
+    merged.ids.extend(nesting.ids.iter().cloned());
+    merged.classes.extend(nesting.classes.iter().cloned());
+    merged.attributes.extend(nesting.attributes.iter().cloned());
+    merged
+        .pseudo_classes
+        .extend(nesting.pseudo_classes.iter().cloned());
+    merged.pseudo_element = merged
+        .pseudo_element
+        .or_else(|| nesting.pseudo_element.clone());
+    Some(merged)
+}
@@ -1,694 +1,2536 @@
+use super::calc::{is_math_function, Calc};
+use super::media::*;
+use super::properties::Property;
 use super::rules::*;
+use super::supports::SupportsCondition;
+use super::tokenizer::{Token, Tokenizer};
 use super::values::*;
+use super::variables;
+use crate::diagnostics::{Diagnostic, DiagnosticCode};
+use crate::span::{Position, Span};
 
-const MAX_MEMORY_BYTES: usize = 10 * 1024 * 1024; // 10MB limit
-
+/// Parses CSS from the tokens of a `Tokenizer`, following the "consume a
+/// list of rules" and "consume a list of declarations" algorithms of CSS
+/// Syntax Level 3.
 pub struct CssParser<'a> {
     input: &'a str,
-    position: usize,
-    bytes_consumed: usize,
+    // Always ends with `Token::EOF`.
+    tokens: Vec<(Token, Span)>,
+    // For each token that opens a block, the index of the token closing it,
+    // or of the EOF token for a block that is never closed. Other tokens map
+    // to their own index, so skipping a component value is a single jump.
+    block_ends: Vec<usize>,
+    // Index of the next token.
+    index: usize,
+    // Tokens from here on read as EOF, which confines parsing to the inside
+    // of a block, a declaration value or a function argument.
+    limit: usize,
+    // Where the last non-whitespace token consumed ended; spans end here.
+    last_end: Position,
+    // Inside the block of a style rule, where nested rules may come among
+    // the declarations.
+    nested: bool,
+    // The prefixes, or None for the default namespace, and URLs declared by
+    // the @namespace rules parsed so far.
+    namespaces: Vec<(Option<String>, String)>,
+    // Inside the selectors of a pseudo-class like `:is()`, where the default
+    // namespace only applies to type and universal selectors.
+    selector_arguments: bool,
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl<'a> CssParser<'a> {
     pub fn new(input: &'a str) -> Self {
-        CssParser {
-            input,
-            position: 0,
-            bytes_consumed: 0,
-        }
-    }
-
-    pub fn parse_stylesheet(&mut self) -> Result<Stylesheet, &'static str> {
-        let mut stylesheet = Stylesheet { rules: Vec::new() };
-        let mut consecutive_errors = 0;
-        const MAX_CONSECUTIVE_ERRORS: usize = 10;
-
-        while !self.eof() && consecutive_errors < MAX_CONSECUTIVE_ERRORS {
-            let start_pos = self.position;
-            self.consume_whitespace_and_comments();
-
-            if self.eof() {
+        let mut tokenizer = Tokenizer::new(input);
+        let mut tokens = Vec::new();
+        loop {
+            let (token, span) = tokenizer.next_token();
+            let eof = token == Token::EOF;
+            tokens.push((token, span));
+            if eof {
                 break;
             }
+        }
 
-            match self.next_char() {
-                '@' => match self.parse_at_rule() {
-                    Ok(rule) => {
-                        stylesheet.rules.push(rule);
-                        consecutive_errors = 0;
-                    }
-                    Err(e) => {
-                        eprintln!("{} at position {}", e, self.position);
-                        consecutive_errors += 1;
-                    }
-                },
-                _ => match self.parse_rule() {
-                    Ok(rule) => {
-                        stylesheet.rules.push(rule);
-                        consecutive_errors = 0;
-                    }
-                    Err(e) => {
-                        eprintln!(
-                            "Skipping malformed rule: {} at position {}",
-                            e, self.position
-                        );
-                        self.skip_to_next_rule();
-                        consecutive_errors += 1;
-                    }
-                },
-            }
-
-            if self.position == start_pos {
-                eprintln!("Forcing progress at position {}", self.position);
-                self.consume_char();
-                consecutive_errors += 1;
-            }
+        CssParser {
+            input,
+            block_ends: block_ends(&tokens),
+            limit: tokens.len() - 1,
+            tokens,
+            index: 0,
+            last_end: Position::start(),
+            nested: false,
+            namespaces: Vec::new(),
+            selector_arguments: false,
+            diagnostics: Vec::new(),
         }
+    }
 
-        if consecutive_errors >= MAX_CONSECUTIVE_ERRORS {
-            eprintln!("Too many errors, aborting parsing");
+    /// Parses the whole input. Malformed rules are skipped and reported in
+    /// the returned diagnostics instead of failing the stylesheet.
+    pub fn parse_stylesheet(&mut self) -> (Stylesheet, Vec<Diagnostic>) {
+        // A leading `@charset` only chose the encoding the input was decoded
+        // with (see `encoding::decode_css`); it is not a rule.
+        if self.input.starts_with("@charset \"") {
+            let end = self.find(|token| *token == Token::Semicolon);
+            self.skip_to(end);
+            self.eat(&Token::Semicolon);
         }
 
-        Ok(stylesheet)
+        let rules = self.parse_rule_list(true);
+        (Stylesheet { rules }, std::mem::take(&mut self.diagnostics))
     }
 
-    fn skip_block(&mut self) -> Result<(), &'static str> {
-        let mut depth = 1;
-        self.consume_char();
-
-        while depth > 0 && !self.eof() {
-            match self.next_char() {
-                '{' => {
-                    depth += 1;
-                    self.consume_char();
-                }
-                '}' => {
-                    depth -= 1;
-                    self.consume_char();
-                }
-                '"' | '\'' => {
-                    let quote = self.consume_char();
-                    self.consume_while(|c| c != quote);
-                    self.consume_char();
-                }
-                '/' if self.peek_char(1) == '*' => {
-                    self.skip_comment();
-                }
-                _ => {
-                    self.consume_char();
-                }
-            }
-        }
+    /// Parses the whole input as a list of declarations without braces, as
+    /// in a `style` attribute. Invalid declarations are skipped and
+    /// reported, like inside a rule.
+    pub fn parse_declaration_list(&mut self) -> (Vec<Declaration>, Vec<Diagnostic>) {
+        let declarations = self.parse_declarations();
+        (declarations, std::mem::take(&mut self.diagnostics))
+    }
 
-        if depth == 0 {
-            Ok(())
-        } else {
-            Err("Unclosed block")
-        }
+    /// Parses the whole input as a property value, as written after the ':'
+    /// of a declaration.
+    pub fn parse_property_value(&mut self) -> Value {
+        self.parse_value()
     }
 
-    fn check_memory_limit(&self) -> Result<(), &'static str> {
-        if self.bytes_consumed > MAX_MEMORY_BYTES {
-            Err("Memory limit exceeded")
-        } else {
-            Ok(())
-        }
+    /// Parses the whole input as a media query list, as passed to
+    /// `matchMedia`. Queries that do not parse become `not all` and are
+    /// reported in the returned diagnostics.
+    pub fn parse_media_query_list(&mut self) -> (MediaQueryList, Vec<Diagnostic>) {
+        let queries = self.parse_media_queries();
+        (queries, std::mem::take(&mut self.diagnostics))
     }
 
-    fn skip_at_rule(&mut self) -> Result<(), &'static str> {
-        self.consume_char(); // Skip @
-        let at_keyword = self.parse_identifier();
+    /// Parses the whole input as a comma-separated selector list, as passed
+    /// to `querySelector`. Unlike in a stylesheet, one bad selector makes the
+    /// whole list invalid.
+    pub fn parse_selector_list(&mut self) -> Result<Vec<Selector>, Diagnostic> {
+        let mut selectors = Vec::new();
+        self.skip_whitespace();
 
-        self.consume_whitespace();
+        loop {
+            selectors.push(self.parse_selector()?);
+            self.skip_whitespace();
 
-        // Skip until { or ; depending on at-rule type
-        match at_keyword.as_str() {
-            "media" | "keyframes" | "supports" => {
-                // Skip the condition
-                self.consume_while(|c| c != '{');
-                if self.next_char() == '{' {
-                    self.skip_block()?;
-                }
+            if self.at_end() {
+                return Ok(selectors);
             }
-            _ => {
-                self.consume_while(|c| c != ';');
-                if self.next_char() == ';' {
-                    self.consume_char();
-                }
+            let start = self.current_position();
+            if !self.eat(&Token::Comma) {
+                self.skip_to(self.limit);
+                return Err(self.error_from(
+                    start,
+                    DiagnosticCode::InvalidSelector,
+                    "Unexpected input in selector",
+                ));
             }
+            self.skip_whitespace();
         }
-
-        Ok(())
     }
 
-    fn skip_to_next_rule(&mut self) -> usize {
-        let start = self.position;
-        let mut depth = 0;
+    // "Consume a list of rules". At the top level, CDO and CDC are ignored
+    // so that style sheets hidden from ancient browsers in `<!-- -->` work.
+    // @import and @namespace are only allowed there, before other rules.
+    fn parse_rule_list(&mut self, top_level: bool) -> Vec<Rule> {
+        let mut rules = Vec::new();
+        let mut imports_allowed = top_level;
+        let mut namespaces_allowed = top_level;
 
-        while !self.eof() {
-            match self.next_char() {
-                '@' if depth == 0 => {
-                    break;
-                }
-                '{' => {
-                    depth += 1;
-                    self.consume_char();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Token::EOF => break,
+                Token::Cdo | Token::Cdc if top_level => {
+                    self.consume_component_value();
+                    continue;
                 }
-                '}' => {
-                    if depth > 0 {
-                        depth -= 1;
-                    }
-                    self.consume_char();
-                    if depth == 0 {
-                        break;
-                    } else {
+                _ => {}
+            }
+            let result = if matches!(self.peek(), Token::AtKeyword(_)) {
+                self.parse_at_rule()
+            } else {
+                self.parse_rule()
+            };
+            match result {
+                Ok(rule) => {
+                    let misplaced = match rule {
+                        Rule::Import { .. } if !imports_allowed => Some("@import"),
+                        Rule::Namespace { .. } if !namespaces_allowed => Some("@namespace"),
+                        _ => None,
+                    };
+                    if let Some(name) = misplaced {
+                        self.diagnostics.push(Diagnostic::error(
+                            DiagnosticCode::InvalidAtRule,
+                            format!("{} is only allowed at the start of a stylesheet", name),
+                            rule.span(),
+                        ));
                         continue;
                     }
+                    imports_allowed &=
+                        matches!(rule, Rule::Import { .. } | Rule::LayerStatement { .. });
+                    namespaces_allowed &= matches!(
+                        rule,
+                        Rule::Import { .. } | Rule::LayerStatement { .. } | Rule::Namespace { .. }
+                    );
+                    if let Rule::Namespace { prefix, url, .. } = &rule {
+                        self.namespaces.push((prefix.clone(), url.clone()));
+                    }
+                    rules.push(rule);
                 }
-                _ => {
-                    self.consume_char();
-                }
+                Err(diagnostic) => self.diagnostics.push(diagnostic),
             }
         }
 
-        self.position - start
+        rules
     }
 
-    fn parse_rule(&mut self) -> Result<Rule, &'static str> {
-        let start_pos = self.position;
-        let selectors = self.parse_selectors()?;
-
-        if self.position == start_pos {
-            return Err("No progress made while parsing selectors");
+    // A qualified rule: a selector list prelude followed by a block of
+    // declarations. The whole rule is consumed even when it is invalid.
+    fn parse_rule(&mut self) -> Result<Rule, Diagnostic> {
+        let start = self.current_position();
+        let prelude_end = self.find(|token| *token == Token::OpenCurly);
+        if prelude_end == self.limit {
+            self.skip_to(prelude_end);
+            return Err(self.error_from(
+                start,
+                DiagnosticCode::InvalidDeclaration,
+                "Expected '{' for declarations block",
+            ));
         }
 
-        let declarations = self.parse_declarations()?;
+        let selectors = self.parse_until(prelude_end, Self::parse_selectors);
+        self.skip_to(prelude_end);
+        let selectors = match selectors {
+            Ok(selectors) => selectors,
+            Err(diagnostic) => {
+                self.consume_component_value();
+                return Err(diagnostic);
+            }
+        };
+
+        let block_start = self.current_position();
+        let ((declarations, rules), closed) = self.parse_block(Self::parse_style_block);
+        if !closed {
+            self.unclosed_block(block_start);
+        }
         Ok(Rule::Style(StyleRule {
             selectors,
             declarations,
+            rules,
+            span: self.span_from(start),
         }))
     }
 
-    fn parse_selectors(&mut self) -> Result<Vec<Selector>, &'static str> {
+    // The prelude of a style rule. Invalid selectors are reported and
+    // dropped; the rule survives as long as one of them is valid. Nested in
+    // another style rule, selectors are relative to it.
+    fn parse_selectors(&mut self) -> Result<Vec<Selector>, Diagnostic> {
+        self.skip_whitespace();
+        let start = self.current_position();
         let mut selectors = Vec::new();
+        let nested = self.nested;
 
         loop {
-            match self.parse_selector() {
-                Ok(selector) => {
-                    selectors.push(selector);
-                    self.consume_whitespace();
-
-                    if self.next_char() == ',' {
-                        self.consume_char();
-                        self.consume_whitespace();
-                    } else if self.next_char() == '{' {
-                        break;
-                    }
+            self.skip_whitespace();
+            let selector_start = self.current_position();
+            let selector = match nested {
+                true => self.parse_complex_selector(true).map(relative_to_parent),
+                false => self.parse_selector(),
+            };
+            let result = selector.and_then(|selector| {
+                self.skip_whitespace();
+                if matches!(self.peek(), Token::Comma | Token::EOF) {
+                    Ok(selector)
+                } else {
+                    self.skip_to_comma();
+                    Err(self.error_from(
+                        selector_start,
+                        DiagnosticCode::InvalidSelector,
+                        "Unexpected character in selector",
+                    ))
                 }
-                Err(_) => {
-                    self.skip_to_next_selector();
-                    if self.next_char() == '{' {
-                        break;
-                    }
+            });
+            match result {
+                Ok(selector) => selectors.push(selector),
+                Err(diagnostic) => {
+                    self.diagnostics.push(diagnostic);
+                    self.skip_to_comma();
                 }
             }
+            if !self.eat(&Token::Comma) {
+                break;
+            }
         }
 
         if selectors.is_empty() {
-            Err("No valid selectors found")
+            Err(self.error_from(
+                start,
+                DiagnosticCode::InvalidSelector,
+                "No valid selectors found",
+            ))
         } else {
             Ok(selectors)
         }
     }
 
-    fn skip_to_next_selector(&mut self) {
-        self.consume_while(|c| c != ',' && c != '{');
-        if self.next_char() == ',' {
-            self.consume_char();
-        }
+    // Stops before the first token that cannot continue the selector, such
+    // as ',', and leaves checking it to the caller.
+    fn parse_selector(&mut self) -> Result<Selector, Diagnostic> {
+        self.parse_complex_selector(false)
     }
 
-    fn parse_selector(&mut self) -> Result<Selector, &'static str> {
-        let mut selector = SimpleSelector::new();
-        let mut has_parts = false;
+    // A relative selector, as inside `:has()`, may start with a combinator.
+    fn parse_complex_selector(&mut self, relative: bool) -> Result<Selector, Diagnostic> {
+        let start = self.current_position();
+        let mut compounds: Vec<CompoundSelector> = Vec::new();
+        let mut combinator = None;
+        if relative {
+            combinator = self.parse_combinator();
+            self.skip_whitespace();
+        }
 
-        while !self.eof() {
-            match self.next_char() {
-                '#' => {
-                    self.consume_char();
-                    selector.id = Some(self.parse_identifier());
-                    has_parts = true;
-                }
-                '.' => {
-                    self.consume_char();
-                    selector.classes.push(self.parse_identifier());
-                    has_parts = true;
-                }
-                '*' => {
-                    self.consume_char();
-                    selector.universal = true;
-                    has_parts = true;
-                }
-                '[' => {
-                    self.consume_char();
-                    selector.attributes.push(self.parse_attribute_selector()?);
-                    has_parts = true;
+        loop {
+            let Some(mut compound) = self.parse_compound_selector()? else {
+                if compounds.is_empty() && combinator.is_none() {
+                    // Cover the offending tokens up to the next selector.
+                    let end = self.find(|token| *token == Token::Comma);
+                    return Err(Diagnostic::error(
+                        DiagnosticCode::InvalidSelector,
+                        "Empty selector",
+                        self.span_until(start, end),
+                    ));
                 }
-                c if valid_identifier_char(c) => {
-                    selector.tag_name = Some(self.parse_identifier());
-                    has_parts = true;
+                return Err(self.error_from(
+                    start,
+                    DiagnosticCode::InvalidSelector,
+                    "Expected a selector after the combinator",
+                ));
+            };
+            compound.combinator = combinator;
+            compounds.push(compound);
+
+            let whitespace = self.skip_whitespace();
+            combinator = match self.parse_combinator() {
+                Some(combinator) => Some(combinator),
+                None if whitespace && self.starts_compound_selector() => {
+                    Some(Combinator::Descendant)
                 }
-                _ => break,
+                None => break,
+            };
+            if compounds.last().is_some_and(|c| c.pseudo_element.is_some()) {
+                return Err(self.error_from(
+                    start,
+                    DiagnosticCode::InvalidSelector,
+                    "A pseudo-element must be at the end of a selector",
+                ));
             }
+            self.skip_whitespace();
         }
 
-        if has_parts {
-            Ok(Selector::Simple(selector))
-        } else {
-            Err("Empty selector")
-        }
+        Ok(Selector { compounds })
     }
 
-    fn parse_attribute_selector(&mut self) -> Result<AttributeSelector, &'static str> {
-        let name = self.parse_identifier();
-        let mut op = None;
-        let mut value = None;
-
-        self.consume_whitespace();
-        if self.next_char() == ']' {
-            self.consume_char();
-            return Ok(AttributeSelector { name, op, value });
-        }
-
-        if self.next_char() == '=' {
-            op = Some(AttributeOperator::Equal);
-            self.consume_char();
-        } else {
-            let op_str = self.consume_while(|c| matches!(c, '~' | '|' | '^' | '$' | '*'));
-            if !op_str.is_empty() && self.next_char() == '=' {
-                op = match op_str {
-                    "~" => Some(AttributeOperator::Includes),
-                    "|" => Some(AttributeOperator::DashMatch),
-                    "^" => Some(AttributeOperator::Prefix),
-                    "$" => Some(AttributeOperator::Suffix),
-                    "*" => Some(AttributeOperator::Substring),
-                    _ => None,
-                };
-                self.consume_char();
-            }
-        }
-
-        if op.is_some() {
-            self.consume_whitespace();
-            let quote = self.next_char();
-            if quote == '\'' || quote == '"' {
-                self.consume_char();
-                value = Some(self.consume_while(|c| c != quote).to_string());
-                self.consume_char();
-            } else {
-                value = Some(self.parse_identifier());
-            }
-        }
-
-        self.consume_whitespace();
-        if self.next_char() == ']' {
-            self.consume_char();
-            Ok(AttributeSelector { name, op, value })
-        } else {
-            self.skip_to_char(']');
-            Err("Invalid attribute selector")
-        }
+    fn parse_combinator(&mut self) -> Option<Combinator> {
+        let combinator = match self.peek() {
+            Token::Delim('>') => Combinator::Child,
+            Token::Delim('+') => Combinator::NextSibling,
+            Token::Delim('~') => Combinator::SubsequentSibling,
+            _ => return None,
+        };
+        self.consume_component_value();
+        Some(combinator)
     }
 
-    fn parse_declarations(&mut self) -> Result<Vec<Declaration>, &'static str> {
-        if self.consume_char() != '{' {
-            return Err("Expected '{' for declarations block");
-        }
+    fn starts_compound_selector(&self) -> bool {
+        matches!(
+            self.peek(),
+            Token::Hash { .. }
+                | Token::Delim('#' | '.' | '*' | '&' | '|')
+                | Token::OpenSquare
+                | Token::Colon
+                | Token::Ident(_)
+        )
+    }
 
-        let mut declarations = Vec::new();
+    // None when there is no compound selector at the cursor at all.
+    fn parse_compound_selector(&mut self) -> Result<Option<CompoundSelector>, Diagnostic> {
+        let mut selector = CompoundSelector::new();
+        let mut has_parts = false;
+        let mut type_allowed = true;
 
         loop {
-            self.consume_whitespace_and_comments();
-            if self.next_char() == '}' {
-                self.consume_char();
+            let start = self.current_position();
+            // Only pseudo-classes may follow a pseudo-element.
+            if selector.pseudo_element.is_some() && *self.peek() != Token::Colon {
                 break;
             }
-
-            match self.parse_declaration() {
-                Ok(declaration) => declarations.push(declaration),
-                Err(_) => {
-                    self.skip_to_next_declaration();
+            match self.peek().clone() {
+                Token::Hash { value, id: true } => {
+                    self.consume_component_value();
+                    selector.ids.push(value);
+                }
+                Token::Hash { .. } | Token::Delim('#') => {
+                    self.consume_component_value();
+                    return Err(self.error_from(
+                        start,
+                        DiagnosticCode::InvalidSelector,
+                        "Expected a name after '#'",
+                    ));
+                }
+                Token::Delim('.') => {
+                    self.consume_component_value();
+                    let class = self.parse_selector_identifier(start, ".")?;
+                    selector.classes.push(class);
+                }
+                // A type or universal selector can only come first, or
+                // after `&`.
+                Token::Ident(_) | Token::Delim('*' | '|') if type_allowed => {
+                    self.parse_type_selector(start, &mut selector)?;
+                }
+                Token::OpenSquare => {
+                    selector.attributes.push(self.parse_attribute_selector()?);
+                }
+                Token::Delim('&') => {
+                    self.consume_component_value();
+                    selector.nesting = true;
+                    has_parts = true;
+                    continue;
+                }
+                Token::Colon => {
+                    self.consume_component_value();
+                    if self.eat(&Token::Colon) {
+                        let name = self.parse_pseudo_name(start, "::")?;
+                        selector.pseudo_element = Some(self.pseudo_element(start, &name)?);
+                    } else if let Token::Function(name) = self.peek().clone() {
+                        let pseudo_class = self.parse_pseudo_class_function(start, &name)?;
+                        selector.pseudo_classes.push(pseudo_class);
+                    } else {
+                        let name = self.parse_selector_identifier(start, ":")?;
+                        if PseudoElement::is_legacy(&name) {
+                            selector.pseudo_element = Some(self.pseudo_element(start, &name)?);
+                        } else {
+                            let pseudo_class = self.pseudo_class(start, &name)?;
+                            selector.pseudo_classes.push(pseudo_class);
+                        }
+                    }
                 }
+                _ => break,
             }
+            has_parts = true;
+            type_allowed = false;
         }
 
-        Ok(declarations)
-    }
-
-    fn skip_to_next_declaration(&mut self) {
-        self.consume_while(|c| c != ';' && c != '}');
-        if self.next_char() == ';' {
-            self.consume_char();
+        // Without a prefix, a compound is limited to the default namespace,
+        // except inside `:is()` and the like unless it has a type or
+        // universal selector.
+        let typed = selector.tag_name.is_some() || selector.universal;
+        if selector.namespace.is_none() && (typed || !self.selector_arguments) {
+            selector.namespace = self
+                .namespace_url(None)
+                .map(|url| NamespaceSelector::Named { prefix: None, url });
         }
+        Ok(has_parts.then_some(selector))
     }
 
-    fn skip_to_char(&mut self, end_char: char) {
-        self.consume_while(|c| c != end_char);
-        if self.next_char() == end_char {
-            self.consume_char();
+    // A type or universal selector, with an optional namespace prefix as in
+    // `svg|rect`, `*|*` or `|rect`.
+    fn parse_type_selector(
+        &mut self,
+        start: Position,
+        selector: &mut CompoundSelector,
+    ) -> Result<(), Diagnostic> {
+        selector.namespace = self.parse_namespace_prefix(start)?;
+        match self.peek().clone() {
+            Token::Ident(name) => selector.tag_name = Some(name),
+            Token::Delim('*') => selector.universal = true,
+            _ => {
+                return Err(self.error_from(
+                    start,
+                    DiagnosticCode::InvalidSelector,
+                    "Expected a name after '|'",
+                ))
+            }
         }
+        self.consume_component_value();
+        Ok(())
     }
 
-    fn parse_declaration(&mut self) -> Result<Declaration, &'static str> {
-        let property_name = self.parse_identifier();
-        if property_name.is_empty() {
-            return Err("Empty property name");
+    // Consumes a namespace prefix followed by '|', if there is one. `|=` is
+    // an attribute operator rather than a prefix.
+    fn parse_namespace_prefix(
+        &mut self,
+        start: Position,
+    ) -> Result<Option<NamespaceSelector>, Diagnostic> {
+        let prefix = match self.peek().clone() {
+            Token::Delim('|') => {
+                self.consume_component_value();
+                return Ok(Some(NamespaceSelector::None));
+            }
+            Token::Ident(prefix) => Some(prefix),
+            Token::Delim('*') => None,
+            _ => return Ok(None),
+        };
+        let token = |offset: usize| {
+            let index = self.index + offset;
+            (index < self.limit).then(|| &self.tokens[index].0)
+        };
+        if token(1) != Some(&Token::Delim('|')) || token(2) == Some(&Token::Delim('=')) {
+            return Ok(None);
         }
+        self.consume_component_value();
+        self.consume_component_value();
 
-        self.consume_whitespace();
-        if self.consume_char() != ':' {
-            return Err("Expected ':' after property name");
+        let Some(prefix) = prefix else {
+            return Ok(Some(NamespaceSelector::Any));
+        };
+        match self.namespace_url(Some(&prefix)) {
+            Some(url) => Ok(Some(NamespaceSelector::Named {
+                prefix: Some(prefix),
+                url,
+            })),
+            None => Err(self.error_from(
+                start,
+                DiagnosticCode::InvalidSelector,
+                format!("Undeclared namespace prefix '{}'", prefix),
+            )),
         }
+    }
 
-        self.consume_whitespace();
-        let value = self.parse_value();
-        self.consume_whitespace();
+    // The URL declared for `prefix`, or for the default namespace with
+    // None. A later @namespace rule for the same prefix wins.
+    fn namespace_url(&self, prefix: Option<&str>) -> Option<String> {
+        self.namespaces
+            .iter()
+            .rev()
+            .find(|(declared, _)| declared.as_deref() == prefix)
+            .map(|(_, url)| url.clone())
+    }
 
-        let important = if self.next_char() == '!' {
-            self.consume_char();
-            let important_str = self.parse_identifier().to_lowercase();
-            important_str == "important"
-        } else {
-            false
+    fn parse_selector_identifier(
+        &mut self,
+        start: Position,
+        prefix: &str,
+    ) -> Result<String, Diagnostic> {
+        let Token::Ident(name) = self.peek().clone() else {
+            return Err(self.error_from(
+                start,
+                DiagnosticCode::InvalidSelector,
+                format!("Expected a name after '{}'", prefix),
+            ));
         };
+        self.consume_component_value();
+        Ok(name)
+    }
 
-        if self.next_char() == ';' {
-            self.consume_char();
+    // The name after `::`. No functional pseudo-elements are supported, so
+    // one is consumed whole and reported as unknown.
+    fn parse_pseudo_name(&mut self, start: Position, prefix: &str) -> Result<String, Diagnostic> {
+        if let Token::Function(name) = self.peek().clone() {
+            self.consume_component_value();
+            return Err(self.error_from(
+                start,
+                DiagnosticCode::InvalidSelector,
+                format!("Unknown pseudo-element {}{}()", prefix, name),
+            ));
         }
+        self.parse_selector_identifier(start, prefix)
+    }
 
-        Ok(Declaration {
-            name: property_name,
-            value,
-            important,
+    fn pseudo_element(&mut self, start: Position, name: &str) -> Result<PseudoElement, Diagnostic> {
+        PseudoElement::from_name(&name.to_ascii_lowercase()).ok_or_else(|| {
+            self.error_from(
+                start,
+                DiagnosticCode::InvalidSelector,
+                format!("Unknown pseudo-element ::{}", name),
+            )
         })
     }
 
-    fn parse_value(&mut self) -> Value {
-        let mut values = Vec::new();
+    fn pseudo_class(&mut self, start: Position, name: &str) -> Result<PseudoClass, Diagnostic> {
+        let name = name.to_ascii_lowercase();
+        PseudoClass::from_name(&name).ok_or_else(|| {
+            self.error_from(
+                start,
+                DiagnosticCode::InvalidSelector,
+                format!("Unknown pseudo-class :{}", name),
+            )
+        })
+    }
 
-        while !self.eof() {
-            self.consume_whitespace();
-            if self.next_char() == ';' || self.next_char() == '!' || self.next_char() == '}' {
-                break;
-            }
+    // The cursor is on the function token. Its arguments are parsed up to
+    // the closing ')', and the cursor is left after it.
+    fn parse_pseudo_class_function(
+        &mut self,
+        start: Position,
+        name: &str,
+    ) -> Result<PseudoClass, Diagnostic> {
+        let name = name.to_ascii_lowercase();
+        let (result, closed) = self.parse_block(|parser| {
+            parser.skip_whitespace();
+            let pseudo_class = parser.parse_pseudo_class_arguments(start, &name)?;
+            parser.skip_whitespace();
+            Ok(parser.at_end().then_some(pseudo_class))
+        });
+        match result {
+            Ok(Some(pseudo_class)) if closed => Ok(pseudo_class),
+            Ok(_) => Err(self.error_from(
+                start,
+                DiagnosticCode::InvalidSelector,
+                format!("Expected ')' to close :{}()", name),
+            )),
+            Err(diagnostic) => Err(diagnostic),
+        }
+    }
 
-            let value = match self.next_char() {
-                '0'..='9' | '.' | '+' | '-' => {
-                    let num = self.parse_float();
-                    if self.next_char() == '%' {
-                        self.consume_char();
-                        Value::Percentage(num)
-                    } else {
-                        Value::Length(num, self.parse_unit())
-                    }
+    fn parse_pseudo_class_arguments(
+        &mut self,
+        start: Position,
+        name: &str,
+    ) -> Result<PseudoClass, Diagnostic> {
+        let pseudo_class = match name {
+            "not" => PseudoClass::Not(self.parse_selector_arguments(start, false, false)?),
+            "is" => PseudoClass::Is(self.parse_selector_arguments(start, false, true)?),
+            "where" => PseudoClass::Where(self.parse_selector_arguments(start, false, true)?),
+            "has" => PseudoClass::Has(self.parse_selector_arguments(start, true, false)?),
+            "nth-child" | "nth-last-child" => {
+                let nth = self.parse_nth(start)?;
+                self.skip_whitespace();
+                let mut of = Vec::new();
+                if matches!(self.peek(), Token::Ident(keyword) if keyword.eq_ignore_ascii_case("of"))
+                {
+                    self.consume_component_value();
+                    self.skip_whitespace();
+                    of = self.parse_selector_arguments(start, false, false)?;
                 }
-                '#' => self.parse_color(),
-                '"' | '\'' => {
-                    let quote = self.consume_char();
-                    let s = self.consume_while(|c| c != quote).to_string();
-                    self.consume_char();
-                    Value::String(s)
+                if name == "nth-child" {
+                    PseudoClass::NthChild(nth, of)
+                } else {
+                    PseudoClass::NthLastChild(nth, of)
                 }
-                '(' => {
-                    self.consume_char();
-                    let func_name = self.parse_identifier();
-                    let args = self.parse_value_list(')');
-                    Value::Function(func_name, args)
-                }
-                _ => {
-                    let ident = self.parse_identifier();
-                    if ident.is_empty() {
-                        break;
-                    }
-                    match ident.to_lowercase().as_str() {
-                        "rgb" | "rgba" | "hsl" | "hsla" => {
-                            self.consume_char(); // Skip (
-                            let args = self.parse_value_list(')');
-                            Value::Function(ident, args)
+            }
+            "nth-of-type" => PseudoClass::NthOfType(self.parse_nth(start)?),
+            "nth-last-of-type" => PseudoClass::NthLastOfType(self.parse_nth(start)?),
+            "lang" => {
+                let mut ranges = Vec::new();
+                loop {
+                    let range = match self.peek().clone() {
+                        Token::String(range) => {
+                            self.consume_component_value();
+                            range
                         }
-                        _ => Value::Keyword(ident),
+                        _ => self.parse_selector_identifier(start, ":lang(")?,
+                    };
+                    ranges.push(range);
+                    self.skip_whitespace();
+                    if !self.eat(&Token::Comma) {
+                        break;
                     }
+                    self.skip_whitespace();
                 }
-            };
-
-            values.push(value);
-        }
-
-        if values.len() == 1 {
-            values.remove(0)
-        } else {
-            Value::List(values)
-        }
+                PseudoClass::Lang(ranges)
+            }
+            "dir" => PseudoClass::Dir(self.parse_selector_identifier(start, ":dir(")?),
+            _ => {
+                self.skip_to(self.limit);
+                return Err(self.error_from(
+                    start,
+                    DiagnosticCode::InvalidSelector,
+                    format!("Unknown pseudo-class :{}()", name),
+                ));
+            }
+        };
+        Ok(pseudo_class)
     }
 
-    fn parse_value_list(&mut self, end_char: char) -> Vec<Value> {
-        let mut values = Vec::new();
+    // The selector list inside `:is()` and friends. A forgiving list, as in
+    // `:is()` and `:where()`, drops invalid selectors instead of failing.
+    fn parse_selector_arguments(
+        &mut self,
+        start: Position,
+        relative: bool,
+        forgiving: bool,
+    ) -> Result<Vec<Selector>, Diagnostic> {
+        let mut selectors = Vec::new();
 
-        while !self.eof() {
-            self.consume_whitespace();
-            if self.next_char() == end_char {
-                self.consume_char();
-                break;
+        loop {
+            self.skip_whitespace();
+            if forgiving && matches!(self.peek(), Token::Comma | Token::EOF) {
+                // Empty entries are allowed in a forgiving list.
+            } else {
+                let selector_start = self.current_position();
+                let outer = std::mem::replace(&mut self.selector_arguments, true);
+                let result = self.parse_complex_selector(relative);
+                self.selector_arguments = outer;
+                let result = result.and_then(|selector| {
+                    self.skip_whitespace();
+                    if matches!(self.peek(), Token::Comma | Token::EOF) {
+                        Ok(selector)
+                    } else {
+                        Err(self.error_from(
+                            selector_start,
+                            DiagnosticCode::InvalidSelector,
+                            "Unexpected character in selector",
+                        ))
+                    }
+                });
+                match result {
+                    Ok(selector) => selectors.push(selector),
+                    Err(diagnostic) if forgiving => {
+                        self.skip_to_comma();
+                        self.diagnostics.push(Diagnostic::warning(
+                            diagnostic.code,
+                            format!("Ignored invalid selector: {}", diagnostic.message),
+                            diagnostic.span,
+                        ));
+                    }
+                    Err(diagnostic) => return Err(diagnostic),
+                }
             }
 
-            if self.next_char() == ',' {
-                self.consume_char();
-                self.consume_whitespace();
-                continue;
+            if !self.eat(&Token::Comma) {
+                break;
             }
-
-            values.push(self.parse_value());
         }
 
-        values
+        if selectors.is_empty() && !forgiving {
+            return Err(self.error_from(
+                start,
+                DiagnosticCode::InvalidSelector,
+                "Expected a selector list",
+            ));
+        }
+        Ok(selectors)
     }
 
-    fn parse_at_rule(&mut self) -> Result<Rule, &'static str> {
-        let start_pos = self.position;
-        self.consume_char(); // Skip @
-        let name = self.parse_identifier();
-        self.consume_whitespace();
-
-        match name.as_str() {
-            "media" => {
-                let query = self.consume_while(|c| c != '{').trim().to_string();
-                if query.is_empty() {
-                    return Err("Empty media query");
+    // The `An+B` microsyntax: `odd`, `even`, `5`, `-n+3`, `2n + 1` and so on.
+    // The tokenizer splits these oddly, e.g. `2n-1` is a single dimension
+    // with the unit `n-1`, so each shape is matched the way the spec lists
+    // them.
+    fn parse_nth(&mut self, start: Position) -> Result<Nth, Diagnostic> {
+        let token = self.peek().clone();
+        self.consume_component_value();
+        let nth = match token {
+            Token::Ident(ident) if ident.eq_ignore_ascii_case("odd") => Some(Nth { a: 2, b: 1 }),
+            Token::Ident(ident) if ident.eq_ignore_ascii_case("even") => Some(Nth { a: 2, b: 0 }),
+            Token::Number {
+                value,
+                integer: true,
+                ..
+            } => Some(Nth {
+                a: 0,
+                b: value as i32,
+            }),
+            Token::Dimension {
+                value,
+                integer: true,
+                unit,
+                ..
+            } => self.parse_nth_b(value as i32, &unit),
+            Token::Ident(ident) => match ident.strip_prefix('-') {
+                Some(rest) => self.parse_nth_b(-1, rest),
+                None => self.parse_nth_b(1, &ident),
+            },
+            // `+n`, where the '+' must be directly followed by the `n`.
+            Token::Delim('+') => match self.peek().clone() {
+                Token::Ident(ident) if !ident.starts_with('-') => {
+                    self.consume_component_value();
+                    self.parse_nth_b(1, &ident)
                 }
-                self.expect_char('{')?;
-                let rules = self.parse_rules_block()?;
-                Ok(Rule::Media { query, rules })
-            }
-            "keyframes" => {
-                let name = self.parse_identifier();
-                self.expect_char('{')?;
-                let frames = self.parse_keyframe_rules()?;
-                Ok(Rule::Keyframes { name, frames })
+                _ => None,
+            },
+            _ => None,
+        };
+
+        nth.ok_or_else(|| {
+            self.skip_to(self.limit);
+            self.error_from(
+                start,
+                DiagnosticCode::InvalidSelector,
+                "Invalid An+B expression",
+            )
+        })
+    }
+
+    // The rest of `An+B` once A is known. `unit` is what followed the A: `n`,
+    // `n-`, or `n-` with the digits of B.
+    fn parse_nth_b(&mut self, a: i32, unit: &str) -> Option<Nth> {
+        let unit = unit.to_ascii_lowercase();
+        let rest = unit.strip_prefix('n')?;
+        if !rest.is_empty() {
+            let digits = rest.strip_prefix('-')?;
+            if digits.is_empty() {
+                // `n- 5`
+                self.skip_whitespace();
+                let b = self.parse_nth_integer(false)?;
+                return Some(Nth { a, b: -b });
             }
-            _ => {
-                self.consume_while(|c| c != ';' && c != '{');
-                if self.next_char() == '{' {
-                    self.skip_block()?;
-                } else if self.next_char() == ';' {
-                    self.consume_char();
-                }
-                Err("Skipped unsupported at-rule")
+            if !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
             }
+            return Some(Nth {
+                a,
+                b: -digits.parse::<i32>().ok()?,
+            });
         }
-    }
 
-    fn expect_char(&mut self, expected: char) -> Result<(), &'static str> {
-        if self.next_char() == expected {
-            self.consume_char();
-            Ok(())
-        } else {
-            Err("Expected character not found")
+        // Whatever follows might not belong to An+B, like the `of` in
+        // `:nth-child(2n of .item)`, so the cursor is put back unless a B
+        // is found.
+        let (index, last_end) = (self.index, self.last_end);
+        self.skip_whitespace();
+        let b = match self.peek() {
+            Token::Number { signed: true, .. } => self.parse_nth_integer(true),
+            Token::Delim(sign @ ('+' | '-')) => {
+                let sign = if *sign == '-' { -1 } else { 1 };
+                self.consume_component_value();
+                self.skip_whitespace();
+                return Some(Nth {
+                    a,
+                    b: sign * self.parse_nth_integer(false)?,
+                });
+            }
+            _ => None,
+        };
+        if b.is_none() {
+            (self.index, self.last_end) = (index, last_end);
         }
+        Some(Nth {
+            a,
+            b: b.unwrap_or(0),
+        })
     }
 
-    fn parse_rules_block(&mut self) -> Result<Vec<Rule>, &'static str> {
-        let mut rules = Vec::new();
-        self.consume_whitespace_and_comments();
-
-        while !self.eof() && self.next_char() != '}' {
-            match self.parse_rule() {
-                Ok(rule) => rules.push(rule),
-                Err(e) => {
-                    eprintln!("Skipping malformed rule in block: {}", e);
-                    self.skip_to_next_rule();
-                }
+    fn parse_nth_integer(&mut self, signed: bool) -> Option<i32> {
+        match *self.peek() {
+            Token::Number {
+                value,
+                integer: true,
+                signed: has_sign,
+            } if has_sign == signed => {
+                self.consume_component_value();
+                Some(value as i32)
             }
-            self.consume_whitespace_and_comments();
+            _ => None,
         }
-
-        self.expect_char('}')?;
-        Ok(rules)
     }
 
-    fn parse_color(&mut self) -> Value {
-        assert_eq!(self.consume_char(), '#');
-        let hex = self.consume_while(|c| c.is_ascii_hexdigit());
-
-        match hex.len() {
-            3 => Value::Color(Color {
-                r: u8::from_str_radix(&hex[0..1].repeat(2), 16).unwrap_or(0),
-                g: u8::from_str_radix(&hex[1..2].repeat(2), 16).unwrap_or(0),
-                b: u8::from_str_radix(&hex[2..3].repeat(2), 16).unwrap_or(0),
-                a: 1.0,
-            }),
-            4 => Value::Color(Color {
-                r: u8::from_str_radix(&hex[0..1].repeat(2), 16).unwrap_or(0),
-                g: u8::from_str_radix(&hex[1..2].repeat(2), 16).unwrap_or(0),
-                b: u8::from_str_radix(&hex[2..3].repeat(2), 16).unwrap_or(0),
-                a: u8::from_str_radix(&hex[3..4].repeat(2), 16).unwrap_or(255) as f32 / 255.0,
-            }),
-            6 => Value::Color(Color {
-                r: u8::from_str_radix(&hex[0..2], 16).unwrap_or(0),
-                g: u8::from_str_radix(&hex[2..4], 16).unwrap_or(0),
-                b: u8::from_str_radix(&hex[4..6], 16).unwrap_or(0),
-                a: 1.0,
-            }),
-            8 => Value::Color(Color {
-                r: u8::from_str_radix(&hex[0..2], 16).unwrap_or(0),
-                g: u8::from_str_radix(&hex[2..4], 16).unwrap_or(0),
-                b: u8::from_str_radix(&hex[4..6], 16).unwrap_or(0),
-                a: u8::from_str_radix(&hex[6..8], 16).unwrap_or(255) as f32 / 255.0,
-            }),
-            _ => Value::Keyword("transparent".to_string()),
+    // The cursor is on the '['. The whole block is consumed, and reported as
+    // a whole when its contents are not a valid attribute selector.
+    fn parse_attribute_selector(&mut self) -> Result<AttributeSelector, Diagnostic> {
+        let start = self.current_position();
+        let (selector, closed) = self.parse_block(|parser| {
+            let selector = parser.parse_attribute_contents(start)?;
+            parser.skip_whitespace();
+            Ok(selector.filter(|_| parser.at_end()))
+        });
+        match selector? {
+            Some(selector) if closed => Ok(selector),
+            _ => Err(self.error_from(
+                start,
+                DiagnosticCode::InvalidSelector,
+                "Invalid attribute selector",
+            )),
         }
     }
 
-    fn parse_float(&mut self) -> f32 {
-        let s = self.consume_while(|c| matches!(c, '0'..='9' | '.' | '+' | '-'));
-        s.parse().unwrap_or(0.0)
+    // None when the contents are not an attribute selector, and an error
+    // only for an undeclared namespace prefix.
+    fn parse_attribute_contents(
+        &mut self,
+        start: Position,
+    ) -> Result<Option<AttributeSelector>, Diagnostic> {
+        self.skip_whitespace();
+        let namespace = self.parse_namespace_prefix(start)?;
+        Ok(self.parse_attribute_name_and_value(namespace))
     }
 
-    fn parse_keyframe_rules(&mut self) -> Result<Vec<Keyframe>, &'static str> {
-        let mut frames = Vec::new();
-        while !self.eof() && self.next_char() != '}' {
-            let selectors = self.parse_keyframe_selectors()?;
-            let declarations = self.parse_declarations()?;
-            frames.push(Keyframe {
-                selectors,
-                declarations,
+    fn parse_attribute_name_and_value(
+        &mut self,
+        namespace: Option<NamespaceSelector>,
+    ) -> Option<AttributeSelector> {
+        let Token::Ident(name) = self.peek().clone() else {
+            return None;
+        };
+        self.consume_component_value();
+        self.skip_whitespace();
+        if self.at_end() {
+            return Some(AttributeSelector {
+                namespace,
+                name,
+                op: None,
+                value: None,
+                case_insensitive: false,
             });
-            self.consume_whitespace_and_comments();
         }
-        self.expect_char('}')?;
-        Ok(frames)
-    }
 
-    fn parse_keyframe_selectors(&mut self) -> Result<Vec<String>, &'static str> {
-        let mut selectors = Vec::new();
-        loop {
-            self.consume_whitespace();
-            selectors.push(self.consume_while(|c| c != ',' && c != '{').to_string());
-            match self.next_char() {
-                ',' => {
-                    self.consume_char();
-                    continue;
+        let op = match *self.peek() {
+            Token::Delim('=') => AttributeOperator::Equal,
+            Token::Delim(c) => {
+                let op = match c {
+                    '~' => AttributeOperator::Includes,
+                    '|' => AttributeOperator::DashMatch,
+                    '^' => AttributeOperator::Prefix,
+                    '$' => AttributeOperator::Suffix,
+                    '*' => AttributeOperator::Substring,
+                    _ => return None,
+                };
+                // The '=' must follow without whitespace.
+                self.consume_component_value();
+                if *self.peek() != Token::Delim('=') {
+                    return None;
                 }
-                '{' => break,
-                _ => return Err("Invalid keyframe selector"),
+                op
             }
-        }
-        Ok(selectors)
-    }
+            _ => return None,
+        };
+        self.consume_component_value();
 
-    fn parse_unit(&mut self) -> Unit {
-        let unit_str = self.parse_identifier().to_lowercase();
-        match unit_str.as_str() {
-            "px" => Unit::Px,
-            "em" => Unit::Em,
-            "rem" => Unit::Rem,
-            "%" => Unit::Percent,
-            "deg" => Unit::Deg,
-            "rad" => Unit::Rad,
-            "turn" => Unit::Turn,
-            "s" => Unit::S,
-            "ms" => Unit::Ms,
-            "hz" => Unit::Hz,
-            "dpi" => Unit::Dpi,
-            _ => Unit::Px, // Default to pixels
+        self.skip_whitespace();
+        let value = match self.peek().clone() {
+            Token::Ident(value) | Token::String(value) => value,
+            _ => return None,
+        };
+        self.consume_component_value();
+
+        self.skip_whitespace();
+        let mut case_insensitive = false;
+        if let Token::Ident(flag) = self.peek() {
+            case_insensitive = match flag.to_ascii_lowercase().as_str() {
+                "i" => true,
+                "s" => false,
+                _ => return None,
+            };
+            self.consume_component_value();
         }
+
+        Some(AttributeSelector {
+            namespace,
+            name,
+            op: Some(op),
+            value: Some(value),
+            case_insensitive,
+        })
     }
 
-    fn consume_whitespace_and_comments(&mut self) {
+    // The contents of a style rule's block, where nested style rules and
+    // conditional rules may come among the declarations. Declarations after
+    // a nested rule keep their place after it, in a rule of their own with
+    // the selector `&`.
+    fn parse_style_block(&mut self) -> (Vec<Declaration>, Vec<Rule>) {
+        let nested = std::mem::replace(&mut self.nested, true);
+        let mut declarations = Vec::new();
+        let mut rules = Vec::new();
+        let mut trailing = Vec::new();
+
         loop {
-            self.consume_whitespace();
-            if self.next_char() == '/' && self.peek_char(1) == '*' {
-                self.skip_comment();
-            } else {
-                break;
+            self.skip_whitespace();
+            let rule = match self.peek() {
+                Token::EOF => break,
+                Token::Semicolon => {
+                    self.consume_component_value();
+                    continue;
+                }
+                Token::AtKeyword(_) => match self.parse_at_rule() {
+                    Ok(
+                        rule @ (Rule::Media { .. }
+                        | Rule::Supports { .. }
+                        | Rule::Layer { .. }
+                        | Rule::LayerStatement { .. }
+                        | Rule::Container { .. }),
+                    ) => Ok(rule),
+                    Ok(rule) => Err(Diagnostic::error(
+                        DiagnosticCode::InvalidAtRule,
+                        "This at-rule is not allowed inside a style rule",
+                        rule.span(),
+                    )),
+                    Err(diagnostic) => Err(diagnostic),
+                },
+                _ if self.starts_nested_rule() => self.parse_rule(),
+                _ => {
+                    match self.parse_declaration() {
+                        Ok(declaration) if rules.is_empty() => declarations.push(declaration),
+                        Ok(declaration) => trailing.push(declaration),
+                        Err(diagnostic) => {
+                            self.diagnostics.push(diagnostic);
+                            let end = self.find(|token| *token == Token::Semicolon);
+                            self.skip_to(end);
+                        }
+                    }
+                    continue;
+                }
+            };
+            match rule {
+                Ok(rule) => {
+                    if let Some(rule) = nested_declarations(std::mem::take(&mut trailing)) {
+                        rules.push(rule);
+                    }
+                    rules.push(rule);
+                }
+                Err(diagnostic) => self.diagnostics.push(diagnostic),
             }
         }
+        rules.extend(nested_declarations(trailing));
+
+        self.nested = nested;
+        (declarations, rules)
     }
 
-    fn skip_comment(&mut self) {
-        assert_eq!(self.consume_char(), '/');
-        assert_eq!(self.consume_char(), '*');
-        self.consume_while(|c| c != '*');
-        if self.next_char() == '*' && self.peek_char(1) == '/' {
-            self.consume_char();
-            self.consume_char();
-        }
+    // Whether a style rule's block goes on with a nested rule rather than a
+    // declaration: a block comes before any ';', and it isn't the value of a
+    // custom property, which may contain blocks.
+    fn starts_nested_rule(&self) -> bool {
+        let end = self.find(|token| matches!(token, Token::Semicolon | Token::OpenCurly));
+        end < self.limit
+            && self.tokens[end].0 == Token::OpenCurly
+            && !matches!(self.peek(), Token::Ident(name) if variables::is_custom_property(name))
     }
 
-    fn peek_char(&self, offset: usize) -> char {
-        self.input[self.position..]
-            .chars()
-            .nth(offset)
-            .unwrap_or('\0')
+    fn parse_declarations(&mut self) -> Vec<Declaration> {
+        self.parse_declarations_with(Self::parse_declaration)
     }
 
-    // Helper methods
-    fn consume_char(&mut self) -> char {
-        let mut iter = self.input[self.position..].char_indices();
-        let (_, cur_char) = iter.next().unwrap();
-        let (next_pos, _) = iter.next().unwrap_or((1, ' '));
-        self.position += next_pos;
-        cur_char
+    // The descriptors of an at-rule like `@font-face`, which are not
+    // properties, so their values are not checked.
+    fn parse_descriptors(&mut self) -> Vec<Declaration> {
+        self.parse_declarations_with(Self::parse_descriptor)
     }
 
-    fn consume_while<F>(&mut self, test: F) -> &'a str
-    where
-        F: Fn(char) -> bool,
-    {
-        let start = self.position;
-        while !self.eof() && test(self.next_char()) {
-            self.position += self.next_char().len_utf8();
-            self.bytes_consumed += self.next_char().len_utf8();
+    // "Consume a list of declarations". At-rules are allowed there by the
+    // grammar, but none is supported inside declarations yet.
+    fn parse_declarations_with(
+        &mut self,
+        parse: fn(&mut Self) -> Result<Declaration, Diagnostic>,
+    ) -> Vec<Declaration> {
+        let mut declarations = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Token::EOF => break,
+                Token::Semicolon => self.consume_component_value(),
+                // Inside a block the '}' would have closed it, so this only
+                // happens without one, as in a `style` attribute.
+                Token::CloseCurly => {
+                    let start = self.current_position();
+                    self.consume_component_value();
+                    let diagnostic = self.error_from(
+                        start,
+                        DiagnosticCode::UnexpectedCharacter,
+                        "Unexpected character '}'",
+                    );
+                    self.diagnostics.push(diagnostic);
+                }
+                Token::AtKeyword(name) => {
+                    let name = name.clone();
+                    let start = self.current_position();
+                    self.consume_component_value();
+                    self.skip_at_rule();
+                    let span = self.span_from(start);
+                    self.diagnostics.push(Diagnostic::warning(
+                        DiagnosticCode::UnsupportedAtRule,
+                        format!("Skipped unsupported at-rule @{}", name),
+                        span,
+                    ));
+                }
+                _ => match parse(self) {
+                    Ok(declaration) => declarations.push(declaration),
+                    Err(diagnostic) => {
+                        self.diagnostics.push(diagnostic);
+                        let end = self.find(|token| *token == Token::Semicolon);
+                        self.skip_to(end);
+                    }
+                },
+            }
         }
-        &self.input[start..self.position]
-    }
 
-    fn consume_whitespace(&mut self) {
-        self.consume_while(|c| c.is_whitespace());
+        declarations
     }
 
-    fn parse_identifier(&mut self) -> String {
-        self.consume_while(valid_identifier_char).to_string()
+    fn parse_declaration(&mut self) -> Result<Declaration, Diagnostic> {
+        let start = self.current_position();
+        let end = self.find(|token| *token == Token::Semicolon);
+        let Token::Ident(property_name) = self.peek().clone() else {
+            return Err(self.invalid_declaration(start, end, "Empty property name"));
+        };
+        self.consume_component_value();
+
+        self.skip_whitespace();
+        if !self.eat(&Token::Colon) {
+            return Err(self.invalid_declaration(start, end, "Expected ':' after property name"));
+        }
+        self.skip_whitespace();
+
+        let (value_end, important) = self.importance(end);
+        let value_start = self.current_position();
+        let bad = self.tokens[self.index..value_end]
+            .iter()
+            .find(|(token, _)| matches!(token, Token::BadString | Token::BadUrl));
+        let tokens: Vec<Token> = self.tokens[self.index..value_end]
+            .iter()
+            .map(|(token, _)| token.clone())
+            .collect();
+        let tokens = variables::trim(&tokens);
+        let value = match bad {
+            // An unterminated string or a malformed URL poisons the value.
+            Some((_, span)) => Err(Value::Keyword(
+                self.input[span.start.offset..span.end.offset].to_string(),
+            )),
+            // Custom properties take any tokens, and values using `var()`
+            // can only be checked once it is substituted.
+            None if variables::is_custom_property(&property_name)
+                || variables::uses_var(tokens) =>
+            {
+                let unparsed = Value::Unparsed(Unparsed {
+                    tokens: tokens.to_vec(),
+                    shorthand: None,
+                });
+                match tokens {
+                    _ if !variables::valid_references(tokens) => Err(unparsed),
+                    [Token::Ident(ident)] => {
+                        match css_wide_keyword(Value::Keyword(ident.clone())) {
+                            Value::Keyword(_) => Ok(unparsed),
+                            wide => Ok(wide),
+                        }
+                    }
+                    _ => Ok(unparsed),
+                }
+            }
+            None => {
+                let value = self.parse_until(value_end, Self::parse_value);
+                // A value that doesn't match the property's grammar drops
+                // the whole declaration.
+                Property::validate(&property_name, &value).map(|()| value)
+            }
+        };
+        let value = match value {
+            Ok(value) => value,
+            Err(invalid) => {
+                self.skip_to(value_end);
+                let message = match invalid {
+                    Value::List(values) if values.is_empty() => {
+                        format!("Expected a value for {}", property_name)
+                    }
+                    invalid => format!("Invalid value for {}: {}", property_name, invalid),
+                };
+                return Err(Diagnostic::error(
+                    DiagnosticCode::InvalidPropertyValue,
+                    message,
+                    self.span_from(value_start),
+                ));
+            }
+        };
+
+        // Consumes the `!important`, leaving out trailing whitespace and the
+        // ';' from the span.
+        self.skip_to(end);
+        Ok(Declaration {
+            name: property_name,
+            value,
+            important,
+            span: self.span_from(start),
+        })
+    }
+
+    fn parse_descriptor(&mut self) -> Result<Declaration, Diagnostic> {
+        let start = self.current_position();
+        let end = self.find(|token| *token == Token::Semicolon);
+        let Token::Ident(name) = self.peek().clone() else {
+            return Err(self.invalid_declaration(start, end, "Empty descriptor name"));
+        };
+        self.consume_component_value();
+
+        self.skip_whitespace();
+        if !self.eat(&Token::Colon) {
+            return Err(self.invalid_declaration(start, end, "Expected ':' after descriptor name"));
+        }
+        let value_start = self.current_position();
+        let value = self.parse_until(end, Self::parse_value);
+        if matches!(&value, Value::List(values) if values.is_empty()) {
+            return Err(Diagnostic::error(
+                DiagnosticCode::InvalidPropertyValue,
+                format!("Expected a value for {}", name),
+                self.span_from(value_start),
+            ));
+        }
+        Ok(Declaration {
+            name,
+            value,
+            important: false,
+            span: self.span_from(start),
+        })
+    }
+
+    // Where the value of a declaration ending at `end` stops, and whether it
+    // is followed by `!important`.
+    fn importance(&self, end: usize) -> (usize, bool) {
+        let mut significant = (self.index..end)
+            .rev()
+            .filter(|&i| self.tokens[i].0 != Token::Whitespace);
+        match (significant.next(), significant.next()) {
+            (Some(last), Some(bang))
+                if matches!(&self.tokens[last].0,
+                    Token::Ident(ident) if ident.eq_ignore_ascii_case("important"))
+                    && self.tokens[bang].0 == Token::Delim('!') =>
+            {
+                (bang, true)
+            }
+            _ => (end, false),
+        }
+    }
+
+    fn parse_value(&mut self) -> Value {
+        let mut values = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+            if self.at_end() {
+                break;
+            }
+            values.push(self.parse_component_value());
+        }
+
+        if values.len() == 1 {
+            css_wide_keyword(values.remove(0))
+        } else {
+            Value::List(values)
+        }
+    }
+
+    fn parse_component_value(&mut self) -> Value {
+        let value = match self.peek().clone() {
+            Token::Number { value, .. } => Value::Number(value),
+            Token::Percentage(value) => Value::Percentage(value),
+            Token::Dimension { value, unit, .. } => match unit.parse() {
+                Ok(unit) => Value::Length(value, unit),
+                Err(()) => Value::Dimension(value, unit),
+            },
+            // A malformed hex color is kept as written, like `#ggg`.
+            Token::Hash { value, .. } => {
+                parse_color(&value).unwrap_or_else(|| Value::Keyword(format!("#{}", value)))
+            }
+            Token::String(value) => Value::String(value),
+            Token::Url(url) => Value::Url(url),
+            Token::Ident(ident) => Value::Keyword(ident),
+            Token::Function(name) => {
+                if is_math_function(&name) {
+                    if let Some(calc) = self.parse_calc(&name) {
+                        return Value::Calc(Box::new(calc.simplify()));
+                    }
+                }
+                let (arguments, _) = self.parse_block(Self::parse_arguments);
+                return function_value(name, arguments);
+            }
+            // '/' separates e.g. the font size from the line height in
+            // `font`, and ',' the entries of a list like `font-family`.
+            Token::Delim('/') => Value::Keyword("/".to_string()),
+            Token::Comma => Value::Keyword(",".to_string()),
+            // Anything else is kept as written, which no property grammar
+            // accepts.
+            _ => {
+                let start = self.tokens[self.index].1.start.offset;
+                let end = self.tokens[self.block_ends[self.index]].1.end.offset;
+                Value::Keyword(self.input[start..end].to_string())
+            }
+        };
+        self.consume_component_value();
+        value
+    }
+
+    fn parse_arguments(&mut self) -> Vec<Value> {
+        let mut arguments = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.at_end() {
+                break;
+            }
+            let end = self.find(|token| *token == Token::Comma);
+            arguments.push(self.parse_until(end, Self::parse_value));
+            self.eat(&Token::Comma);
+        }
+        arguments
+    }
+
+    // Parses the math function the cursor is on as a calculation. When its
+    // arguments are not one, the cursor is left where it was, and the
+    // function is kept as written, which no property grammar accepts.
+    fn parse_calc(&mut self, name: &str) -> Option<Calc> {
+        let start = self.index;
+        let last_end = self.last_end;
+        let name = name.to_ascii_lowercase();
+        let (calc, _) = self.parse_block(|parser| parser.parse_math_arguments(&name));
+        if calc.is_none() {
+            self.index = start;
+            self.last_end = last_end;
+        }
+        calc
+    }
+
+    fn parse_math_arguments(&mut self, name: &str) -> Option<Calc> {
+        let mut arguments = Vec::new();
+        loop {
+            let end = self.find(|token| *token == Token::Comma);
+            arguments.push(self.parse_until(end, Self::parse_calc_sum)?);
+            if !self.eat(&Token::Comma) {
+                break;
+            }
+        }
+        let calc = match (name, arguments.len()) {
+            ("calc", 1) => arguments.remove(0),
+            ("min", _) => Calc::Min(arguments),
+            ("max", _) => Calc::Max(arguments),
+            ("clamp", 3) => {
+                let max = arguments.pop()?;
+                let value = arguments.pop()?;
+                let min = arguments.pop()?;
+                Calc::Clamp(Box::new(min), Box::new(value), Box::new(max))
+            }
+            _ => return None,
+        };
+        calc.kind().map(|_| calc)
+    }
+
+    // A sum takes up everything up to the limit. The '+' and '-' between its
+    // terms need whitespace on both sides, or `1px -2px` would be a sum.
+    fn parse_calc_sum(&mut self) -> Option<Calc> {
+        self.skip_whitespace();
+        let mut terms = vec![self.parse_calc_product()?];
+        loop {
+            let space_before = self.skip_whitespace();
+            if self.at_end() {
+                break;
+            }
+            let negate = match self.peek() {
+                Token::Delim('+') => false,
+                Token::Delim('-') => true,
+                _ => return None,
+            };
+            self.consume_component_value();
+            if !space_before || !self.skip_whitespace() {
+                return None;
+            }
+            let term = self.parse_calc_product()?;
+            terms.push(if negate {
+                Calc::Negate(Box::new(term))
+            } else {
+                term
+            });
+        }
+        Some(if terms.len() == 1 {
+            terms.remove(0)
+        } else {
+            Calc::Sum(terms)
+        })
+    }
+
+    fn parse_calc_product(&mut self) -> Option<Calc> {
+        let mut factors = vec![self.parse_calc_value()?];
+        loop {
+            let start = self.index;
+            self.skip_whitespace();
+            let invert = match self.peek() {
+                Token::Delim('*') => false,
+                Token::Delim('/') => true,
+                _ => {
+                    // Leave the whitespace for the sum to see.
+                    self.index = start;
+                    break;
+                }
+            };
+            self.consume_component_value();
+            self.skip_whitespace();
+            let factor = self.parse_calc_value()?;
+            factors.push(if invert {
+                Calc::Invert(Box::new(factor))
+            } else {
+                factor
+            });
+        }
+        Some(if factors.len() == 1 {
+            factors.remove(0)
+        } else {
+            Calc::Product(factors)
+        })
+    }
+
+    fn parse_calc_value(&mut self) -> Option<Calc> {
+        let calc = match self.peek().clone() {
+            Token::Number { value, .. } => Calc::Number(value),
+            Token::Percentage(value) => Calc::Percentage(value),
+            Token::Dimension { value, unit, .. } => Calc::Dimension(value, unit.parse().ok()?),
+            Token::Ident(ident) if ident.eq_ignore_ascii_case("e") => {
+                Calc::Number(std::f32::consts::E)
+            }
+            Token::Ident(ident) if ident.eq_ignore_ascii_case("pi") => {
+                Calc::Number(std::f32::consts::PI)
+            }
+            Token::OpenParen => return self.parse_block(Self::parse_calc_sum).0,
+            Token::Function(name) if is_math_function(&name) => {
+                let name = name.to_ascii_lowercase();
+                return self
+                    .parse_block(|parser| parser.parse_math_arguments(&name))
+                    .0;
+            }
+            _ => return None,
+        };
+        self.consume_component_value();
+        Some(calc)
     }
 
-    fn next_char(&self) -> char {
-        self.input[self.position..].chars().next().unwrap_or('\0')
+    // The cursor is on the at-keyword.
+    fn parse_at_rule(&mut self) -> Result<Rule, Diagnostic> {
+        let start = self.current_position();
+        let Token::AtKeyword(name) = self.peek().clone() else {
+            unreachable!("parse_at_rule called without an at-keyword");
+        };
+        self.consume_component_value();
+
+        match name.to_ascii_lowercase().as_str() {
+            "media" => {
+                self.skip_whitespace();
+                let prelude_end = self.find(|token| *token == Token::OpenCurly);
+                if self.index == prelude_end {
+                    self.skip_at_rule();
+                    return Err(self.error_from(
+                        start,
+                        DiagnosticCode::InvalidAtRule,
+                        "Empty media query",
+                    ));
+                }
+                let query = self.parse_until(prelude_end, Self::parse_media_queries);
+                let rules = self.parse_group_rule_body(start)?;
+                Ok(Rule::Media {
+                    query,
+                    rules,
+                    span: self.span_from(start),
+                })
+            }
+            "keyframes" => {
+                self.skip_whitespace();
+                let name = match self.peek().clone() {
+                    Token::Ident(name) | Token::String(name) => name,
+                    _ => {
+                        self.skip_at_rule();
+                        return Err(self.error_from(
+                            start,
+                            DiagnosticCode::InvalidAtRule,
+                            "Expected a name for @keyframes",
+                        ));
+                    }
+                };
+                self.consume_component_value();
+                self.skip_whitespace();
+                self.expect_block(start)?;
+                let block_start = self.current_position();
+                let (frames, closed) = self.parse_block(Self::parse_keyframe_rules);
+                if !closed {
+                    self.unclosed_block(block_start);
+                }
+                Ok(Rule::Keyframes {
+                    name,
+                    frames,
+                    span: self.span_from(start),
+                })
+            }
+            "import" => self.parse_import_rule(start),
+            "namespace" => self.parse_namespace_rule(start),
+            "font-face" => {
+                self.skip_whitespace();
+                self.expect_block(start)?;
+                let block_start = self.current_position();
+                let (descriptors, closed) = self.parse_block(Self::parse_descriptors);
+                if !closed {
+                    self.unclosed_block(block_start);
+                }
+                Ok(Rule::FontFace {
+                    descriptors,
+                    span: self.span_from(start),
+                })
+            }
+            "supports" => {
+                self.skip_whitespace();
+                let prelude_end = self.find(|token| *token == Token::OpenCurly);
+                let condition = self.parse_until(prelude_end, |parser| {
+                    let condition = parser.parse_supports_condition()?;
+                    parser.skip_whitespace();
+                    parser.at_end().then_some(condition)
+                });
+                let Some(condition) = condition else {
+                    self.skip_at_rule();
+                    return Err(self.error_from(
+                        start,
+                        DiagnosticCode::InvalidAtRule,
+                        "Invalid @supports condition",
+                    ));
+                };
+                let rules = self.parse_group_rule_body(start)?;
+                Ok(Rule::Supports {
+                    condition,
+                    rules,
+                    span: self.span_from(start),
+                })
+            }
+            "layer" => {
+                self.skip_whitespace();
+                let prelude_end =
+                    self.find(|token| matches!(token, Token::Semicolon | Token::OpenCurly));
+                let names = self.parse_until(prelude_end, Self::parse_layer_names);
+                let block = *self.peek() == Token::OpenCurly;
+                match names {
+                    Some(mut names) if block && names.len() <= 1 => {
+                        let rules = self.parse_group_rule_body(start)?;
+                        Ok(Rule::Layer {
+                            name: names.pop(),
+                            rules,
+                            span: self.span_from(start),
+                        })
+                    }
+                    Some(names) if !block && !names.is_empty() => {
+                        let span = self.end_statement(start)?;
+                        Ok(Rule::LayerStatement { names, span })
+                    }
+                    _ => {
+                        self.skip_at_rule();
+                        Err(self.error_from(
+                            start,
+                            DiagnosticCode::InvalidAtRule,
+                            "Invalid layer name",
+                        ))
+                    }
+                }
+            }
+            "page" => {
+                let prelude_end = self.find(|token| *token == Token::OpenCurly);
+                let selector = self.prelude_text(prelude_end).to_string();
+                self.expect_block(start)?;
+                let block_start = self.current_position();
+                let (declarations, closed) = self.parse_block(Self::parse_declarations);
+                if !closed {
+                    self.unclosed_block(block_start);
+                }
+                Ok(Rule::Page {
+                    selector,
+                    declarations,
+                    span: self.span_from(start),
+                })
+            }
+            "container" => {
+                self.skip_whitespace();
+                let prelude_end = self.find(|token| *token == Token::OpenCurly);
+                let prelude = self.parse_until(prelude_end, Self::parse_container_prelude);
+                let Some((name, condition)) = prelude else {
+                    self.skip_at_rule();
+                    return Err(self.error_from(
+                        start,
+                        DiagnosticCode::InvalidAtRule,
+                        "Invalid @container condition",
+                    ));
+                };
+                let rules = self.parse_group_rule_body(start)?;
+                Ok(Rule::Container {
+                    name,
+                    condition,
+                    rules,
+                    span: self.span_from(start),
+                })
+            }
+            "charset" => {
+                self.skip_at_rule();
+                Err(self.error_from(
+                    start,
+                    DiagnosticCode::InvalidAtRule,
+                    "@charset is only allowed at the start of a stylesheet",
+                ))
+            }
+            _ => {
+                self.skip_at_rule();
+                let span = self.span_from(start);
+                Err(Diagnostic::warning(
+                    DiagnosticCode::UnsupportedAtRule,
+                    format!("Skipped unsupported at-rule @{}", name),
+                    span,
+                ))
+            }
+        }
+    }
+
+    // A query that does not parse becomes `not all`, and the list goes on
+    // with the next one.
+    fn parse_media_queries(&mut self) -> MediaQueryList {
+        let mut queries = Vec::new();
+        self.skip_whitespace();
+        if self.at_end() {
+            return MediaQueryList { queries };
+        }
+
+        loop {
+            self.skip_whitespace();
+            let start = self.current_position();
+            let start_index = self.index;
+            let end = self.find(|token| *token == Token::Comma);
+            let query = self.parse_until(end, |parser| {
+                let query = parser.parse_media_query()?;
+                parser.skip_whitespace();
+                parser.at_end().then_some(query)
+            });
+            match query {
+                Some(query) => queries.push(query),
+                None => {
+                    self.index = start_index;
+                    self.diagnostics.push(Diagnostic::warning(
+                        DiagnosticCode::InvalidMediaQuery,
+                        "Invalid media query, treated as 'not all'",
+                        self.span_until(start, end),
+                    ));
+                    self.skip_to(end);
+                    queries.push(MediaQuery::not_all());
+                }
+            }
+            if !self.eat(&Token::Comma) {
+                return MediaQueryList { queries };
+            }
+        }
     }
 
-    fn eof(&self) -> bool {
-        self.position >= self.input.len()
+    fn parse_media_query(&mut self) -> Option<MediaQuery> {
+        let start = self.index;
+        if let Some(condition) = self.parse_media_condition(true) {
+            return Some(MediaQuery {
+                qualifier: None,
+                media_type: None,
+                condition: Some(condition),
+            });
+        }
+        self.index = start;
+
+        let mut media_type = self.parse_keyword()?;
+        let qualifier = match media_type.as_str() {
+            "not" => Some(Qualifier::Not),
+            "only" => Some(Qualifier::Only),
+            _ => None,
+        };
+        if qualifier.is_some() {
+            self.skip_whitespace();
+            media_type = self.parse_keyword()?;
+        }
+        if matches!(media_type.as_str(), "not" | "only" | "and" | "or" | "layer") {
+            return None;
+        }
+
+        let before_and = self.index;
+        self.skip_whitespace();
+        let condition = if self.parse_keyword().as_deref() == Some("and") {
+            Some(self.parse_media_condition(false)?)
+        } else {
+            self.index = before_and;
+            None
+        };
+        Some(MediaQuery {
+            qualifier,
+            media_type: Some(media_type),
+            condition,
+        })
+    }
+
+    // Consumes an identifier, lowercased.
+    fn parse_keyword(&mut self) -> Option<String> {
+        let Token::Ident(ident) = self.peek() else {
+            return None;
+        };
+        let ident = ident.to_ascii_lowercase();
+        self.consume_component_value();
+        Some(ident)
+    }
+
+    // Conditions joined by `and`, or by `or` where it is allowed, as one
+    // operator may not follow the other without parentheses.
+    fn parse_media_condition(&mut self, allow_or: bool) -> Option<MediaCondition> {
+        self.skip_whitespace();
+        let start = self.index;
+        if self.parse_keyword().as_deref() == Some("not") {
+            self.skip_whitespace();
+            let condition = self.parse_media_in_parens()?;
+            return Some(MediaCondition::Not(Box::new(condition)));
+        }
+        self.index = start;
+
+        let mut conditions = vec![self.parse_media_in_parens()?];
+        let mut operator: Option<String> = None;
+        loop {
+            let before = self.index;
+            self.skip_whitespace();
+            let word = self.parse_keyword();
+            let joins = match word.as_deref() {
+                Some("and") => true,
+                Some("or") => allow_or,
+                _ => false,
+            };
+            if !joins {
+                self.index = before;
+                break;
+            }
+            if operator.is_some() && operator != word {
+                return None;
+            }
+            operator = word;
+            self.skip_whitespace();
+            conditions.push(self.parse_media_in_parens()?);
+        }
+
+        Some(match operator.as_deref() {
+            None => conditions.remove(0),
+            Some("and") => MediaCondition::And(conditions),
+            _ => MediaCondition::Or(conditions),
+        })
+    }
+
+    // A parenthesized condition or feature. Anything else in parentheses,
+    // or in a function, is kept as written, to be evaluated as unknown.
+    fn parse_media_in_parens(&mut self) -> Option<MediaCondition> {
+        let start = self.index;
+        match self.peek() {
+            Token::OpenParen => {
+                let (condition, _) = self.parse_block(|parser| {
+                    let inside = parser.index;
+                    if let Some(condition) = parser.parse_media_condition(true) {
+                        parser.skip_whitespace();
+                        if parser.at_end() {
+                            return Some(condition);
+                        }
+                    }
+                    parser.index = inside;
+                    let feature = parser.parse_media_feature()?;
+                    parser.skip_whitespace();
+                    parser.at_end().then_some(MediaCondition::Feature(feature))
+                });
+                Some(condition.unwrap_or_else(|| self.general_enclosed(start)))
+            }
+            Token::Function(_) => {
+                self.consume_component_value();
+                Some(self.general_enclosed(start))
+            }
+            _ => None,
+        }
+    }
+
+    fn general_enclosed(&self, start: usize) -> MediaCondition {
+        let start = self.tokens[start].1.start.offset;
+        let text = &self.input[start..self.last_end.offset.max(start)];
+        MediaCondition::GeneralEnclosed(text.to_string())
+    }
+
+    // The inside of `(name)`, `(name: value)` or a range like
+    // `(400px <= width < 800px)`.
+    fn parse_media_feature(&mut self) -> Option<MediaFeature> {
+        self.skip_whitespace();
+        let start = self.index;
+        if let Some(name) = self.parse_keyword() {
+            self.skip_whitespace();
+            if self.at_end() {
+                return Some(MediaFeature::Boolean(name));
+            }
+            if self.eat(&Token::Colon) {
+                let value = self.parse_media_value()?;
+                return Some(MediaFeature::Plain(name, value));
+            }
+            self.index = start;
+        }
+
+        // The feature is the one side that is a name; the values are
+        // numeric.
+        let first = self.parse_range_operand()?;
+        let first_comparison = self.parse_comparison()?;
+        let second = self.parse_range_operand()?;
+        self.skip_whitespace();
+        if self.at_end() {
+            return match (first, second) {
+                (RangeOperand::Name(name), RangeOperand::Value(value)) => {
+                    Some(MediaFeature::Range(name, vec![(first_comparison, value)]))
+                }
+                (RangeOperand::Value(value), RangeOperand::Name(name)) => Some(
+                    MediaFeature::Range(name, vec![(first_comparison.flip(), value)]),
+                ),
+                _ => None,
+            };
+        }
+
+        let second_comparison = self.parse_comparison()?;
+        let third = self.parse_range_operand()?;
+        let (RangeOperand::Value(low), RangeOperand::Name(name), RangeOperand::Value(high)) =
+            (first, second, third)
+        else {
+            return None;
+        };
+        let same_direction = match first_comparison {
+            Comparison::Less | Comparison::LessOrEqual => matches!(
+                second_comparison,
+                Comparison::Less | Comparison::LessOrEqual
+            ),
+            Comparison::Greater | Comparison::GreaterOrEqual => matches!(
+                second_comparison,
+                Comparison::Greater | Comparison::GreaterOrEqual
+            ),
+            Comparison::Equal => false,
+        };
+        same_direction.then(|| {
+            MediaFeature::Range(
+                name,
+                vec![(first_comparison.flip(), low), (second_comparison, high)],
+            )
+        })
+    }
+
+    fn parse_range_operand(&mut self) -> Option<RangeOperand> {
+        self.skip_whitespace();
+        match self.parse_keyword() {
+            Some(name) => Some(RangeOperand::Name(name)),
+            None => self.parse_media_value().map(RangeOperand::Value),
+        }
+    }
+
+    fn parse_comparison(&mut self) -> Option<Comparison> {
+        self.skip_whitespace();
+        let comparison = match self.peek() {
+            Token::Delim('<') => Comparison::Less,
+            Token::Delim('>') => Comparison::Greater,
+            Token::Delim('=') => Comparison::Equal,
+            _ => return None,
+        };
+        self.consume_component_value();
+        if comparison == Comparison::Equal {
+            return Some(comparison);
+        }
+        // `<=` and `>=` are two tokens, with nothing between them.
+        Some(match (comparison, self.eat(&Token::Delim('='))) {
+            (Comparison::Less, true) => Comparison::LessOrEqual,
+            (Comparison::Greater, true) => Comparison::GreaterOrEqual,
+            (comparison, _) => comparison,
+        })
     }
+
+    // A number, a ratio like `16/9`, a dimension, a keyword or a math
+    // function. Resolutions may use `x` for `dppx`.
+    fn parse_media_value(&mut self) -> Option<Value> {
+        self.skip_whitespace();
+        let value = match self.peek().clone() {
+            Token::Number { value, .. } => {
+                self.consume_component_value();
+                let before = self.index;
+                self.skip_whitespace();
+                if !self.eat(&Token::Delim('/')) {
+                    self.index = before;
+                    return Some(Value::Number(value));
+                }
+                self.skip_whitespace();
+                let Token::Number {
+                    value: denominator, ..
+                } = *self.peek()
+                else {
+                    return None;
+                };
+                Value::List(vec![
+                    Value::Number(value),
+                    Value::Keyword("/".to_string()),
+                    Value::Number(denominator),
+                ])
+            }
+            Token::Dimension { value, unit, .. } => {
+                let unit = if unit.eq_ignore_ascii_case("x") {
+                    Unit::Dppx
+                } else {
+                    unit.parse().ok()?
+                };
+                Value::Length(value, unit)
+            }
+            Token::Ident(ident) => Value::Keyword(ident.to_ascii_lowercase()),
+            Token::Function(name) if is_math_function(&name) => {
+                let calc = self.parse_calc(&name)?;
+                return Some(Value::Calc(Box::new(calc.simplify())));
+            }
+            _ => return None,
+        };
+        self.consume_component_value();
+        Some(value)
+    }
+
+    // `@import url [layer | layer(name)] [supports(condition)] [media];`
+    fn parse_import_rule(&mut self, start: Position) -> Result<Rule, Diagnostic> {
+        self.skip_whitespace();
+        let Some(url) = self.parse_url() else {
+            self.skip_at_rule();
+            return Err(self.error_from(
+                start,
+                DiagnosticCode::InvalidAtRule,
+                "Expected a URL for @import",
+            ));
+        };
+
+        self.skip_whitespace();
+        let layer = match self.peek().clone() {
+            Token::Ident(ident) if ident.eq_ignore_ascii_case("layer") => {
+                self.consume_component_value();
+                Some(None)
+            }
+            Token::Function(name) if name.eq_ignore_ascii_case("layer") => {
+                let (name, _) = self.parse_block(|parser| {
+                    parser.skip_whitespace();
+                    let name = parser.parse_layer_name()?;
+                    parser.skip_whitespace();
+                    parser.at_end().then_some(name)
+                });
+                let Some(name) = name else {
+                    self.skip_at_rule();
+                    return Err(self.error_from(
+                        start,
+                        DiagnosticCode::InvalidAtRule,
+                        "Invalid layer name",
+                    ));
+                };
+                Some(Some(name))
+            }
+            _ => None,
+        };
+
+        self.skip_whitespace();
+        let supports = match self.peek().clone() {
+            Token::Function(name) if name.eq_ignore_ascii_case("supports") => {
+                let (condition, _) = self.parse_block(|parser| {
+                    let inside = parser.index;
+                    if let Some(condition) = parser.parse_supports_condition() {
+                        parser.skip_whitespace();
+                        if parser.at_end() {
+                            return Some(condition);
+                        }
+                    }
+                    parser.index = inside;
+                    parser.parse_supports_declaration()
+                });
+                let Some(condition) = condition else {
+                    self.skip_at_rule();
+                    return Err(self.error_from(
+                        start,
+                        DiagnosticCode::InvalidAtRule,
+                        "Invalid supports() condition",
+                    ));
+                };
+                Some(condition)
+            }
+            _ => None,
+        };
+
+        let prelude_end = self.find(|token| matches!(token, Token::Semicolon | Token::OpenCurly));
+        let media = self.parse_until(prelude_end, Self::parse_media_queries);
+        let span = self.end_statement(start)?;
+        Ok(Rule::Import {
+            url,
+            layer,
+            supports,
+            media,
+            span,
+        })
+    }
+
+    // `@namespace [prefix] url;`
+    fn parse_namespace_rule(&mut self, start: Position) -> Result<Rule, Diagnostic> {
+        self.skip_whitespace();
+        let prefix = match self.peek().clone() {
+            Token::Ident(prefix) => {
+                self.consume_component_value();
+                self.skip_whitespace();
+                Some(prefix)
+            }
+            _ => None,
+        };
+        let Some(url) = self.parse_url() else {
+            self.skip_at_rule();
+            return Err(self.error_from(
+                start,
+                DiagnosticCode::InvalidAtRule,
+                "Expected a URL for @namespace",
+            ));
+        };
+        self.skip_whitespace();
+        let span = self.end_statement(start)?;
+        Ok(Rule::Namespace { prefix, url, span })
+    }
+
+    // A string, or `url()` with or without quotes.
+    fn parse_url(&mut self) -> Option<String> {
+        let url = match self.peek().clone() {
+            Token::String(url) | Token::Url(url) => url,
+            Token::Function(name) if name.eq_ignore_ascii_case("url") => {
+                let (url, _) = self.parse_block(|parser| {
+                    parser.skip_whitespace();
+                    let Token::String(url) = parser.peek().clone() else {
+                        return None;
+                    };
+                    parser.consume_component_value();
+                    parser.skip_whitespace();
+                    parser.at_end().then_some(url)
+                });
+                return url;
+            }
+            _ => return None,
+        };
+        self.consume_component_value();
+        Some(url)
+    }
+
+    // Comma-separated layer names, or none.
+    fn parse_layer_names(&mut self) -> Option<Vec<String>> {
+        let mut names = Vec::new();
+        self.skip_whitespace();
+        if self.at_end() {
+            return Some(names);
+        }
+        loop {
+            self.skip_whitespace();
+            names.push(self.parse_layer_name()?);
+            self.skip_whitespace();
+            if self.at_end() {
+                return Some(names);
+            }
+            if !self.eat(&Token::Comma) {
+                return None;
+            }
+        }
+    }
+
+    // Identifiers joined by '.', with nothing between them, like
+    // `framework.base`. CSS-wide keywords are reserved.
+    fn parse_layer_name(&mut self) -> Option<String> {
+        let mut name = String::new();
+        loop {
+            let Token::Ident(ident) = self.peek().clone() else {
+                return None;
+            };
+            if matches!(
+                ident.to_ascii_lowercase().as_str(),
+                "initial" | "inherit" | "unset" | "revert" | "revert-layer"
+            ) {
+                return None;
+            }
+            self.consume_component_value();
+            name.push_str(&ident);
+            if !self.eat(&Token::Delim('.')) {
+                return Some(name);
+            }
+            name.push('.');
+        }
+    }
+
+    // An optional container name, then a condition written like a media
+    // condition. One of them is required.
+    fn parse_container_prelude(&mut self) -> Option<(Option<String>, Option<MediaCondition>)> {
+        let name = match self.peek().clone() {
+            Token::Ident(name)
+                if !matches!(
+                    name.to_ascii_lowercase().as_str(),
+                    "none" | "not" | "and" | "or"
+                ) =>
+            {
+                self.consume_component_value();
+                Some(name)
+            }
+            _ => None,
+        };
+        self.skip_whitespace();
+        if self.at_end() {
+            return name.is_some().then_some((name, None));
+        }
+        let condition = self.parse_media_condition(true)?;
+        self.skip_whitespace();
+        self.at_end().then_some((name, Some(condition)))
+    }
+
+    // Conditions joined by `and` or by `or`, as in a media condition.
+    fn parse_supports_condition(&mut self) -> Option<SupportsCondition> {
+        self.skip_whitespace();
+        let start = self.index;
+        if self.parse_keyword().as_deref() == Some("not") {
+            self.skip_whitespace();
+            let condition = self.parse_supports_in_parens()?;
+            return Some(SupportsCondition::Not(Box::new(condition)));
+        }
+        self.index = start;
+
+        let mut conditions = vec![self.parse_supports_in_parens()?];
+        let mut operator: Option<String> = None;
+        loop {
+            let before = self.index;
+            self.skip_whitespace();
+            let word = self.parse_keyword();
+            if !matches!(word.as_deref(), Some("and" | "or")) {
+                self.index = before;
+                break;
+            }
+            if operator.is_some() && operator != word {
+                return None;
+            }
+            operator = word;
+            self.skip_whitespace();
+            conditions.push(self.parse_supports_in_parens()?);
+        }
+
+        Some(match operator.as_deref() {
+            None => conditions.remove(0),
+            Some("and") => SupportsCondition::And(conditions),
+            _ => SupportsCondition::Or(conditions),
+        })
+    }
+
+    fn parse_supports_in_parens(&mut self) -> Option<SupportsCondition> {
+        let start = self.index;
+        let condition = match self.peek().clone() {
+            Token::OpenParen => {
+                self.parse_block(|parser| {
+                    let inside = parser.index;
+                    if let Some(condition) = parser.parse_supports_condition() {
+                        parser.skip_whitespace();
+                        if parser.at_end() {
+                            return Some(condition);
+                        }
+                    }
+                    parser.index = inside;
+                    parser.parse_supports_declaration()
+                })
+                .0
+            }
+            Token::Function(name) if name.eq_ignore_ascii_case("selector") => {
+                self.parse_block(|parser| {
+                    let selectors = parser.prelude_text(parser.limit);
+                    Some(SupportsCondition::Selector(selectors.to_string()))
+                })
+                .0
+            }
+            Token::Function(_) => {
+                self.consume_component_value();
+                None
+            }
+            _ => return None,
+        };
+        Some(condition.unwrap_or_else(|| {
+            let start = self.tokens[start].1.start.offset;
+            let text = &self.input[start..self.last_end.offset.max(start)];
+            SupportsCondition::GeneralEnclosed(text.to_string())
+        }))
+    }
+
+    // `property: value`, up to the limit.
+    fn parse_supports_declaration(&mut self) -> Option<SupportsCondition> {
+        self.skip_whitespace();
+        let start = self.index;
+        self.parse_keyword()?;
+        self.skip_whitespace();
+        if *self.peek() != Token::Colon {
+            return None;
+        }
+        self.index = start;
+        let declaration = self.prelude_text(self.limit);
+        Some(SupportsCondition::Declaration(declaration.to_string()))
+    }
+
+    // The block of rules of a conditional or grouping at-rule, whose prelude
+    // ends here. Nested in a style rule, the block may hold declarations
+    // too, which apply as a rule with the selector `&`.
+    fn parse_group_rule_body(&mut self, start: Position) -> Result<Vec<Rule>, Diagnostic> {
+        self.expect_block(start)?;
+        let block_start = self.current_position();
+        let (rules, closed) = if self.nested {
+            let ((declarations, rules), closed) = self.parse_block(Self::parse_style_block);
+            let declarations = nested_declarations(declarations);
+            (declarations.into_iter().chain(rules).collect(), closed)
+        } else {
+            self.parse_block(|parser| parser.parse_rule_list(false))
+        };
+        if !closed {
+            self.unclosed_block(block_start);
+        }
+        Ok(rules)
+    }
+
+    // Ends an at-rule without a block, like `@import`, at its ';' or the end
+    // of the input. The span leaves out the ';'.
+    fn end_statement(&mut self, start: Position) -> Result<Span, Diagnostic> {
+        let span = self.span_from(start);
+        match self.peek() {
+            Token::Semicolon => {
+                self.consume_component_value();
+                Ok(span)
+            }
+            Token::EOF => Ok(span),
+            _ => {
+                self.skip_at_rule();
+                Err(self.error_from(start, DiagnosticCode::InvalidAtRule, "Expected ';'"))
+            }
+        }
+    }
+
+    // Checks that the prelude of an at-rule ends here with a '{'. `start` is
+    // where the at-rule began, which the error covers.
+    fn expect_block(&mut self, start: Position) -> Result<(), Diagnostic> {
+        if *self.peek() == Token::OpenCurly {
+            return Ok(());
+        }
+        self.skip_at_rule();
+        Err(self.error_from(start, DiagnosticCode::InvalidAtRule, "Expected '{'"))
+    }
+
+    // Consumes the rest of an at-rule: its prelude, then a block or ';'.
+    fn skip_at_rule(&mut self) {
+        let end = self.find(|token| matches!(token, Token::Semicolon | Token::OpenCurly));
+        self.skip_to(end);
+        self.consume_component_value();
+    }
+
+    // The source text of the tokens up to `end`, without surrounding
+    // whitespace. The tokens are consumed.
+    fn prelude_text(&mut self, end: usize) -> &'a str {
+        self.skip_whitespace();
+        let start = self.tokens[self.index].1.start.offset;
+        self.skip_to(end);
+        let end = self.last_end.offset.max(start);
+        &self.input[start..end]
+    }
+
+    fn parse_keyframe_rules(&mut self) -> Vec<Keyframe> {
+        let mut frames = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+            if self.at_end() {
+                break;
+            }
+            let start = self.current_position();
+            let prelude_end = self.find(|token| *token == Token::OpenCurly);
+            let selectors = self.parse_until(prelude_end, Self::parse_keyframe_selectors);
+            self.skip_to(prelude_end);
+            if self.at_end() {
+                let diagnostic = self.error_from(
+                    start,
+                    DiagnosticCode::InvalidDeclaration,
+                    "Expected '{' for declarations block",
+                );
+                self.diagnostics.push(diagnostic);
+                break;
+            }
+            let Some(selectors) = selectors else {
+                let diagnostic = self.error_from(
+                    start,
+                    DiagnosticCode::InvalidKeyframeSelector,
+                    "Invalid keyframe selector",
+                );
+                self.diagnostics.push(diagnostic);
+                self.consume_component_value();
+                continue;
+            };
+
+            let block_start = self.current_position();
+            let (declarations, closed) = self.parse_block(Self::parse_declarations);
+            if !closed {
+                self.unclosed_block(block_start);
+            }
+            frames.push(Keyframe {
+                selectors,
+                declarations,
+                span: self.span_from(start),
+            });
+        }
+
+        frames
+    }
+
+    // `from`, `to` and percentages, as written.
+    fn parse_keyframe_selectors(&mut self) -> Option<Vec<String>> {
+        let mut selectors = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let valid = match self.peek() {
+                Token::Percentage(_) => true,
+                Token::Ident(ident) => {
+                    ident.eq_ignore_ascii_case("from") || ident.eq_ignore_ascii_case("to")
+                }
+                _ => false,
+            };
+            if !valid {
+                return None;
+            }
+            let span = self.tokens[self.index].1;
+            selectors.push(self.input[span.start.offset..span.end.offset].to_string());
+            self.consume_component_value();
+            self.skip_whitespace();
+            if !self.eat(&Token::Comma) {
+                return self.at_end().then_some(selectors);
+            }
+        }
+    }
+
+    // Parses the contents of the block the cursor is on with `parse`, and
+    // leaves the cursor after the block. The flag is false when the input
+    // ended before the block was closed.
+    fn parse_block<T>(&mut self, parse: impl FnOnce(&mut Self) -> T) -> (T, bool) {
+        let end = self.block_ends[self.index];
+        self.index += 1;
+        let result = self.parse_until(end, parse);
+        self.index = end;
+        let closed = self.tokens[end].0 != Token::EOF;
+        if closed {
+            self.last_end = self.tokens[end].1.end;
+            self.index += 1;
+        }
+        (result, closed)
+    }
+
+    // Runs `parse` with the tokens from `end` on reading as EOF.
+    fn parse_until<T>(&mut self, end: usize, parse: impl FnOnce(&mut Self) -> T) -> T {
+        let limit = std::mem::replace(&mut self.limit, end);
+        let result = parse(self);
+        self.limit = limit;
+        result
+    }
+
+    // The index of the first token from the cursor on, outside any block,
+    // that `stop` holds for, or the limit.
+    fn find(&self, stop: impl Fn(&Token) -> bool) -> usize {
+        let mut index = self.index;
+        while index < self.limit && !stop(&self.tokens[index].0) {
+            index = self.block_ends[index] + 1;
+        }
+        index.min(self.limit)
+    }
+
+    // Consumes a token, or a whole block when the token opens one.
+    fn consume_component_value(&mut self) {
+        if self.at_end() {
+            return;
+        }
+        let end = self.block_ends[self.index];
+        if self.tokens[self.index].0 != Token::Whitespace {
+            self.last_end = self.tokens[end].1.end;
+        }
+        self.index = (end + 1).min(self.limit);
+    }
+
+    fn skip_to(&mut self, end: usize) {
+        while self.index < end && !self.at_end() {
+            self.consume_component_value();
+        }
+    }
+
+    fn skip_to_comma(&mut self) {
+        let end = self.find(|token| *token == Token::Comma);
+        self.skip_to(end);
+    }
+
+    // Returns whether there was any whitespace.
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.index;
+        while *self.peek() == Token::Whitespace {
+            self.index += 1;
+        }
+        self.index > start
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == token {
+            self.consume_component_value();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn peek(&self) -> &Token {
+        if self.at_end() {
+            &Token::EOF
+        } else {
+            &self.tokens[self.index].0
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.index >= self.limit
+    }
+
+    // Where the next token starts.
+    fn current_position(&self) -> Position {
+        self.tokens[self.index.min(self.limit)].1.start
+    }
+
+    fn span_from(&self, start: Position) -> Span {
+        Span::new(start, self.last_end.max(start))
+    }
+
+    // The span from `start` to the end of the last non-whitespace token
+    // before `end`, which have not been consumed yet.
+    fn span_until(&self, start: Position, end: usize) -> Span {
+        let end = self.tokens[self.index..end]
+            .iter()
+            .rev()
+            .find(|(token, _)| *token != Token::Whitespace)
+            .map_or(self.last_end, |(_, span)| span.end);
+        Span::new(start, end.max(start))
+    }
+
+    fn error_from(
+        &self,
+        start: Position,
+        code: DiagnosticCode,
+        message: impl Into<String>,
+    ) -> Diagnostic {
+        Diagnostic::error(code, message, self.span_from(start))
+    }
+
+    // Covers the declaration up to the ';' it is skipped to.
+    fn invalid_declaration(&self, start: Position, end: usize, message: &str) -> Diagnostic {
+        Diagnostic::error(
+            DiagnosticCode::InvalidDeclaration,
+            message,
+            self.span_until(start, end),
+        )
+    }
+
+    fn unclosed_block(&mut self, start: Position) {
+        let end = self.tokens[self.tokens.len() - 1].1.end;
+        self.diagnostics.push(Diagnostic::error(
+            DiagnosticCode::UnclosedBlock,
+            "Unclosed block",
+            Span::new(start, end),
+        ));
+    }
+}
+
+// Makes a selector nested in a style rule relative to that rule, by
+// starting it with `&` unless it already contains one. A leading
+// combinator, as in `> img`, relates it to the `&`; otherwise it is a
+// descendant.
+fn relative_to_parent(mut selector: Selector) -> Selector {
+    if selector.compounds[0].combinator.is_none()
+        && selector.compounds.iter().any(|compound| compound.nesting)
+    {
+        return selector;
+    }
+    selector.compounds[0]
+        .combinator
+        .get_or_insert(Combinator::Descendant);
+    let mut parent = CompoundSelector::new();
+    parent.nesting = true;
+    selector.compounds.insert(0, parent);
+    selector
+}
+
+// The rule `& { ... }` that declarations among nested rules apply as, or
+// None without any.
+fn nested_declarations(declarations: Vec<Declaration>) -> Option<Rule> {
+    let (first, last) = (declarations.first()?, declarations.last()?);
+    let mut parent = CompoundSelector::new();
+    parent.nesting = true;
+    Some(Rule::Style(StyleRule {
+        selectors: vec![Selector {
+            compounds: vec![parent],
+        }],
+        span: Span::new(first.span.start, last.span.end),
+        declarations,
+        rules: Vec::new(),
+    }))
+}
+
+// One side of a comparison in a media feature.
+enum RangeOperand {
+    Name(String),
+    Value(Value),
+}
+
+// Pairs up the blocks of a token list for `CssParser::block_ends`. A closing
+// token only closes the innermost open block, and only if it matches it;
+// otherwise it is an ordinary token.
+fn block_ends(tokens: &[(Token, Span)]) -> Vec<usize> {
+    let eof = tokens.len() - 1;
+    let mut ends: Vec<usize> = (0..tokens.len()).collect();
+    let mut open: Vec<(usize, Token)> = Vec::new();
+
+    for (index, (token, _)) in tokens.iter().enumerate() {
+        if let Some(closing) = token.closing() {
+            open.push((index, closing));
+            ends[index] = eof;
+        } else if open.last().is_some_and(|(_, closing)| closing == token) {
+            let (start, _) = open.pop().unwrap();
+            ends[start] = index;
+        }
+    }
+
+    ends
+}
+
+// A quoted `url()`, color functions and `linear-gradient()` have values of
+// their own; other functions, including malformed color functions, keep
+// their arguments as parsed.
+fn function_value(name: String, arguments: Vec<Value>) -> Value {
+    match name.to_ascii_lowercase().as_str() {
+        "url" => {
+            if let [Value::String(url)] = arguments.as_slice() {
+                return Value::Url(url.clone());
+            }
+        }
+        "rgb" | "rgba" | "hsl" | "hsla" => {
+            if let Some(color) = Color::from_function(&name, &arguments) {
+                return Value::Color(color);
+            }
+        }
+        "linear-gradient" => {
+            if let Some(gradient) = LinearGradient::from_arguments(&arguments) {
+                return Value::LinearGradient(Box::new(gradient));
+            }
+        }
+        _ => {}
+    }
+    Value::Function(name, arguments)
+}
+
+// The color `#hex` stands for, or None unless it has 3, 4, 6 or 8 hex digits.
+fn parse_color(hex: &str) -> Option<Value> {
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let color = match hex.len() {
+        3 => Color {
+            r: u8::from_str_radix(&hex[0..1].repeat(2), 16).unwrap_or(0),
+            g: u8::from_str_radix(&hex[1..2].repeat(2), 16).unwrap_or(0),
+            b: u8::from_str_radix(&hex[2..3].repeat(2), 16).unwrap_or(0),
+            a: 1.0,
+        },
+        4 => Color {
+            r: u8::from_str_radix(&hex[0..1].repeat(2), 16).unwrap_or(0),
+            g: u8::from_str_radix(&hex[1..2].repeat(2), 16).unwrap_or(0),
+            b: u8::from_str_radix(&hex[2..3].repeat(2), 16).unwrap_or(0),
+            a: u8::from_str_radix(&hex[3..4].repeat(2), 16).unwrap_or(255) as f32 / 255.0,
+        },
+        6 => Color {
+            r: u8::from_str_radix(&hex[0..2], 16).unwrap_or(0),
+            g: u8::from_str_radix(&hex[2..4], 16).unwrap_or(0),
+            b: u8::from_str_radix(&hex[4..6], 16).unwrap_or(0),
+            a: 1.0,
+        },
+        8 => Color {
+            r: u8::from_str_radix(&hex[0..2], 16).unwrap_or(0),
+            g: u8::from_str_radix(&hex[2..4], 16).unwrap_or(0),
+            b: u8::from_str_radix(&hex[4..6], 16).unwrap_or(0),
+            a: u8::from_str_radix(&hex[6..8], 16).unwrap_or(255) as f32 / 255.0,
+        },
+        _ => return None,
+    };
+    Some(Value::Color(color))
 }
 
-fn valid_identifier_char(c: char) -> bool {
-    c.is_alphanumeric() || c == '-' || c == '_' || c as u32 > 0x7f
+// `initial`, `inherit` and `unset` are valid for every property, but only
+// as the whole value.
+fn css_wide_keyword(value: Value) -> Value {
+    match &value {
+        Value::Keyword(keyword) if keyword.eq_ignore_ascii_case("initial") => Value::Initial,
+        Value::Keyword(keyword) if keyword.eq_ignore_ascii_case("inherit") => Value::Inherit,
+        Value::Keyword(keyword) if keyword.eq_ignore_ascii_case("unset") => Value::Unset,
+        _ => value,
+    }
 }
@@ -1,4 +1,8 @@
+use super::parser::CssParser;
 use super::values::Value;
+use super::variables::is_custom_property;
+use std::collections::HashMap;
+use std::sync::OnceLock;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Property {
@@ -23,6 +27,18 @@ pub enum Property {
     BorderRight(Value),
     BorderBottom(Value),
     BorderLeft(Value),
+    BorderTopWidth(Value),
+    BorderRightWidth(Value),
+    BorderBottomWidth(Value),
+    BorderLeftWidth(Value),
+    BorderTopStyle(Value),
+    BorderRightStyle(Value),
+    BorderBottomStyle(Value),
+    BorderLeftStyle(Value),
+    BorderTopColor(Value),
+    BorderRightColor(Value),
+    BorderBottomColor(Value),
+    BorderLeftColor(Value),
     BorderWidth(Value),
     BorderStyle(Value),
     BorderColor(Value),
@@ -42,9 +58,11 @@ pub enum Property {
 
     // Typography
     Color(Value),
+    Font(Value),
     FontFamily(Value),
     FontSize(Value),
     FontStyle(Value),
+    FontVariant(Value),
     FontWeight(Value),
     LineHeight(Value),
     TextAlign(Value),
@@ -53,17 +71,27 @@ pub enum Property {
     LetterSpacing(Value),
     WordSpacing(Value),
     WhiteSpace(Value),
+    VerticalAlign(Value),
+
+    // Lists
+    ListStyle(Value),
+    ListStyleType(Value),
+    ListStylePosition(Value),
+    ListStyleImage(Value),
 
     // Visual
+    Background(Value),
     BackgroundColor(Value),
     BackgroundImage(Value),
     BackgroundPosition(Value),
     BackgroundRepeat(Value),
     BackgroundSize(Value),
+    BackgroundAttachment(Value),
     Opacity(Value),
     Visibility(Value),
 
     // Flexbox
+    Flex(Value),
     FlexDirection(Value),
     FlexWrap(Value),
     FlexGrow(Value),
@@ -84,6 +112,10 @@ pub enum Property {
 
     // Animation
     Transition(Value),
+    TransitionProperty(Value),
+    TransitionDuration(Value),
+    TransitionTimingFunction(Value),
+    TransitionDelay(Value),
     Animation(Value),
     AnimationName(Value),
     AnimationDuration(Value),
@@ -91,6 +123,8 @@ pub enum Property {
     AnimationDelay(Value),
     AnimationIterationCount(Value),
     AnimationDirection(Value),
+    AnimationFillMode(Value),
+    AnimationPlayState(Value),
 
     // Other
     Cursor(Value),
@@ -125,6 +159,18 @@ impl Property {
             Property::BorderRight(_) => "border-right",
             Property::BorderBottom(_) => "border-bottom",
             Property::BorderLeft(_) => "border-left",
+            Property::BorderTopWidth(_) => "border-top-width",
+            Property::BorderRightWidth(_) => "border-right-width",
+            Property::BorderBottomWidth(_) => "border-bottom-width",
+            Property::BorderLeftWidth(_) => "border-left-width",
+            Property::BorderTopStyle(_) => "border-top-style",
+            Property::BorderRightStyle(_) => "border-right-style",
+            Property::BorderBottomStyle(_) => "border-bottom-style",
+            Property::BorderLeftStyle(_) => "border-left-style",
+            Property::BorderTopColor(_) => "border-top-color",
+            Property::BorderRightColor(_) => "border-right-color",
+            Property::BorderBottomColor(_) => "border-bottom-color",
+            Property::BorderLeftColor(_) => "border-left-color",
             Property::BorderWidth(_) => "border-width",
             Property::BorderStyle(_) => "border-style",
             Property::BorderColor(_) => "border-color",
@@ -144,9 +190,11 @@ impl Property {
 
             // Typography
             Property::Color(_) => "color",
+            Property::Font(_) => "font",
             Property::FontFamily(_) => "font-family",
             Property::FontSize(_) => "font-size",
             Property::FontStyle(_) => "font-style",
+            Property::FontVariant(_) => "font-variant",
             Property::FontWeight(_) => "font-weight",
             Property::LineHeight(_) => "line-height",
             Property::TextAlign(_) => "text-align",
@@ -155,17 +203,27 @@ impl Property {
             Property::LetterSpacing(_) => "letter-spacing",
             Property::WordSpacing(_) => "word-spacing",
             Property::WhiteSpace(_) => "white-space",
+            Property::VerticalAlign(_) => "vertical-align",
+
+            // Lists
+            Property::ListStyle(_) => "list-style",
+            Property::ListStyleType(_) => "list-style-type",
+            Property::ListStylePosition(_) => "list-style-position",
+            Property::ListStyleImage(_) => "list-style-image",
 
             // Visual
+            Property::Background(_) => "background",
             Property::BackgroundColor(_) => "background-color",
             Property::BackgroundImage(_) => "background-image",
             Property::BackgroundPosition(_) => "background-position",
             Property::BackgroundRepeat(_) => "background-repeat",
             Property::BackgroundSize(_) => "background-size",
+            Property::BackgroundAttachment(_) => "background-attachment",
             Property::Opacity(_) => "opacity",
             Property::Visibility(_) => "visibility",
 
             // Flexbox
+            Property::Flex(_) => "flex",
             Property::FlexDirection(_) => "flex-direction",
             Property::FlexWrap(_) => "flex-wrap",
             Property::FlexGrow(_) => "flex-grow",
@@ -186,6 +244,10 @@ impl Property {
 
             // Animation
             Property::Transition(_) => "transition",
+            Property::TransitionProperty(_) => "transition-property",
+            Property::TransitionDuration(_) => "transition-duration",
+            Property::TransitionTimingFunction(_) => "transition-timing-function",
+            Property::TransitionDelay(_) => "transition-delay",
             Property::Animation(_) => "animation",
             Property::AnimationName(_) => "animation-name",
             Property::AnimationDuration(_) => "animation-duration",
@@ -193,6 +255,8 @@ impl Property {
             Property::AnimationDelay(_) => "animation-delay",
             Property::AnimationIterationCount(_) => "animation-iteration-count",
             Property::AnimationDirection(_) => "animation-direction",
+            Property::AnimationFillMode(_) => "animation-fill-mode",
+            Property::AnimationPlayState(_) => "animation-play-state",
 
             // Other
             Property::Cursor(_) => "cursor",
@@ -203,7 +267,15 @@ impl Property {
         }
     }
 
+    /// The declaration `name: value`, or None when the property is unknown
+    /// or the value doesn't match its grammar.
     pub fn parse(name: &str, value: Value) -> Option<Self> {
+        Property::validate(name, &value).ok()?;
+        Property::new(name, value)
+    }
+
+    // Like parse, without checking the value.
+    pub(super) fn new(name: &str, value: Value) -> Option<Self> {
         match name {
             // Box Model
             "width" => Some(Property::Width(value)),
@@ -227,6 +299,18 @@ impl Property {
             "border-right" => Some(Property::BorderRight(value)),
             "border-bottom" => Some(Property::BorderBottom(value)),
             "border-left" => Some(Property::BorderLeft(value)),
+            "border-top-width" => Some(Property::BorderTopWidth(value)),
+            "border-right-width" => Some(Property::BorderRightWidth(value)),
+            "border-bottom-width" => Some(Property::BorderBottomWidth(value)),
+            "border-left-width" => Some(Property::BorderLeftWidth(value)),
+            "border-top-style" => Some(Property::BorderTopStyle(value)),
+            "border-right-style" => Some(Property::BorderRightStyle(value)),
+            "border-bottom-style" => Some(Property::BorderBottomStyle(value)),
+            "border-left-style" => Some(Property::BorderLeftStyle(value)),
+            "border-top-color" => Some(Property::BorderTopColor(value)),
+            "border-right-color" => Some(Property::BorderRightColor(value)),
+            "border-bottom-color" => Some(Property::BorderBottomColor(value)),
+            "border-left-color" => Some(Property::BorderLeftColor(value)),
             "border-width" => Some(Property::BorderWidth(value)),
             "border-style" => Some(Property::BorderStyle(value)),
             "border-color" => Some(Property::BorderColor(value)),
@@ -246,9 +330,11 @@ impl Property {
 
             // Typography
             "color" => Some(Property::Color(value)),
+            "font" => Some(Property::Font(value)),
             "font-family" => Some(Property::FontFamily(value)),
             "font-size" => Some(Property::FontSize(value)),
             "font-style" => Some(Property::FontStyle(value)),
+            "font-variant" => Some(Property::FontVariant(value)),
             "font-weight" => Some(Property::FontWeight(value)),
             "line-height" => Some(Property::LineHeight(value)),
             "text-align" => Some(Property::TextAlign(value)),
@@ -257,17 +343,27 @@ impl Property {
             "letter-spacing" => Some(Property::LetterSpacing(value)),
             "word-spacing" => Some(Property::WordSpacing(value)),
             "white-space" => Some(Property::WhiteSpace(value)),
+            "vertical-align" => Some(Property::VerticalAlign(value)),
+
+            // Lists
+            "list-style" => Some(Property::ListStyle(value)),
+            "list-style-type" => Some(Property::ListStyleType(value)),
+            "list-style-position" => Some(Property::ListStylePosition(value)),
+            "list-style-image" => Some(Property::ListStyleImage(value)),
 
             // Visual
+            "background" => Some(Property::Background(value)),
             "background-color" => Some(Property::BackgroundColor(value)),
             "background-image" => Some(Property::BackgroundImage(value)),
             "background-position" => Some(Property::BackgroundPosition(value)),
             "background-repeat" => Some(Property::BackgroundRepeat(value)),
             "background-size" => Some(Property::BackgroundSize(value)),
+            "background-attachment" => Some(Property::BackgroundAttachment(value)),
             "opacity" => Some(Property::Opacity(value)),
             "visibility" => Some(Property::Visibility(value)),
 
             // Flexbox
+            "flex" => Some(Property::Flex(value)),
             "flex-direction" => Some(Property::FlexDirection(value)),
             "flex-wrap" => Some(Property::FlexWrap(value)),
             "flex-grow" => Some(Property::FlexGrow(value)),
@@ -288,6 +384,10 @@ impl Property {
 
             // Animation
             "transition" => Some(Property::Transition(value)),
+            "transition-property" => Some(Property::TransitionProperty(value)),
+            "transition-duration" => Some(Property::TransitionDuration(value)),
+            "transition-timing-function" => Some(Property::TransitionTimingFunction(value)),
+            "transition-delay" => Some(Property::TransitionDelay(value)),
             "animation" => Some(Property::Animation(value)),
             "animation-name" => Some(Property::AnimationName(value)),
             "animation-duration" => Some(Property::AnimationDuration(value)),
@@ -295,6 +395,8 @@ impl Property {
             "animation-delay" => Some(Property::AnimationDelay(value)),
             "animation-iteration-count" => Some(Property::AnimationIterationCount(value)),
             "animation-direction" => Some(Property::AnimationDirection(value)),
+            "animation-fill-mode" => Some(Property::AnimationFillMode(value)),
+            "animation-play-state" => Some(Property::AnimationPlayState(value)),
 
             // Other
             "cursor" => Some(Property::Cursor(value)),
@@ -307,3 +409,272 @@ impl Property {
         }
     }
 }
+
+// Each property's initial value, as CSS text, and whether it is inherited.
+const DEFAULTS: &[(&str, &str, bool)] = &[
+    // Box Model
+    ("width", "auto", false),
+    ("height", "auto", false),
+    ("min-width", "auto", false),
+    ("min-height", "auto", false),
+    ("max-width", "none", false),
+    ("max-height", "none", false),
+    ("margin", "0", false),
+    ("margin-top", "0", false),
+    ("margin-right", "0", false),
+    ("margin-bottom", "0", false),
+    ("margin-left", "0", false),
+    ("padding", "0", false),
+    ("padding-top", "0", false),
+    ("padding-right", "0", false),
+    ("padding-bottom", "0", false),
+    ("padding-left", "0", false),
+    ("border", "medium none currentcolor", false),
+    ("border-top", "medium none currentcolor", false),
+    ("border-right", "medium none currentcolor", false),
+    ("border-bottom", "medium none currentcolor", false),
+    ("border-left", "medium none currentcolor", false),
+    ("border-top-width", "medium", false),
+    ("border-right-width", "medium", false),
+    ("border-bottom-width", "medium", false),
+    ("border-left-width", "medium", false),
+    ("border-top-style", "none", false),
+    ("border-right-style", "none", false),
+    ("border-bottom-style", "none", false),
+    ("border-left-style", "none", false),
+    ("border-top-color", "currentcolor", false),
+    ("border-right-color", "currentcolor", false),
+    ("border-bottom-color", "currentcolor", false),
+    ("border-left-color", "currentcolor", false),
+    ("border-width", "medium", false),
+    ("border-style", "none", false),
+    ("border-color", "currentcolor", false),
+    ("border-radius", "0", false),
+    ("box-sizing", "content-box", false),
+    ("display", "inline", false),
+    // Positioning
+    ("position", "static", false),
+    ("top", "auto", false),
+    ("right", "auto", false),
+    ("bottom", "auto", false),
+    ("left", "auto", false),
+    ("z-index", "auto", false),
+    ("float", "none", false),
+    ("clear", "none", false),
+    // Typography
+    ("color", "black", true),
+    ("font", "normal normal normal 16px / normal serif", true),
+    ("font-family", "serif", true),
+    ("font-size", "16px", true),
+    ("font-style", "normal", true),
+    ("font-variant", "normal", true),
+    ("font-weight", "normal", true),
+    ("line-height", "normal", true),
+    ("text-align", "start", true),
+    ("text-decoration", "none", false),
+    ("text-transform", "none", true),
+    ("letter-spacing", "normal", true),
+    ("word-spacing", "normal", true),
+    ("white-space", "normal", true),
+    ("vertical-align", "baseline", false),
+    // Lists
+    ("list-style", "outside none disc", true),
+    ("list-style-type", "disc", true),
+    ("list-style-position", "outside", true),
+    ("list-style-image", "none", true),
+    // Visual
+    (
+        "background",
+        "transparent none repeat scroll 0% 0% / auto",
+        false,
+    ),
+    ("background-color", "transparent", false),
+    ("background-image", "none", false),
+    ("background-position", "0% 0%", false),
+    ("background-repeat", "repeat", false),
+    ("background-size", "auto", false),
+    ("background-attachment", "scroll", false),
+    ("opacity", "1", false),
+    ("visibility", "visible", true),
+    // Flexbox
+    ("flex", "0 1 auto", false),
+    ("flex-direction", "row", false),
+    ("flex-wrap", "nowrap", false),
+    ("flex-grow", "0", false),
+    ("flex-shrink", "1", false),
+    ("flex-basis", "auto", false),
+    ("justify-content", "normal", false),
+    ("align-items", "normal", false),
+    ("align-self", "auto", false),
+    ("align-content", "normal", false),
+    // Grid
+    ("grid-template-columns", "none", false),
+    ("grid-template-rows", "none", false),
+    ("grid-column-gap", "0", false),
+    ("grid-row-gap", "0", false),
+    ("grid-column", "auto", false),
+    ("grid-row", "auto", false),
+    // Animation
+    ("transition", "all 0s ease 0s", false),
+    ("transition-property", "all", false),
+    ("transition-duration", "0s", false),
+    ("transition-timing-function", "ease", false),
+    ("transition-delay", "0s", false),
+    ("animation", "none 0s ease 0s 1 normal none running", false),
+    ("animation-name", "none", false),
+    ("animation-duration", "0s", false),
+    ("animation-timing-function", "ease", false),
+    ("animation-delay", "0s", false),
+    ("animation-iteration-count", "1", false),
+    ("animation-direction", "normal", false),
+    ("animation-fill-mode", "none", false),
+    ("animation-play-state", "running", false),
+    // Other
+    ("cursor", "auto", true),
+    ("overflow", "visible", false),
+    ("content", "normal", false),
+    ("pointer-events", "auto", true),
+    ("user-select", "auto", false),
+];
+
+impl Property {
+    /// The value a property has when nothing sets or inherits it, parsed
+    /// the same way as a declared value. None for unknown properties.
+    pub fn initial_value(name: &str) -> Option<&'static Value> {
+        static INITIAL_VALUES: OnceLock<HashMap<&str, Value>> = OnceLock::new();
+        INITIAL_VALUES
+            .get_or_init(|| {
+                DEFAULTS
+                    .iter()
+                    .map(|(name, initial, _)| {
+                        (*name, CssParser::new(initial).parse_property_value())
+                    })
+                    .collect()
+            })
+            .get(name)
+    }
+
+    /// Whether an element takes the property from its parent when no
+    /// declaration sets it. Custom properties always do.
+    pub fn is_inherited(name: &str) -> bool {
+        is_custom_property(name)
+            || DEFAULTS
+                .iter()
+                .any(|(property, _, inherited)| *property == name && *inherited)
+    }
+
+    pub fn value(&self) -> &Value {
+        match self {
+            Property::Width(value)
+            | Property::Height(value)
+            | Property::MinWidth(value)
+            | Property::MinHeight(value)
+            | Property::MaxWidth(value)
+            | Property::MaxHeight(value)
+            | Property::Margin(value)
+            | Property::MarginTop(value)
+            | Property::MarginRight(value)
+            | Property::MarginBottom(value)
+            | Property::MarginLeft(value)
+            | Property::Padding(value)
+            | Property::PaddingTop(value)
+            | Property::PaddingRight(value)
+            | Property::PaddingBottom(value)
+            | Property::PaddingLeft(value)
+            | Property::Border(value)
+            | Property::BorderTop(value)
+            | Property::BorderRight(value)
+            | Property::BorderBottom(value)
+            | Property::BorderLeft(value)
+            | Property::BorderTopWidth(value)
+            | Property::BorderRightWidth(value)
+            | Property::BorderBottomWidth(value)
+            | Property::BorderLeftWidth(value)
+            | Property::BorderTopStyle(value)
+            | Property::BorderRightStyle(value)
+            | Property::BorderBottomStyle(value)
+            | Property::BorderLeftStyle(value)
+            | Property::BorderTopColor(value)
+            | Property::BorderRightColor(value)
+            | Property::BorderBottomColor(value)
+            | Property::BorderLeftColor(value)
+            | Property::BorderWidth(value)
+            | Property::BorderStyle(value)
+            | Property::BorderColor(value)
+            | Property::BorderRadius(value)
+            | Property::BoxSizing(value)
+            | Property::Display(value)
+            | Property::Position(value)
+            | Property::Top(value)
+            | Property::Right(value)
+            | Property::Bottom(value)
+            | Property::Left(value)
+            | Property::ZIndex(value)
+            | Property::Float(value)
+            | Property::Clear(value)
+            | Property::Color(value)
+            | Property::Font(value)
+            | Property::FontFamily(value)
+            | Property::FontSize(value)
+            | Property::FontStyle(value)
+            | Property::FontVariant(value)
+            | Property::FontWeight(value)
+            | Property::LineHeight(value)
+            | Property::TextAlign(value)
+            | Property::TextDecoration(value)
+            | Property::TextTransform(value)
+            | Property::LetterSpacing(value)
+            | Property::WordSpacing(value)
+            | Property::WhiteSpace(value)
+            | Property::VerticalAlign(value)
+            | Property::ListStyle(value)
+            | Property::ListStyleType(value)
+            | Property::ListStylePosition(value)
+            | Property::ListStyleImage(value)
+            | Property::Background(value)
+            | Property::BackgroundColor(value)
+            | Property::BackgroundImage(value)
+            | Property::BackgroundPosition(value)
+            | Property::BackgroundRepeat(value)
+            | Property::BackgroundSize(value)
+            | Property::BackgroundAttachment(value)
+            | Property::Opacity(value)
+            | Property::Visibility(value)
+            | Property::Flex(value)
+            | Property::FlexDirection(value)
+            | Property::FlexWrap(value)
+            | Property::FlexGrow(value)
+            | Property::FlexShrink(value)
+            | Property::FlexBasis(value)
+            | Property::JustifyContent(value)
+            | Property::AlignItems(value)
+            | Property::AlignSelf(value)
+            | Property::AlignContent(value)
+            | Property::GridTemplateColumns(value)
+            | Property::GridTemplateRows(value)
+            | Property::GridColumnGap(value)
+            | Property::GridRowGap(value)
+            | Property::GridColumn(value)
+            | Property::GridRow(value)
+            | Property::Transition(value)
+            | Property::TransitionProperty(value)
+            | Property::TransitionDuration(value)
+            | Property::TransitionTimingFunction(value)
+            | Property::TransitionDelay(value)
+            | Property::Animation(value)
+            | Property::AnimationName(value)
+            | Property::AnimationDuration(value)
+            | Property::AnimationTimingFunction(value)
+            | Property::AnimationDelay(value)
+            | Property::AnimationIterationCount(value)
+            | Property::AnimationDirection(value)
+            | Property::AnimationFillMode(value)
+            | Property::AnimationPlayState(value)
+            | Property::Cursor(value)
+            | Property::Overflow(value)
+            | Property::Content(value)
+            | Property::PointerEvents(value)
+            | Property::UserSelect(value) => value,
+        }
+    }
+}
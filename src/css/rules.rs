@@ -1,4 +1,7 @@
+use super::media::{MediaCondition, MediaQueryList};
+use super::supports::SupportsCondition;
 use super::values::Value;
+use crate::span::Span;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Stylesheet {
@@ -8,20 +11,98 @@ pub struct Stylesheet {
 #[derive(Debug, PartialEq, Clone)]
 pub enum Rule {
     Style(StyleRule),
-    Media { query: String, rules: Vec<Rule> },
-    Keyframes { name: String, frames: Vec<Keyframe> },
+    Media {
+        query: MediaQueryList,
+        rules: Vec<Rule>,
+        span: Span,
+    },
+    Keyframes {
+        name: String,
+        frames: Vec<Keyframe>,
+        span: Span,
+    },
+    // The imported stylesheet is not fetched.
+    Import {
+        url: String,
+        // `layer` gives Some(None), `layer(name)` Some(Some(name)).
+        layer: Option<Option<String>>,
+        supports: Option<SupportsCondition>,
+        media: MediaQueryList,
+        span: Span,
+    },
+    FontFace {
+        descriptors: Vec<Declaration>,
+        span: Span,
+    },
+    Supports {
+        condition: SupportsCondition,
+        rules: Vec<Rule>,
+        span: Span,
+    },
+    // `@layer name { ... }`, or an anonymous layer without the name. Layer
+    // names are dotted, like `framework.base`.
+    Layer {
+        name: Option<String>,
+        rules: Vec<Rule>,
+        span: Span,
+    },
+    // `@layer a, b;`, which only declares the layers and their order.
+    LayerStatement {
+        names: Vec<String>,
+        span: Span,
+    },
+    Page {
+        // Like `:first` or `toc:left`, as written; empty for every page.
+        selector: String,
+        declarations: Vec<Declaration>,
+        span: Span,
+    },
+    Container {
+        name: Option<String>,
+        condition: Option<MediaCondition>,
+        rules: Vec<Rule>,
+        span: Span,
+    },
+    Namespace {
+        prefix: Option<String>,
+        url: String,
+        span: Span,
+    },
+}
+
+impl Rule {
+    pub fn span(&self) -> Span {
+        match self {
+            Rule::Style(style_rule) => style_rule.span,
+            Rule::Media { span, .. }
+            | Rule::Keyframes { span, .. }
+            | Rule::Import { span, .. }
+            | Rule::FontFace { span, .. }
+            | Rule::Supports { span, .. }
+            | Rule::Layer { span, .. }
+            | Rule::LayerStatement { span, .. }
+            | Rule::Page { span, .. }
+            | Rule::Container { span, .. }
+            | Rule::Namespace { span, .. } => *span,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Keyframe {
     pub selectors: Vec<String>,
     pub declarations: Vec<Declaration>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct StyleRule {
     pub selectors: Vec<Selector>,
     pub declarations: Vec<Declaration>,
+    // Style rules nested in this one, whose selectors contain `&`, and
+    // conditional rules nested the same way; see `Stylesheet::flatten`.
+    pub rules: Vec<Rule>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -29,27 +110,67 @@ pub struct Declaration {
     pub name: String,
     pub value: Value,
     pub important: bool,
+    pub span: Span,
 }
 
-#[derive(Debug, PartialEq, Clone)]
-pub enum Selector {
-    Simple(SimpleSelector),
+/// A complex selector: compound selectors joined by combinators, like
+/// `ul > li.item:hover`.
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct Selector {
+    pub compounds: Vec<CompoundSelector>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum Combinator {
+    Descendant,
+    Child,
+    NextSibling,
+    SubsequentSibling,
 }
 
-#[derive(Debug, PartialEq, Clone, Eq)] // Added Eq
-pub struct SimpleSelector {
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct CompoundSelector {
+    // How this compound relates to the one before it. Only the first compound
+    // of a relative selector, as in `:has(> img)`, has one without a
+    // compound before it.
+    pub combinator: Option<Combinator>,
+    // The namespace of the type or universal selector. None without a
+    // prefix or default namespace, which matches elements in any namespace.
+    pub namespace: Option<NamespaceSelector>,
     pub tag_name: Option<String>,
-    pub id: Option<String>,
+    pub ids: Vec<String>,
     pub classes: Vec<String>,
     pub attributes: Vec<AttributeSelector>,
+    pub pseudo_classes: Vec<PseudoClass>,
+    pub pseudo_element: Option<PseudoElement>,
     pub universal: bool,
+    // The nesting selector `&`, which stands for the selectors of the rule
+    // this one is nested in, or for `:scope` outside any.
+    pub nesting: bool,
 }
 
 #[derive(Debug, PartialEq, Clone, Eq)]
 pub struct AttributeSelector {
+    // None without a prefix, which matches only attributes in no namespace.
+    pub namespace: Option<NamespaceSelector>,
     pub name: String,
     pub op: Option<AttributeOperator>,
     pub value: Option<String>,
+    // Set by the `i` flag, as in `[type="a" i]`.
+    pub case_insensitive: bool,
+}
+
+/// The namespace part of a type, universal or attribute selector, as in
+/// `svg|rect`, `*|*` or `[xlink|href]`.
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub enum NamespaceSelector {
+    // `|rect`: in no namespace.
+    None,
+    // `*|rect`: in any namespace, or none.
+    Any,
+    // `svg|rect`: in the namespace an @namespace rule declares for the
+    // prefix. The default namespace has no prefix.
+    Named { prefix: Option<String>, url: String },
 }
 
 #[derive(Debug, PartialEq, Clone, Eq)]
@@ -62,8 +183,107 @@ pub enum AttributeOperator {
     Substring,
 }
 
+/// The `An+B` argument of `:nth-child()` and friends. It matches the
+/// 1-based positions `An+B` for any n >= 0.
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub struct Nth {
+    pub a: i32,
+    pub b: i32,
+}
+
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub enum PseudoClass {
+    // Tree-structural
+    Root,
+    Empty,
+    Scope,
+    FirstChild,
+    LastChild,
+    OnlyChild,
+    FirstOfType,
+    LastOfType,
+    OnlyOfType,
+    // The selectors are from `of S` and empty without it.
+    NthChild(Nth, Vec<Selector>),
+    NthLastChild(Nth, Vec<Selector>),
+    NthOfType(Nth),
+    NthLastOfType(Nth),
+
+    // Logical combinations
+    Not(Vec<Selector>),
+    Is(Vec<Selector>),
+    Where(Vec<Selector>),
+    Has(Vec<Selector>),
+
+    // Linguistic
+    Lang(Vec<String>),
+    Dir(String),
+
+    // Locations
+    AnyLink,
+    Link,
+    Visited,
+    LocalLink,
+    Target,
+    TargetWithin,
+
+    // User actions
+    Hover,
+    Active,
+    Focus,
+    FocusVisible,
+    FocusWithin,
+
+    // Input states
+    Enabled,
+    Disabled,
+    ReadOnly,
+    ReadWrite,
+    PlaceholderShown,
+    Default,
+    Checked,
+    Indeterminate,
+    Valid,
+    Invalid,
+    InRange,
+    OutOfRange,
+    Required,
+    Optional,
+    UserValid,
+    UserInvalid,
+    Blank,
+
+    // Resource and element states
+    Defined,
+    Playing,
+    Paused,
+
+    // A vendor-prefixed name like `-moz-focusring`, kept but never matched.
+    Vendor(String),
+}
+
 #[derive(Debug, PartialEq, Clone, Eq)]
-pub struct Specificity(u32, u32, u32);
+pub enum PseudoElement {
+    Before,
+    After,
+    FirstLine,
+    FirstLetter,
+    Marker,
+    Placeholder,
+    Selection,
+    Backdrop,
+    FileSelectorButton,
+    TargetText,
+    SpellingError,
+    GrammarError,
+    // A vendor-prefixed name like `-webkit-scrollbar`.
+    Vendor(String),
+}
+
+/// Selector specificity as (ids, classes, types). Each column is compared
+/// in turn, so one id outweighs any number of classes.
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Default)]
+pub struct Specificity(pub u32, pub u32, pub u32);
 
 impl Ord for Specificity {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
@@ -83,21 +303,273 @@ impl PartialOrd for Specificity {
     }
 }
 
-impl SimpleSelector {
+impl std::ops::Add for Specificity {
+    type Output = Specificity;
+
+    fn add(self, other: Self) -> Self {
+        Specificity(self.0 + other.0, self.1 + other.1, self.2 + other.2)
+    }
+}
+
+impl Specificity {
+    /// The specificity of the most specific selector in a list, which is
+    /// what `:is()`, `:not()` and `:has()` take from their arguments.
+    pub fn max_of(selectors: &[Selector]) -> Specificity {
+        selectors
+            .iter()
+            .map(Selector::specificity)
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+impl Selector {
+    /// The rightmost compound, whose matches are the selector's matches.
+    pub fn subject(&self) -> &CompoundSelector {
+        self.compounds
+            .last()
+            .expect("a selector has at least one compound")
+    }
+
+    pub fn pseudo_element(&self) -> Option<&PseudoElement> {
+        self.subject().pseudo_element.as_ref()
+    }
+
+    /// The sum of the specificities of the compounds; combinators add none.
+    pub fn specificity(&self) -> Specificity {
+        self.compounds
+            .iter()
+            .map(CompoundSelector::specificity)
+            .fold(Specificity::default(), |total, specificity| {
+                total + specificity
+            })
+    }
+}
+
+impl Combinator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Combinator::Descendant => " ",
+            Combinator::Child => ">",
+            Combinator::NextSibling => "+",
+            Combinator::SubsequentSibling => "~",
+        }
+    }
+}
+
+impl Default for CompoundSelector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CompoundSelector {
     pub fn new() -> Self {
-        SimpleSelector {
+        CompoundSelector {
+            combinator: None,
+            namespace: None,
             tag_name: None,
-            id: None,
+            ids: Vec::new(),
             classes: Vec::new(),
             attributes: Vec::new(),
+            pseudo_classes: Vec::new(),
+            pseudo_element: None,
             universal: false,
+            nesting: false,
         }
     }
 
+    /// Ids count in the first column; classes, attributes and pseudo-classes
+    /// in the second; type selectors and pseudo-elements in the third. The
+    /// universal selector counts for nothing.
     pub fn specificity(&self) -> Specificity {
-        let a = if self.id.is_some() { 1 } else { 0 };
-        let b = self.classes.len() as u32;
-        let c = if self.tag_name.is_some() { 1 } else { 0 };
-        Specificity(a, b, c)
+        let a = self.ids.len() as u32;
+        // Outside a flattened stylesheet, `&` weighs as `:scope`.
+        let b = (self.classes.len() + self.attributes.len() + self.nesting as usize) as u32;
+        let c = (self.tag_name.is_some() as u32) + (self.pseudo_element.is_some() as u32);
+        self.pseudo_classes
+            .iter()
+            .map(PseudoClass::specificity)
+            .fold(Specificity(a, b, c), |total, specificity| {
+                total + specificity
+            })
+    }
+}
+
+impl Nth {
+    /// Whether the 1-based `position` is `An+B` for some n >= 0.
+    pub fn matches(&self, position: i32) -> bool {
+        let offset = position - self.b;
+        if self.a == 0 {
+            offset == 0
+        } else {
+            offset % self.a == 0 && offset / self.a >= 0
+        }
+    }
+}
+
+impl PseudoClass {
+    pub fn specificity(&self) -> Specificity {
+        match self {
+            PseudoClass::Not(selectors)
+            | PseudoClass::Is(selectors)
+            | PseudoClass::Has(selectors) => Specificity::max_of(selectors),
+            PseudoClass::Where(_) => Specificity::default(),
+            // `:nth-child(An+B of S)` counts as a class plus the most
+            // specific selector in S.
+            PseudoClass::NthChild(_, of) | PseudoClass::NthLastChild(_, of) => {
+                Specificity(0, 1, 0) + Specificity::max_of(of)
+            }
+            _ => Specificity(0, 1, 0),
+        }
+    }
+
+    /// Looks up a pseudo-class that takes no argument.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "root" => PseudoClass::Root,
+            "empty" => PseudoClass::Empty,
+            "scope" => PseudoClass::Scope,
+            "first-child" => PseudoClass::FirstChild,
+            "last-child" => PseudoClass::LastChild,
+            "only-child" => PseudoClass::OnlyChild,
+            "first-of-type" => PseudoClass::FirstOfType,
+            "last-of-type" => PseudoClass::LastOfType,
+            "only-of-type" => PseudoClass::OnlyOfType,
+            "any-link" => PseudoClass::AnyLink,
+            "link" => PseudoClass::Link,
+            "visited" => PseudoClass::Visited,
+            "local-link" => PseudoClass::LocalLink,
+            "target" => PseudoClass::Target,
+            "target-within" => PseudoClass::TargetWithin,
+            "hover" => PseudoClass::Hover,
+            "active" => PseudoClass::Active,
+            "focus" => PseudoClass::Focus,
+            "focus-visible" => PseudoClass::FocusVisible,
+            "focus-within" => PseudoClass::FocusWithin,
+            "enabled" => PseudoClass::Enabled,
+            "disabled" => PseudoClass::Disabled,
+            "read-only" => PseudoClass::ReadOnly,
+            "read-write" => PseudoClass::ReadWrite,
+            "placeholder-shown" => PseudoClass::PlaceholderShown,
+            "default" => PseudoClass::Default,
+            "checked" => PseudoClass::Checked,
+            "indeterminate" => PseudoClass::Indeterminate,
+            "valid" => PseudoClass::Valid,
+            "invalid" => PseudoClass::Invalid,
+            "in-range" => PseudoClass::InRange,
+            "out-of-range" => PseudoClass::OutOfRange,
+            "required" => PseudoClass::Required,
+            "optional" => PseudoClass::Optional,
+            "user-valid" => PseudoClass::UserValid,
+            "user-invalid" => PseudoClass::UserInvalid,
+            "blank" => PseudoClass::Blank,
+            "defined" => PseudoClass::Defined,
+            "playing" => PseudoClass::Playing,
+            "paused" => PseudoClass::Paused,
+            _ if name.starts_with('-') => PseudoClass::Vendor(name.to_string()),
+            _ => return None,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            PseudoClass::Root => "root",
+            PseudoClass::Empty => "empty",
+            PseudoClass::Scope => "scope",
+            PseudoClass::FirstChild => "first-child",
+            PseudoClass::LastChild => "last-child",
+            PseudoClass::OnlyChild => "only-child",
+            PseudoClass::FirstOfType => "first-of-type",
+            PseudoClass::LastOfType => "last-of-type",
+            PseudoClass::OnlyOfType => "only-of-type",
+            PseudoClass::NthChild(..) => "nth-child",
+            PseudoClass::NthLastChild(..) => "nth-last-child",
+            PseudoClass::NthOfType(_) => "nth-of-type",
+            PseudoClass::NthLastOfType(_) => "nth-last-of-type",
+            PseudoClass::Not(_) => "not",
+            PseudoClass::Is(_) => "is",
+            PseudoClass::Where(_) => "where",
+            PseudoClass::Has(_) => "has",
+            PseudoClass::Lang(_) => "lang",
+            PseudoClass::Dir(_) => "dir",
+            PseudoClass::AnyLink => "any-link",
+            PseudoClass::Link => "link",
+            PseudoClass::Visited => "visited",
+            PseudoClass::LocalLink => "local-link",
+            PseudoClass::Target => "target",
+            PseudoClass::TargetWithin => "target-within",
+            PseudoClass::Hover => "hover",
+            PseudoClass::Active => "active",
+            PseudoClass::Focus => "focus",
+            PseudoClass::FocusVisible => "focus-visible",
+            PseudoClass::FocusWithin => "focus-within",
+            PseudoClass::Enabled => "enabled",
+            PseudoClass::Disabled => "disabled",
+            PseudoClass::ReadOnly => "read-only",
+            PseudoClass::ReadWrite => "read-write",
+            PseudoClass::PlaceholderShown => "placeholder-shown",
+            PseudoClass::Default => "default",
+            PseudoClass::Checked => "checked",
+            PseudoClass::Indeterminate => "indeterminate",
+            PseudoClass::Valid => "valid",
+            PseudoClass::Invalid => "invalid",
+            PseudoClass::InRange => "in-range",
+            PseudoClass::OutOfRange => "out-of-range",
+            PseudoClass::Required => "required",
+            PseudoClass::Optional => "optional",
+            PseudoClass::UserValid => "user-valid",
+            PseudoClass::UserInvalid => "user-invalid",
+            PseudoClass::Blank => "blank",
+            PseudoClass::Defined => "defined",
+            PseudoClass::Playing => "playing",
+            PseudoClass::Paused => "paused",
+            PseudoClass::Vendor(name) => name,
+        }
+    }
+}
+
+impl PseudoElement {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "before" => PseudoElement::Before,
+            "after" => PseudoElement::After,
+            "first-line" => PseudoElement::FirstLine,
+            "first-letter" => PseudoElement::FirstLetter,
+            "marker" => PseudoElement::Marker,
+            "placeholder" => PseudoElement::Placeholder,
+            "selection" => PseudoElement::Selection,
+            "backdrop" => PseudoElement::Backdrop,
+            "file-selector-button" => PseudoElement::FileSelectorButton,
+            "target-text" => PseudoElement::TargetText,
+            "spelling-error" => PseudoElement::SpellingError,
+            "grammar-error" => PseudoElement::GrammarError,
+            _ if name.starts_with('-') => PseudoElement::Vendor(name.to_string()),
+            _ => return None,
+        })
+    }
+
+    /// The pseudo-elements CSS 2 wrote with a single colon, which must still
+    /// be accepted that way.
+    pub fn is_legacy(name: &str) -> bool {
+        matches!(name, "before" | "after" | "first-line" | "first-letter")
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            PseudoElement::Before => "before",
+            PseudoElement::After => "after",
+            PseudoElement::FirstLine => "first-line",
+            PseudoElement::FirstLetter => "first-letter",
+            PseudoElement::Marker => "marker",
+            PseudoElement::Placeholder => "placeholder",
+            PseudoElement::Selection => "selection",
+            PseudoElement::Backdrop => "backdrop",
+            PseudoElement::FileSelectorButton => "file-selector-button",
+            PseudoElement::TargetText => "target-text",
+            PseudoElement::SpellingError => "spelling-error",
+            PseudoElement::GrammarError => "grammar-error",
+            PseudoElement::Vendor(name) => name,
+        }
     }
 }
@@ -0,0 +1,745 @@
+use super::grammar::{
+    comma_separated, components, is_color, is_easing, is_font_weight, is_image, is_iteration_count,
+    is_keyword, is_length_percentage, is_line_style, is_line_width, is_position, is_size, is_time,
+    ATTACHMENTS, DIRECTIONS, FILL_MODES, FONT_SIZES, FONT_STYLES, FONT_VARIANTS, LIST_POSITIONS,
+    PLAY_STATES, REPEATS,
+};
+use super::properties::Property;
+use super::values::{Unparsed, Value};
+
+// Each shorthand and the longhands it sets, in the order they serialize.
+const SHORTHANDS: &[(&str, &[&str])] = &[
+    (
+        "margin",
+        &["margin-top", "margin-right", "margin-bottom", "margin-left"],
+    ),
+    (
+        "padding",
+        &[
+            "padding-top",
+            "padding-right",
+            "padding-bottom",
+            "padding-left",
+        ],
+    ),
+    (
+        "border-width",
+        &[
+            "border-top-width",
+            "border-right-width",
+            "border-bottom-width",
+            "border-left-width",
+        ],
+    ),
+    (
+        "border-style",
+        &[
+            "border-top-style",
+            "border-right-style",
+            "border-bottom-style",
+            "border-left-style",
+        ],
+    ),
+    (
+        "border-color",
+        &[
+            "border-top-color",
+            "border-right-color",
+            "border-bottom-color",
+            "border-left-color",
+        ],
+    ),
+    (
+        "border-top",
+        &["border-top-width", "border-top-style", "border-top-color"],
+    ),
+    (
+        "border-right",
+        &[
+            "border-right-width",
+            "border-right-style",
+            "border-right-color",
+        ],
+    ),
+    (
+        "border-bottom",
+        &[
+            "border-bottom-width",
+            "border-bottom-style",
+            "border-bottom-color",
+        ],
+    ),
+    (
+        "border-left",
+        &[
+            "border-left-width",
+            "border-left-style",
+            "border-left-color",
+        ],
+    ),
+    (
+        "border",
+        &[
+            "border-top-width",
+            "border-right-width",
+            "border-bottom-width",
+            "border-left-width",
+            "border-top-style",
+            "border-right-style",
+            "border-bottom-style",
+            "border-left-style",
+            "border-top-color",
+            "border-right-color",
+            "border-bottom-color",
+            "border-left-color",
+        ],
+    ),
+    (
+        "list-style",
+        &["list-style-position", "list-style-image", "list-style-type"],
+    ),
+    (
+        "background",
+        &[
+            "background-color",
+            "background-image",
+            "background-repeat",
+            "background-attachment",
+            "background-position",
+            "background-size",
+        ],
+    ),
+    (
+        "font",
+        &[
+            "font-style",
+            "font-variant",
+            "font-weight",
+            "font-size",
+            "line-height",
+            "font-family",
+        ],
+    ),
+    ("flex", &["flex-grow", "flex-shrink", "flex-basis"]),
+    (
+        "transition",
+        &[
+            "transition-property",
+            "transition-duration",
+            "transition-timing-function",
+            "transition-delay",
+        ],
+    ),
+    (
+        "animation",
+        &[
+            "animation-name",
+            "animation-duration",
+            "animation-timing-function",
+            "animation-delay",
+            "animation-iteration-count",
+            "animation-direction",
+            "animation-fill-mode",
+            "animation-play-state",
+        ],
+    ),
+];
+
+type Matcher = fn(&Value) -> bool;
+
+impl Property {
+    /// The longhands a shorthand property sets, or None for a longhand.
+    pub fn longhands(name: &str) -> Option<&'static [&'static str]> {
+        SHORTHANDS
+            .iter()
+            .find(|(shorthand, _)| *shorthand == name)
+            .map(|(_, longhands)| *longhands)
+    }
+
+    /// Expands a shorthand into one declaration per longhand, with the ones
+    /// it leaves out reset to their initial values. A longhand expands to
+    /// itself. Errors with the first component that doesn't fit when the
+    /// value doesn't match the shorthand's grammar.
+    pub fn expand(&self) -> Result<Vec<Property>, Value> {
+        let name = self.name();
+        let Some(longhands) = Property::longhands(name) else {
+            return Ok(vec![self.clone()]);
+        };
+
+        let value = self.value();
+        let values = match value {
+            Value::Initial | Value::Inherit | Value::Unset => {
+                vec![Some(value.clone()); longhands.len()]
+            }
+            // Which longhand gets what is only known once `var()` is
+            // substituted, so each keeps the whole value.
+            Value::Unparsed(unparsed) => {
+                let pending = Value::Unparsed(Unparsed {
+                    tokens: unparsed.tokens.clone(),
+                    shorthand: Some(name.to_string()),
+                });
+                vec![Some(pending); longhands.len()]
+            }
+            _ => {
+                let components = components(value);
+                if components.is_empty() {
+                    return Err(value.clone());
+                }
+                let expanded = match name {
+                    "margin" => expand_sides(components, |value| {
+                        is_length_percentage(value) || is_keyword(value, &["auto"])
+                    }),
+                    "padding" => expand_sides(components, is_length_percentage),
+                    "border-width" => expand_sides(components, is_line_width),
+                    "border-style" => expand_sides(components, is_line_style),
+                    "border-color" => expand_sides(components, is_color),
+                    "border" => unordered(components, &[is_line_width, is_line_style, is_color])
+                        .map(|values| {
+                            values
+                                .into_iter()
+                                .flat_map(|value| vec![value; 4])
+                                .collect()
+                        }),
+                    "list-style" => expand_list_style(components),
+                    "font" => expand_font(components),
+                    "flex" => expand_flex(components),
+                    "background" | "transition" | "animation" => {
+                        expand_list(name, longhands, components)
+                    }
+                    // border-top, border-right, border-bottom and border-left.
+                    _ => unordered(components, &[is_line_width, is_line_style, is_color]),
+                };
+                expanded?
+            }
+        };
+
+        let mut properties = Vec::new();
+        for (longhand, value) in longhands.iter().zip(values) {
+            let value = value
+                .or_else(|| Property::initial_value(longhand).cloned())
+                .unwrap_or(Value::Initial);
+            Property::validate(longhand, &value)?;
+            properties.extend(Property::new(longhand, value));
+        }
+        Ok(properties)
+    }
+
+    /// The shortest declaration of the shorthand `name` that sets its
+    /// longhands to the values in `longhands`. None if one of them is
+    /// missing, or the values can't be written with this shorthand.
+    pub fn shorthand(name: &str, longhands: &[Property]) -> Option<Property> {
+        let names = Property::longhands(name)?;
+        let values = names
+            .iter()
+            .map(|name| {
+                longhands
+                    .iter()
+                    .find(|property| property.name() == *name)
+                    .map(Property::value)
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        // Longhands all pending the same `var()` substitution come from one
+        // declaration of this shorthand.
+        if let Value::Unparsed(unparsed) = values[0] {
+            let pending = |value: &&Value| {
+                matches!(value, Value::Unparsed(other)
+                    if other.shorthand.as_deref() == Some(name) && other.tokens == unparsed.tokens)
+            };
+            return values.iter().all(pending).then(|| {
+                let value = Value::Unparsed(Unparsed {
+                    tokens: unparsed.tokens.clone(),
+                    shorthand: None,
+                });
+                Property::new(name, value)
+            })?;
+        }
+
+        // A CSS-wide keyword can only be written for all longhands at once.
+        let wide = |value: &&Value| matches!(value, Value::Initial | Value::Inherit | Value::Unset);
+        if values.iter().any(wide) {
+            let first = values[0];
+            return if values.iter().all(|value| *value == first) {
+                Property::parse(name, first.clone())
+            } else {
+                None
+            };
+        }
+
+        let parts = match name {
+            "margin" | "padding" | "border-width" | "border-style" | "border-color" => {
+                shortest_sides(&values)
+            }
+            "border" => {
+                let sides_equal = |i: usize| values[i..i + 4].iter().all(|v| *v == values[i]);
+                if !(sides_equal(0) && sides_equal(4) && sides_equal(8)) {
+                    return None;
+                }
+                let top = ["border-top-width", "border-top-style", "border-top-color"];
+                let values = [values[0], values[4], values[8]];
+                without_initial(&top, &values, |_| false).unwrap_or(vec![values[1].clone()])
+            }
+            "list-style" => {
+                without_initial(names, &values, |_| false).unwrap_or(vec![values[2].clone()])
+            }
+            "font" => serialize_font(names, &values),
+            "flex" => serialize_flex(&values),
+            "background" | "transition" | "animation" => serialize_list(name, names, &values)?,
+            _ => without_initial(names, &values, |_| false).unwrap_or(vec![values[1].clone()]),
+        };
+        Property::parse(name, join(parts))
+    }
+}
+
+// Puts parts back together as one value, flattening lists.
+fn join(parts: Vec<Value>) -> Value {
+    let mut values: Vec<Value> = parts
+        .into_iter()
+        .flat_map(|part| match part {
+            Value::List(values) => values,
+            value => vec![value],
+        })
+        .collect();
+    if values.len() == 1 {
+        values.remove(0)
+    } else {
+        Value::List(values)
+    }
+}
+
+// One to four values for top, right, bottom and left, where a missing
+// value copies the one opposite it.
+fn expand_sides(
+    components: &[Value],
+    matches: impl Fn(&Value) -> bool,
+) -> Result<Vec<Option<Value>>, Value> {
+    if let Some(invalid) = components.iter().find(|value| !matches(value)) {
+        return Err(invalid.clone());
+    }
+    let [top, right, bottom, left] = match components {
+        [all] => [all, all, all, all],
+        [vertical, horizontal] => [vertical, horizontal, vertical, horizontal],
+        [top, horizontal, bottom] => [top, horizontal, bottom, horizontal],
+        [top, right, bottom, left] => [top, right, bottom, left],
+        _ => return Err(components[4].clone()),
+    };
+    Ok(vec![
+        Some(top.clone()),
+        Some(right.clone()),
+        Some(bottom.clone()),
+        Some(left.clone()),
+    ])
+}
+
+fn shortest_sides(values: &[&Value]) -> Vec<Value> {
+    let [top, right, bottom, left] = [values[0], values[1], values[2], values[3]];
+    let sides: &[&Value] = if left != right {
+        &[top, right, bottom, left]
+    } else if bottom != top {
+        &[top, right, bottom]
+    } else if right != top {
+        &[top, right]
+    } else {
+        &[top]
+    };
+    sides.iter().map(|value| (*value).clone()).collect()
+}
+
+// Components that may appear in any order, each going to the first
+// longhand it is valid for that hasn't been set yet.
+fn unordered(components: &[Value], matchers: &[Matcher]) -> Result<Vec<Option<Value>>, Value> {
+    let mut values = vec![None; matchers.len()];
+    for component in components {
+        let slot = (0..matchers.len())
+            .find(|&i| values[i].is_none() && matchers[i](component))
+            .ok_or_else(|| component.clone())?;
+        values[slot] = Some(component.clone());
+    }
+    Ok(values)
+}
+
+// A shorthand whose longhands are comma-separated lists, one item per
+// layer, transition or animation. Each item of the value expands on its
+// own, and the longhands it leaves out get their initial value in that
+// item. Only the last background layer may have a color, which is
+// `background-color` as a whole.
+fn expand_list(
+    name: &str,
+    longhands: &[&str],
+    components: &[Value],
+) -> Result<Vec<Option<Value>>, Value> {
+    let items = comma_separated(components);
+    let mut expanded = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        if item.is_empty() {
+            return Err(comma());
+        }
+        let values = expand_item(name, item)?;
+        if name == "background" && i + 1 < items.len() {
+            if let Some(color) = &values[0] {
+                return Err(color.clone());
+            }
+        }
+        expanded.push(values);
+    }
+    if let [values] = expanded.as_slice() {
+        return Ok(values.clone());
+    }
+
+    let values = longhands
+        .iter()
+        .enumerate()
+        .map(|(i, longhand)| {
+            if name == "background" && i == 0 {
+                return expanded[expanded.len() - 1][0].clone();
+            }
+            let items = expanded
+                .iter()
+                .map(|values| {
+                    values[i]
+                        .clone()
+                        .or_else(|| Property::initial_value(longhand).cloned())
+                        .unwrap_or(Value::Initial)
+                })
+                .collect();
+            Some(comma_list(items))
+        })
+        .collect();
+    Ok(values)
+}
+
+fn expand_item(name: &str, components: &[Value]) -> Result<Vec<Option<Value>>, Value> {
+    match name {
+        "background" => expand_background(components),
+        "transition" => unordered(
+            components,
+            &[is_transition_property, is_time, is_easing, is_time],
+        ),
+        // The name is matched last, so that a keyword goes to the other
+        // longhands first, then moved to the front.
+        _ => unordered(
+            components,
+            &[
+                is_time,
+                is_easing,
+                is_time,
+                is_iteration_count,
+                |value| is_keyword(value, DIRECTIONS),
+                |value| is_keyword(value, FILL_MODES),
+                |value| is_keyword(value, PLAY_STATES),
+                |value| matches!(value, Value::Keyword(_) | Value::String(_)),
+            ],
+        )
+        .map(|mut values| {
+            values.rotate_right(1);
+            values
+        }),
+    }
+}
+
+// The inverse of `expand_list`: one item per layer, which needs every
+// longhand to have as many items as there are layers.
+fn serialize_list(name: &str, names: &[&str], values: &[&Value]) -> Option<Vec<Value>> {
+    // The number of layers is set by the image, or by the name.
+    let lists: Vec<Vec<&[Value]>> = values
+        .iter()
+        .map(|value| comma_separated(components(value)))
+        .collect();
+    let count = match name {
+        "background" => lists[1].len(),
+        _ => lists[0].len(),
+    };
+
+    let mut parts = Vec::new();
+    for i in 0..count {
+        let mut item = Vec::new();
+        for (j, list) in lists.iter().enumerate() {
+            let value = if name == "background" && j == 0 {
+                // Layers before the last are transparent.
+                if i + 1 < count {
+                    Property::initial_value(names[0])?.clone()
+                } else {
+                    values[0].clone()
+                }
+            } else if list.len() == count {
+                join(list[i].to_vec())
+            } else {
+                return None;
+            };
+            item.push(value);
+        }
+        let item: Vec<&Value> = item.iter().collect();
+        if i > 0 {
+            parts.push(comma());
+        }
+        parts.extend(serialize_item(name, names, &item));
+    }
+    Some(parts)
+}
+
+fn serialize_item(name: &str, names: &[&str], values: &[&Value]) -> Vec<Value> {
+    match name {
+        "background" => serialize_background(names, values),
+        // The first time is the duration, so a delay needs one before it.
+        _ => {
+            let delay = !is_initial(names[3], values[3]);
+            without_initial(names, values, |i| i == 1 && delay).unwrap_or(vec![values[0].clone()])
+        }
+    }
+}
+
+fn comma() -> Value {
+    Value::Keyword(",".to_string())
+}
+
+// Items put together as a comma-separated list.
+fn comma_list(items: Vec<Value>) -> Value {
+    let mut parts = Vec::new();
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            parts.push(comma());
+        }
+        parts.push(item);
+    }
+    join(parts)
+}
+
+fn expand_list_style(components: &[Value]) -> Result<Vec<Option<Value>>, Value> {
+    let mut values = unordered(
+        components,
+        &[
+            |value| is_keyword(value, LIST_POSITIONS),
+            is_image,
+            |value| matches!(value, Value::Keyword(_) | Value::None | Value::String(_)),
+        ],
+    )?;
+    // A lone `none` takes away both the image and the marker.
+    let none = Some(Value::Keyword("none".to_string()));
+    if values[1] == none && values[2].is_none() {
+        values[2] = none;
+    }
+    Ok(values)
+}
+
+fn expand_background(components: &[Value]) -> Result<Vec<Option<Value>>, Value> {
+    let mut values: Vec<Option<Value>> = vec![None; 6];
+    let mut i = 0;
+    while i < components.len() {
+        let component = &components[i];
+        // Position is one to four values, optionally followed by `/` and
+        // one or two size values.
+        if is_position(component) && values[4].is_none() {
+            let count = run(&components[i..], is_position, 4);
+            values[4] = Some(join(components[i..i + count].to_vec()));
+            i += count;
+            if components
+                .get(i)
+                .is_some_and(|value| is_keyword(value, &["/"]))
+            {
+                let count = run(&components[i + 1..], is_size, 2);
+                if count == 0 {
+                    return Err(components[i].clone());
+                }
+                values[5] = Some(join(components[i + 1..i + 1 + count].to_vec()));
+                i += 1 + count;
+            }
+            continue;
+        }
+        if is_keyword(component, &["repeat-x", "repeat-y"]) && values[2].is_none() {
+            values[2] = Some(component.clone());
+        } else if is_keyword(component, REPEATS) && values[2].is_none() {
+            let count = run(&components[i..], |value| is_keyword(value, REPEATS), 2);
+            values[2] = Some(join(components[i..i + count].to_vec()));
+            i += count;
+            continue;
+        } else if is_keyword(component, ATTACHMENTS) && values[3].is_none() {
+            values[3] = Some(component.clone());
+        } else if is_image(component) && values[1].is_none() {
+            values[1] = Some(component.clone());
+        } else if is_color(component) && values[0].is_none() {
+            values[0] = Some(component.clone());
+        } else {
+            return Err(component.clone());
+        }
+        i += 1;
+    }
+    Ok(values)
+}
+
+// The number of values at the start of `components` that match, up to `max`.
+fn run(components: &[Value], matches: impl Fn(&Value) -> bool, max: usize) -> usize {
+    components
+        .iter()
+        .take(max)
+        .take_while(|value| matches(value))
+        .count()
+}
+
+fn expand_font(components: &[Value]) -> Result<Vec<Option<Value>>, Value> {
+    let mut values: Vec<Option<Value>> = vec![None; 6];
+    let mut i = 0;
+
+    // Style, variant and weight come first, in any order; `normal` resets
+    // whichever of them is left.
+    while let Some(component) = components.get(i) {
+        let slot = if is_keyword(component, FONT_STYLES) {
+            0
+        } else if is_keyword(component, FONT_VARIANTS) {
+            1
+        } else if is_font_weight(component) {
+            2
+        } else if is_keyword(component, &["normal"]) {
+            (0..3)
+                .find(|&slot| values[slot].is_none())
+                .ok_or_else(|| component.clone())?
+        } else {
+            break;
+        };
+        if values[slot].is_some() {
+            return Err(component.clone());
+        }
+        values[slot] = Some(component.clone());
+        i += 1;
+    }
+
+    // The size and family are required.
+    let missing = || components[components.len() - 1].clone();
+    let size = components.get(i).ok_or_else(missing)?;
+    if !(is_length_percentage(size) || is_keyword(size, FONT_SIZES)) {
+        return Err(size.clone());
+    }
+    values[3] = Some(size.clone());
+    i += 1;
+
+    if components
+        .get(i)
+        .is_some_and(|value| is_keyword(value, &["/"]))
+    {
+        let line_height = components.get(i + 1).ok_or_else(missing)?;
+        if !(is_length_percentage(line_height)
+            || matches!(line_height, Value::Number(_))
+            || is_keyword(line_height, &["normal"]))
+        {
+            return Err(line_height.clone());
+        }
+        values[4] = Some(line_height.clone());
+        i += 2;
+    }
+
+    // The rest is the family name, which may be several words.
+    let family = &components[i..];
+    if family.is_empty() {
+        return Err(missing());
+    }
+    if let Some(invalid) = family
+        .iter()
+        .find(|value| !matches!(value, Value::Keyword(_) | Value::String(_)))
+    {
+        return Err(invalid.clone());
+    }
+    values[5] = Some(join(family.to_vec()));
+    Ok(values)
+}
+
+fn expand_flex(components: &[Value]) -> Result<Vec<Option<Value>>, Value> {
+    let number = |n: f32| Some(Value::Number(n));
+    let auto = || Some(Value::Keyword("auto".to_string()));
+    let is_basis =
+        |value: &Value| is_length_percentage(value) || is_keyword(value, &["auto", "content"]);
+    let values = match components {
+        [value] if is_keyword(value, &["none"]) => vec![number(0.0), number(0.0), auto()],
+        [value] if is_keyword(value, &["auto"]) => vec![number(1.0), number(1.0), auto()],
+        // A lone number is the grow factor, and makes the basis zero.
+        [Value::Number(grow)] => vec![number(*grow), number(1.0), number(0.0)],
+        [basis] if is_basis(basis) => vec![number(1.0), number(1.0), Some(basis.clone())],
+        [Value::Number(grow), Value::Number(shrink)] => {
+            vec![number(*grow), number(*shrink), number(0.0)]
+        }
+        [Value::Number(grow), basis] | [basis, Value::Number(grow)] if is_basis(basis) => {
+            vec![number(*grow), number(1.0), Some(basis.clone())]
+        }
+        [Value::Number(grow), Value::Number(shrink), basis]
+        | [basis, Value::Number(grow), Value::Number(shrink)]
+            if is_basis(basis) =>
+        {
+            vec![number(*grow), number(*shrink), Some(basis.clone())]
+        }
+        // Anything else is invalid, but there's no one component to blame.
+        _ => return Err(join(components.to_vec())),
+    };
+    Ok(values)
+}
+
+fn is_initial(name: &str, value: &Value) -> bool {
+    Property::initial_value(name) == Some(value)
+}
+
+// The values that aren't initial, in order, plus those `keep` asks for.
+// None if that leaves nothing.
+fn without_initial(
+    names: &[&str],
+    values: &[&Value],
+    keep: impl Fn(usize) -> bool,
+) -> Option<Vec<Value>> {
+    let parts: Vec<Value> = names
+        .iter()
+        .zip(values)
+        .enumerate()
+        .filter(|(i, (name, value))| keep(*i) || !is_initial(name, value))
+        .map(|(_, (_, value))| (*value).clone())
+        .collect();
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts)
+    }
+}
+
+fn serialize_background(names: &[&str], values: &[&Value]) -> Vec<Value> {
+    // A size can only be written after a position.
+    let size = !is_initial(names[5], values[5]);
+    let mut parts = without_initial(&names[..5], &values[..5], |i| i == 4 && size)
+        .unwrap_or(vec![values[1].clone()]);
+    if size {
+        parts.push(Value::Keyword("/".to_string()));
+        parts.push(values[5].clone());
+    }
+    parts
+}
+
+fn serialize_font(names: &[&str], values: &[&Value]) -> Vec<Value> {
+    let mut parts = without_initial(&names[..3], &values[..3], |_| false).unwrap_or_default();
+    parts.push(values[3].clone());
+    if !is_initial(names[4], values[4]) {
+        parts.push(Value::Keyword("/".to_string()));
+        parts.push(values[4].clone());
+    }
+    parts.push(values[5].clone());
+    parts
+}
+
+fn serialize_flex(values: &[&Value]) -> Vec<Value> {
+    let (grow, shrink, basis) = (values[0], values[1], values[2]);
+    let auto = is_keyword(basis, &["auto"]);
+    let zero =
+        matches!(basis, Value::Number(n) | Value::Length(n, _) | Value::Percentage(n) if *n == 0.0);
+    match (grow, shrink) {
+        (Value::Number(g), Value::Number(s)) if *g == 0.0 && *s == 0.0 && auto => {
+            vec![Value::Keyword("none".to_string())]
+        }
+        (Value::Number(g), Value::Number(s)) if *g == 1.0 && *s == 1.0 && auto => {
+            vec![Value::Keyword("auto".to_string())]
+        }
+        // A zero basis and a shrink factor of one are what a number alone
+        // implies.
+        (Value::Number(_), Value::Number(s)) if *s == 1.0 && zero => vec![grow.clone()],
+        (Value::Number(_), Value::Number(_)) if zero => vec![grow.clone(), shrink.clone()],
+        (Value::Number(_), Value::Number(s)) if *s == 1.0 => vec![grow.clone(), basis.clone()],
+        _ => vec![grow.clone(), shrink.clone(), basis.clone()],
+    }
+}
+
+// Any identifier that isn't an easing function names a property.
+fn is_transition_property(value: &Value) -> bool {
+    matches!(value, Value::Keyword(_)) && !is_easing(value)
+}
@@ -0,0 +1,77 @@
+use super::parser::CssParser;
+use super::properties::Property;
+use super::variables::is_custom_property;
+use std::fmt;
+
+/// The condition of an `@supports` rule, or of `supports()` in `@import`.
+/// Declarations and selectors are kept as written, and are checked against
+/// what this parser understands when the condition is evaluated.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SupportsCondition {
+    Not(Box<SupportsCondition>),
+    And(Vec<SupportsCondition>),
+    Or(Vec<SupportsCondition>),
+    // `(display: grid)`, without the parentheses.
+    Declaration(String),
+    // `selector(a > b)`, the selector list only.
+    Selector(String),
+    // Anything else in parentheses, or a function, which is false.
+    GeneralEnclosed(String),
+}
+
+impl SupportsCondition {
+    pub fn evaluate(&self) -> bool {
+        match self {
+            SupportsCondition::Not(condition) => !condition.evaluate(),
+            SupportsCondition::And(conditions) => conditions.iter().all(Self::evaluate),
+            SupportsCondition::Or(conditions) => conditions.iter().any(Self::evaluate),
+            // A known property with a value that is valid for it, as far as
+            // can be told before `var()` is substituted.
+            SupportsCondition::Declaration(text) => {
+                let (declarations, diagnostics) = CssParser::new(text).parse_declaration_list();
+                diagnostics.is_empty()
+                    && matches!(declarations.as_slice(), [declaration]
+                        if is_custom_property(&declaration.name)
+                            || Property::new(&declaration.name, declaration.value.clone()).is_some())
+            }
+            SupportsCondition::Selector(text) => CssParser::new(text).parse_selector_list().is_ok(),
+            SupportsCondition::GeneralEnclosed(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for SupportsCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |f: &mut fmt::Formatter, conditions: &[SupportsCondition], operator: &str| {
+            for (i, condition) in conditions.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " {} ", operator)?;
+                }
+                write_in_parens(f, condition)?;
+            }
+            Ok(())
+        };
+        match self {
+            SupportsCondition::Not(condition) => {
+                write!(f, "not ")?;
+                write_in_parens(f, condition)
+            }
+            SupportsCondition::And(conditions) => join(f, conditions, "and"),
+            SupportsCondition::Or(conditions) => join(f, conditions, "or"),
+            SupportsCondition::Declaration(text) => write!(f, "({})", text),
+            SupportsCondition::Selector(text) => write!(f, "selector({})", text),
+            SupportsCondition::GeneralEnclosed(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+// Declarations, selectors and general-enclosed conditions bring their own
+// parentheses.
+fn write_in_parens(f: &mut fmt::Formatter, condition: &SupportsCondition) -> fmt::Result {
+    match condition {
+        SupportsCondition::Not(_) | SupportsCondition::And(_) | SupportsCondition::Or(_) => {
+            write!(f, "({})", condition)
+        }
+        condition => write!(f, "{}", condition),
+    }
+}
@@ -0,0 +1,653 @@
+use crate::span::{Locator, Span};
+use std::fmt;
+
+/// Tokens from the CSS Syntax Level 3 tokenization algorithm. Comments are
+/// dropped; everything else, whitespace included, is kept.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Ident(String),
+    // The name without the '(' that ends it.
+    Function(String),
+    AtKeyword(String),
+    // `id` is set when the name is also a valid identifier, which `#123`
+    // is not.
+    Hash {
+        value: String,
+        id: bool,
+    },
+    String(String),
+    BadString,
+    Url(String),
+    BadUrl,
+    Delim(char),
+    // `integer` is the spec's "type" flag and `signed` records a leading
+    // '+' or '-', both of which the An+B microsyntax looks at.
+    Number {
+        value: f32,
+        integer: bool,
+        signed: bool,
+    },
+    Percentage(f32),
+    Dimension {
+        value: f32,
+        integer: bool,
+        signed: bool,
+        unit: String,
+    },
+    UnicodeRange(u32, u32),
+    Whitespace,
+    Cdo,
+    Cdc,
+    Colon,
+    Semicolon,
+    Comma,
+    OpenSquare,
+    CloseSquare,
+    OpenParen,
+    CloseParen,
+    OpenCurly,
+    CloseCurly,
+    EOF,
+}
+
+impl Token {
+    /// The token that closes a block opened by this one. A function is
+    /// closed like a '(' block.
+    pub fn closing(&self) -> Option<Token> {
+        match self {
+            Token::OpenParen | Token::Function(_) => Some(Token::CloseParen),
+            Token::OpenSquare => Some(Token::CloseSquare),
+            Token::OpenCurly => Some(Token::CloseCurly),
+            _ => None,
+        }
+    }
+}
+
+/// Serializes tokens back to CSS text that tokenizes the same, putting an
+/// empty comment between two tokens that would otherwise run together,
+/// like an identifier substituted right after a number.
+pub fn serialize(tokens: &[Token]) -> String {
+    let mut css = String::new();
+    for (i, token) in tokens.iter().enumerate() {
+        if i > 0 && needs_comment(&tokens[i - 1], token) {
+            css.push_str("/**/");
+        }
+        css.push_str(&token.to_string());
+    }
+    css
+}
+
+// The pairs from the serialization table of CSS Syntax Level 3.
+fn needs_comment(before: &Token, after: &Token) -> bool {
+    let ident_like = matches!(
+        after,
+        Token::Ident(_) | Token::Function(_) | Token::Url(_) | Token::BadUrl
+    );
+    let numeric = matches!(
+        after,
+        Token::Number { .. } | Token::Percentage(_) | Token::Dimension { .. }
+    );
+    match before {
+        Token::Ident(_) => {
+            ident_like
+                || numeric
+                || matches!(after, Token::Delim('-') | Token::Cdc | Token::OpenParen)
+        }
+        Token::AtKeyword(_) | Token::Hash { .. } | Token::Dimension { .. } => {
+            ident_like || numeric || matches!(after, Token::Delim('-') | Token::Cdc)
+        }
+        Token::Delim('#' | '-') => {
+            ident_like || numeric || matches!(after, Token::Delim('-') | Token::Cdc)
+        }
+        Token::Number { .. } => ident_like || numeric || matches!(after, Token::Delim('%')),
+        Token::Delim('@') => ident_like || matches!(after, Token::Delim('-') | Token::Cdc),
+        Token::Delim('.' | '+') => numeric,
+        Token::Delim('/') => matches!(after, Token::Delim('*')),
+        _ => false,
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write_ident(f, name),
+            Token::Function(name) => {
+                write_ident(f, name)?;
+                write!(f, "(")
+            }
+            Token::AtKeyword(name) => {
+                write!(f, "@")?;
+                write_ident(f, name)
+            }
+            Token::Hash { value, .. } => {
+                write!(f, "#")?;
+                for c in value.chars() {
+                    write_name_char(f, c)?;
+                }
+                Ok(())
+            }
+            Token::String(value) => write_string(f, value),
+            // Only a string or URL cut short by a newline is bad.
+            Token::BadString => writeln!(f, "\""),
+            Token::Url(url) => {
+                write!(f, "url(")?;
+                for c in url.chars() {
+                    match c {
+                        '"' | '\'' | '(' | ')' | '\\' => write!(f, "\\{}", c)?,
+                        c if is_whitespace(c) || is_non_printable(c) => {
+                            write!(f, "\\{:x} ", c as u32)?
+                        }
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, ")")
+            }
+            Token::BadUrl => write!(f, "url()"),
+            Token::Delim(c) => write!(f, "{}", c),
+            Token::Number { value, signed, .. } => write_number(f, *value, *signed),
+            Token::Percentage(value) => write!(f, "{}%", value),
+            Token::Dimension {
+                value,
+                signed,
+                unit,
+                ..
+            } => {
+                write_number(f, *value, *signed)?;
+                // A unit like `e3` would read back as an exponent.
+                let mut chars = unit.chars();
+                match chars.next() {
+                    Some(c @ ('e' | 'E'))
+                        if chars.next().is_some_and(|c| c.is_ascii_digit() || c == '-') =>
+                    {
+                        write!(f, "\\{:x} ", c as u32)?;
+                        write_ident(f, &unit[1..])
+                    }
+                    _ => write_ident(f, unit),
+                }
+            }
+            Token::UnicodeRange(start, end) if start == end => write!(f, "U+{:X}", start),
+            Token::UnicodeRange(start, end) => write!(f, "U+{:X}-{:X}", start, end),
+            Token::Whitespace => write!(f, " "),
+            Token::Cdo => write!(f, "<!--"),
+            Token::Cdc => write!(f, "-->"),
+            Token::Colon => write!(f, ":"),
+            Token::Semicolon => write!(f, ";"),
+            Token::Comma => write!(f, ","),
+            Token::OpenSquare => write!(f, "["),
+            Token::CloseSquare => write!(f, "]"),
+            Token::OpenParen => write!(f, "("),
+            Token::CloseParen => write!(f, ")"),
+            Token::OpenCurly => write!(f, "{{"),
+            Token::CloseCurly => write!(f, "}}"),
+            Token::EOF => Ok(()),
+        }
+    }
+}
+
+// "Serialize an identifier" from CSSOM.
+fn write_ident(f: &mut fmt::Formatter, name: &str) -> fmt::Result {
+    if name == "-" {
+        return write!(f, "\\-");
+    }
+    for (i, c) in name.chars().enumerate() {
+        let leading_digit = c.is_ascii_digit() && (i == 0 || (i == 1 && name.starts_with('-')));
+        if leading_digit {
+            write!(f, "\\{:x} ", c as u32)?;
+        } else {
+            write_name_char(f, c)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_name_char(f: &mut fmt::Formatter, c: char) -> fmt::Result {
+    match c {
+        '\0' => write!(f, "{}", char::REPLACEMENT_CHARACTER),
+        c if is_non_printable(c) || c == '\t' || is_newline(c) => write!(f, "\\{:x} ", c as u32),
+        c if is_name_char(c) => write!(f, "{}", c),
+        c => write!(f, "\\{}", c),
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, value: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => write!(f, "\\{}", c)?,
+            c if is_non_printable(c) || is_newline(c) => write!(f, "\\{:x} ", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+fn write_number(f: &mut fmt::Formatter, value: f32, signed: bool) -> fmt::Result {
+    if signed && value >= 0.0 {
+        write!(f, "+")?;
+    }
+    write!(f, "{}", value)
+}
+
+pub struct Tokenizer<'a> {
+    input: &'a str,
+    // Byte offset of the cursor, always on a char boundary.
+    position: usize,
+    locator: Locator,
+}
+
+impl<'a> Tokenizer<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Tokenizer {
+            input,
+            position: 0,
+            locator: Locator::new(),
+        }
+    }
+
+    /// Consumes the next token, returning `Token::EOF` at the end of the
+    /// input. The span covers the token's source text, escapes and quotes
+    /// included.
+    pub fn next_token(&mut self) -> (Token, Span) {
+        self.consume_comments();
+        let start = self.position;
+        let token = self.consume_token();
+        let span = self.locator.span(self.input, start, self.position);
+        (token, span)
+    }
+
+    fn consume_comments(&mut self) {
+        while self.input[self.position..].starts_with("/*") {
+            // An unterminated comment runs to the end of the input.
+            self.position = match self.input[self.position + 2..].find("*/") {
+                Some(end) => self.position + 2 + end + 2,
+                None => self.input.len(),
+            };
+        }
+    }
+
+    fn consume_token(&mut self) -> Token {
+        let Some(c) = self.consume_char() else {
+            return Token::EOF;
+        };
+        match c {
+            c if is_whitespace(c) => {
+                while self.peek(0).is_some_and(is_whitespace) {
+                    self.consume_char();
+                }
+                Token::Whitespace
+            }
+            '"' | '\'' => self.consume_string(c),
+            '#' => {
+                if self.peek(0).is_some_and(is_name_char) || self.starts_escape(0) {
+                    let id = self.starts_ident(0);
+                    Token::Hash {
+                        value: self.consume_name(),
+                        id,
+                    }
+                } else {
+                    Token::Delim('#')
+                }
+            }
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            '[' => Token::OpenSquare,
+            ']' => Token::CloseSquare,
+            '{' => Token::OpenCurly,
+            '}' => Token::CloseCurly,
+            ',' => Token::Comma,
+            ':' => Token::Colon,
+            ';' => Token::Semicolon,
+            '+' | '-' | '.' if self.starts_number(-1) => {
+                self.reconsume(c);
+                self.consume_numeric()
+            }
+            '-' if self.rest().starts_with("->") => {
+                self.position += 2;
+                Token::Cdc
+            }
+            '-' if self.starts_ident(-1) => {
+                self.reconsume(c);
+                self.consume_ident_like()
+            }
+            '<' if self.rest().starts_with("!--") => {
+                self.position += 3;
+                Token::Cdo
+            }
+            '@' if self.starts_ident(0) => Token::AtKeyword(self.consume_name()),
+            '\\' if self.starts_escape(-1) => {
+                self.reconsume(c);
+                self.consume_ident_like()
+            }
+            '0'..='9' => {
+                self.reconsume(c);
+                self.consume_numeric()
+            }
+            'u' | 'U'
+                if self.peek(0) == Some('+')
+                    && self
+                        .peek(1)
+                        .is_some_and(|c| c.is_ascii_hexdigit() || c == '?') =>
+            {
+                self.consume_char();
+                self.consume_unicode_range()
+            }
+            c if is_name_start_char(c) => {
+                self.reconsume(c);
+                self.consume_ident_like()
+            }
+            c => Token::Delim(c),
+        }
+    }
+
+    // The opening quote has been consumed.
+    fn consume_string(&mut self, quote: char) -> Token {
+        let mut value = String::new();
+        loop {
+            match self.peek(0) {
+                // Unterminated at the end of the input is still a string.
+                None => return Token::String(value),
+                Some(c) if c == quote => {
+                    self.consume_char();
+                    return Token::String(value);
+                }
+                // The newline is left for the next token.
+                Some(c) if is_newline(c) => return Token::BadString,
+                Some('\\') => {
+                    self.consume_char();
+                    match self.peek(0) {
+                        None => {}
+                        Some(c) if is_newline(c) => self.consume_newline(),
+                        Some(_) => value.push(self.consume_escape()),
+                    }
+                }
+                Some(_) => value.push(replace_null(self.consume_char().unwrap())),
+            }
+        }
+    }
+
+    fn consume_numeric(&mut self) -> Token {
+        let start = self.position;
+        let signed = matches!(self.peek(0), Some('+' | '-'));
+        if signed {
+            self.consume_char();
+        }
+        self.consume_digits();
+        let mut integer = true;
+        if self.peek(0) == Some('.') && self.peek(1).is_some_and(|c| c.is_ascii_digit()) {
+            integer = false;
+            self.position += 1;
+            self.consume_digits();
+        }
+        if matches!(self.peek(0), Some('e' | 'E')) {
+            let digits_at = if matches!(self.peek(1), Some('+' | '-')) {
+                2
+            } else {
+                1
+            };
+            if self.peek(digits_at).is_some_and(|c| c.is_ascii_digit()) {
+                integer = false;
+                self.position += digits_at as usize;
+                self.consume_digits();
+            }
+        }
+        // Out of range exponents saturate to infinity, which is fine here.
+        let value = self.input[start..self.position]
+            .parse::<f64>()
+            .unwrap_or(0.0) as f32;
+
+        if self.starts_ident(0) {
+            Token::Dimension {
+                value,
+                integer,
+                signed,
+                unit: self.consume_name(),
+            }
+        } else if self.peek(0) == Some('%') {
+            self.consume_char();
+            Token::Percentage(value)
+        } else {
+            Token::Number {
+                value,
+                integer,
+                signed,
+            }
+        }
+    }
+
+    fn consume_digits(&mut self) {
+        while self.peek(0).is_some_and(|c| c.is_ascii_digit()) {
+            self.position += 1;
+        }
+    }
+
+    // Names, functions and `url(` with an unquoted URL.
+    fn consume_ident_like(&mut self) -> Token {
+        let name = self.consume_name();
+        if self.peek(0) != Some('(') {
+            return Token::Ident(name);
+        }
+        self.consume_char();
+        if !name.eq_ignore_ascii_case("url") {
+            return Token::Function(name);
+        }
+
+        // A quoted URL is a plain function whose argument is a string; the
+        // whitespace before the quote becomes a token of its own.
+        while self.peek(0).is_some_and(is_whitespace) && self.peek(1).is_some_and(is_whitespace) {
+            self.consume_char();
+        }
+        let quote_at = if self.peek(0).is_some_and(is_whitespace) {
+            1
+        } else {
+            0
+        };
+        if matches!(self.peek(quote_at), Some('"' | '\'')) {
+            return Token::Function(name);
+        }
+        self.consume_url()
+    }
+
+    // `url(` has been consumed.
+    fn consume_url(&mut self) -> Token {
+        let mut url = String::new();
+        while self.peek(0).is_some_and(is_whitespace) {
+            self.consume_char();
+        }
+        loop {
+            match self.consume_char() {
+                Some(')') | None => return Token::Url(url),
+                Some(c) if is_whitespace(c) => {
+                    while self.peek(0).is_some_and(is_whitespace) {
+                        self.consume_char();
+                    }
+                    return match self.peek(0) {
+                        Some(')') | None => {
+                            self.consume_char();
+                            Token::Url(url)
+                        }
+                        Some(_) => self.consume_bad_url(),
+                    };
+                }
+                Some('"' | '\'' | '(') => return self.consume_bad_url(),
+                Some(c) if is_non_printable(c) => return self.consume_bad_url(),
+                Some('\\') if self.starts_escape(-1) => url.push(self.consume_escape()),
+                Some('\\') => return self.consume_bad_url(),
+                Some(c) => url.push(replace_null(c)),
+            }
+        }
+    }
+
+    // Skips what is left of a bad URL, up to and including its ')', so that
+    // the parser can recover.
+    fn consume_bad_url(&mut self) -> Token {
+        loop {
+            match self.consume_char() {
+                Some(')') | None => return Token::BadUrl,
+                Some('\\') if self.starts_escape(-1) => {
+                    self.consume_escape();
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    // `U+` has been consumed. `?` wildcards stand for any hex digit.
+    fn consume_unicode_range(&mut self) -> Token {
+        let start_at = self.position;
+        while self.position - start_at < 6 && self.peek(0).is_some_and(|c| c.is_ascii_hexdigit()) {
+            self.position += 1;
+        }
+        while self.position - start_at < 6 && self.peek(0) == Some('?') {
+            self.position += 1;
+        }
+        let digits = &self.input[start_at..self.position];
+        if digits.contains('?') {
+            let low = u32::from_str_radix(&digits.replace('?', "0"), 16).unwrap_or(0);
+            let high = u32::from_str_radix(&digits.replace('?', "F"), 16).unwrap_or(0);
+            return Token::UnicodeRange(low, high);
+        }
+        let low = u32::from_str_radix(digits, 16).unwrap_or(0);
+
+        let mut high = low;
+        if self.peek(0) == Some('-') && self.peek(1).is_some_and(|c| c.is_ascii_hexdigit()) {
+            self.position += 1;
+            let end_at = self.position;
+            while self.position - end_at < 6 && self.peek(0).is_some_and(|c| c.is_ascii_hexdigit())
+            {
+                self.position += 1;
+            }
+            high = u32::from_str_radix(&self.input[end_at..self.position], 16).unwrap_or(0);
+        }
+        Token::UnicodeRange(low, high)
+    }
+
+    fn consume_name(&mut self) -> String {
+        let mut name = String::new();
+        loop {
+            match self.peek(0) {
+                Some(c) if is_name_char(c) => {
+                    self.consume_char();
+                    name.push(replace_null(c));
+                }
+                Some('\\') if self.starts_escape(0) => {
+                    self.consume_char();
+                    name.push(self.consume_escape());
+                }
+                _ => return name,
+            }
+        }
+    }
+
+    // The '\' has been consumed. Up to six hex digits name a code point, and
+    // one whitespace character after them is part of the escape.
+    fn consume_escape(&mut self) -> char {
+        let Some(c) = self.consume_char() else {
+            return char::REPLACEMENT_CHARACTER;
+        };
+        if !c.is_ascii_hexdigit() {
+            return replace_null(c);
+        }
+        let start = self.position - 1;
+        while self.position - start < 6 && self.peek(0).is_some_and(|c| c.is_ascii_hexdigit()) {
+            self.position += 1;
+        }
+        let code = u32::from_str_radix(&self.input[start..self.position], 16).unwrap_or(0);
+        if self.peek(0).is_some_and(is_whitespace) {
+            self.consume_newline();
+        }
+        match char::from_u32(code) {
+            Some('\0') | None => char::REPLACEMENT_CHARACTER,
+            Some(c) => c,
+        }
+    }
+
+    // Consumes one whitespace character, counting "\r\n" as one.
+    fn consume_newline(&mut self) {
+        if self.consume_char() == Some('\r') && self.peek(0) == Some('\n') {
+            self.consume_char();
+        }
+    }
+
+    // Whether the characters at `offset`, relative to the cursor in
+    // characters, start a number. An offset of -1 looks at the character
+    // just consumed.
+    fn starts_number(&self, offset: isize) -> bool {
+        match self.peek(offset) {
+            Some('+' | '-') => match self.peek(offset + 1) {
+                Some('.') => self.peek(offset + 2).is_some_and(|c| c.is_ascii_digit()),
+                c => c.is_some_and(|c| c.is_ascii_digit()),
+            },
+            Some('.') => self.peek(offset + 1).is_some_and(|c| c.is_ascii_digit()),
+            c => c.is_some_and(|c| c.is_ascii_digit()),
+        }
+    }
+
+    fn starts_ident(&self, offset: isize) -> bool {
+        match self.peek(offset) {
+            Some('-') => {
+                self.peek(offset + 1)
+                    .is_some_and(|c| is_name_start_char(c) || c == '-')
+                    || self.starts_escape(offset + 1)
+            }
+            Some('\\') => self.starts_escape(offset),
+            c => c.is_some_and(is_name_start_char),
+        }
+    }
+
+    fn starts_escape(&self, offset: isize) -> bool {
+        self.peek(offset) == Some('\\') && self.peek(offset + 1).is_some_and(|c| !is_newline(c))
+    }
+
+    // The character `offset` characters from the cursor, where -1 is the one
+    // before it.
+    fn peek(&self, offset: isize) -> Option<char> {
+        if offset < 0 {
+            return self.input[..self.position].chars().next_back();
+        }
+        self.rest().chars().nth(offset as usize)
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.position..]
+    }
+
+    // Steps back over `c`, which was consumed to pick the token type.
+    fn reconsume(&mut self, c: char) {
+        self.position -= c.len_utf8();
+    }
+
+    fn consume_char(&mut self) -> Option<char> {
+        let c = self.rest().chars().next()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+}
+
+fn is_newline(c: char) -> bool {
+    matches!(c, '\n' | '\r' | '\x0c')
+}
+
+fn is_whitespace(c: char) -> bool {
+    is_newline(c) || c == ' ' || c == '\t'
+}
+
+fn is_name_start_char(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '\0' || !c.is_ascii()
+}
+
+fn is_name_char(c: char) -> bool {
+    is_name_start_char(c) || c.is_ascii_digit() || c == '-'
+}
+
+fn is_non_printable(c: char) -> bool {
+    matches!(c, '\x01'..='\x08' | '\x0b' | '\x0e'..='\x1f' | '\x7f')
+}
+
+// NUL is replaced in the input stream before tokenizing.
+fn replace_null(c: char) -> char {
+    if c == '\0' {
+        char::REPLACEMENT_CHARACTER
+    } else {
+        c
+    }
+}
@@ -0,0 +1,248 @@
+use super::parser::CssParser;
+use super::rules::Stylesheet;
+use std::sync::OnceLock;
+
+// The user agent stylesheet, after the Rendering section of the HTML
+// Standard.
+const USER_AGENT_CSS: &str = r#"
+/* Hidden elements */
+area, base, basefont, datalist, head, link, meta, noembed,
+noframes, param, rp, script, style, template, title {
+  display: none;
+}
+
+[hidden], dialog:not([open]) {
+  display: none;
+}
+
+/* Flow content */
+html, body, address, blockquote, center, div, figure, figcaption,
+footer, form, header, hr, legend, listing, main, p, plaintext, pre,
+search, xmp, article, aside, h1, h2, h3, h4, h5, h6, hgroup, nav,
+section, details, summary, dialog, fieldset {
+  display: block;
+}
+
+body {
+  margin: 8px;
+}
+
+p, blockquote, figure, listing, plaintext, pre, xmp, dl {
+  margin-top: 1em;
+  margin-bottom: 1em;
+}
+
+blockquote, figure {
+  margin-left: 40px;
+  margin-right: 40px;
+}
+
+address, cite, dfn, em, i, var {
+  font-style: italic;
+}
+
+b, strong, th, h1, h2, h3, h4, h5, h6 {
+  font-weight: bold;
+}
+
+listing, plaintext, pre, xmp {
+  font-family: monospace;
+  white-space: pre;
+}
+
+code, kbd, samp, tt {
+  font-family: monospace;
+}
+
+center {
+  text-align: center;
+}
+
+hr {
+  margin-top: 0.5em;
+  margin-bottom: 0.5em;
+  border-style: inset;
+  border-width: 1px;
+}
+
+fieldset {
+  margin-left: 2px;
+  margin-right: 2px;
+  border: 2px groove threedface;
+  padding: 0.35em 0.75em 0.625em;
+}
+
+/* Headings */
+h1 { margin-top: 0.67em; margin-bottom: 0.67em; font-size: 2em; }
+h2 { margin-top: 0.83em; margin-bottom: 0.83em; font-size: 1.5em; }
+h3 { margin-top: 1em; margin-bottom: 1em; font-size: 1.17em; }
+h4 { margin-top: 1.33em; margin-bottom: 1.33em; font-size: 1em; }
+h5 { margin-top: 1.67em; margin-bottom: 1.67em; font-size: 0.83em; }
+h6 { margin-top: 2.33em; margin-bottom: 2.33em; font-size: 0.67em; }
+
+/* Phrasing content */
+a:link, a:visited {
+  color: #0000ee;
+  text-decoration: underline;
+  cursor: pointer;
+}
+
+a:visited {
+  color: #551a8b;
+}
+
+u, ins {
+  text-decoration: underline;
+}
+
+s, strike, del {
+  text-decoration: line-through;
+}
+
+big {
+  font-size: larger;
+}
+
+small {
+  font-size: smaller;
+}
+
+sub {
+  vertical-align: sub;
+  font-size: smaller;
+}
+
+sup {
+  vertical-align: super;
+  font-size: smaller;
+}
+
+mark {
+  background-color: yellow;
+  color: black;
+}
+
+nobr {
+  white-space: nowrap;
+}
+
+/* Lists */
+ul, menu, dir, ol, dl {
+  display: block;
+}
+
+ul, menu, dir, ol {
+  margin-top: 1em;
+  margin-bottom: 1em;
+  padding-left: 40px;
+}
+
+:is(ul, ol, menu, dir) :is(ul, ol, menu, dir) {
+  margin-top: 0;
+  margin-bottom: 0;
+}
+
+dd {
+  display: block;
+  margin-left: 40px;
+}
+
+dt {
+  display: block;
+}
+
+ul, menu, dir {
+  list-style-type: disc;
+}
+
+ol {
+  list-style-type: decimal;
+}
+
+:is(ul, ol, menu, dir) :is(ul, menu, dir) {
+  list-style-type: circle;
+}
+
+:is(ul, ol, menu, dir) :is(ul, ol, menu, dir) :is(ul, menu, dir) {
+  list-style-type: square;
+}
+
+li {
+  display: list-item;
+}
+
+summary {
+  display: list-item;
+  list-style-type: disclosure-closed;
+  list-style-position: inside;
+}
+
+details[open] > summary {
+  list-style-type: disclosure-open;
+}
+
+/* Tables */
+table {
+  display: table;
+  box-sizing: border-box;
+}
+
+caption {
+  display: table-caption;
+  text-align: center;
+}
+
+colgroup {
+  display: table-column-group;
+}
+
+col {
+  display: table-column;
+}
+
+thead {
+  display: table-header-group;
+  vertical-align: middle;
+}
+
+tbody {
+  display: table-row-group;
+  vertical-align: middle;
+}
+
+tfoot {
+  display: table-footer-group;
+  vertical-align: middle;
+}
+
+tr {
+  display: table-row;
+  vertical-align: inherit;
+}
+
+td, th {
+  display: table-cell;
+  vertical-align: inherit;
+  padding: 1px;
+}
+
+th {
+  text-align: center;
+}
+
+/* Form controls */
+input, select, button, textarea {
+  display: inline-block;
+}
+
+textarea {
+  white-space: pre-wrap;
+}
+"#;
+
+/// The default styles of HTML elements, parsed the first time they are
+/// needed.
+pub fn user_agent_stylesheet() -> &'static Stylesheet {
+    static STYLESHEET: OnceLock<Stylesheet> = OnceLock::new();
+    STYLESHEET.get_or_init(|| CssParser::new(USER_AGENT_CSS).parse_stylesheet().0)
+}
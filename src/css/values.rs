@@ -1,15 +1,23 @@
+use super::calc::{Calc, CalcContext, CalcType};
+use super::tokenizer::{self, Token};
 use std::fmt;
 use std::str::FromStr;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Keyword(String),
+    Number(f32),
     Length(f32, Unit),
+    // A number with a unit this parser doesn't know, which no grammar
+    // accepts.
+    Dimension(f32, String),
     Percentage(f32),
     Color(Color),
     Url(String),
     String(String),
     Function(String, Vec<Value>),
+    // A math function whose arguments parsed as a calculation.
+    Calc(Box<Calc>),
     Rect(Rect),
     Initial,
     Inherit,
@@ -19,6 +27,19 @@ pub enum Value {
     None,
     LinearGradient(Box<LinearGradient>), // Boxed to prevent infinite size
     List(Vec<Value>),
+    Unparsed(Unparsed),
+}
+
+/// A value kept as tokens until its `var()` references are substituted at
+/// computed-value time: that of a custom property, or of any declaration
+/// that uses `var()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unparsed {
+    // Without leading and trailing whitespace.
+    pub tokens: Vec<Token>,
+    // For a longhand set by a shorthand that uses `var()`, the shorthand,
+    // whose value the tokens are.
+    pub shorthand: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -32,6 +53,32 @@ pub enum Unit {
     Vh,
     Vmin,
     Vmax,
+    Vi,
+    Vb,
+    Svw,
+    Svh,
+    Svi,
+    Svb,
+    Svmin,
+    Svmax,
+    Lvw,
+    Lvh,
+    Lvi,
+    Lvb,
+    Lvmin,
+    Lvmax,
+    Dvw,
+    Dvh,
+    Dvi,
+    Dvb,
+    Dvmin,
+    Dvmax,
+    Cqw,
+    Cqh,
+    Cqi,
+    Cqb,
+    Cqmin,
+    Cqmax,
     Pt,
     Pc,
     In,
@@ -88,6 +135,14 @@ impl Value {
             Value::Length(n, Unit::Em) => *n * base_font_size,
             Value::Length(n, Unit::Rem) => *n * base_font_size,
             Value::Percentage(p) => *p / 100.0 * base_font_size,
+            Value::Calc(calc) => calc
+                .resolve(&CalcContext {
+                    font_size: base_font_size,
+                    root_font_size: base_font_size,
+                    viewport: None,
+                    percentage_basis: Some(base_font_size),
+                })
+                .unwrap_or(0.0),
             _ => 0.0,
         }
     }
@@ -96,11 +151,199 @@ impl Value {
         match self {
             Value::Color(c) => Some(c.clone()),
             Value::CurrentColor => None,
+            Value::Keyword(name) => Color::parse_named(name),
             _ => None,
         }
     }
 }
 
+impl LinearGradient {
+    /// Builds a gradient from the comma-separated arguments of
+    /// `linear-gradient()`: an optional angle or `to <side>`, then at least
+    /// two color stops. A stop with two positions stands for two stops.
+    pub fn from_arguments(arguments: &[Value]) -> Option<Self> {
+        let (direction, stops) = match arguments.first()? {
+            direction if is_gradient_direction(direction) => (direction.clone(), &arguments[1..]),
+            _ => (
+                Value::List(vec![
+                    Value::Keyword("to".to_string()),
+                    Value::Keyword("bottom".to_string()),
+                ]),
+                arguments,
+            ),
+        };
+
+        let mut gradient = LinearGradient {
+            direction: Box::new(direction),
+            stops: Vec::new(),
+        };
+        for stop in stops {
+            let (color, positions) = match stop {
+                Value::List(values) => values.split_first()?,
+                value => (value, &[][..]),
+            };
+            let color = color.to_color()?;
+            match positions {
+                [] => gradient.stops.push(GradientStop {
+                    color,
+                    position: None,
+                }),
+                [_] | [_, _] if positions.iter().all(is_stop_position) => {
+                    for position in positions {
+                        gradient.stops.push(GradientStop {
+                            color: color.clone(),
+                            position: Some(position.clone()),
+                        });
+                    }
+                }
+                _ => return None,
+            }
+        }
+
+        (gradient.stops.len() >= 2).then_some(gradient)
+    }
+}
+
+// An angle, or `to` with one or two sides, like `to top left`.
+fn is_gradient_direction(value: &Value) -> bool {
+    match value {
+        Value::Length(_, Unit::Deg | Unit::Rad | Unit::Grad | Unit::Turn) => true,
+        Value::List(values) => match values.split_first() {
+            Some((Value::Keyword(to), sides)) if to.eq_ignore_ascii_case("to") => {
+                matches!(sides.len(), 1 | 2)
+                    && sides.iter().all(|side| {
+                        matches!(side, Value::Keyword(side)
+                            if matches!(side.to_ascii_lowercase().as_str(),
+                                "top" | "right" | "bottom" | "left"))
+                    })
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn is_stop_position(value: &Value) -> bool {
+    match value {
+        Value::Length(_, unit) => !matches!(
+            unit,
+            Unit::Deg | Unit::Rad | Unit::Grad | Unit::Turn | Unit::S | Unit::Ms
+        ),
+        Value::Percentage(_) => true,
+        Value::Number(n) => *n == 0.0,
+        Value::Calc(calc) => matches!(
+            calc.kind(),
+            Some(CalcType::Length | CalcType::Percentage | CalcType::LengthPercentage)
+        ),
+        _ => false,
+    }
+}
+
+// Serializes as CSS text.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Keyword(keyword) => write!(f, "{}", keyword),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Length(n, unit) => write!(f, "{}{}", n, unit),
+            Value::Dimension(n, unit) => write!(f, "{}{}", n, unit),
+            Value::Percentage(p) => write!(f, "{}%", p),
+            Value::Color(color) => write!(f, "{}", color),
+            Value::Url(url) => write!(f, "url({:?})", url),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::Function(name, arguments) => {
+                write!(f, "{}(", name)?;
+                write_separated(f, arguments, ", ")?;
+                write!(f, ")")
+            }
+            // `min()`, `max()` and `clamp()` need no `calc()` around them.
+            Value::Calc(calc) => match **calc {
+                Calc::Min(_) | Calc::Max(_) | Calc::Clamp(..) => write!(f, "{}", calc),
+                _ => write!(f, "calc({})", calc),
+            },
+            Value::Rect(rect) => write!(
+                f,
+                "rect({}, {}, {}, {})",
+                rect.top, rect.right, rect.bottom, rect.left
+            ),
+            Value::Initial => write!(f, "initial"),
+            Value::Inherit => write!(f, "inherit"),
+            Value::Unset => write!(f, "unset"),
+            Value::CurrentColor => write!(f, "currentcolor"),
+            Value::Auto => write!(f, "auto"),
+            Value::None => write!(f, "none"),
+            Value::LinearGradient(gradient) => {
+                write!(f, "linear-gradient({}", gradient.direction)?;
+                for stop in &gradient.stops {
+                    write!(f, ", {}", stop.color)?;
+                    if let Some(position) = &stop.position {
+                        write!(f, " {}", position)?;
+                    }
+                }
+                write!(f, ")")
+            }
+            Value::Unparsed(unparsed) => write!(f, "{}", tokenizer::serialize(&unparsed.tokens)),
+            Value::List(values) => {
+                for (i, value) in values.iter().enumerate() {
+                    // A comma hugs the component before it.
+                    if i > 0 && !matches!(value, Value::Keyword(k) if k == ",") {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn write_separated(f: &mut fmt::Formatter, values: &[Value], separator: &str) -> fmt::Result {
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            write!(f, "{}", separator)?;
+        }
+        write!(f, "{}", value)?;
+    }
+    Ok(())
+}
+
+// Serializes as `rgb()`, or `rgba()` when not opaque, like CSSOM does.
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.a == 1.0 {
+            write!(f, "rgb({}, {}, {})", self.r, self.g, self.b)
+        } else {
+            write!(f, "rgba({}, {}, {}, {})", self.r, self.g, self.b, self.a)
+        }
+    }
+}
+
+impl Unit {
+    /// Whether the unit is a percentage of the viewport, or of a size
+    /// container.
+    pub fn is_viewport_relative(&self) -> bool {
+        self.viewport_length(0.0, (0.0, 0.0)).is_some()
+    }
+
+    /// `n` of a viewport-relative unit in pixels, for a viewport of the
+    /// given width and height. There is no browser UI to come and go, so
+    /// the small, large and dynamic viewports are all the same; without
+    /// size containers, container units fall back to the small viewport;
+    /// and writing is horizontal, so the inline axis is the width.
+    pub fn viewport_length(&self, n: f32, (width, height): (f32, f32)) -> Option<f32> {
+        let size = match self {
+            Unit::Vw | Unit::Svw | Unit::Lvw | Unit::Dvw | Unit::Cqw => width,
+            Unit::Vi | Unit::Svi | Unit::Lvi | Unit::Dvi | Unit::Cqi => width,
+            Unit::Vh | Unit::Svh | Unit::Lvh | Unit::Dvh | Unit::Cqh => height,
+            Unit::Vb | Unit::Svb | Unit::Lvb | Unit::Dvb | Unit::Cqb => height,
+            Unit::Vmin | Unit::Svmin | Unit::Lvmin | Unit::Dvmin | Unit::Cqmin => width.min(height),
+            Unit::Vmax | Unit::Svmax | Unit::Lvmax | Unit::Dvmax | Unit::Cqmax => width.max(height),
+            _ => return None,
+        };
+        Some(n / 100.0 * size)
+    }
+}
+
 impl FromStr for Unit {
     type Err = ();
 
@@ -115,6 +358,32 @@ impl FromStr for Unit {
             "vh" => Ok(Unit::Vh),
             "vmin" => Ok(Unit::Vmin),
             "vmax" => Ok(Unit::Vmax),
+            "vi" => Ok(Unit::Vi),
+            "vb" => Ok(Unit::Vb),
+            "svw" => Ok(Unit::Svw),
+            "svh" => Ok(Unit::Svh),
+            "svi" => Ok(Unit::Svi),
+            "svb" => Ok(Unit::Svb),
+            "svmin" => Ok(Unit::Svmin),
+            "svmax" => Ok(Unit::Svmax),
+            "lvw" => Ok(Unit::Lvw),
+            "lvh" => Ok(Unit::Lvh),
+            "lvi" => Ok(Unit::Lvi),
+            "lvb" => Ok(Unit::Lvb),
+            "lvmin" => Ok(Unit::Lvmin),
+            "lvmax" => Ok(Unit::Lvmax),
+            "dvw" => Ok(Unit::Dvw),
+            "dvh" => Ok(Unit::Dvh),
+            "dvi" => Ok(Unit::Dvi),
+            "dvb" => Ok(Unit::Dvb),
+            "dvmin" => Ok(Unit::Dvmin),
+            "dvmax" => Ok(Unit::Dvmax),
+            "cqw" => Ok(Unit::Cqw),
+            "cqh" => Ok(Unit::Cqh),
+            "cqi" => Ok(Unit::Cqi),
+            "cqb" => Ok(Unit::Cqb),
+            "cqmin" => Ok(Unit::Cqmin),
+            "cqmax" => Ok(Unit::Cqmax),
             "pt" => Ok(Unit::Pt),
             "pc" => Ok(Unit::Pc),
             "in" => Ok(Unit::In),
@@ -153,6 +422,32 @@ impl fmt::Display for Unit {
                 Unit::Vh => "vh",
                 Unit::Vmin => "vmin",
                 Unit::Vmax => "vmax",
+                Unit::Vi => "vi",
+                Unit::Vb => "vb",
+                Unit::Svw => "svw",
+                Unit::Svh => "svh",
+                Unit::Svi => "svi",
+                Unit::Svb => "svb",
+                Unit::Svmin => "svmin",
+                Unit::Svmax => "svmax",
+                Unit::Lvw => "lvw",
+                Unit::Lvh => "lvh",
+                Unit::Lvi => "lvi",
+                Unit::Lvb => "lvb",
+                Unit::Lvmin => "lvmin",
+                Unit::Lvmax => "lvmax",
+                Unit::Dvw => "dvw",
+                Unit::Dvh => "dvh",
+                Unit::Dvi => "dvi",
+                Unit::Dvb => "dvb",
+                Unit::Dvmin => "dvmin",
+                Unit::Dvmax => "dvmax",
+                Unit::Cqw => "cqw",
+                Unit::Cqh => "cqh",
+                Unit::Cqi => "cqi",
+                Unit::Cqb => "cqb",
+                Unit::Cqmin => "cqmin",
+                Unit::Cqmax => "cqmax",
                 Unit::Pt => "pt",
                 Unit::Pc => "pc",
                 Unit::In => "in",
@@ -177,6 +472,158 @@ impl fmt::Display for Unit {
     }
 }
 
+// The named colors of CSS Color Module Level 4, sorted by name.
+const NAMED_COLORS: &[(&str, u8, u8, u8)] = &[
+    ("aliceblue", 0xf0, 0xf8, 0xff),
+    ("antiquewhite", 0xfa, 0xeb, 0xd7),
+    ("aqua", 0x00, 0xff, 0xff),
+    ("aquamarine", 0x7f, 0xff, 0xd4),
+    ("azure", 0xf0, 0xff, 0xff),
+    ("beige", 0xf5, 0xf5, 0xdc),
+    ("bisque", 0xff, 0xe4, 0xc4),
+    ("black", 0x00, 0x00, 0x00),
+    ("blanchedalmond", 0xff, 0xeb, 0xcd),
+    ("blue", 0x00, 0x00, 0xff),
+    ("blueviolet", 0x8a, 0x2b, 0xe2),
+    ("brown", 0xa5, 0x2a, 0x2a),
+    ("burlywood", 0xde, 0xb8, 0x87),
+    ("cadetblue", 0x5f, 0x9e, 0xa0),
+    ("chartreuse", 0x7f, 0xff, 0x00),
+    ("chocolate", 0xd2, 0x69, 0x1e),
+    ("coral", 0xff, 0x7f, 0x50),
+    ("cornflowerblue", 0x64, 0x95, 0xed),
+    ("cornsilk", 0xff, 0xf8, 0xdc),
+    ("crimson", 0xdc, 0x14, 0x3c),
+    ("cyan", 0x00, 0xff, 0xff),
+    ("darkblue", 0x00, 0x00, 0x8b),
+    ("darkcyan", 0x00, 0x8b, 0x8b),
+    ("darkgoldenrod", 0xb8, 0x86, 0x0b),
+    ("darkgray", 0xa9, 0xa9, 0xa9),
+    ("darkgreen", 0x00, 0x64, 0x00),
+    ("darkgrey", 0xa9, 0xa9, 0xa9),
+    ("darkkhaki", 0xbd, 0xb7, 0x6b),
+    ("darkmagenta", 0x8b, 0x00, 0x8b),
+    ("darkolivegreen", 0x55, 0x6b, 0x2f),
+    ("darkorange", 0xff, 0x8c, 0x00),
+    ("darkorchid", 0x99, 0x32, 0xcc),
+    ("darkred", 0x8b, 0x00, 0x00),
+    ("darksalmon", 0xe9, 0x96, 0x7a),
+    ("darkseagreen", 0x8f, 0xbc, 0x8f),
+    ("darkslateblue", 0x48, 0x3d, 0x8b),
+    ("darkslategray", 0x2f, 0x4f, 0x4f),
+    ("darkslategrey", 0x2f, 0x4f, 0x4f),
+    ("darkturquoise", 0x00, 0xce, 0xd1),
+    ("darkviolet", 0x94, 0x00, 0xd3),
+    ("deeppink", 0xff, 0x14, 0x93),
+    ("deepskyblue", 0x00, 0xbf, 0xff),
+    ("dimgray", 0x69, 0x69, 0x69),
+    ("dimgrey", 0x69, 0x69, 0x69),
+    ("dodgerblue", 0x1e, 0x90, 0xff),
+    ("firebrick", 0xb2, 0x22, 0x22),
+    ("floralwhite", 0xff, 0xfa, 0xf0),
+    ("forestgreen", 0x22, 0x8b, 0x22),
+    ("fuchsia", 0xff, 0x00, 0xff),
+    ("gainsboro", 0xdc, 0xdc, 0xdc),
+    ("ghostwhite", 0xf8, 0xf8, 0xff),
+    ("gold", 0xff, 0xd7, 0x00),
+    ("goldenrod", 0xda, 0xa5, 0x20),
+    ("gray", 0x80, 0x80, 0x80),
+    ("green", 0x00, 0x80, 0x00),
+    ("greenyellow", 0xad, 0xff, 0x2f),
+    ("grey", 0x80, 0x80, 0x80),
+    ("honeydew", 0xf0, 0xff, 0xf0),
+    ("hotpink", 0xff, 0x69, 0xb4),
+    ("indianred", 0xcd, 0x5c, 0x5c),
+    ("indigo", 0x4b, 0x00, 0x82),
+    ("ivory", 0xff, 0xff, 0xf0),
+    ("khaki", 0xf0, 0xe6, 0x8c),
+    ("lavender", 0xe6, 0xe6, 0xfa),
+    ("lavenderblush", 0xff, 0xf0, 0xf5),
+    ("lawngreen", 0x7c, 0xfc, 0x00),
+    ("lemonchiffon", 0xff, 0xfa, 0xcd),
+    ("lightblue", 0xad, 0xd8, 0xe6),
+    ("lightcoral", 0xf0, 0x80, 0x80),
+    ("lightcyan", 0xe0, 0xff, 0xff),
+    ("lightgoldenrodyellow", 0xfa, 0xfa, 0xd2),
+    ("lightgray", 0xd3, 0xd3, 0xd3),
+    ("lightgreen", 0x90, 0xee, 0x90),
+    ("lightgrey", 0xd3, 0xd3, 0xd3),
+    ("lightpink", 0xff, 0xb6, 0xc1),
+    ("lightsalmon", 0xff, 0xa0, 0x7a),
+    ("lightseagreen", 0x20, 0xb2, 0xaa),
+    ("lightskyblue", 0x87, 0xce, 0xfa),
+    ("lightslategray", 0x77, 0x88, 0x99),
+    ("lightslategrey", 0x77, 0x88, 0x99),
+    ("lightsteelblue", 0xb0, 0xc4, 0xde),
+    ("lightyellow", 0xff, 0xff, 0xe0),
+    ("lime", 0x00, 0xff, 0x00),
+    ("limegreen", 0x32, 0xcd, 0x32),
+    ("linen", 0xfa, 0xf0, 0xe6),
+    ("magenta", 0xff, 0x00, 0xff),
+    ("maroon", 0x80, 0x00, 0x00),
+    ("mediumaquamarine", 0x66, 0xcd, 0xaa),
+    ("mediumblue", 0x00, 0x00, 0xcd),
+    ("mediumorchid", 0xba, 0x55, 0xd3),
+    ("mediumpurple", 0x93, 0x70, 0xdb),
+    ("mediumseagreen", 0x3c, 0xb3, 0x71),
+    ("mediumslateblue", 0x7b, 0x68, 0xee),
+    ("mediumspringgreen", 0x00, 0xfa, 0x9a),
+    ("mediumturquoise", 0x48, 0xd1, 0xcc),
+    ("mediumvioletred", 0xc7, 0x15, 0x85),
+    ("midnightblue", 0x19, 0x19, 0x70),
+    ("mintcream", 0xf5, 0xff, 0xfa),
+    ("mistyrose", 0xff, 0xe4, 0xe1),
+    ("moccasin", 0xff, 0xe4, 0xb5),
+    ("navajowhite", 0xff, 0xde, 0xad),
+    ("navy", 0x00, 0x00, 0x80),
+    ("oldlace", 0xfd, 0xf5, 0xe6),
+    ("olive", 0x80, 0x80, 0x00),
+    ("olivedrab", 0x6b, 0x8e, 0x23),
+    ("orange", 0xff, 0xa5, 0x00),
+    ("orangered", 0xff, 0x45, 0x00),
+    ("orchid", 0xda, 0x70, 0xd6),
+    ("palegoldenrod", 0xee, 0xe8, 0xaa),
+    ("palegreen", 0x98, 0xfb, 0x98),
+    ("paleturquoise", 0xaf, 0xee, 0xee),
+    ("palevioletred", 0xdb, 0x70, 0x93),
+    ("papayawhip", 0xff, 0xef, 0xd5),
+    ("peachpuff", 0xff, 0xda, 0xb9),
+    ("peru", 0xcd, 0x85, 0x3f),
+    ("pink", 0xff, 0xc0, 0xcb),
+    ("plum", 0xdd, 0xa0, 0xdd),
+    ("powderblue", 0xb0, 0xe0, 0xe6),
+    ("purple", 0x80, 0x00, 0x80),
+    ("rebeccapurple", 0x66, 0x33, 0x99),
+    ("red", 0xff, 0x00, 0x00),
+    ("rosybrown", 0xbc, 0x8f, 0x8f),
+    ("royalblue", 0x41, 0x69, 0xe1),
+    ("saddlebrown", 0x8b, 0x45, 0x13),
+    ("salmon", 0xfa, 0x80, 0x72),
+    ("sandybrown", 0xf4, 0xa4, 0x60),
+    ("seagreen", 0x2e, 0x8b, 0x57),
+    ("seashell", 0xff, 0xf5, 0xee),
+    ("sienna", 0xa0, 0x52, 0x2d),
+    ("silver", 0xc0, 0xc0, 0xc0),
+    ("skyblue", 0x87, 0xce, 0xeb),
+    ("slateblue", 0x6a, 0x5a, 0xcd),
+    ("slategray", 0x70, 0x80, 0x90),
+    ("slategrey", 0x70, 0x80, 0x90),
+    ("snow", 0xff, 0xfa, 0xfa),
+    ("springgreen", 0x00, 0xff, 0x7f),
+    ("steelblue", 0x46, 0x82, 0xb4),
+    ("tan", 0xd2, 0xb4, 0x8c),
+    ("teal", 0x00, 0x80, 0x80),
+    ("thistle", 0xd8, 0xbf, 0xd8),
+    ("tomato", 0xff, 0x63, 0x47),
+    ("turquoise", 0x40, 0xe0, 0xd0),
+    ("violet", 0xee, 0x82, 0xee),
+    ("wheat", 0xf5, 0xde, 0xb3),
+    ("white", 0xff, 0xff, 0xff),
+    ("whitesmoke", 0xf5, 0xf5, 0xf5),
+    ("yellow", 0xff, 0xff, 0x00),
+    ("yellowgreen", 0x9a, 0xcd, 0x32),
+];
+
 impl Color {
     /// Parses hex color formats: #rgb, #rgba, #rrggbb, #rrggbbaa
     pub fn parse_hex(hex: &str) -> Option<Self> {
@@ -214,121 +661,8 @@ impl Color {
 
     /// Parses named colors according to CSS Color Module Level 4
     pub fn parse_named(name: &str) -> Option<Self> {
-        match name.to_lowercase().as_str() {
-            // Basic colors
-            "black" => Some(Color {
-                r: 0,
-                g: 0,
-                b: 0,
-                a: 1.0,
-            }),
-            "silver" => Some(Color {
-                r: 192,
-                g: 192,
-                b: 192,
-                a: 1.0,
-            }),
-            "gray" => Some(Color {
-                r: 128,
-                g: 128,
-                b: 128,
-                a: 1.0,
-            }),
-            "white" => Some(Color {
-                r: 255,
-                g: 255,
-                b: 255,
-                a: 1.0,
-            }),
-            "maroon" => Some(Color {
-                r: 128,
-                g: 0,
-                b: 0,
-                a: 1.0,
-            }),
-            "red" => Some(Color {
-                r: 255,
-                g: 0,
-                b: 0,
-                a: 1.0,
-            }),
-            "purple" => Some(Color {
-                r: 128,
-                g: 0,
-                b: 128,
-                a: 1.0,
-            }),
-            "fuchsia" => Some(Color {
-                r: 255,
-                g: 0,
-                b: 255,
-                a: 1.0,
-            }),
-            "green" => Some(Color {
-                r: 0,
-                g: 128,
-                b: 0,
-                a: 1.0,
-            }),
-            "lime" => Some(Color {
-                r: 0,
-                g: 255,
-                b: 0,
-                a: 1.0,
-            }),
-            "olive" => Some(Color {
-                r: 128,
-                g: 128,
-                b: 0,
-                a: 1.0,
-            }),
-            "yellow" => Some(Color {
-                r: 255,
-                g: 255,
-                b: 0,
-                a: 1.0,
-            }),
-            "navy" => Some(Color {
-                r: 0,
-                g: 0,
-                b: 128,
-                a: 1.0,
-            }),
-            "blue" => Some(Color {
-                r: 0,
-                g: 0,
-                b: 255,
-                a: 1.0,
-            }),
-            "teal" => Some(Color {
-                r: 0,
-                g: 128,
-                b: 128,
-                a: 1.0,
-            }),
-            "aqua" => Some(Color {
-                r: 0,
-                g: 255,
-                b: 255,
-                a: 1.0,
-            }),
-
-            // Extended colors
-            "aliceblue" => Some(Color {
-                r: 240,
-                g: 248,
-                b: 255,
-                a: 1.0,
-            }),
-            "antiquewhite" => Some(Color {
-                r: 250,
-                g: 235,
-                b: 215,
-                a: 1.0,
-            }),
-            // TODO (include all 140+ named colors from CSS Color Module)
-
-            // Special cases
+        let name = name.to_lowercase();
+        match name.as_str() {
             "transparent" => Some(Color {
                 r: 0,
                 g: 0,
@@ -336,94 +670,83 @@ impl Color {
                 a: 0.0,
             }),
             "currentcolor" => None, // Special keyword handled during style calculation
-
-            _ => None,
+            _ => {
+                let index = NAMED_COLORS
+                    .binary_search_by(|(named, ..)| named.cmp(&name.as_str()))
+                    .ok()?;
+                let (_, r, g, b) = NAMED_COLORS[index];
+                Some(Color { r, g, b, a: 1.0 })
+            }
         }
     }
 
-    /// Parses rgb() and rgba() functions
-    pub fn parse_rgb(rgb: &str) -> Option<Self> {
-        let rgb = rgb.trim();
-        if !rgb.starts_with("rgb(") && !rgb.starts_with("rgba(") {
-            return None;
-        }
-
-        let is_rgba = rgb.starts_with("rgba(");
-        let content = rgb
-            .trim_start_matches("rgb(")
-            .trim_start_matches("rgba(")
-            .trim_end_matches(')');
-
-        let components: Vec<&str> = content.split(',').map(|s| s.trim()).collect();
-
-        if (is_rgba && components.len() != 4) || (!is_rgba && components.len() != 3) {
-            return None;
-        }
-
-        let parse_component = |s: &str| -> Option<f32> {
-            if s.ends_with('%') {
-                s.trim_end_matches('%')
-                    .parse::<f32>()
-                    .ok()
-                    .map(|p| p * 255.0 / 100.0)
-            } else {
-                s.parse::<f32>().ok()
-            }
+    /// The color an `rgb()`, `rgba()`, `hsl()` or `hsla()` call stands for,
+    /// given its comma-separated arguments, as in `rgb(255, 0, 0, 0.5)` or
+    /// `rgb(255 0 0 / 50%)`. None unless the channels are numbers or
+    /// percentages, with a hue angle or number first for `hsl()`.
+    pub fn from_function(name: &str, arguments: &[Value]) -> Option<Self> {
+        let (channels, alpha) = match arguments {
+            [Value::List(components)] => match components.as_slice() {
+                [x, y, z] => ([x, y, z], None),
+                [x, y, z, Value::Keyword(slash), alpha] if slash == "/" => ([x, y, z], Some(alpha)),
+                _ => return None,
+            },
+            [x, y, z] => ([x, y, z], None),
+            [x, y, z, alpha] => ([x, y, z], Some(alpha)),
+            _ => return None,
         };
-
-        let r = parse_component(components[0])?.clamp(0.0, 255.0) as u8;
-        let g = parse_component(components[1])?.clamp(0.0, 255.0) as u8;
-        let b = parse_component(components[2])?.clamp(0.0, 255.0) as u8;
-        let a = if is_rgba {
-            components[3].parse::<f32>().ok()?.clamp(0.0, 1.0)
-        } else {
-            1.0
+        let a = match alpha {
+            None => 1.0,
+            Some(Value::Number(a)) => a.clamp(0.0, 1.0),
+            Some(Value::Percentage(a)) => (a / 100.0).clamp(0.0, 1.0),
+            Some(_) => return None,
         };
 
-        Some(Color { r, g, b, a })
-    }
-
-    /// Parses hsl() and hsla() functions
-    pub fn parse_hsl(hsl: &str) -> Option<Self> {
-        let hsl = hsl.trim();
-        if !hsl.starts_with("hsl(") && !hsl.starts_with("hsla(") {
-            return None;
-        }
-
-        let is_hsla = hsl.starts_with("hsla(");
-        let content = hsl
-            .trim_start_matches("hsl(")
-            .trim_start_matches("hsla(")
-            .trim_end_matches(')');
-
-        let components: Vec<&str> = content.split(',').map(|s| s.trim()).collect();
-
-        if (is_hsla && components.len() != 4) || (!is_hsla && components.len() != 3) {
-            return None;
+        match name.to_ascii_lowercase().as_str() {
+            "rgb" | "rgba" => {
+                let channel = |value: &Value| {
+                    let n = match value {
+                        Value::Number(n) => *n,
+                        Value::Percentage(p) => p * 255.0 / 100.0,
+                        _ => return None,
+                    };
+                    Some(n.clamp(0.0, 255.0).round() as u8)
+                };
+                Some(Color {
+                    r: channel(channels[0])?,
+                    g: channel(channels[1])?,
+                    b: channel(channels[2])?,
+                    a,
+                })
+            }
+            "hsl" | "hsla" => {
+                let h = match channels[0] {
+                    Value::Number(degrees) => *degrees,
+                    Value::Length(n, Unit::Deg) => *n,
+                    Value::Length(n, Unit::Rad) => n.to_degrees(),
+                    Value::Length(n, Unit::Grad) => n * 0.9,
+                    Value::Length(n, Unit::Turn) => n * 360.0,
+                    _ => return None,
+                };
+                let fraction = |value: &Value| match value {
+                    Value::Number(n) | Value::Percentage(n) => Some((n / 100.0).clamp(0.0, 1.0)),
+                    _ => None,
+                };
+                let s = fraction(channels[1])?;
+                let l = fraction(channels[2])?;
+                Some(Color::from_hsl(h.rem_euclid(360.0), s, l, a))
+            }
+            _ => None,
         }
+    }
 
-        let h = components[0]
-            .trim_end_matches("deg")
-            .trim_end_matches("rad")
-            .trim_end_matches("grad")
-            .trim_end_matches("turn")
-            .parse::<f32>()
-            .ok()?;
-
-        let s = components[1].trim_end_matches('%').parse::<f32>().ok()? / 100.0;
-        let l = components[2].trim_end_matches('%').parse::<f32>().ok()? / 100.0;
-        let a = if is_hsla {
-            components[3].parse::<f32>().ok()?.clamp(0.0, 1.0)
-        } else {
-            1.0
-        };
-
-        // Convert HSL to RGB
+    // `h` is in degrees in [0, 360), `s` and `l` in [0, 1].
+    fn from_hsl(h: f32, s: f32, l: f32, a: f32) -> Self {
         let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
         let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
         let m = l - c / 2.0;
 
-        let (r, g, b) = match (h / 60.0).floor() as i32 % 6 {
+        let (r, g, b) = match (h / 60.0).floor() as i32 {
             0 => (c, x, 0.0),
             1 => (x, c, 0.0),
             2 => (0.0, c, x),
@@ -432,11 +755,11 @@ impl Color {
             _ => (c, 0.0, x),
         };
 
-        Some(Color {
+        Color {
             r: ((r + m) * 255.0).round() as u8,
             g: ((g + m) * 255.0).round() as u8,
             b: ((b + m) * 255.0).round() as u8,
             a,
-        })
+        }
     }
 }
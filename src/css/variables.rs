@@ -0,0 +1,100 @@
+use super::tokenizer::Token;
+
+/// Whether the name is that of a custom property, like `--brand-color`.
+pub fn is_custom_property(name: &str) -> bool {
+    name.starts_with("--")
+}
+
+/// Whether the tokens contain a `var()`, at any depth.
+pub fn uses_var(tokens: &[Token]) -> bool {
+    tokens.iter().any(is_var)
+}
+
+/// Whether every `var()` in the tokens names a custom property, optionally
+/// followed by a comma and a fallback. A declaration using any other form
+/// is invalid when parsed.
+pub fn valid_references(tokens: &[Token]) -> bool {
+    tokens.iter().enumerate().all(|(i, token)| {
+        !is_var(token) || reference(&tokens[i + 1..block_end(tokens, i)]).is_some()
+    })
+}
+
+/// Replaces each `var()` in the tokens with the value `lookup` gives for
+/// the custom property it names, or else with its fallback. None when a
+/// reference has neither, which makes the value invalid at computed-value
+/// time.
+pub fn substitute(
+    tokens: &[Token],
+    lookup: &mut dyn FnMut(&str) -> Option<Vec<Token>>,
+) -> Option<Vec<Token>> {
+    let mut substituted = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        if !is_var(&tokens[i]) {
+            substituted.push(tokens[i].clone());
+            i += 1;
+            continue;
+        }
+        let end = block_end(tokens, i);
+        let (name, fallback) = reference(&tokens[i + 1..end])?;
+        match lookup(name) {
+            Some(value) => substituted.extend(value),
+            // The fallback may itself use `var()`.
+            None => substituted.extend(substitute(fallback?, lookup)?),
+        }
+        i = end + 1;
+    }
+    Some(substituted)
+}
+
+/// The tokens without leading and trailing whitespace.
+pub fn trim(tokens: &[Token]) -> &[Token] {
+    let start = tokens
+        .iter()
+        .position(|token| *token != Token::Whitespace)
+        .unwrap_or(tokens.len());
+    let end = tokens
+        .iter()
+        .rposition(|token| *token != Token::Whitespace)
+        .map_or(start, |end| end + 1);
+    &tokens[start..end]
+}
+
+fn is_var(token: &Token) -> bool {
+    matches!(token, Token::Function(name) if name.eq_ignore_ascii_case("var"))
+}
+
+// The custom property a `var()` with these arguments names, and its
+// fallback if it has one. An empty fallback, as in `var(--x,)`, is one.
+fn reference(arguments: &[Token]) -> Option<(&str, Option<&[Token]>)> {
+    let arguments = trim(arguments);
+    let (Token::Ident(name), rest) = arguments.split_first()? else {
+        return None;
+    };
+    if !is_custom_property(name) {
+        return None;
+    }
+    match trim(rest).split_first() {
+        None => Some((name, None)),
+        Some((Token::Comma, fallback)) => Some((name, Some(trim(fallback)))),
+        Some(_) => None,
+    }
+}
+
+// The index of the token closing the block opened at `start`, or the length
+// of the tokens when it is never closed. As in the parser, a closing token
+// only closes the innermost block, if it matches it.
+fn block_end(tokens: &[Token], start: usize) -> usize {
+    let mut open = Vec::new();
+    for (i, token) in tokens.iter().enumerate().skip(start) {
+        if let Some(closing) = token.closing() {
+            open.push(closing);
+        } else if open.last() == Some(token) {
+            open.pop();
+            if open.is_empty() {
+                return i;
+            }
+        }
+    }
+    tokens.len()
+}
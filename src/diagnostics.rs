@@ -0,0 +1,192 @@
+use crate::span::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// Problems found while parsing. The parsers always recover, so these are
+/// reported next to the parsed result rather than instead of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticCode {
+    // HTML tokenizer, named after the WHATWG parse errors
+    AbruptClosingOfEmptyComment,
+    AbruptDoctypePublicIdentifier,
+    AbruptDoctypeSystemIdentifier,
+    AbsenceOfDigitsInNumericCharacterReference,
+    CdataInHtmlContent,
+    CharacterReferenceOutsideUnicodeRange,
+    ControlCharacterReference,
+    DuplicateAttribute,
+    EndTagWithAttributes,
+    EndTagWithTrailingSolidus,
+    EofBeforeTagName,
+    EofInCdata,
+    EofInComment,
+    EofInDoctype,
+    EofInScriptHtmlCommentLikeText,
+    EofInTag,
+    IncorrectlyClosedComment,
+    IncorrectlyOpenedComment,
+    InvalidCharacterSequenceAfterDoctypeName,
+    InvalidFirstCharacterOfTagName,
+    MissingAttributeValue,
+    MissingDoctypeName,
+    MissingEndTagName,
+    MissingSemicolonAfterCharacterReference,
+    MissingWhitespaceBetweenAttributes,
+    NestedComment,
+    NoncharacterCharacterReference,
+    NullCharacterReference,
+    SurrogateCharacterReference,
+    UnexpectedCharacterInAttributeName,
+    UnexpectedCharacterInUnquotedAttributeValue,
+    UnexpectedEqualsSignBeforeAttributeName,
+    UnexpectedNullCharacter,
+    UnexpectedQuestionMarkInsteadOfTagName,
+    UnexpectedSolidusInTag,
+    UnknownNamedCharacterReference,
+
+    // HTML tree construction
+    MissingDoctype,
+    UnexpectedDoctype,
+    UnexpectedStartTag,
+    UnexpectedEndTag,
+    UnexpectedText,
+    MisnestedTags,
+    UnclosedElements,
+    NonVoidElementWithTrailingSolidus,
+
+    // CSS
+    InvalidSelector,
+    InvalidDeclaration,
+    InvalidPropertyValue,
+    InvalidAtRule,
+    InvalidMediaQuery,
+    UnsupportedAtRule,
+    InvalidKeyframeSelector,
+    UnclosedBlock,
+    UnexpectedCharacter,
+}
+
+impl DiagnosticCode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DiagnosticCode::AbruptClosingOfEmptyComment => "abrupt-closing-of-empty-comment",
+            DiagnosticCode::AbruptDoctypePublicIdentifier => "abrupt-doctype-public-identifier",
+            DiagnosticCode::AbruptDoctypeSystemIdentifier => "abrupt-doctype-system-identifier",
+            DiagnosticCode::AbsenceOfDigitsInNumericCharacterReference => {
+                "absence-of-digits-in-numeric-character-reference"
+            }
+            DiagnosticCode::CdataInHtmlContent => "cdata-in-html-content",
+            DiagnosticCode::CharacterReferenceOutsideUnicodeRange => {
+                "character-reference-outside-unicode-range"
+            }
+            DiagnosticCode::ControlCharacterReference => "control-character-reference",
+            DiagnosticCode::DuplicateAttribute => "duplicate-attribute",
+            DiagnosticCode::EndTagWithAttributes => "end-tag-with-attributes",
+            DiagnosticCode::EndTagWithTrailingSolidus => "end-tag-with-trailing-solidus",
+            DiagnosticCode::EofBeforeTagName => "eof-before-tag-name",
+            DiagnosticCode::EofInCdata => "eof-in-cdata",
+            DiagnosticCode::EofInComment => "eof-in-comment",
+            DiagnosticCode::EofInDoctype => "eof-in-doctype",
+            DiagnosticCode::EofInScriptHtmlCommentLikeText => {
+                "eof-in-script-html-comment-like-text"
+            }
+            DiagnosticCode::EofInTag => "eof-in-tag",
+            DiagnosticCode::IncorrectlyClosedComment => "incorrectly-closed-comment",
+            DiagnosticCode::IncorrectlyOpenedComment => "incorrectly-opened-comment",
+            DiagnosticCode::InvalidCharacterSequenceAfterDoctypeName => {
+                "invalid-character-sequence-after-doctype-name"
+            }
+            DiagnosticCode::InvalidFirstCharacterOfTagName => "invalid-first-character-of-tag-name",
+            DiagnosticCode::MissingAttributeValue => "missing-attribute-value",
+            DiagnosticCode::MissingDoctypeName => "missing-doctype-name",
+            DiagnosticCode::MissingEndTagName => "missing-end-tag-name",
+            DiagnosticCode::MissingSemicolonAfterCharacterReference => {
+                "missing-semicolon-after-character-reference"
+            }
+            DiagnosticCode::MissingWhitespaceBetweenAttributes => {
+                "missing-whitespace-between-attributes"
+            }
+            DiagnosticCode::NestedComment => "nested-comment",
+            DiagnosticCode::NoncharacterCharacterReference => "noncharacter-character-reference",
+            DiagnosticCode::NullCharacterReference => "null-character-reference",
+            DiagnosticCode::SurrogateCharacterReference => "surrogate-character-reference",
+            DiagnosticCode::UnexpectedCharacterInAttributeName => {
+                "unexpected-character-in-attribute-name"
+            }
+            DiagnosticCode::UnexpectedCharacterInUnquotedAttributeValue => {
+                "unexpected-character-in-unquoted-attribute-value"
+            }
+            DiagnosticCode::UnexpectedEqualsSignBeforeAttributeName => {
+                "unexpected-equals-sign-before-attribute-name"
+            }
+            DiagnosticCode::UnexpectedNullCharacter => "unexpected-null-character",
+            DiagnosticCode::UnexpectedQuestionMarkInsteadOfTagName => {
+                "unexpected-question-mark-instead-of-tag-name"
+            }
+            DiagnosticCode::UnexpectedSolidusInTag => "unexpected-solidus-in-tag",
+            DiagnosticCode::UnknownNamedCharacterReference => "unknown-named-character-reference",
+
+            DiagnosticCode::MissingDoctype => "missing-doctype",
+            DiagnosticCode::UnexpectedDoctype => "unexpected-doctype",
+            DiagnosticCode::UnexpectedStartTag => "unexpected-start-tag",
+            DiagnosticCode::UnexpectedEndTag => "unexpected-end-tag",
+            DiagnosticCode::UnexpectedText => "unexpected-text",
+            DiagnosticCode::MisnestedTags => "misnested-tags",
+            DiagnosticCode::UnclosedElements => "unclosed-elements",
+            DiagnosticCode::NonVoidElementWithTrailingSolidus => {
+                "non-void-html-element-start-tag-with-trailing-solidus"
+            }
+
+            DiagnosticCode::InvalidSelector => "invalid-selector",
+            DiagnosticCode::InvalidDeclaration => "invalid-declaration",
+            DiagnosticCode::InvalidPropertyValue => "invalid-property-value",
+            DiagnosticCode::InvalidAtRule => "invalid-at-rule",
+            DiagnosticCode::InvalidMediaQuery => "invalid-media-query",
+            DiagnosticCode::UnsupportedAtRule => "unsupported-at-rule",
+            DiagnosticCode::InvalidKeyframeSelector => "invalid-keyframe-selector",
+            DiagnosticCode::UnclosedBlock => "unclosed-block",
+            DiagnosticCode::UnexpectedCharacter => "unexpected-character",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub code: DiagnosticCode,
+    pub message: String,
+    pub span: Span,
+    pub severity: Severity,
+}
+
+impl Diagnostic {
+    pub fn error(code: DiagnosticCode, message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            code,
+            message: message.into(),
+            span,
+            severity: Severity::Error,
+        }
+    }
+
+    pub fn warning(code: DiagnosticCode, message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            code,
+            message: message.into(),
+            span,
+            severity: Severity::Warning,
+        }
+    }
+}
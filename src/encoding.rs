@@ -0,0 +1,305 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252, X_USER_DEFINED};
+
+/// How sure the HTML sniffer is about an encoding. A tentative encoding came
+/// from prescanning or the fallback and may be wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confidence {
+    Certain,
+    Tentative,
+}
+
+// Both the `<meta>` prescan and the `@charset` check only look this far.
+const PRESCAN_LENGTH: usize = 1024;
+
+/// Determines the encoding of an HTML document with the WHATWG sniffing
+/// algorithm: a byte order mark wins, then the transport layer's encoding
+/// (e.g. the charset of a Content-Type header), then a `<meta>` found by
+/// prescanning the first 1024 bytes, and finally windows-1252. Input that
+/// is entirely valid UTF-8 is taken as UTF-8 before falling back, which the
+/// spec allows as autodetection and which local files nearly always want.
+pub fn sniff_html(
+    bytes: &[u8],
+    transport: Option<&'static Encoding>,
+) -> (&'static Encoding, Confidence) {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return (encoding, Confidence::Certain);
+    }
+    if let Some(encoding) = transport {
+        return (encoding, Confidence::Certain);
+    }
+    if let Some(encoding) = prescan(&bytes[..bytes.len().min(PRESCAN_LENGTH)]) {
+        return (encoding, Confidence::Tentative);
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return (UTF_8, Confidence::Tentative);
+    }
+    (WINDOWS_1252, Confidence::Tentative)
+}
+
+/// Decodes an HTML document into the text `html::Parser` works on, returning
+/// the encoding that was used. Malformed sequences become U+FFFD.
+pub fn decode_html(
+    bytes: &[u8],
+    transport: Option<&'static Encoding>,
+) -> (String, &'static Encoding) {
+    let (encoding, _) = sniff_html(bytes, transport);
+    decode(bytes, encoding)
+}
+
+/// Decodes a stylesheet following CSS Syntax: a byte order mark wins, then
+/// the transport layer's encoding, then an `@charset` rule at the very start,
+/// then the encoding of the referring document, and finally UTF-8.
+pub fn decode_css(
+    bytes: &[u8],
+    transport: Option<&'static Encoding>,
+    environment: Option<&'static Encoding>,
+) -> (String, &'static Encoding) {
+    let encoding = transport
+        .or_else(|| charset_rule(bytes))
+        .or(environment)
+        .unwrap_or(UTF_8);
+    decode(bytes, encoding)
+}
+
+// A byte order mark overrides `encoding` and is removed from the text.
+fn decode(bytes: &[u8], encoding: &'static Encoding) -> (String, &'static Encoding) {
+    let (text, encoding, _) = encoding.decode(bytes);
+    (text.into_owned(), encoding)
+}
+
+// `@charset "label";` only counts written exactly like that, as the first
+// bytes of the stylesheet.
+fn charset_rule(bytes: &[u8]) -> Option<&'static Encoding> {
+    let bytes = &bytes[..bytes.len().min(PRESCAN_LENGTH)];
+    let rest = bytes.strip_prefix(b"@charset \"")?;
+    let end = rest.windows(2).position(|window| window == b"\";")?;
+    let encoding = Encoding::for_label(&rest[..end])?;
+    Some(utf_16_as_utf_8(encoding))
+}
+
+// A document that can be prescanned is ASCII-compatible, so a UTF-16 label
+// in it must be wrong.
+fn utf_16_as_utf_8(encoding: &'static Encoding) -> &'static Encoding {
+    if encoding == UTF_16BE || encoding == UTF_16LE {
+        UTF_8
+    } else {
+        encoding
+    }
+}
+
+enum Prescanned {
+    Attribute(Vec<u8>, Vec<u8>),
+    EndOfTag,
+}
+
+// "Prescan a byte stream to determine its encoding". Returns None when no
+// usable `<meta>` is found, including when the input ends inside a tag.
+fn prescan(bytes: &[u8]) -> Option<&'static Encoding> {
+    let mut position = 0;
+
+    while position < bytes.len() {
+        let rest = &bytes[position..];
+
+        if rest.starts_with(b"<!--") {
+            // The dashes of "<!--" may also end the comment, as in "<!-->".
+            let end = find(&rest[2..], b"-->")?;
+            position += 2 + end + 3;
+            continue;
+        }
+
+        if rest.len() > 5 && rest[..5].eq_ignore_ascii_case(b"<meta") && is_space_or_slash(rest[5])
+        {
+            position += 5;
+            if let Some(encoding) = prescan_meta(bytes, &mut position)? {
+                return Some(encoding);
+            }
+            position += 1;
+            continue;
+        }
+
+        let is_tag = rest.len() > 1
+            && rest[0] == b'<'
+            && (rest[1].is_ascii_alphabetic()
+                || (rest[1] == b'/' && rest.get(2).is_some_and(|b| b.is_ascii_alphabetic())));
+        if is_tag {
+            // Other tags are skipped, attributes and all, so that a quoted
+            // '>' does not end them early.
+            let name_end = rest.iter().position(|&b| is_space(b) || b == b'>')?;
+            position += name_end;
+            while let Prescanned::Attribute(..) = get_attribute(bytes, &mut position)? {}
+            position += 1;
+            continue;
+        }
+
+        if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            position += rest.iter().position(|&b| b == b'>')? + 1;
+            continue;
+        }
+
+        position += 1;
+    }
+
+    None
+}
+
+// Reads the attributes of a `<meta>` tag; the outer None means the input
+// ended, the inner one that this `<meta>` does not declare an encoding.
+fn prescan_meta(bytes: &[u8], position: &mut usize) -> Option<Option<&'static Encoding>> {
+    let mut seen: Vec<Vec<u8>> = vec![];
+    let mut got_pragma = false;
+    let mut need_pragma = None;
+    // Some(None) records a charset that was present but not a known label.
+    let mut charset: Option<Option<&'static Encoding>> = None;
+
+    while let Prescanned::Attribute(name, value) = get_attribute(bytes, position)? {
+        if seen.contains(&name) {
+            continue;
+        }
+        match name.as_slice() {
+            b"http-equiv" if value == b"content-type" => got_pragma = true,
+            b"content" if charset.is_none() => {
+                if let Some(encoding) = charset_from_content(&value) {
+                    charset = Some(Some(encoding));
+                    need_pragma = Some(true);
+                }
+            }
+            b"charset" if charset.is_none() => {
+                charset = Some(Encoding::for_label(&value));
+                need_pragma = Some(false);
+            }
+            _ => {}
+        }
+        seen.push(name);
+    }
+
+    let declared = match need_pragma {
+        None => None,
+        Some(true) if !got_pragma => None,
+        _ => charset.flatten(),
+    };
+    Some(declared.map(|encoding| {
+        if encoding == X_USER_DEFINED {
+            WINDOWS_1252
+        } else {
+            utf_16_as_utf_8(encoding)
+        }
+    }))
+}
+
+// "Get an attribute" from the prescan algorithm. Names and values are ASCII
+// lowercased; None means the input ended first.
+fn get_attribute(bytes: &[u8], position: &mut usize) -> Option<Prescanned> {
+    while is_space_or_slash(*bytes.get(*position)?) {
+        *position += 1;
+    }
+    if bytes[*position] == b'>' {
+        return Some(Prescanned::EndOfTag);
+    }
+
+    let mut name = vec![];
+    let mut value = vec![];
+
+    loop {
+        let b = *bytes.get(*position)?;
+        match b {
+            b'=' if !name.is_empty() => {
+                *position += 1;
+                break;
+            }
+            b if is_space(b) => {
+                while is_space(*bytes.get(*position)?) {
+                    *position += 1;
+                }
+                if bytes[*position] != b'=' {
+                    return Some(Prescanned::Attribute(name, value));
+                }
+                *position += 1;
+                break;
+            }
+            b'/' | b'>' => return Some(Prescanned::Attribute(name, value)),
+            b => name.push(b.to_ascii_lowercase()),
+        }
+        *position += 1;
+    }
+
+    while is_space(*bytes.get(*position)?) {
+        *position += 1;
+    }
+
+    match bytes[*position] {
+        quote @ (b'"' | b'\'') => loop {
+            *position += 1;
+            let b = *bytes.get(*position)?;
+            if b == quote {
+                *position += 1;
+                return Some(Prescanned::Attribute(name, value));
+            }
+            value.push(b.to_ascii_lowercase());
+        },
+        b'>' => return Some(Prescanned::Attribute(name, value)),
+        _ => {}
+    }
+
+    loop {
+        let b = *bytes.get(*position)?;
+        if is_space(b) || b == b'>' {
+            return Some(Prescanned::Attribute(name, value));
+        }
+        value.push(b.to_ascii_lowercase());
+        *position += 1;
+    }
+}
+
+// "Extracting a character encoding from a meta element", for values like
+// `text/html; charset=shift_jis`.
+fn charset_from_content(content: &[u8]) -> Option<&'static Encoding> {
+    let mut position = 0;
+    loop {
+        position += find_ignore_ascii_case(&content[position..], b"charset")? + b"charset".len();
+        while content.get(position).is_some_and(|&b| is_space(b)) {
+            position += 1;
+        }
+        if content.get(position) == Some(&b'=') {
+            position += 1;
+            break;
+        }
+    }
+    while content.get(position).is_some_and(|&b| is_space(b)) {
+        position += 1;
+    }
+
+    let rest = &content[position..];
+    match rest.first()? {
+        &quote @ (b'"' | b'\'') => {
+            let end = rest[1..].iter().position(|&b| b == quote)?;
+            Encoding::for_label(&rest[1..1 + end])
+        }
+        _ => {
+            let end = rest
+                .iter()
+                .position(|&b| is_space(b) || b == b';')
+                .unwrap_or(rest.len());
+            Encoding::for_label(&rest[..end])
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn find_ignore_ascii_case(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle))
+}
+
+fn is_space(b: u8) -> bool {
+    matches!(b, b'\t' | b'\n' | b'\x0C' | b'\r' | b' ')
+}
+
+fn is_space_or_slash(b: u8) -> bool {
+    is_space(b) || b == b'/'
+}
@@ -0,0 +1,518 @@
+use super::dom::{ElementData, Node, NodeType};
+use crate::span::Span;
+use std::ops::{Index, IndexMut};
+
+/// A handle to a node in a `Dom`. Ids stay valid for the life of the `Dom`,
+/// including after the node is removed from the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct DomNode {
+    pub node_type: NodeType,
+    // None for nodes the parser implied, like a missing <head> or <tbody>.
+    pub span: Option<Span>,
+    // The links are only changed through `Dom` so they always agree.
+    parent: Option<NodeId>,
+    previous_sibling: Option<NodeId>,
+    next_sibling: Option<NodeId>,
+    first_child: Option<NodeId>,
+    last_child: Option<NodeId>,
+}
+
+/// A DOM stored in an arena. Unlike `Node`, which owns its children, every
+/// node here knows its parent and siblings, so the tree can be walked in any
+/// direction and edited in place.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Dom {
+    nodes: Vec<DomNode>,
+    root: NodeId,
+}
+
+impl Dom {
+    /// Creates a tree holding only a root node of the given type.
+    pub fn new(node_type: NodeType) -> Self {
+        let mut dom = Dom {
+            nodes: Vec::new(),
+            root: NodeId(0),
+        };
+        dom.create(node_type, None);
+        dom
+    }
+
+    /// Builds an arena copy of a tree produced by `html::Parser`.
+    pub fn from_node(node: &Node) -> Self {
+        let mut dom = Dom::new(node.node_type.clone());
+        let root = dom.root;
+        dom[root].span = node.span;
+        dom.append_node_children(root, node);
+        dom
+    }
+
+    fn append_node_children(&mut self, parent: NodeId, node: &Node) {
+        for child in &node.children {
+            let id = self.create(child.node_type.clone(), child.span);
+            self.append_child(parent, id);
+            self.append_node_children(id, child);
+        }
+    }
+
+    /// Converts the subtree rooted at `id` back into an owned `Node` tree,
+    /// for code that still works on `Node`.
+    pub fn to_node(&self, id: NodeId) -> Node {
+        let children = self.children(id).map(|child| self.to_node(child)).collect();
+        Node::new(self[id].node_type.clone(), children).with_span(self[id].span)
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    /// Adds a node that is not yet attached anywhere in the tree.
+    pub fn create(&mut self, node_type: NodeType, span: Option<Span>) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(DomNode {
+            node_type,
+            span,
+            parent: None,
+            previous_sibling: None,
+            next_sibling: None,
+            first_child: None,
+            last_child: None,
+        });
+        id
+    }
+
+    pub fn element(&self, id: NodeId) -> Option<&ElementData> {
+        match &self[id].node_type {
+            NodeType::Element(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self[id].parent
+    }
+
+    pub fn previous_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self[id].previous_sibling
+    }
+
+    pub fn next_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self[id].next_sibling
+    }
+
+    pub fn first_child(&self, id: NodeId) -> Option<NodeId> {
+        self[id].first_child
+    }
+
+    pub fn last_child(&self, id: NodeId) -> Option<NodeId> {
+        self[id].last_child
+    }
+
+    pub fn children(&self, id: NodeId) -> Siblings<'_> {
+        Siblings {
+            dom: self,
+            next: self.first_child(id),
+        }
+    }
+
+    /// The parent of `id`, its parent, and so on up to the root.
+    pub fn ancestors(&self, id: NodeId) -> Ancestors<'_> {
+        Ancestors {
+            dom: self,
+            next: self.parent(id),
+        }
+    }
+
+    /// The nodes below `id` in tree order, not including `id` itself.
+    pub fn descendants(&self, id: NodeId) -> Descendants<'_> {
+        Descendants {
+            dom: self,
+            root: id,
+            next: self.first_child(id),
+        }
+    }
+
+    fn is_inclusive_ancestor(&self, ancestor: NodeId, id: NodeId) -> bool {
+        ancestor == id || self.ancestors(id).any(|node| node == ancestor)
+    }
+
+    /// Appends `child` as the last child of `parent`, first removing it from
+    /// wherever it was.
+    ///
+    /// # Panics
+    ///
+    /// If `child` is `parent` or one of its ancestors.
+    pub fn append_child(&mut self, parent: NodeId, child: NodeId) {
+        self.insert_before(parent, child, None);
+    }
+
+    /// Inserts `child` into `parent` just before `reference`, or at the end
+    /// when `reference` is None, first removing it from wherever it was.
+    ///
+    /// # Panics
+    ///
+    /// If `child` is `parent` or one of its ancestors, or if `reference` is
+    /// not a child of `parent`.
+    pub fn insert_before(&mut self, parent: NodeId, child: NodeId, reference: Option<NodeId>) {
+        assert!(
+            !self.is_inclusive_ancestor(child, parent),
+            "cannot insert a node into itself or its descendants"
+        );
+        if let Some(reference) = reference {
+            assert_eq!(
+                self.parent(reference),
+                Some(parent),
+                "reference node is not a child of the parent"
+            );
+            if reference == child {
+                return;
+            }
+        }
+
+        self.remove(child);
+
+        let previous = match reference {
+            Some(reference) => self[reference].previous_sibling,
+            None => self[parent].last_child,
+        };
+        self[child].parent = Some(parent);
+        self[child].previous_sibling = previous;
+        self[child].next_sibling = reference;
+        match previous {
+            Some(previous) => self[previous].next_sibling = Some(child),
+            None => self[parent].first_child = Some(child),
+        }
+        match reference {
+            Some(reference) => self[reference].previous_sibling = Some(child),
+            None => self[parent].last_child = Some(child),
+        }
+    }
+
+    /// Detaches `id`, with its subtree, from its parent. The node stays in
+    /// the arena and can be inserted again.
+    pub fn remove(&mut self, id: NodeId) {
+        let Some(parent) = self[id].parent else {
+            return;
+        };
+        let previous = self[id].previous_sibling;
+        let next = self[id].next_sibling;
+        match previous {
+            Some(previous) => self[previous].next_sibling = next,
+            None => self[parent].first_child = next,
+        }
+        match next {
+            Some(next) => self[next].previous_sibling = previous,
+            None => self[parent].last_child = previous,
+        }
+        let node = &mut self[id];
+        node.parent = None;
+        node.previous_sibling = None;
+        node.next_sibling = None;
+    }
+
+    /// Puts `new` where `old` is in the tree and detaches `old`.
+    ///
+    /// # Panics
+    ///
+    /// If `old` has no parent, or if `new` is an ancestor of `old`.
+    pub fn replace(&mut self, old: NodeId, new: NodeId) {
+        let parent = self
+            .parent(old)
+            .expect("cannot replace a node without a parent");
+        if old == new {
+            return;
+        }
+        self.insert_before(parent, new, Some(old));
+        self.remove(old);
+    }
+
+    /// Moves all children of `from` to the end of `to`, keeping their order.
+    ///
+    /// # Panics
+    ///
+    /// If `to` is `from` or one of its descendants.
+    pub fn reparent_children(&mut self, from: NodeId, to: NodeId) {
+        assert!(
+            !self.is_inclusive_ancestor(from, to),
+            "cannot move children into their own subtree"
+        );
+        while let Some(child) = self.first_child(from) {
+            self.append_child(to, child);
+        }
+    }
+}
+
+impl From<&Node> for Dom {
+    fn from(node: &Node) -> Self {
+        Dom::from_node(node)
+    }
+}
+
+impl Index<NodeId> for Dom {
+    type Output = DomNode;
+
+    fn index(&self, id: NodeId) -> &DomNode {
+        &self.nodes[id.0]
+    }
+}
+
+impl IndexMut<NodeId> for Dom {
+    fn index_mut(&mut self, id: NodeId) -> &mut DomNode {
+        &mut self.nodes[id.0]
+    }
+}
+
+pub struct Siblings<'a> {
+    dom: &'a Dom,
+    next: Option<NodeId>,
+}
+
+impl Iterator for Siblings<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        self.next = self.dom.next_sibling(id);
+        Some(id)
+    }
+}
+
+pub struct Ancestors<'a> {
+    dom: &'a Dom,
+    next: Option<NodeId>,
+}
+
+impl Iterator for Ancestors<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        self.next = self.dom.parent(id);
+        Some(id)
+    }
+}
+
+pub struct Descendants<'a> {
+    dom: &'a Dom,
+    root: NodeId,
+    next: Option<NodeId>,
+}
+
+impl Iterator for Descendants<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        self.next = self.dom.first_child(id).or_else(|| {
+            // Climb until a node has a next sibling, stopping at the root.
+            let mut node = id;
+            loop {
+                if node == self.root {
+                    return None;
+                }
+                if let Some(next) = self.dom.next_sibling(node) {
+                    return Some(next);
+                }
+                node = self.dom.parent(node)?;
+            }
+        });
+        Some(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(dom: &mut Dom, text: &str) -> NodeId {
+        dom.create(NodeType::Text(text.to_string()), None)
+    }
+
+    // The children of `parent`, after checking that the sibling links agree
+    // in both directions and that each child points back at `parent`.
+    fn children(dom: &Dom, parent: NodeId) -> Vec<NodeId> {
+        let forward: Vec<NodeId> = dom.children(parent).collect();
+        let mut backward = Vec::new();
+        let mut child = dom.last_child(parent);
+        while let Some(id) = child {
+            backward.push(id);
+            child = dom.previous_sibling(id);
+        }
+        backward.reverse();
+        assert_eq!(forward, backward);
+        assert_eq!(dom.first_child(parent), forward.first().copied());
+        for &id in &forward {
+            assert_eq!(dom.parent(id), Some(parent));
+        }
+        forward
+    }
+
+    fn assert_detached(dom: &Dom, id: NodeId) {
+        assert_eq!(dom.parent(id), None);
+        assert_eq!(dom.previous_sibling(id), None);
+        assert_eq!(dom.next_sibling(id), None);
+    }
+
+    #[test]
+    fn insert_before() {
+        let mut dom = Dom::new(NodeType::Document);
+        let root = dom.root();
+        let [a, b, c, d] = ["a", "b", "c", "d"].map(|name| text(&mut dom, name));
+
+        dom.insert_before(root, b, None);
+        dom.insert_before(root, a, Some(b));
+        assert_eq!(children(&dom, root), [a, b]);
+        dom.insert_before(root, c, Some(b));
+        assert_eq!(children(&dom, root), [a, c, b]);
+        dom.insert_before(root, d, None);
+        assert_eq!(children(&dom, root), [a, c, b, d]);
+
+        // Inserting a node that is already in the tree moves it.
+        dom.insert_before(root, d, Some(a));
+        assert_eq!(children(&dom, root), [d, a, c, b]);
+        dom.insert_before(root, a, Some(a));
+        assert_eq!(children(&dom, root), [d, a, c, b]);
+    }
+
+    #[test]
+    fn insert_into_another_parent() {
+        let mut dom = Dom::new(NodeType::Document);
+        let root = dom.root();
+        let [a, b, c] = ["a", "b", "c"].map(|name| text(&mut dom, name));
+        for id in [a, b, c] {
+            dom.append_child(root, id);
+        }
+
+        dom.append_child(a, b);
+        assert_eq!(children(&dom, root), [a, c]);
+        assert_eq!(children(&dom, a), [b]);
+        assert_eq!(dom.ancestors(b).collect::<Vec<_>>(), [a, root]);
+    }
+
+    #[test]
+    fn remove() {
+        let mut dom = Dom::new(NodeType::Document);
+        let root = dom.root();
+        let [a, b, c, d] = ["a", "b", "c", "d"].map(|name| text(&mut dom, name));
+        for id in [a, b, c] {
+            dom.append_child(root, id);
+        }
+        dom.append_child(b, d);
+
+        dom.remove(b);
+        assert_eq!(children(&dom, root), [a, c]);
+        assert_detached(&dom, b);
+        // The subtree goes with it.
+        assert_eq!(children(&dom, b), [d]);
+
+        dom.remove(a);
+        dom.remove(c);
+        assert_eq!(children(&dom, root), []);
+        assert_eq!(dom.last_child(root), None);
+
+        // Removing a detached node does nothing.
+        dom.remove(a);
+        assert_detached(&dom, a);
+    }
+
+    #[test]
+    fn replace() {
+        let mut dom = Dom::new(NodeType::Document);
+        let root = dom.root();
+        let [a, b, c, d] = ["a", "b", "c", "d"].map(|name| text(&mut dom, name));
+        for id in [a, b, c] {
+            dom.append_child(root, id);
+        }
+
+        dom.replace(b, d);
+        assert_eq!(children(&dom, root), [a, d, c]);
+        assert_detached(&dom, b);
+
+        // A node already in the tree is moved into place.
+        dom.replace(a, c);
+        assert_eq!(children(&dom, root), [c, d]);
+        assert_detached(&dom, a);
+
+        dom.replace(d, d);
+        assert_eq!(children(&dom, root), [c, d]);
+    }
+
+    #[test]
+    fn reparent_children() {
+        let mut dom = Dom::new(NodeType::Document);
+        let root = dom.root();
+        let [a, b, c, d, e] = ["a", "b", "c", "d", "e"].map(|name| text(&mut dom, name));
+        dom.append_child(root, a);
+        dom.append_child(root, b);
+        for id in [c, d] {
+            dom.append_child(a, id);
+        }
+        dom.append_child(b, e);
+
+        dom.reparent_children(a, b);
+        assert_eq!(children(&dom, a), []);
+        assert_eq!(children(&dom, b), [e, c, d]);
+        assert_eq!(children(&dom, root), [a, b]);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot insert a node into itself or its descendants")]
+    fn insert_into_own_subtree() {
+        let mut dom = Dom::new(NodeType::Document);
+        let root = dom.root();
+        let [a, b] = ["a", "b"].map(|name| text(&mut dom, name));
+        dom.append_child(root, a);
+        dom.append_child(a, b);
+        dom.append_child(b, a);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot insert a node into itself or its descendants")]
+    fn insert_into_itself() {
+        let mut dom = Dom::new(NodeType::Document);
+        let a = text(&mut dom, "a");
+        dom.append_child(a, a);
+    }
+
+    #[test]
+    #[should_panic(expected = "reference node is not a child of the parent")]
+    fn insert_before_a_node_elsewhere() {
+        let mut dom = Dom::new(NodeType::Document);
+        let root = dom.root();
+        let [a, b, c] = ["a", "b", "c"].map(|name| text(&mut dom, name));
+        dom.append_child(root, a);
+        dom.append_child(a, b);
+        dom.insert_before(root, c, Some(b));
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot replace a node without a parent")]
+    fn replace_a_detached_node() {
+        let mut dom = Dom::new(NodeType::Document);
+        let [a, b] = ["a", "b"].map(|name| text(&mut dom, name));
+        dom.replace(a, b);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot insert a node into itself or its descendants")]
+    fn replace_with_an_ancestor() {
+        let mut dom = Dom::new(NodeType::Document);
+        let root = dom.root();
+        let [a, b] = ["a", "b"].map(|name| text(&mut dom, name));
+        dom.append_child(root, a);
+        dom.append_child(a, b);
+        dom.replace(b, a);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot move children into their own subtree")]
+    fn reparent_children_into_a_descendant() {
+        let mut dom = Dom::new(NodeType::Document);
+        let root = dom.root();
+        let a = text(&mut dom, "a");
+        dom.append_child(root, a);
+        dom.reparent_children(root, a);
+    }
+}
@@ -1,4 +1,7 @@
-#[derive(Debug, PartialEq)]
+use super::foreign::Namespace;
+use crate::span::Span;
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum NodeType {
     Document,
     Doctype,
@@ -11,36 +14,53 @@ pub enum NodeType {
 pub struct Node {
     pub children: Vec<Node>,
     pub node_type: NodeType,
+    // None for nodes the parser implied, like a missing <head> or <tbody>.
+    pub span: Option<Span>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct ElementData {
     pub tag_name: String,
-    pub attributes: Vec<(String, String)>,
+    pub namespace: Namespace,
+    pub attributes: Vec<Attribute>,
     pub is_self_closing: bool,
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub struct Attribute {
+    pub name: String,
+    pub value: String,
+    pub span: Option<Span>,
+}
+
 impl Node {
     pub fn new(node_type: NodeType, children: Vec<Node>) -> Self {
         Node {
             children,
             node_type,
+            span: None,
         }
     }
 
+    pub fn with_span(mut self, span: Option<Span>) -> Self {
+        self.span = span;
+        self
+    }
+
     pub fn text(data: String) -> Self {
         Node::new(NodeType::Text(data), vec![])
     }
 
     pub fn elem(
         name: String,
-        attrs: Vec<(String, String)>,
+        attrs: Vec<Attribute>,
         children: Vec<Node>,
         is_self_closing: bool,
     ) -> Self {
         Node::new(
             NodeType::Element(ElementData {
                 tag_name: name,
+                namespace: Namespace::Html,
                 attributes: attrs,
                 is_self_closing,
             }),
@@ -77,3 +97,16 @@ pub fn is_void_element(tag_name: &str) -> bool {
             | "wbr"
     )
 }
+
+// Elements whose text content is serialized without escaping
+pub fn is_raw_text_element(tag_name: &str) -> bool {
+    matches!(
+        tag_name,
+        "script" | "style" | "xmp" | "iframe" | "noembed" | "noframes" | "plaintext"
+    )
+}
+
+// Elements whose whitespace is significant and must not be collapsed
+pub fn is_preformatted_element(tag_name: &str) -> bool {
+    matches!(tag_name, "pre" | "textarea" | "listing" | "plaintext")
+}
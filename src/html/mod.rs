@@ -1,5 +1,9 @@
+pub mod arena;
 pub mod dom;
+pub mod entities;
+pub mod foreign;
 pub mod parser;
+pub mod query;
 pub mod tokenizer;
 
 pub use parser::Parser;
@@ -1,149 +1,2665 @@
-use super::dom::{self, Node, NodeType};
-use super::tokenizer::{Token, Tokenizer};
+use super::dom::{self, Attribute, ElementData, Node, NodeType};
+use super::foreign::{self, Namespace};
+use super::tokenizer::{State, Token, Tokenizer};
+use crate::diagnostics::{Diagnostic, DiagnosticCode};
+use crate::span::{Position, Span};
+
+/// Insertion modes of the WHATWG tree construction stage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InsertionMode {
+    Initial,
+    BeforeHtml,
+    BeforeHead,
+    InHead,
+    InHeadNoscript,
+    AfterHead,
+    InBody,
+    Text,
+    InTable,
+    InTableText,
+    InCaption,
+    InColumnGroup,
+    InTableBody,
+    InRow,
+    InCell,
+    InSelect,
+    InSelectInTable,
+    InTemplate,
+    AfterBody,
+    InFrameset,
+    AfterFrameset,
+    AfterAfterBody,
+    AfterAfterFrameset,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    Default,
+    ListItem,
+    Button,
+    Table,
+    Select,
+}
+
+#[derive(Debug, Clone)]
+struct Tag {
+    name: String,
+    attributes: Vec<Attribute>,
+    self_closing: bool,
+    // None for tags the parser implies or clones.
+    span: Option<Span>,
+}
+
+#[derive(Debug)]
+enum TreeToken {
+    Doctype,
+    StartTag(Tag),
+    EndTag(String),
+    Comment(String),
+    Text(String),
+    Eof,
+}
+
+#[derive(Debug, Clone)]
+enum FormattingEntry {
+    Marker,
+    Element(usize, Tag),
+}
+
+// Nodes live in an arena while the tree is built, since the adoption agency
+// algorithm and foster parenting move already-inserted nodes around.
+struct TreeNode {
+    node_type: NodeType,
+    namespace: Namespace,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    span: Option<Span>,
+}
+
+const DOCUMENT: usize = 0;
+
+const WHITESPACE: &[char] = &['\t', '\n', '\x0C', '\r', ' '];
 
 pub struct Parser {
     tokenizer: Tokenizer,
-    current_token: Token,
+    nodes: Vec<TreeNode>,
+    open_elements: Vec<usize>,
+    active_formatting: Vec<FormattingEntry>,
+    mode: InsertionMode,
+    original_mode: InsertionMode,
+    template_modes: Vec<InsertionMode>,
+    head: Option<usize>,
+    form: Option<usize>,
+    frameset_ok: bool,
+    foster_parenting: bool,
+    pending_table_text: String,
+    pending_table_text_span: Option<Span>,
+    // Span of the token being processed, and its name if it is an end tag;
+    // elements popped off the stack are closed at this token.
+    token_span: Span,
+    end_tag: Option<String>,
+    quirks: bool,
+    ignore_lf: bool,
+    stopped: bool,
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl Parser {
     pub fn new(input: String) -> Self {
-        let mut tokenizer = Tokenizer::new(input);
-        let current_token = tokenizer.next_token();
         Parser {
-            tokenizer,
-            current_token,
+            tokenizer: Tokenizer::new(input),
+            nodes: vec![TreeNode {
+                node_type: NodeType::Document,
+                namespace: Namespace::Html,
+                parent: None,
+                children: vec![],
+                span: None,
+            }],
+            open_elements: vec![],
+            active_formatting: vec![],
+            mode: InsertionMode::Initial,
+            original_mode: InsertionMode::Initial,
+            template_modes: vec![],
+            head: None,
+            form: None,
+            frameset_ok: true,
+            foster_parenting: false,
+            pending_table_text: String::new(),
+            pending_table_text_span: None,
+            token_span: Span::new(Position::start(), Position::start()),
+            end_tag: None,
+            quirks: false,
+            ignore_lf: false,
+            stopped: false,
+            diagnostics: vec![],
         }
     }
 
-    pub fn parse(&mut self) -> Node {
-        let mut children = vec![];
-        let mut has_doctype = false;
-        let mut has_html = false;
+    /// Builds the document tree. Parsing never fails; the parse errors it
+    /// recovered from are returned alongside the tree, in source order.
+    pub fn parse(&mut self) -> (Node, Vec<Diagnostic>) {
+        while !self.stopped {
+            let foreign = self
+                .current_node()
+                .is_some_and(|id| self.nodes[id].namespace != Namespace::Html);
+            self.tokenizer.set_allow_cdata(foreign);
 
-        // Parse doctype if present
-        if let Token::Doctype = self.current_token {
-            children.push(Node::doctype());
-            has_doctype = true;
-            self.consume_token();
-        }
+            let (token, span) = self.tokenizer.next_token();
+            self.diagnostics.extend(self.tokenizer.take_diagnostics());
+            self.token_span = span;
+            let mut token = match token {
+                Token::Doctype => TreeToken::Doctype,
+                Token::StartTag(name, attributes) => TreeToken::StartTag(Tag {
+                    name,
+                    attributes,
+                    self_closing: false,
+                    span: Some(span),
+                }),
+                Token::SelfClosingTag(name, attributes) => TreeToken::StartTag(Tag {
+                    name,
+                    attributes,
+                    self_closing: true,
+                    span: Some(span),
+                }),
+                Token::EndTag(name) => TreeToken::EndTag(name),
+                Token::Comment(text) => TreeToken::Comment(text),
+                Token::Text(text) => TreeToken::Text(text),
+                Token::EOF => TreeToken::Eof,
+            };
 
-        while self.current_token != Token::EOF {
-            if let Some(node) = self.parse_node() {
-                if let NodeType::Element(ref elem) = node.node_type {
-                    if elem.tag_name == "html" {
-                        has_html = true;
+            // A newline straight after <pre>, <listing> or <textarea> is dropped.
+            if std::mem::take(&mut self.ignore_lf) {
+                if let TreeToken::Text(text) = &mut token {
+                    if text.starts_with('\n') {
+                        text.remove(0);
+                        if text.is_empty() {
+                            continue;
+                        }
                     }
                 }
-                children.push(node);
+            }
+
+            self.end_tag = match &token {
+                TreeToken::EndTag(name) => Some(name.clone()),
+                _ => None,
+            };
+            let is_eof = matches!(token, TreeToken::Eof);
+            self.dispatch(token);
+            if is_eof {
+                self.stopped = true;
             }
         }
 
-        if !has_html {
-            let mut html_children = vec![];
-            let mut head = None;
-            let mut body_children = vec![];
+        // Whatever is still open ends with the input.
+        self.pop_to(0);
+        self.nodes[DOCUMENT].span = Some(Span::new(Position::start(), self.token_span.end));
 
-            for child in children {
-                if let NodeType::Element(ref elem) = child.node_type {
-                    if elem.tag_name == "head" {
-                        head = Some(child);
-                        continue;
-                    }
+        // Tokenizer errors are found ahead of the tokens the tree builder is
+        // working on.
+        let mut diagnostics = std::mem::take(&mut self.diagnostics);
+        diagnostics.sort_by_key(|diagnostic| diagnostic.span.start.offset);
+        (self.build_node(DOCUMENT), diagnostics)
+    }
+
+    // Tree construction dispatcher: tokens go through the current insertion
+    // mode unless the adjusted current node is in foreign content.
+    fn dispatch(&mut self, token: TreeToken) {
+        let use_html_rules = match self.current_node() {
+            None => true,
+            Some(node) => {
+                let namespace = self.nodes[node].namespace;
+                namespace == Namespace::Html
+                    || (self.is_mathml_text_integration_point(node)
+                        && match &token {
+                            TreeToken::StartTag(tag) => {
+                                tag.name != "mglyph" && tag.name != "malignmark"
+                            }
+                            TreeToken::Text(_) => true,
+                            _ => false,
+                        })
+                    || (namespace == Namespace::MathMl
+                        && self.tag_name(node) == "annotation-xml"
+                        && matches!(&token, TreeToken::StartTag(tag) if tag.name == "svg"))
+                    || (self.is_html_integration_point(node)
+                        && matches!(token, TreeToken::StartTag(_) | TreeToken::Text(_)))
+                    || matches!(token, TreeToken::Eof)
+            }
+        };
+
+        if use_html_rules {
+            self.process(self.mode, token);
+        } else {
+            self.process_foreign(token);
+        }
+    }
+
+    fn process(&mut self, mode: InsertionMode, token: TreeToken) {
+        match mode {
+            InsertionMode::Initial => self.initial(token),
+            InsertionMode::BeforeHtml => self.before_html(token),
+            InsertionMode::BeforeHead => self.before_head(token),
+            InsertionMode::InHead => self.in_head(token),
+            InsertionMode::InHeadNoscript => self.in_head_noscript(token),
+            InsertionMode::AfterHead => self.after_head(token),
+            InsertionMode::InBody => self.in_body(token),
+            InsertionMode::Text => self.text(token),
+            InsertionMode::InTable => self.in_table(token),
+            InsertionMode::InTableText => self.in_table_text(token),
+            InsertionMode::InCaption => self.in_caption(token),
+            InsertionMode::InColumnGroup => self.in_column_group(token),
+            InsertionMode::InTableBody => self.in_table_body(token),
+            InsertionMode::InRow => self.in_row(token),
+            InsertionMode::InCell => self.in_cell(token),
+            InsertionMode::InSelect => self.in_select(token),
+            InsertionMode::InSelectInTable => self.in_select_in_table(token),
+            InsertionMode::InTemplate => self.in_template(token),
+            InsertionMode::AfterBody => self.after_body(token),
+            InsertionMode::InFrameset => self.in_frameset(token),
+            InsertionMode::AfterFrameset => self.after_frameset(token),
+            InsertionMode::AfterAfterBody => self.after_after_body(token),
+            InsertionMode::AfterAfterFrameset => self.after_after_frameset(token),
+        }
+    }
+
+    fn reprocess(&mut self, mode: InsertionMode, token: TreeToken) {
+        self.mode = mode;
+        self.process(mode, token);
+    }
+
+    // Parse errors are reported at the token being processed.
+    fn error(&mut self, code: DiagnosticCode, message: impl Into<String>) {
+        self.diagnostics
+            .push(Diagnostic::error(code, message, self.token_span));
+    }
+
+    fn unexpected(&mut self, token: &TreeToken, context: &str) {
+        let (code, message) = match token {
+            TreeToken::Doctype => (DiagnosticCode::UnexpectedDoctype, "doctype".to_string()),
+            TreeToken::StartTag(tag) => (
+                DiagnosticCode::UnexpectedStartTag,
+                format!("<{}> start tag", tag.name),
+            ),
+            TreeToken::EndTag(name) => (
+                DiagnosticCode::UnexpectedEndTag,
+                format!("</{}> end tag", name),
+            ),
+            TreeToken::Text(_) => (DiagnosticCode::UnexpectedText, "text".to_string()),
+            TreeToken::Comment(_) | TreeToken::Eof => return,
+        };
+        self.error(code, format!("unexpected {} {}", message, context));
+    }
+
+    fn unexpected_doctype(&mut self) {
+        self.error(
+            DiagnosticCode::UnexpectedDoctype,
+            "unexpected doctype ignored",
+        );
+    }
+
+    fn ignore_start_tag(&mut self, name: &str) {
+        self.error(
+            DiagnosticCode::UnexpectedStartTag,
+            format!("unexpected <{}> start tag ignored", name),
+        );
+    }
+
+    fn ignore_end_tag(&mut self, name: &str) {
+        self.error(
+            DiagnosticCode::UnexpectedEndTag,
+            format!("stray </{}> end tag ignored", name),
+        );
+    }
+
+    // An end tag should close the current node once implied end tags are
+    // generated; otherwise it also closes elements that were left open.
+    fn check_current_is(&mut self, name: &str) {
+        if !self.current_is(name) {
+            self.error(
+                DiagnosticCode::MisnestedTags,
+                format!("</{}> closes elements that are still open", name),
+            );
+        }
+    }
+
+    // Elements that may be left open at </body> or the end of the input.
+    fn check_unclosed_elements(&mut self) {
+        let unclosed: Vec<String> = self
+            .open_elements
+            .iter()
+            .filter(|&&node| {
+                !self.is_html_one_of(
+                    node,
+                    &[
+                        "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc",
+                        "tbody", "td", "tfoot", "th", "thead", "tr", "body", "html",
+                    ],
+                )
+            })
+            .map(|&node| format!("<{}>", self.tag_name(node)))
+            .collect();
+        if !unclosed.is_empty() {
+            let message = format!("unclosed elements: {}", unclosed.join(", "));
+            self.error(DiagnosticCode::UnclosedElements, message);
+        }
+    }
+
+    fn initial(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => {
+                let rest = text.trim_start_matches(WHITESPACE);
+                if !rest.is_empty() {
+                    self.error(DiagnosticCode::MissingDoctype, "document has no doctype");
+                    self.quirks = true;
+                    self.reprocess(InsertionMode::BeforeHtml, TreeToken::Text(rest.to_string()));
                 }
-                body_children.push(child);
             }
+            TreeToken::Comment(text) => self.append_comment(DOCUMENT, text),
+            TreeToken::Doctype => {
+                let doctype =
+                    self.new_node(NodeType::Doctype, Namespace::Html, Some(self.token_span));
+                self.append_child(DOCUMENT, doctype);
+                self.mode = InsertionMode::BeforeHtml;
+            }
+            token => {
+                if !matches!(token, TreeToken::Eof) {
+                    self.error(DiagnosticCode::MissingDoctype, "document has no doctype");
+                }
+                self.quirks = true;
+                self.reprocess(InsertionMode::BeforeHtml, token);
+            }
+        }
+    }
+
+    fn before_html(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Doctype => self.unexpected_doctype(),
+            TreeToken::Comment(text) => self.append_comment(DOCUMENT, text),
+            TreeToken::Text(text) => {
+                let rest = text.trim_start_matches(WHITESPACE);
+                if !rest.is_empty() {
+                    self.insert_html_root(None);
+                    self.reprocess(InsertionMode::BeforeHead, TreeToken::Text(rest.to_string()));
+                }
+            }
+            TreeToken::StartTag(tag) if tag.name == "html" => {
+                self.insert_html_root(Some(&tag));
+                self.mode = InsertionMode::BeforeHead;
+            }
+            TreeToken::EndTag(name)
+                if !matches!(name.as_str(), "head" | "body" | "html" | "br") =>
+            {
+                self.ignore_end_tag(&name)
+            }
+            token => {
+                self.insert_html_root(None);
+                self.reprocess(InsertionMode::BeforeHead, token);
+            }
+        }
+    }
 
-            let head =
-                head.unwrap_or_else(|| Node::elem("head".to_string(), vec![], vec![], false));
+    fn before_head(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => {
+                let rest = text.trim_start_matches(WHITESPACE);
+                if !rest.is_empty() {
+                    self.head = Some(self.insert_html_element(&synthetic_tag("head")));
+                    self.reprocess(InsertionMode::InHead, TreeToken::Text(rest.to_string()));
+                }
+            }
+            TreeToken::Comment(text) => self.insert_comment(text),
+            TreeToken::Doctype => self.unexpected_doctype(),
+            TreeToken::StartTag(tag) if tag.name == "html" => {
+                self.in_body(TreeToken::StartTag(tag))
+            }
+            TreeToken::StartTag(tag) if tag.name == "head" => {
+                self.head = Some(self.insert_html_element(&tag));
+                self.mode = InsertionMode::InHead;
+            }
+            TreeToken::EndTag(name)
+                if !matches!(name.as_str(), "head" | "body" | "html" | "br") =>
+            {
+                self.ignore_end_tag(&name)
+            }
+            token => {
+                self.head = Some(self.insert_html_element(&synthetic_tag("head")));
+                self.reprocess(InsertionMode::InHead, token);
+            }
+        }
+    }
 
-            let body = Node::elem("body".to_string(), vec![], body_children, false);
+    fn in_head(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => {
+                let (whitespace, rest) = split_leading_whitespace(&text);
+                if !whitespace.is_empty() {
+                    self.insert_text(whitespace);
+                }
+                if !rest.is_empty() {
+                    self.pop();
+                    self.reprocess(InsertionMode::AfterHead, TreeToken::Text(rest.to_string()));
+                }
+            }
+            TreeToken::Comment(text) => self.insert_comment(text),
+            TreeToken::Doctype => self.unexpected_doctype(),
+            TreeToken::StartTag(tag) => match tag.name.as_str() {
+                "html" => self.in_body(TreeToken::StartTag(tag)),
+                "base" | "basefont" | "bgsound" | "link" | "meta" => {
+                    self.insert_html_element(&tag);
+                    self.pop();
+                }
+                "title" => self.parse_raw_text(&tag, State::Rcdata),
+                "noframes" | "style" => self.parse_raw_text(&tag, State::Rawtext),
+                "noscript" => {
+                    // Scripting is disabled, so <noscript> content is parsed as markup.
+                    self.insert_html_element(&tag);
+                    self.mode = InsertionMode::InHeadNoscript;
+                }
+                "script" => self.parse_raw_text(&tag, State::ScriptData),
+                "template" => {
+                    self.insert_html_element(&tag);
+                    self.active_formatting.push(FormattingEntry::Marker);
+                    self.frameset_ok = false;
+                    self.mode = InsertionMode::InTemplate;
+                    self.template_modes.push(InsertionMode::InTemplate);
+                }
+                "head" => self.ignore_start_tag(&tag.name),
+                _ => {
+                    self.pop();
+                    self.reprocess(InsertionMode::AfterHead, TreeToken::StartTag(tag));
+                }
+            },
+            TreeToken::EndTag(name) => match name.as_str() {
+                "head" => {
+                    self.pop();
+                    self.mode = InsertionMode::AfterHead;
+                }
+                "body" | "html" | "br" => {
+                    self.pop();
+                    self.reprocess(InsertionMode::AfterHead, TreeToken::EndTag(name));
+                }
+                "template" => {
+                    if !self.has_open_element("template") {
+                        self.ignore_end_tag(&name);
+                        return;
+                    }
+                    self.generate_all_implied_end_tags_thoroughly();
+                    self.check_current_is("template");
+                    self.pop_until_one_of(&["template"]);
+                    self.clear_formatting_to_last_marker();
+                    self.template_modes.pop();
+                    self.reset_insertion_mode();
+                }
+                _ => self.ignore_end_tag(&name),
+            },
+            TreeToken::Eof => {
+                self.pop();
+                self.reprocess(InsertionMode::AfterHead, TreeToken::Eof);
+            }
+        }
+    }
 
-            html_children.push(head);
-            html_children.push(body);
+    fn in_head_noscript(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Doctype => self.unexpected_doctype(),
+            TreeToken::StartTag(tag) if tag.name == "html" => {
+                self.in_body(TreeToken::StartTag(tag))
+            }
+            TreeToken::EndTag(name) if name == "noscript" => {
+                self.pop();
+                self.mode = InsertionMode::InHead;
+            }
+            TreeToken::Text(text) => {
+                let (whitespace, rest) = split_leading_whitespace(&text);
+                if !whitespace.is_empty() {
+                    self.insert_text(whitespace);
+                }
+                if !rest.is_empty() {
+                    self.pop();
+                    self.reprocess(InsertionMode::InHead, TreeToken::Text(rest.to_string()));
+                }
+            }
+            TreeToken::Comment(_) => self.in_head(token),
+            TreeToken::StartTag(tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "basefont" | "bgsound" | "link" | "meta" | "noframes" | "style"
+                ) =>
+            {
+                self.in_head(TreeToken::StartTag(tag))
+            }
+            TreeToken::StartTag(tag) if tag.name == "head" || tag.name == "noscript" => {
+                self.ignore_start_tag(&tag.name)
+            }
+            TreeToken::EndTag(name) if name != "br" => self.ignore_end_tag(&name),
+            token => {
+                self.unexpected(&token, "in <noscript> closes it");
+                self.pop();
+                self.reprocess(InsertionMode::InHead, token);
+            }
+        }
+    }
 
-            children = vec![Node::elem("html".to_string(), vec![], html_children, false)];
+    fn after_head(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => {
+                let (whitespace, rest) = split_leading_whitespace(&text);
+                if !whitespace.is_empty() {
+                    self.insert_text(whitespace);
+                }
+                if !rest.is_empty() {
+                    self.insert_html_element(&synthetic_tag("body"));
+                    self.reprocess(InsertionMode::InBody, TreeToken::Text(rest.to_string()));
+                }
+            }
+            TreeToken::Comment(text) => self.insert_comment(text),
+            TreeToken::Doctype => self.unexpected_doctype(),
+            TreeToken::StartTag(tag) => match tag.name.as_str() {
+                "html" => self.in_body(TreeToken::StartTag(tag)),
+                "body" => {
+                    self.insert_html_element(&tag);
+                    self.frameset_ok = false;
+                    self.mode = InsertionMode::InBody;
+                }
+                "frameset" => {
+                    self.insert_html_element(&tag);
+                    self.mode = InsertionMode::InFrameset;
+                }
+                "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script"
+                | "style" | "template" | "title" => {
+                    // Head content after </head> still goes into the head.
+                    let Some(head) = self.head else { return };
+                    self.open_elements.push(head);
+                    self.in_head(TreeToken::StartTag(tag));
+                    self.remove_from_stack(head);
+                }
+                "head" => self.ignore_start_tag(&tag.name),
+                _ => {
+                    self.insert_html_element(&synthetic_tag("body"));
+                    self.reprocess(InsertionMode::InBody, TreeToken::StartTag(tag));
+                }
+            },
+            TreeToken::EndTag(name) if name == "template" => self.in_head(TreeToken::EndTag(name)),
+            TreeToken::EndTag(name) if !matches!(name.as_str(), "body" | "html" | "br") => {
+                self.ignore_end_tag(&name)
+            }
+            token => {
+                self.insert_html_element(&synthetic_tag("body"));
+                self.reprocess(InsertionMode::InBody, token);
+            }
         }
+    }
 
-        Node::new(NodeType::Document, children)
+    fn in_body(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => {
+                let text = text.replace('\0', "");
+                if text.is_empty() {
+                    return;
+                }
+                self.reconstruct_active_formatting();
+                self.insert_text(&text);
+                if !is_whitespace(&text) {
+                    self.frameset_ok = false;
+                }
+            }
+            TreeToken::Comment(text) => self.insert_comment(text),
+            TreeToken::Doctype => self.unexpected_doctype(),
+            TreeToken::StartTag(tag) => self.in_body_start_tag(tag),
+            TreeToken::EndTag(name) => self.in_body_end_tag(name),
+            TreeToken::Eof => {
+                if !self.template_modes.is_empty() {
+                    self.in_template(TreeToken::Eof);
+                } else {
+                    self.check_unclosed_elements();
+                }
+            }
+        }
     }
 
-    fn parse_node(&mut self) -> Option<Node> {
-        match &self.current_token {
-            Token::StartTag(name, attrs) => {
-                let name = name.clone();
-                let attrs = attrs.clone();
-                self.consume_token();
+    fn in_body_start_tag(&mut self, mut tag: Tag) {
+        match tag.name.as_str() {
+            "html" => {
+                self.error(
+                    DiagnosticCode::UnexpectedStartTag,
+                    "unexpected <html> start tag",
+                );
+                if self.has_open_element("template") {
+                    return;
+                }
+                if let Some(&html) = self.open_elements.first() {
+                    self.merge_attributes(html, tag.attributes);
+                }
+            }
+            "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style"
+            | "template" | "title" => self.in_head(TreeToken::StartTag(tag)),
+            "body" => {
+                self.error(
+                    DiagnosticCode::UnexpectedStartTag,
+                    "unexpected <body> start tag",
+                );
+                let body = self.open_elements.get(1).copied();
+                match body {
+                    Some(body)
+                        if self.is_html_element(body, "body")
+                            && !self.has_open_element("template") =>
+                    {
+                        self.frameset_ok = false;
+                        self.merge_attributes(body, tag.attributes);
+                    }
+                    _ => {}
+                }
+            }
+            "frameset" => {
+                self.error(
+                    DiagnosticCode::UnexpectedStartTag,
+                    "unexpected <frameset> start tag",
+                );
+                let Some(body) = self.open_elements.get(1).copied() else {
+                    return;
+                };
+                if !self.is_html_element(body, "body") || !self.frameset_ok {
+                    return;
+                }
+                self.detach(body);
+                self.pop_to(1);
+                self.insert_html_element(&tag);
+                self.mode = InsertionMode::InFrameset;
+            }
+            "address" | "article" | "aside" | "blockquote" | "center" | "details" | "dialog"
+            | "dir" | "div" | "dl" | "fieldset" | "figcaption" | "figure" | "footer" | "header"
+            | "hgroup" | "main" | "menu" | "nav" | "ol" | "p" | "search" | "section"
+            | "summary" | "ul" => {
+                self.close_p_in_button_scope();
+                self.insert_html_element(&tag);
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.close_p_in_button_scope();
+                if self
+                    .current_node()
+                    .is_some_and(|node| self.is_html_one_of(node, HEADINGS))
+                {
+                    self.pop();
+                }
+                self.insert_html_element(&tag);
+            }
+            "pre" | "listing" => {
+                self.close_p_in_button_scope();
+                self.insert_html_element(&tag);
+                self.ignore_lf = true;
+                self.frameset_ok = false;
+            }
+            "form" => {
+                let in_template = self.has_open_element("template");
+                if self.form.is_some() && !in_template {
+                    self.ignore_start_tag(&tag.name);
+                    return;
+                }
+                self.close_p_in_button_scope();
+                let form = self.insert_html_element(&tag);
+                if !in_template {
+                    self.form = Some(form);
+                }
+            }
+            "li" => {
+                self.frameset_ok = false;
+                self.close_list_item(&["li"]);
+                self.close_p_in_button_scope();
+                self.insert_html_element(&tag);
+            }
+            "dd" | "dt" => {
+                self.frameset_ok = false;
+                self.close_list_item(&["dd", "dt"]);
+                self.close_p_in_button_scope();
+                self.insert_html_element(&tag);
+            }
+            "plaintext" => {
+                self.close_p_in_button_scope();
+                self.insert_html_element(&tag);
+                self.tokenizer.set_state(State::Plaintext);
+            }
+            "button" => {
+                if self.has_in_scope("button", Scope::Default) {
+                    self.generate_implied_end_tags(None);
+                    self.pop_until_one_of(&["button"]);
+                }
+                self.reconstruct_active_formatting();
+                self.insert_html_element(&tag);
+                self.frameset_ok = false;
+            }
+            "a" => {
+                let open_a = self.formatting_after_last_marker("a");
+                if let Some(element) = open_a {
+                    self.error(
+                        DiagnosticCode::MisnestedTags,
+                        "<a> inside an open <a> element",
+                    );
+                    if !self.adoption_agency("a") {
+                        self.any_other_end_tag("a");
+                    }
+                    self.remove_from_formatting(element);
+                    self.remove_from_stack(element);
+                }
+                self.reconstruct_active_formatting();
+                let element = self.insert_html_element(&tag);
+                self.push_formatting(element, tag);
+            }
+            "b" | "big" | "code" | "em" | "font" | "i" | "s" | "small" | "strike" | "strong"
+            | "tt" | "u" => {
+                self.reconstruct_active_formatting();
+                let element = self.insert_html_element(&tag);
+                self.push_formatting(element, tag);
+            }
+            "nobr" => {
+                self.reconstruct_active_formatting();
+                if self.has_in_scope("nobr", Scope::Default) {
+                    self.error(
+                        DiagnosticCode::MisnestedTags,
+                        "<nobr> inside an open <nobr> element",
+                    );
+                    if !self.adoption_agency("nobr") {
+                        self.any_other_end_tag("nobr");
+                    }
+                    self.reconstruct_active_formatting();
+                }
+                let element = self.insert_html_element(&tag);
+                self.push_formatting(element, tag);
+            }
+            "applet" | "marquee" | "object" => {
+                self.reconstruct_active_formatting();
+                self.insert_html_element(&tag);
+                self.active_formatting.push(FormattingEntry::Marker);
+                self.frameset_ok = false;
+            }
+            "table" => {
+                if !self.quirks {
+                    self.close_p_in_button_scope();
+                }
+                self.insert_html_element(&tag);
+                self.frameset_ok = false;
+                self.mode = InsertionMode::InTable;
+            }
+            "area" | "br" | "embed" | "img" | "keygen" | "wbr" => {
+                self.reconstruct_active_formatting();
+                self.insert_html_element(&tag);
+                self.pop();
+                self.frameset_ok = false;
+            }
+            "input" => {
+                self.reconstruct_active_formatting();
+                self.insert_html_element(&tag);
+                self.pop();
+                if !is_hidden_input(&tag) {
+                    self.frameset_ok = false;
+                }
+            }
+            "param" | "source" | "track" => {
+                self.insert_html_element(&tag);
+                self.pop();
+            }
+            "hr" => {
+                self.close_p_in_button_scope();
+                self.insert_html_element(&tag);
+                self.pop();
+                self.frameset_ok = false;
+            }
+            "image" => {
+                tag.name = "img".to_string();
+                self.in_body_start_tag(tag);
+            }
+            "textarea" => {
+                self.insert_html_element(&tag);
+                self.ignore_lf = true;
+                self.tokenizer.set_state(State::Rcdata);
+                self.original_mode = self.mode;
+                self.frameset_ok = false;
+                self.mode = InsertionMode::Text;
+            }
+            "xmp" => {
+                self.close_p_in_button_scope();
+                self.reconstruct_active_formatting();
+                self.frameset_ok = false;
+                self.parse_raw_text(&tag, State::Rawtext);
+            }
+            "iframe" => {
+                self.frameset_ok = false;
+                self.parse_raw_text(&tag, State::Rawtext);
+            }
+            "noembed" => self.parse_raw_text(&tag, State::Rawtext),
+            "select" => {
+                self.reconstruct_active_formatting();
+                self.insert_html_element(&tag);
+                self.frameset_ok = false;
+                self.mode = match self.mode {
+                    InsertionMode::InTable
+                    | InsertionMode::InCaption
+                    | InsertionMode::InTableBody
+                    | InsertionMode::InRow
+                    | InsertionMode::InCell => InsertionMode::InSelectInTable,
+                    _ => InsertionMode::InSelect,
+                };
+            }
+            "optgroup" | "option" => {
+                if self.current_is("option") {
+                    self.pop();
+                }
+                self.reconstruct_active_formatting();
+                self.insert_html_element(&tag);
+            }
+            "rb" | "rtc" => {
+                if self.has_in_scope("ruby", Scope::Default) {
+                    self.generate_implied_end_tags(None);
+                }
+                self.insert_html_element(&tag);
+            }
+            "rp" | "rt" => {
+                if self.has_in_scope("ruby", Scope::Default) {
+                    self.generate_implied_end_tags(Some("rtc"));
+                }
+                self.insert_html_element(&tag);
+            }
+            "math" | "svg" => {
+                self.reconstruct_active_formatting();
+                let namespace = if tag.name == "math" {
+                    Namespace::MathMl
+                } else {
+                    Namespace::Svg
+                };
+                self.insert_foreign_element(tag, namespace);
+            }
+            "caption" | "col" | "colgroup" | "frame" | "head" | "tbody" | "td" | "tfoot" | "th"
+            | "thead" | "tr" => self.ignore_start_tag(&tag.name),
+            _ => {
+                self.reconstruct_active_formatting();
+                self.insert_html_element(&tag);
+            }
+        }
+    }
 
-                if dom::is_void_element(&name) {
-                    Some(Node::elem(name, attrs, vec![], true))
+    fn in_body_end_tag(&mut self, name: String) {
+        match name.as_str() {
+            "template" => self.in_head(TreeToken::EndTag(name)),
+            "body" => {
+                if self.has_in_scope("body", Scope::Default) {
+                    self.check_unclosed_elements();
+                    self.mode = InsertionMode::AfterBody;
+                } else {
+                    self.ignore_end_tag(&name);
+                }
+            }
+            "html" => {
+                if self.has_in_scope("body", Scope::Default) {
+                    self.check_unclosed_elements();
+                    self.reprocess(InsertionMode::AfterBody, TreeToken::EndTag(name));
+                } else {
+                    self.ignore_end_tag(&name);
+                }
+            }
+            "address" | "article" | "aside" | "blockquote" | "button" | "center" | "details"
+            | "dialog" | "dir" | "div" | "dl" | "fieldset" | "figcaption" | "figure" | "footer"
+            | "header" | "hgroup" | "listing" | "main" | "menu" | "nav" | "ol" | "pre"
+            | "search" | "section" | "summary" | "ul" => {
+                if !self.has_in_scope(&name, Scope::Default) {
+                    self.ignore_end_tag(&name);
+                    return;
+                }
+                self.generate_implied_end_tags(None);
+                self.check_current_is(&name);
+                self.pop_until_one_of(&[name.as_str()]);
+            }
+            "form" => {
+                if self.has_open_element("template") {
+                    if !self.has_in_scope("form", Scope::Default) {
+                        self.ignore_end_tag(&name);
+                        return;
+                    }
+                    self.generate_implied_end_tags(None);
+                    self.check_current_is("form");
+                    self.pop_until_one_of(&["form"]);
+                } else {
+                    let form = self.form.take();
+                    match form {
+                        Some(form) if self.has_element_in_scope(form, Scope::Default) => {
+                            self.generate_implied_end_tags(None);
+                            if self.current_node() != Some(form) {
+                                self.error(
+                                    DiagnosticCode::MisnestedTags,
+                                    "</form> closes a form with open elements",
+                                );
+                            }
+                            self.remove_from_stack(form);
+                        }
+                        _ => self.ignore_end_tag(&name),
+                    }
+                }
+            }
+            "p" => {
+                if !self.has_in_scope("p", Scope::Button) {
+                    self.error(
+                        DiagnosticCode::UnexpectedEndTag,
+                        "</p> without an open <p> element",
+                    );
+                    self.insert_html_element(&synthetic_tag("p"));
+                }
+                self.close_p();
+            }
+            "li" => {
+                if self.has_in_scope("li", Scope::ListItem) {
+                    self.generate_implied_end_tags(Some("li"));
+                    self.check_current_is("li");
+                    self.pop_until_one_of(&["li"]);
                 } else {
-                    Some(self.parse_element(name, attrs))
+                    self.ignore_end_tag(&name);
                 }
             }
-            Token::SelfClosingTag(name, attrs) => {
-                let name = name.clone();
-                let attrs = attrs.clone();
-                self.consume_token();
-                Some(Node::elem(name, attrs, vec![], true))
+            "dd" | "dt" => {
+                if self.has_in_scope(&name, Scope::Default) {
+                    self.generate_implied_end_tags(Some(&name));
+                    self.check_current_is(&name);
+                    self.pop_until_one_of(&[name.as_str()]);
+                } else {
+                    self.ignore_end_tag(&name);
+                }
             }
-            Token::Text(text) => {
-                let text = text.clone();
-                self.consume_token();
-                Some(Node::text(text))
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                if HEADINGS
+                    .iter()
+                    .any(|heading| self.has_in_scope(heading, Scope::Default))
+                {
+                    self.generate_implied_end_tags(None);
+                    self.check_current_is(&name);
+                    self.pop_until_one_of(HEADINGS);
+                } else {
+                    self.ignore_end_tag(&name);
+                }
             }
-            Token::Comment(text) => {
-                let text = text.clone();
-                self.consume_token();
-                Some(Node::comment(text))
+            "a" | "b" | "big" | "code" | "em" | "font" | "i" | "nobr" | "s" | "small"
+            | "strike" | "strong" | "tt" | "u" => {
+                if !self.adoption_agency(&name) {
+                    self.any_other_end_tag(&name);
+                }
             }
-            Token::Doctype => {
-                self.consume_token();
-                Some(Node::doctype())
+            "applet" | "marquee" | "object" => {
+                if self.has_in_scope(&name, Scope::Default) {
+                    self.generate_implied_end_tags(None);
+                    self.check_current_is(&name);
+                    self.pop_until_one_of(&[name.as_str()]);
+                    self.clear_formatting_to_last_marker();
+                } else {
+                    self.ignore_end_tag(&name);
+                }
             }
-            Token::EndTag(_) => {
-                self.consume_token();
-                None
+            "br" => {
+                self.error(DiagnosticCode::UnexpectedEndTag, "</br> is treated as <br>");
+                self.in_body_start_tag(synthetic_tag("br"));
             }
-            Token::EOF => None,
+            _ => self.any_other_end_tag(&name),
         }
     }
 
-    fn parse_element(&mut self, tag_name: String, attributes: Vec<(String, String)>) -> Node {
-        let mut children = vec![];
-        let mut stack = vec![tag_name.clone()];
+    fn any_other_end_tag(&mut self, name: &str) {
+        for index in (0..self.open_elements.len()).rev() {
+            let node = self.open_elements[index];
+            if self.is_html_element(node, name) {
+                self.generate_implied_end_tags(Some(name));
+                self.check_current_is(name);
+                self.pop_to(index);
+                return;
+            }
+            if self.is_special(node) {
+                self.ignore_end_tag(name);
+                return;
+            }
+        }
+    }
 
-        while self.current_token != Token::EOF {
-            match &self.current_token {
-                Token::EndTag(end_tag) if end_tag == &tag_name => {
-                    self.consume_token();
-                    break;
+    fn text(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => self.insert_text(&text),
+            TreeToken::Eof => {
+                if let Some(node) = self.current_node() {
+                    let message = format!("end of file in <{}>", self.tag_name(node));
+                    self.error(DiagnosticCode::UnclosedElements, message);
                 }
-                Token::EndTag(unexpected_end) => {
-                    if stack.last() == Some(unexpected_end) {
-                        stack.pop();
-                        self.consume_token();
+                self.pop();
+                self.reprocess(self.original_mode, TreeToken::Eof);
+            }
+            TreeToken::EndTag(_) => {
+                self.pop();
+                self.mode = self.original_mode;
+            }
+            _ => {}
+        }
+    }
+
+    fn in_table(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text)
+                if self.current_node().is_some_and(|node| {
+                    self.is_html_one_of(
+                        node,
+                        &["table", "tbody", "template", "tfoot", "thead", "tr"],
+                    )
+                }) =>
+            {
+                self.pending_table_text.clear();
+                self.pending_table_text_span = None;
+                self.original_mode = self.mode;
+                self.reprocess(InsertionMode::InTableText, TreeToken::Text(text));
+            }
+            TreeToken::Comment(text) => self.insert_comment(text),
+            TreeToken::Doctype => self.unexpected_doctype(),
+            TreeToken::StartTag(tag) => match tag.name.as_str() {
+                "caption" => {
+                    self.clear_stack_back_to(&["table", "template", "html"]);
+                    self.active_formatting.push(FormattingEntry::Marker);
+                    self.insert_html_element(&tag);
+                    self.mode = InsertionMode::InCaption;
+                }
+                "colgroup" => {
+                    self.clear_stack_back_to(&["table", "template", "html"]);
+                    self.insert_html_element(&tag);
+                    self.mode = InsertionMode::InColumnGroup;
+                }
+                "col" => {
+                    self.clear_stack_back_to(&["table", "template", "html"]);
+                    self.insert_html_element(&synthetic_tag("colgroup"));
+                    self.reprocess(InsertionMode::InColumnGroup, TreeToken::StartTag(tag));
+                }
+                "tbody" | "tfoot" | "thead" => {
+                    self.clear_stack_back_to(&["table", "template", "html"]);
+                    self.insert_html_element(&tag);
+                    self.mode = InsertionMode::InTableBody;
+                }
+                "td" | "th" | "tr" => {
+                    self.clear_stack_back_to(&["table", "template", "html"]);
+                    self.insert_html_element(&synthetic_tag("tbody"));
+                    self.reprocess(InsertionMode::InTableBody, TreeToken::StartTag(tag));
+                }
+                "table" => {
+                    self.error(
+                        DiagnosticCode::UnexpectedStartTag,
+                        "<table> inside a table closes it",
+                    );
+                    if self.has_in_scope("table", Scope::Table) {
+                        self.pop_until_one_of(&["table"]);
+                        self.reset_insertion_mode();
+                        self.process(self.mode, TreeToken::StartTag(tag));
+                    }
+                }
+                "style" | "script" | "template" => self.in_head(TreeToken::StartTag(tag)),
+                "input" if is_hidden_input(&tag) => {
+                    self.insert_html_element(&tag);
+                    self.pop();
+                }
+                "form" => {
+                    self.error(DiagnosticCode::UnexpectedStartTag, "<form> inside a table");
+                    if self.has_open_element("template") || self.form.is_some() {
+                        return;
+                    }
+                    self.form = Some(self.insert_html_element(&tag));
+                    self.pop();
+                }
+                _ => self.foster_in_body(TreeToken::StartTag(tag)),
+            },
+            TreeToken::EndTag(name) => match name.as_str() {
+                "table" => {
+                    if self.has_in_scope("table", Scope::Table) {
+                        self.pop_until_one_of(&["table"]);
+                        self.reset_insertion_mode();
                     } else {
-                        self.consume_token();
+                        self.ignore_end_tag(&name);
                     }
                 }
-                _ => {
-                    if let Some(child) = self.parse_node() {
-                        if let NodeType::Element(ref elem) = child.node_type {
-                            if !dom::is_void_element(&elem.tag_name) {
-                                stack.push(elem.tag_name.clone());
-                            }
-                        }
-                        children.push(child);
+                "body" | "caption" | "col" | "colgroup" | "html" | "tbody" | "td" | "tfoot"
+                | "th" | "thead" | "tr" => self.ignore_end_tag(&name),
+                "template" => self.in_head(TreeToken::EndTag(name)),
+                _ => self.foster_in_body(TreeToken::EndTag(name)),
+            },
+            TreeToken::Eof => self.in_body(TreeToken::Eof),
+            token => self.foster_in_body(token),
+        }
+    }
+
+    // "Anything else" in table modes: process in body with foster parenting.
+    fn foster_in_body(&mut self, token: TreeToken) {
+        self.unexpected(&token, "inside a table");
+        self.foster_parenting = true;
+        self.in_body(token);
+        self.foster_parenting = false;
+    }
+
+    fn in_table_text(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => {
+                self.pending_table_text.push_str(&text.replace('\0', ""));
+                self.pending_table_text_span =
+                    merge_spans(self.pending_table_text_span, Some(self.token_span));
+            }
+            token => {
+                let text = std::mem::take(&mut self.pending_table_text);
+                if !text.is_empty() {
+                    // The buffered text is inserted under its own span, not
+                    // that of the token which ended it.
+                    let token_span = self.token_span;
+                    if let Some(span) = self.pending_table_text_span.take() {
+                        self.token_span = span;
+                    }
+                    if is_whitespace(&text) {
+                        self.insert_text(&text);
+                    } else {
+                        self.foster_in_body(TreeToken::Text(text));
                     }
+                    self.token_span = token_span;
+                }
+                self.reprocess(self.original_mode, token);
+            }
+        }
+    }
+
+    fn in_caption(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::EndTag(name) if name == "caption" => {
+                if !self.close_caption() {
+                    self.ignore_end_tag(&name);
                 }
             }
+            TreeToken::StartTag(tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "caption"
+                        | "col"
+                        | "colgroup"
+                        | "tbody"
+                        | "td"
+                        | "tfoot"
+                        | "th"
+                        | "thead"
+                        | "tr"
+                ) =>
+            {
+                if self.close_caption() {
+                    self.process(self.mode, TreeToken::StartTag(tag));
+                } else {
+                    self.ignore_start_tag(&tag.name);
+                }
+            }
+            TreeToken::EndTag(name) if name == "table" => {
+                if self.close_caption() {
+                    self.process(self.mode, TreeToken::EndTag(name));
+                } else {
+                    self.ignore_end_tag(&name);
+                }
+            }
+            TreeToken::EndTag(name)
+                if matches!(
+                    name.as_str(),
+                    "body"
+                        | "col"
+                        | "colgroup"
+                        | "html"
+                        | "tbody"
+                        | "td"
+                        | "tfoot"
+                        | "th"
+                        | "thead"
+                        | "tr"
+                ) =>
+            {
+                self.ignore_end_tag(&name)
+            }
+            token => self.in_body(token),
         }
+    }
 
-        Node::elem(tag_name, attributes, children, false)
+    fn close_caption(&mut self) -> bool {
+        if !self.has_in_scope("caption", Scope::Table) {
+            return false;
+        }
+        self.generate_implied_end_tags(None);
+        self.check_current_is("caption");
+        self.pop_until_one_of(&["caption"]);
+        self.clear_formatting_to_last_marker();
+        self.mode = InsertionMode::InTable;
+        true
     }
 
-    fn consume_token(&mut self) {
-        self.current_token = self.tokenizer.next_token();
+    fn in_column_group(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => {
+                let (whitespace, rest) = split_leading_whitespace(&text);
+                if !whitespace.is_empty() {
+                    self.insert_text(whitespace);
+                }
+                if !rest.is_empty() && self.current_is("colgroup") {
+                    self.pop();
+                    self.reprocess(InsertionMode::InTable, TreeToken::Text(rest.to_string()));
+                }
+            }
+            TreeToken::Comment(text) => self.insert_comment(text),
+            TreeToken::Doctype => self.unexpected_doctype(),
+            TreeToken::StartTag(tag) if tag.name == "html" => {
+                self.in_body(TreeToken::StartTag(tag))
+            }
+            TreeToken::StartTag(tag) if tag.name == "col" => {
+                self.insert_html_element(&tag);
+                self.pop();
+            }
+            TreeToken::EndTag(name) if name == "colgroup" => {
+                if self.current_is("colgroup") {
+                    self.pop();
+                    self.mode = InsertionMode::InTable;
+                } else {
+                    self.ignore_end_tag(&name);
+                }
+            }
+            TreeToken::EndTag(name) if name == "col" => self.ignore_end_tag(&name),
+            TreeToken::StartTag(tag) if tag.name == "template" => {
+                self.in_head(TreeToken::StartTag(tag))
+            }
+            TreeToken::EndTag(name) if name == "template" => self.in_head(TreeToken::EndTag(name)),
+            TreeToken::Eof => self.in_body(TreeToken::Eof),
+            token => {
+                if self.current_is("colgroup") {
+                    self.pop();
+                    self.reprocess(InsertionMode::InTable, token);
+                } else {
+                    self.unexpected(&token, "ignored");
+                }
+            }
+        }
     }
+
+    fn in_table_body(&mut self, token: TreeToken) {
+        const CONTEXT: &[&str] = &["tbody", "tfoot", "thead", "template", "html"];
+        match token {
+            TreeToken::StartTag(tag) if tag.name == "tr" => {
+                self.clear_stack_back_to(CONTEXT);
+                self.insert_html_element(&tag);
+                self.mode = InsertionMode::InRow;
+            }
+            TreeToken::StartTag(tag) if tag.name == "th" || tag.name == "td" => {
+                self.error(
+                    DiagnosticCode::UnexpectedStartTag,
+                    format!("<{}> outside of a table row", tag.name),
+                );
+                self.clear_stack_back_to(CONTEXT);
+                self.insert_html_element(&synthetic_tag("tr"));
+                self.reprocess(InsertionMode::InRow, TreeToken::StartTag(tag));
+            }
+            TreeToken::EndTag(name) if matches!(name.as_str(), "tbody" | "tfoot" | "thead") => {
+                if self.has_in_scope(&name, Scope::Table) {
+                    self.clear_stack_back_to(CONTEXT);
+                    self.pop();
+                    self.mode = InsertionMode::InTable;
+                } else {
+                    self.ignore_end_tag(&name);
+                }
+            }
+            TreeToken::StartTag(_) | TreeToken::EndTag(_)
+                if match &token {
+                    TreeToken::StartTag(tag) => matches!(
+                        tag.name.as_str(),
+                        "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead"
+                    ),
+                    TreeToken::EndTag(name) => name == "table",
+                    _ => false,
+                } =>
+            {
+                if ["tbody", "thead", "tfoot"]
+                    .iter()
+                    .any(|name| self.has_in_scope(name, Scope::Table))
+                {
+                    self.clear_stack_back_to(CONTEXT);
+                    self.pop();
+                    self.reprocess(InsertionMode::InTable, token);
+                } else {
+                    self.unexpected(&token, "ignored");
+                }
+            }
+            TreeToken::EndTag(name)
+                if matches!(
+                    name.as_str(),
+                    "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th" | "tr"
+                ) =>
+            {
+                self.ignore_end_tag(&name)
+            }
+            token => self.in_table(token),
+        }
+    }
+
+    fn in_row(&mut self, token: TreeToken) {
+        const CONTEXT: &[&str] = &["tr", "template", "html"];
+        match token {
+            TreeToken::StartTag(tag) if tag.name == "th" || tag.name == "td" => {
+                self.clear_stack_back_to(CONTEXT);
+                self.insert_html_element(&tag);
+                self.mode = InsertionMode::InCell;
+                self.active_formatting.push(FormattingEntry::Marker);
+            }
+            TreeToken::EndTag(name) if name == "tr" => {
+                if self.has_in_scope("tr", Scope::Table) {
+                    self.clear_stack_back_to(CONTEXT);
+                    self.pop();
+                    self.mode = InsertionMode::InTableBody;
+                } else {
+                    self.ignore_end_tag(&name);
+                }
+            }
+            TreeToken::StartTag(_) | TreeToken::EndTag(_)
+                if match &token {
+                    TreeToken::StartTag(tag) => matches!(
+                        tag.name.as_str(),
+                        "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead" | "tr"
+                    ),
+                    TreeToken::EndTag(name) => name == "table",
+                    _ => false,
+                } =>
+            {
+                if self.has_in_scope("tr", Scope::Table) {
+                    self.clear_stack_back_to(CONTEXT);
+                    self.pop();
+                    self.reprocess(InsertionMode::InTableBody, token);
+                } else {
+                    self.unexpected(&token, "ignored");
+                }
+            }
+            TreeToken::EndTag(name) if matches!(name.as_str(), "tbody" | "tfoot" | "thead") => {
+                if self.has_in_scope(&name, Scope::Table) && self.has_in_scope("tr", Scope::Table) {
+                    self.clear_stack_back_to(CONTEXT);
+                    self.pop();
+                    self.reprocess(InsertionMode::InTableBody, TreeToken::EndTag(name));
+                } else {
+                    self.ignore_end_tag(&name);
+                }
+            }
+            TreeToken::EndTag(name)
+                if matches!(
+                    name.as_str(),
+                    "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th"
+                ) =>
+            {
+                self.ignore_end_tag(&name)
+            }
+            token => self.in_table(token),
+        }
+    }
+
+    fn in_cell(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::EndTag(name) if name == "td" || name == "th" => {
+                if self.has_in_scope(&name, Scope::Table) {
+                    self.generate_implied_end_tags(None);
+                    self.check_current_is(&name);
+                    self.pop_until_one_of(&[name.as_str()]);
+                    self.clear_formatting_to_last_marker();
+                    self.mode = InsertionMode::InRow;
+                } else {
+                    self.ignore_end_tag(&name);
+                }
+            }
+            TreeToken::StartTag(tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "caption"
+                        | "col"
+                        | "colgroup"
+                        | "tbody"
+                        | "td"
+                        | "tfoot"
+                        | "th"
+                        | "thead"
+                        | "tr"
+                ) =>
+            {
+                if self.has_in_scope("td", Scope::Table) || self.has_in_scope("th", Scope::Table) {
+                    self.close_cell();
+                    self.process(self.mode, TreeToken::StartTag(tag));
+                } else {
+                    self.ignore_start_tag(&tag.name);
+                }
+            }
+            TreeToken::EndTag(name)
+                if matches!(
+                    name.as_str(),
+                    "body" | "caption" | "col" | "colgroup" | "html"
+                ) =>
+            {
+                self.ignore_end_tag(&name)
+            }
+            TreeToken::EndTag(name)
+                if matches!(name.as_str(), "table" | "tbody" | "tfoot" | "thead" | "tr") =>
+            {
+                if self.has_in_scope(&name, Scope::Table) {
+                    self.close_cell();
+                    self.process(self.mode, TreeToken::EndTag(name));
+                } else {
+                    self.ignore_end_tag(&name);
+                }
+            }
+            token => self.in_body(token),
+        }
+    }
+
+    fn close_cell(&mut self) {
+        self.generate_implied_end_tags(None);
+        if !self.current_is("td") && !self.current_is("th") {
+            self.error(
+                DiagnosticCode::MisnestedTags,
+                "table cell closed with open elements",
+            );
+        }
+        self.pop_until_one_of(&["td", "th"]);
+        self.clear_formatting_to_last_marker();
+        self.mode = InsertionMode::InRow;
+    }
+
+    fn in_select(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => {
+                let text = text.replace('\0', "");
+                if !text.is_empty() {
+                    self.insert_text(&text);
+                }
+            }
+            TreeToken::Comment(text) => self.insert_comment(text),
+            TreeToken::Doctype => self.unexpected_doctype(),
+            TreeToken::StartTag(tag) => match tag.name.as_str() {
+                "html" => self.in_body(TreeToken::StartTag(tag)),
+                "option" => {
+                    if self.current_is("option") {
+                        self.pop();
+                    }
+                    self.insert_html_element(&tag);
+                }
+                "optgroup" | "hr" => {
+                    if self.current_is("option") {
+                        self.pop();
+                    }
+                    if self.current_is("optgroup") {
+                        self.pop();
+                    }
+                    self.insert_html_element(&tag);
+                    if tag.name == "hr" {
+                        self.pop();
+                    }
+                }
+                "select" => {
+                    self.error(
+                        DiagnosticCode::UnexpectedStartTag,
+                        "<select> inside a select closes it",
+                    );
+                    if self.has_in_scope("select", Scope::Select) {
+                        self.pop_until_one_of(&["select"]);
+                        self.reset_insertion_mode();
+                    }
+                }
+                "input" | "keygen" | "textarea" => {
+                    self.error(
+                        DiagnosticCode::UnexpectedStartTag,
+                        format!("<{}> inside a select closes it", tag.name),
+                    );
+                    if self.has_in_scope("select", Scope::Select) {
+                        self.pop_until_one_of(&["select"]);
+                        self.reset_insertion_mode();
+                        self.process(self.mode, TreeToken::StartTag(tag));
+                    }
+                }
+                "script" | "template" => self.in_head(TreeToken::StartTag(tag)),
+                _ => self.ignore_start_tag(&tag.name),
+            },
+            TreeToken::EndTag(name) => match name.as_str() {
+                "optgroup" => {
+                    let len = self.open_elements.len();
+                    if self.current_is("option")
+                        && len >= 2
+                        && self.is_html_element(self.open_elements[len - 2], "optgroup")
+                    {
+                        self.pop();
+                    }
+                    if self.current_is("optgroup") {
+                        self.pop();
+                    } else {
+                        self.ignore_end_tag(&name);
+                    }
+                }
+                "option" => {
+                    if self.current_is("option") {
+                        self.pop();
+                    } else {
+                        self.ignore_end_tag(&name);
+                    }
+                }
+                "select" => {
+                    if self.has_in_scope("select", Scope::Select) {
+                        self.pop_until_one_of(&["select"]);
+                        self.reset_insertion_mode();
+                    } else {
+                        self.ignore_end_tag(&name);
+                    }
+                }
+                "template" => self.in_head(TreeToken::EndTag(name)),
+                _ => self.ignore_end_tag(&name),
+            },
+            TreeToken::Eof => self.in_body(TreeToken::Eof),
+        }
+    }
+
+    fn in_select_in_table(&mut self, token: TreeToken) {
+        const TABLE_TAGS: &[&str] = &[
+            "caption", "table", "tbody", "tfoot", "thead", "tr", "td", "th",
+        ];
+        match token {
+            TreeToken::StartTag(tag) if TABLE_TAGS.contains(&tag.name.as_str()) => {
+                self.error(
+                    DiagnosticCode::UnexpectedStartTag,
+                    format!("<{}> inside a select closes it", tag.name),
+                );
+                self.pop_until_one_of(&["select"]);
+                self.reset_insertion_mode();
+                self.process(self.mode, TreeToken::StartTag(tag));
+            }
+            TreeToken::EndTag(name) if TABLE_TAGS.contains(&name.as_str()) => {
+                self.error(
+                    DiagnosticCode::UnexpectedEndTag,
+                    format!("</{}> inside a select", name),
+                );
+                if self.has_in_scope(&name, Scope::Table) {
+                    self.pop_until_one_of(&["select"]);
+                    self.reset_insertion_mode();
+                    self.process(self.mode, TreeToken::EndTag(name));
+                }
+            }
+            token => self.in_select(token),
+        }
+    }
+
+    fn in_template(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(_) | TreeToken::Comment(_) | TreeToken::Doctype => self.in_body(token),
+            TreeToken::StartTag(tag) => {
+                let mode = match tag.name.as_str() {
+                    "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script"
+                    | "style" | "template" | "title" => {
+                        self.in_head(TreeToken::StartTag(tag));
+                        return;
+                    }
+                    "caption" | "colgroup" | "tbody" | "tfoot" | "thead" => InsertionMode::InTable,
+                    "col" => InsertionMode::InColumnGroup,
+                    "tr" => InsertionMode::InTableBody,
+                    "td" | "th" => InsertionMode::InRow,
+                    _ => InsertionMode::InBody,
+                };
+                self.template_modes.pop();
+                self.template_modes.push(mode);
+                self.reprocess(mode, TreeToken::StartTag(tag));
+            }
+            TreeToken::EndTag(name) if name == "template" => self.in_head(TreeToken::EndTag(name)),
+            TreeToken::EndTag(name) => self.ignore_end_tag(&name),
+            TreeToken::Eof => {
+                if !self.has_open_element("template") {
+                    return;
+                }
+                self.error(
+                    DiagnosticCode::UnclosedElements,
+                    "end of file in <template>",
+                );
+                self.pop_until_one_of(&["template"]);
+                self.clear_formatting_to_last_marker();
+                self.template_modes.pop();
+                self.reset_insertion_mode();
+                self.process(self.mode, TreeToken::Eof);
+            }
+        }
+    }
+
+    fn after_body(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(ref text) if is_whitespace(text) => self.in_body(token),
+            TreeToken::Comment(text) => {
+                if let Some(&html) = self.open_elements.first() {
+                    self.append_comment(html, text);
+                }
+            }
+            TreeToken::Doctype => self.unexpected_doctype(),
+            TreeToken::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            TreeToken::EndTag(ref name) if name == "html" => {
+                self.mode = InsertionMode::AfterAfterBody;
+            }
+            TreeToken::Eof => {}
+            token => {
+                self.unexpected(&token, "after </body>");
+                self.reprocess(InsertionMode::InBody, token);
+            }
+        }
+    }
+
+    fn in_frameset(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => {
+                if !is_whitespace(&text) {
+                    self.error(
+                        DiagnosticCode::UnexpectedText,
+                        "text inside a frameset ignored",
+                    );
+                }
+                let whitespace: String = text.chars().filter(|c| WHITESPACE.contains(c)).collect();
+                if !whitespace.is_empty() {
+                    self.insert_text(&whitespace);
+                }
+            }
+            TreeToken::Comment(text) => self.insert_comment(text),
+            TreeToken::Doctype => self.unexpected_doctype(),
+            TreeToken::StartTag(tag) => match tag.name.as_str() {
+                "html" => self.in_body(TreeToken::StartTag(tag)),
+                "frameset" => {
+                    self.insert_html_element(&tag);
+                }
+                "frame" => {
+                    self.insert_html_element(&tag);
+                    self.pop();
+                }
+                "noframes" => self.in_head(TreeToken::StartTag(tag)),
+                _ => self.ignore_start_tag(&tag.name),
+            },
+            TreeToken::EndTag(name) if name == "frameset" => {
+                if self.open_elements.len() > 1 {
+                    self.pop();
+                    if !self.current_is("frameset") {
+                        self.mode = InsertionMode::AfterFrameset;
+                    }
+                } else {
+                    self.ignore_end_tag(&name);
+                }
+            }
+            TreeToken::EndTag(name) => self.ignore_end_tag(&name),
+            TreeToken::Eof => {
+                if self.open_elements.len() > 1 {
+                    self.error(
+                        DiagnosticCode::UnclosedElements,
+                        "end of file in <frameset>",
+                    );
+                }
+            }
+        }
+    }
+
+    fn after_frameset(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => {
+                if !is_whitespace(&text) {
+                    self.error(
+                        DiagnosticCode::UnexpectedText,
+                        "text after </frameset> ignored",
+                    );
+                }
+                let whitespace: String = text.chars().filter(|c| WHITESPACE.contains(c)).collect();
+                if !whitespace.is_empty() {
+                    self.insert_text(&whitespace);
+                }
+            }
+            TreeToken::Comment(text) => self.insert_comment(text),
+            TreeToken::StartTag(tag) if tag.name == "html" => {
+                self.in_body(TreeToken::StartTag(tag))
+            }
+            TreeToken::StartTag(tag) if tag.name == "noframes" => {
+                self.in_head(TreeToken::StartTag(tag))
+            }
+            TreeToken::EndTag(name) if name == "html" => {
+                self.mode = InsertionMode::AfterAfterFrameset;
+            }
+            _ => self.unexpected(&token, "after </frameset> ignored"),
+        }
+    }
+
+    fn after_after_body(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Comment(text) => self.append_comment(DOCUMENT, text),
+            TreeToken::Doctype => self.in_body(token),
+            TreeToken::Text(ref text) if is_whitespace(text) => self.in_body(token),
+            TreeToken::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            TreeToken::Eof => {}
+            token => {
+                self.unexpected(&token, "after </html>");
+                self.reprocess(InsertionMode::InBody, token);
+            }
+        }
+    }
+
+    fn after_after_frameset(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Comment(text) => self.append_comment(DOCUMENT, text),
+            TreeToken::Doctype => self.in_body(token),
+            TreeToken::Text(ref text) if is_whitespace(text) => self.in_body(token),
+            TreeToken::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            TreeToken::StartTag(tag) if tag.name == "noframes" => {
+                self.in_head(TreeToken::StartTag(tag))
+            }
+            _ => self.unexpected(&token, "after </html> ignored"),
+        }
+    }
+
+    fn process_foreign(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => {
+                let text = text.replace('\0', "\u{FFFD}");
+                if !is_whitespace(&text) {
+                    self.frameset_ok = false;
+                }
+                self.insert_text(&text);
+            }
+            TreeToken::Comment(text) => self.insert_comment(text),
+            TreeToken::Doctype => self.unexpected_doctype(),
+            TreeToken::StartTag(tag)
+                if foreign::is_breakout_tag(&tag.name)
+                    || (tag.name == "font"
+                        && tag.attributes.iter().any(|attribute| {
+                            matches!(attribute.name.as_str(), "color" | "face" | "size")
+                        })) =>
+            {
+                self.error(
+                    DiagnosticCode::UnexpectedStartTag,
+                    format!("<{}> closes the open SVG or MathML elements", tag.name),
+                );
+                self.pop_to_html_content();
+                self.process(self.mode, TreeToken::StartTag(tag));
+            }
+            TreeToken::StartTag(tag) => {
+                let namespace = self
+                    .current_node()
+                    .map_or(Namespace::Html, |node| self.nodes[node].namespace);
+                self.insert_foreign_element(tag, namespace);
+            }
+            TreeToken::EndTag(name) if name == "br" || name == "p" => {
+                self.pop_to_html_content();
+                self.process(self.mode, TreeToken::EndTag(name));
+            }
+            TreeToken::EndTag(name) => {
+                if let Some(current) = self.current_node() {
+                    if !self.tag_name(current).eq_ignore_ascii_case(&name) {
+                        self.error(
+                            DiagnosticCode::MisnestedTags,
+                            format!("</{}> does not close the current element", name),
+                        );
+                    }
+                }
+                for index in (0..self.open_elements.len()).rev() {
+                    let node = self.open_elements[index];
+                    if index == 0 {
+                        return;
+                    }
+                    if self.tag_name(node).eq_ignore_ascii_case(&name) {
+                        self.pop_to(index);
+                        return;
+                    }
+                    let previous = self.open_elements[index - 1];
+                    if self.nodes[previous].namespace == Namespace::Html {
+                        self.process(self.mode, TreeToken::EndTag(name));
+                        return;
+                    }
+                }
+            }
+            TreeToken::Eof => self.process(self.mode, TreeToken::Eof),
+        }
+    }
+
+    fn pop_to_html_content(&mut self) {
+        while let Some(node) = self.current_node() {
+            if self.nodes[node].namespace == Namespace::Html
+                || self.is_mathml_text_integration_point(node)
+                || self.is_html_integration_point(node)
+            {
+                break;
+            }
+            self.pop();
+        }
+    }
+
+    fn insert_foreign_element(&mut self, mut tag: Tag, namespace: Namespace) {
+        for attribute in tag.attributes.iter_mut() {
+            let adjusted = match namespace {
+                Namespace::Svg => foreign::adjust_svg_attribute(&attribute.name),
+                Namespace::MathMl => foreign::adjust_mathml_attribute(&attribute.name),
+                Namespace::Html => None,
+            };
+            if let Some(adjusted) = adjusted {
+                attribute.name = adjusted.to_string();
+            }
+        }
+        if namespace == Namespace::Svg {
+            if let Some(adjusted) = foreign::adjust_svg_tag_name(&tag.name) {
+                tag.name = adjusted.to_string();
+            }
+        }
+
+        let element = self.create_element(&tag, namespace);
+        let place = self.appropriate_place(None);
+        self.insert_at(place, element);
+        if !tag.self_closing {
+            self.open_elements.push(element);
+        }
+    }
+
+    fn is_mathml_text_integration_point(&self, node: usize) -> bool {
+        self.nodes[node].namespace == Namespace::MathMl
+            && matches!(self.tag_name(node), "mi" | "mo" | "mn" | "ms" | "mtext")
+    }
+
+    fn is_html_integration_point(&self, node: usize) -> bool {
+        match self.nodes[node].namespace {
+            Namespace::MathMl => {
+                self.tag_name(node) == "annotation-xml"
+                    && self.attribute(node, "encoding").is_some_and(|encoding| {
+                        encoding.eq_ignore_ascii_case("text/html")
+                            || encoding.eq_ignore_ascii_case("application/xhtml+xml")
+                    })
+            }
+            Namespace::Svg => matches!(self.tag_name(node), "foreignObject" | "desc" | "title"),
+            Namespace::Html => false,
+        }
+    }
+
+    // Generic raw text and RCDATA element parsing algorithm.
+    fn parse_raw_text(&mut self, tag: &Tag, state: State) {
+        self.insert_html_element(tag);
+        self.tokenizer.set_state(state);
+        self.original_mode = self.mode;
+        self.mode = InsertionMode::Text;
+    }
+
+    fn insert_html_root(&mut self, tag: Option<&Tag>) {
+        let tag = tag.cloned().unwrap_or_else(|| synthetic_tag("html"));
+        let html = self.create_element(&tag, Namespace::Html);
+        self.append_child(DOCUMENT, html);
+        self.open_elements.push(html);
+    }
+
+    fn close_p_in_button_scope(&mut self) {
+        if self.has_in_scope("p", Scope::Button) {
+            self.close_p();
+        }
+    }
+
+    fn close_p(&mut self) {
+        self.generate_implied_end_tags(Some("p"));
+        self.pop_until_one_of(&["p"]);
+    }
+
+    // Shared start-tag handling for <li>, <dd> and <dt>: close the nearest
+    // open list item unless a special element sits in between.
+    fn close_list_item(&mut self, names: &[&str]) {
+        for index in (0..self.open_elements.len()).rev() {
+            let node = self.open_elements[index];
+            if let Some(&name) = names.iter().find(|name| self.is_html_element(node, name)) {
+                self.generate_implied_end_tags(Some(name));
+                self.pop_until_one_of(&[name]);
+                return;
+            }
+            if self.is_special(node) && !self.is_html_one_of(node, &["address", "div", "p"]) {
+                return;
+            }
+        }
+    }
+
+    fn adoption_agency(&mut self, subject: &str) -> bool {
+        if let Some(current) = self.current_node() {
+            if self.is_html_element(current, subject) && self.formatting_index(current).is_none() {
+                self.pop();
+                return true;
+            }
+        }
+
+        for round in 0..8 {
+            let Some(formatting_element) = self.formatting_after_last_marker(subject) else {
+                return false;
+            };
+
+            let Some(stack_index) = self.stack_index(formatting_element) else {
+                self.error(
+                    DiagnosticCode::UnexpectedEndTag,
+                    format!("<{}> element was already closed", subject),
+                );
+                self.remove_from_formatting(formatting_element);
+                return true;
+            };
+            if !self.has_element_in_scope(formatting_element, Scope::Default) {
+                self.error(
+                    DiagnosticCode::UnexpectedEndTag,
+                    format!("no <{}> element in scope", subject),
+                );
+                return true;
+            }
+            if round == 0 && self.current_node() != Some(formatting_element) {
+                self.error(
+                    DiagnosticCode::MisnestedTags,
+                    format!("<{}> element is misnested", subject),
+                );
+            }
+
+            let furthest_block = (stack_index + 1..self.open_elements.len())
+                .find(|&index| self.is_special(self.open_elements[index]));
+            let Some(mut furthest_index) = furthest_block else {
+                self.pop_to(stack_index);
+                self.remove_from_formatting(formatting_element);
+                return true;
+            };
+            let furthest_block = self.open_elements[furthest_index];
+            let common_ancestor = self.open_elements[stack_index - 1];
+
+            let mut bookmark = self.formatting_index(formatting_element).unwrap_or(0);
+            let mut node_index = furthest_index;
+            let mut last_node = furthest_block;
+            let mut inner = 0;
+
+            loop {
+                inner += 1;
+                node_index -= 1;
+                let node = self.open_elements[node_index];
+                if node == formatting_element {
+                    break;
+                }
+
+                let mut position = self.formatting_index(node);
+                if inner > 3 {
+                    if let Some(index) = position.take() {
+                        self.active_formatting.remove(index);
+                        if index < bookmark {
+                            bookmark -= 1;
+                        }
+                    }
+                }
+                let Some(position) = position else {
+                    self.close_element(node);
+                    self.open_elements.remove(node_index);
+                    if node_index < furthest_index {
+                        furthest_index -= 1;
+                    }
+                    continue;
+                };
+
+                let tag = match &self.active_formatting[position] {
+                    FormattingEntry::Element(_, tag) => tag.clone(),
+                    FormattingEntry::Marker => break,
+                };
+                let replacement = self.create_element(&tag.without_span(), Namespace::Html);
+                self.active_formatting[position] = FormattingEntry::Element(replacement, tag);
+                self.close_element(node);
+                self.open_elements[node_index] = replacement;
+
+                if last_node == furthest_block {
+                    bookmark = position + 1;
+                }
+                self.detach(last_node);
+                self.append_child(replacement, last_node);
+                last_node = replacement;
+            }
+
+            self.detach(last_node);
+            let place = self.appropriate_place(Some(common_ancestor));
+            self.insert_at(place, last_node);
+
+            let Some(FormattingEntry::Element(_, tag)) = self
+                .formatting_index(formatting_element)
+                .map(|index| self.active_formatting[index].clone())
+            else {
+                return true;
+            };
+            let replacement = self.create_element(&tag.without_span(), Namespace::Html);
+            let children = std::mem::take(&mut self.nodes[furthest_block].children);
+            for &child in &children {
+                self.nodes[child].parent = Some(replacement);
+            }
+            self.nodes[replacement].children = children;
+            self.append_child(furthest_block, replacement);
+
+            if let Some(index) = self.formatting_index(formatting_element) {
+                self.active_formatting.remove(index);
+                if index < bookmark {
+                    bookmark -= 1;
+                }
+            }
+            let bookmark = bookmark.min(self.active_formatting.len());
+            self.active_formatting
+                .insert(bookmark, FormattingEntry::Element(replacement, tag));
+
+            self.remove_from_stack(formatting_element);
+            if let Some(index) = self.stack_index(furthest_block) {
+                self.open_elements.insert(index + 1, replacement);
+            }
+        }
+
+        true
+    }
+
+    fn push_formatting(&mut self, element: usize, tag: Tag) {
+        // Noah's Ark clause: at most three identical entries after the last marker.
+        let mut matching = vec![];
+        for (index, entry) in self.active_formatting.iter().enumerate().rev() {
+            match entry {
+                FormattingEntry::Marker => break,
+                FormattingEntry::Element(_, existing)
+                    if existing.name == tag.name
+                        && existing.attributes.len() == tag.attributes.len()
+                        && existing.attributes.iter().all(|attribute| {
+                            tag.attributes.iter().any(|other| {
+                                other.name == attribute.name && other.value == attribute.value
+                            })
+                        }) =>
+                {
+                    matching.push(index);
+                }
+                _ => {}
+            }
+        }
+        if matching.len() >= 3 {
+            if let Some(&earliest) = matching.last() {
+                self.active_formatting.remove(earliest);
+            }
+        }
+        self.active_formatting
+            .push(FormattingEntry::Element(element, tag));
+    }
+
+    fn reconstruct_active_formatting(&mut self) {
+        let needs_reconstruction = match self.active_formatting.last() {
+            None | Some(FormattingEntry::Marker) => false,
+            Some(FormattingEntry::Element(element, _)) => self.stack_index(*element).is_none(),
+        };
+        if !needs_reconstruction {
+            return;
+        }
+
+        let mut index = self.active_formatting.len() - 1;
+        while index > 0 {
+            match &self.active_formatting[index - 1] {
+                FormattingEntry::Marker => break,
+                FormattingEntry::Element(element, _) if self.stack_index(*element).is_some() => {
+                    break
+                }
+                _ => index -= 1,
+            }
+        }
+
+        for entry_index in index..self.active_formatting.len() {
+            let FormattingEntry::Element(_, tag) = self.active_formatting[entry_index].clone()
+            else {
+                continue;
+            };
+            let element = self.insert_html_element(&tag.without_span());
+            self.active_formatting[entry_index] = FormattingEntry::Element(element, tag);
+        }
+    }
+
+    fn clear_formatting_to_last_marker(&mut self) {
+        while let Some(entry) = self.active_formatting.pop() {
+            if let FormattingEntry::Marker = entry {
+                break;
+            }
+        }
+    }
+
+    fn formatting_after_last_marker(&self, name: &str) -> Option<usize> {
+        for entry in self.active_formatting.iter().rev() {
+            match entry {
+                FormattingEntry::Marker => return None,
+                FormattingEntry::Element(element, tag) if tag.name == name => {
+                    return Some(*element)
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn formatting_index(&self, element: usize) -> Option<usize> {
+        self.active_formatting
+            .iter()
+            .position(|entry| matches!(entry, FormattingEntry::Element(id, _) if *id == element))
+    }
+
+    fn remove_from_formatting(&mut self, element: usize) {
+        if let Some(index) = self.formatting_index(element) {
+            self.active_formatting.remove(index);
+        }
+    }
+
+    fn reset_insertion_mode(&mut self) {
+        for index in (0..self.open_elements.len()).rev() {
+            let node = self.open_elements[index];
+            let last = index == 0;
+            let mode = match self.tag_name(node) {
+                _ if self.nodes[node].namespace != Namespace::Html => None,
+                "select" => {
+                    let in_table = self.open_elements[..index]
+                        .iter()
+                        .rev()
+                        .take_while(|&&ancestor| !self.is_html_element(ancestor, "template"))
+                        .any(|&ancestor| self.is_html_element(ancestor, "table"));
+                    Some(if in_table {
+                        InsertionMode::InSelectInTable
+                    } else {
+                        InsertionMode::InSelect
+                    })
+                }
+                "td" | "th" if !last => Some(InsertionMode::InCell),
+                "tr" => Some(InsertionMode::InRow),
+                "tbody" | "thead" | "tfoot" => Some(InsertionMode::InTableBody),
+                "caption" => Some(InsertionMode::InCaption),
+                "colgroup" => Some(InsertionMode::InColumnGroup),
+                "table" => Some(InsertionMode::InTable),
+                "template" => self.template_modes.last().copied(),
+                "head" if !last => Some(InsertionMode::InHead),
+                "body" => Some(InsertionMode::InBody),
+                "frameset" => Some(InsertionMode::InFrameset),
+                "html" => Some(if self.head.is_none() {
+                    InsertionMode::BeforeHead
+                } else {
+                    InsertionMode::AfterHead
+                }),
+                _ => None,
+            };
+            if let Some(mode) = mode {
+                self.mode = mode;
+                return;
+            }
+            if last {
+                break;
+            }
+        }
+        self.mode = InsertionMode::InBody;
+    }
+
+    fn generate_implied_end_tags(&mut self, except: Option<&str>) {
+        while let Some(node) = self.current_node() {
+            let name = self.tag_name(node);
+            if Some(name) == except
+                || !self.is_html_one_of(
+                    node,
+                    &[
+                        "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc",
+                    ],
+                )
+            {
+                break;
+            }
+            self.pop();
+        }
+    }
+
+    fn generate_all_implied_end_tags_thoroughly(&mut self) {
+        while let Some(node) = self.current_node() {
+            if !self.is_html_one_of(
+                node,
+                &[
+                    "caption", "colgroup", "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp",
+                    "rt", "rtc", "tbody", "td", "tfoot", "th", "thead", "tr",
+                ],
+            ) {
+                break;
+            }
+            self.pop();
+        }
+    }
+
+    fn clear_stack_back_to(&mut self, names: &[&str]) {
+        while let Some(node) = self.current_node() {
+            if self.is_html_one_of(node, names) {
+                break;
+            }
+            self.pop();
+        }
+    }
+
+    fn pop_until_one_of(&mut self, names: &[&str]) {
+        while let Some(node) = self.open_elements.pop() {
+            self.close_element(node);
+            if self.is_html_one_of(node, names) {
+                break;
+            }
+        }
+    }
+
+    fn has_in_scope(&self, name: &str, scope: Scope) -> bool {
+        for &node in self.open_elements.iter().rev() {
+            if self.is_html_element(node, name) {
+                return true;
+            }
+            if self.is_scope_boundary(node, scope) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn has_element_in_scope(&self, element: usize, scope: Scope) -> bool {
+        for &node in self.open_elements.iter().rev() {
+            if node == element {
+                return true;
+            }
+            if self.is_scope_boundary(node, scope) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn is_scope_boundary(&self, node: usize, scope: Scope) -> bool {
+        let name = self.tag_name(node);
+        match self.nodes[node].namespace {
+            Namespace::Html => match scope {
+                Scope::Select => !matches!(name, "optgroup" | "option"),
+                Scope::Table => matches!(name, "html" | "table" | "template"),
+                _ => {
+                    matches!(
+                        name,
+                        "applet"
+                            | "caption"
+                            | "html"
+                            | "table"
+                            | "td"
+                            | "th"
+                            | "marquee"
+                            | "object"
+                            | "template"
+                    ) || (scope == Scope::ListItem && matches!(name, "ol" | "ul"))
+                        || (scope == Scope::Button && name == "button")
+                }
+            },
+            Namespace::MathMl => {
+                !matches!(scope, Scope::Table | Scope::Select)
+                    && matches!(name, "mi" | "mo" | "mn" | "ms" | "mtext" | "annotation-xml")
+            }
+            Namespace::Svg => {
+                !matches!(scope, Scope::Table | Scope::Select)
+                    && matches!(name, "foreignObject" | "desc" | "title")
+            }
+        }
+    }
+
+    fn is_special(&self, node: usize) -> bool {
+        let name = self.tag_name(node);
+        match self.nodes[node].namespace {
+            Namespace::Html => is_special_html_element(name),
+            Namespace::MathMl => {
+                matches!(name, "mi" | "mo" | "mn" | "ms" | "mtext" | "annotation-xml")
+            }
+            Namespace::Svg => matches!(name, "foreignObject" | "desc" | "title"),
+        }
+    }
+
+    fn has_open_element(&self, name: &str) -> bool {
+        self.open_elements
+            .iter()
+            .any(|&node| self.is_html_element(node, name))
+    }
+
+    fn current_node(&self) -> Option<usize> {
+        self.open_elements.last().copied()
+    }
+
+    fn current_is(&self, name: &str) -> bool {
+        self.current_node()
+            .is_some_and(|node| self.is_html_element(node, name))
+    }
+
+    fn pop(&mut self) {
+        if let Some(node) = self.open_elements.pop() {
+            self.close_element(node);
+        }
+    }
+
+    fn pop_to(&mut self, len: usize) {
+        while self.open_elements.len() > len {
+            self.pop();
+        }
+    }
+
+    // Extends an element's span to where it was closed: through its own end
+    // tag, or up to the token that implicitly closed it.
+    fn close_element(&mut self, node: usize) {
+        let end = match &self.end_tag {
+            Some(name) if name.eq_ignore_ascii_case(self.tag_name(node)) => self.token_span.end,
+            _ => self.token_span.start,
+        };
+        if let Some(span) = self.nodes[node].span.as_mut() {
+            span.end = span.end.max(end);
+        }
+    }
+
+    fn stack_index(&self, element: usize) -> Option<usize> {
+        self.open_elements.iter().rposition(|&node| node == element)
+    }
+
+    fn remove_from_stack(&mut self, element: usize) {
+        if let Some(index) = self.stack_index(element) {
+            self.close_element(element);
+            self.open_elements.remove(index);
+        }
+    }
+
+    fn tag_name(&self, node: usize) -> &str {
+        match &self.nodes[node].node_type {
+            NodeType::Element(elem) => &elem.tag_name,
+            _ => "",
+        }
+    }
+
+    fn attribute(&self, node: usize, name: &str) -> Option<&str> {
+        match &self.nodes[node].node_type {
+            NodeType::Element(elem) => elem
+                .attributes
+                .iter()
+                .find(|attribute| attribute.name == name)
+                .map(|attribute| attribute.value.as_str()),
+            _ => None,
+        }
+    }
+
+    fn is_html_element(&self, node: usize, name: &str) -> bool {
+        self.nodes[node].namespace == Namespace::Html && self.tag_name(node) == name
+    }
+
+    fn is_html_one_of(&self, node: usize, names: &[&str]) -> bool {
+        self.nodes[node].namespace == Namespace::Html && names.contains(&self.tag_name(node))
+    }
+
+    fn merge_attributes(&mut self, node: usize, attributes: Vec<Attribute>) {
+        if let NodeType::Element(elem) = &mut self.nodes[node].node_type {
+            for attribute in attributes {
+                if !elem
+                    .attributes
+                    .iter()
+                    .any(|existing| existing.name == attribute.name)
+                {
+                    elem.attributes.push(attribute);
+                }
+            }
+        }
+    }
+
+    fn new_node(&mut self, node_type: NodeType, namespace: Namespace, span: Option<Span>) -> usize {
+        self.nodes.push(TreeNode {
+            node_type,
+            namespace,
+            parent: None,
+            children: vec![],
+            span,
+        });
+        self.nodes.len() - 1
+    }
+
+    fn create_element(&mut self, tag: &Tag, namespace: Namespace) -> usize {
+        let is_self_closing = match namespace {
+            Namespace::Html => dom::is_void_element(&tag.name),
+            _ => tag.self_closing,
+        };
+        self.new_node(
+            NodeType::Element(ElementData {
+                tag_name: tag.name.clone(),
+                namespace,
+                attributes: tag.attributes.clone(),
+                is_self_closing,
+            }),
+            namespace,
+            tag.span,
+        )
+    }
+
+    fn insert_html_element(&mut self, tag: &Tag) -> usize {
+        if tag.self_closing && !dom::is_void_element(&tag.name) {
+            self.error(
+                DiagnosticCode::NonVoidElementWithTrailingSolidus,
+                format!("'/>' does not close a <{}> element", tag.name),
+            );
+        }
+        let element = self.create_element(tag, Namespace::Html);
+        let place = self.appropriate_place(None);
+        self.insert_at(place, element);
+        self.open_elements.push(element);
+        element
+    }
+
+    // The appropriate place for inserting a node, as (parent, insert before).
+    fn appropriate_place(&self, override_target: Option<usize>) -> (usize, Option<usize>) {
+        let target = override_target.or(self.current_node()).unwrap_or(DOCUMENT);
+
+        if self.foster_parenting
+            && self.is_html_one_of(target, &["table", "tbody", "tfoot", "thead", "tr"])
+        {
+            let last_template = self
+                .open_elements
+                .iter()
+                .rposition(|&node| self.is_html_element(node, "template"));
+            let last_table = self
+                .open_elements
+                .iter()
+                .rposition(|&node| self.is_html_element(node, "table"));
+
+            match (last_template, last_table) {
+                (Some(template), table) if table.is_none_or(|table| template > table) => {
+                    return (self.open_elements[template], None);
+                }
+                (_, None) => return (self.open_elements[0], None),
+                (_, Some(index)) => {
+                    let table = self.open_elements[index];
+                    return match self.nodes[table].parent {
+                        Some(parent) => (parent, Some(table)),
+                        None => (self.open_elements[index - 1], None),
+                    };
+                }
+            }
+        }
+
+        (target, None)
+    }
+
+    fn insert_at(&mut self, (parent, before): (usize, Option<usize>), child: usize) {
+        self.nodes[child].parent = Some(parent);
+        let children = &mut self.nodes[parent].children;
+        match before.and_then(|before| children.iter().position(|&id| id == before)) {
+            Some(index) => children.insert(index, child),
+            None => children.push(child),
+        }
+    }
+
+    fn append_child(&mut self, parent: usize, child: usize) {
+        self.insert_at((parent, None), child);
+    }
+
+    fn detach(&mut self, node: usize) {
+        if let Some(parent) = self.nodes[node].parent.take() {
+            self.nodes[parent].children.retain(|&child| child != node);
+        }
+    }
+
+    fn insert_comment(&mut self, text: String) {
+        let place = self.appropriate_place(None);
+        let comment = self.new_node(
+            NodeType::Comment(text),
+            Namespace::Html,
+            Some(self.token_span),
+        );
+        self.insert_at(place, comment);
+    }
+
+    fn append_comment(&mut self, parent: usize, text: String) {
+        let comment = self.new_node(
+            NodeType::Comment(text),
+            Namespace::Html,
+            Some(self.token_span),
+        );
+        self.append_child(parent, comment);
+    }
+
+    fn insert_text(&mut self, text: &str) {
+        let (parent, before) = self.appropriate_place(None);
+        if parent == DOCUMENT {
+            return;
+        }
+
+        let children = &self.nodes[parent].children;
+        let previous = match before.and_then(|before| children.iter().position(|&id| id == before))
+        {
+            Some(0) => None,
+            Some(index) => Some(children[index - 1]),
+            None => children.last().copied(),
+        };
+        if let Some(previous) = previous {
+            let previous = &mut self.nodes[previous];
+            if let NodeType::Text(existing) = &mut previous.node_type {
+                existing.push_str(text);
+                previous.span = merge_spans(previous.span, Some(self.token_span));
+                return;
+            }
+        }
+
+        let node = self.new_node(
+            NodeType::Text(text.to_string()),
+            Namespace::Html,
+            Some(self.token_span),
+        );
+        self.insert_at((parent, before), node);
+    }
+
+    fn build_node(&mut self, id: usize) -> Node {
+        let node_type = std::mem::replace(&mut self.nodes[id].node_type, NodeType::Document);
+        let children = std::mem::take(&mut self.nodes[id].children)
+            .into_iter()
+            .map(|child| self.build_node(child))
+            .collect();
+        Node::new(node_type, children).with_span(self.nodes[id].span)
+    }
+}
+
+const HEADINGS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6"];
+
+impl Tag {
+    // Elements recreated by the parser (reconstructed formatting elements and
+    // adoption agency clones) don't correspond to any source markup.
+    fn without_span(&self) -> Tag {
+        Tag {
+            span: None,
+            ..self.clone()
+        }
+    }
+}
+
+fn synthetic_tag(name: &str) -> Tag {
+    Tag {
+        name: name.to_string(),
+        attributes: vec![],
+        self_closing: false,
+        span: None,
+    }
+}
+
+fn merge_spans(a: Option<Span>, b: Option<Span>) -> Option<Span> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(Span::new(a.start.min(b.start), a.end.max(b.end))),
+        (a, b) => a.or(b),
+    }
+}
+
+fn is_hidden_input(tag: &Tag) -> bool {
+    tag.attributes
+        .iter()
+        .any(|attribute| attribute.name == "type" && attribute.value.eq_ignore_ascii_case("hidden"))
+}
+
+fn is_whitespace(text: &str) -> bool {
+    text.chars().all(|c| WHITESPACE.contains(&c))
+}
+
+fn split_leading_whitespace(text: &str) -> (&str, &str) {
+    let rest = text.trim_start_matches(WHITESPACE);
+    text.split_at(text.len() - rest.len())
+}
+
+fn is_special_html_element(name: &str) -> bool {
+    matches!(
+        name,
+        "address"
+            | "applet"
+            | "area"
+            | "article"
+            | "aside"
+            | "base"
+            | "basefont"
+            | "bgsound"
+            | "blockquote"
+            | "body"
+            | "br"
+            | "button"
+            | "caption"
+            | "center"
+            | "col"
+            | "colgroup"
+            | "dd"
+            | "details"
+            | "dir"
+            | "div"
+            | "dl"
+            | "dt"
+            | "embed"
+            | "fieldset"
+            | "figcaption"
+            | "figure"
+            | "footer"
+            | "form"
+            | "frame"
+            | "frameset"
+            | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+            | "head"
+            | "header"
+            | "hgroup"
+            | "hr"
+            | "html"
+            | "iframe"
+            | "img"
+            | "input"
+            | "keygen"
+            | "li"
+            | "link"
+            | "listing"
+            | "main"
+            | "marquee"
+            | "menu"
+            | "meta"
+            | "nav"
+            | "noembed"
+            | "noframes"
+            | "noscript"
+            | "object"
+            | "ol"
+            | "p"
+            | "param"
+            | "plaintext"
+            | "pre"
+            | "script"
+            | "search"
+            | "section"
+            | "select"
+            | "source"
+            | "style"
+            | "summary"
+            | "table"
+            | "tbody"
+            | "td"
+            | "template"
+            | "textarea"
+            | "tfoot"
+            | "th"
+            | "thead"
+            | "title"
+            | "tr"
+            | "track"
+            | "ul"
+            | "wbr"
+            | "xmp"
+    )
 }
@@ -0,0 +1,238 @@
+use super::arena::{Dom, NodeId};
+use crate::css::matching::matches_in_scope;
+use crate::css::parser::CssParser;
+use crate::css::rules::Selector;
+use crate::diagnostics::Diagnostic;
+
+// The DOM query methods. Each takes a selector list as text and reports an
+// invalid one as an error, where the DOM would throw a SyntaxError.
+impl Dom {
+    /// The first element below `scope`, in tree order, that matches.
+    pub fn query_selector(
+        &self,
+        scope: NodeId,
+        selectors: &str,
+    ) -> Result<Option<NodeId>, Diagnostic> {
+        let selectors = parse(selectors)?;
+        Ok(self
+            .descendants(scope)
+            .find(|&id| self.matches_any(&selectors, id, scope)))
+    }
+
+    /// Every element below `scope` that matches, in tree order.
+    pub fn query_selector_all(
+        &self,
+        scope: NodeId,
+        selectors: &str,
+    ) -> Result<Vec<NodeId>, Diagnostic> {
+        let selectors = parse(selectors)?;
+        Ok(self
+            .descendants(scope)
+            .filter(|&id| self.matches_any(&selectors, id, scope))
+            .collect())
+    }
+
+    /// Whether `id` is an element that matches.
+    pub fn matches(&self, id: NodeId, selectors: &str) -> Result<bool, Diagnostic> {
+        let selectors = parse(selectors)?;
+        Ok(self.matches_any(&selectors, id, id))
+    }
+
+    /// The nearest of `id` and its ancestors that matches.
+    pub fn closest(&self, id: NodeId, selectors: &str) -> Result<Option<NodeId>, Diagnostic> {
+        let selectors = parse(selectors)?;
+        Ok(std::iter::once(id)
+            .chain(self.ancestors(id))
+            .find(|&ancestor| self.matches_any(&selectors, ancestor, id)))
+    }
+
+    // `:scope` refers to the element the method was called on.
+    fn matches_any(&self, selectors: &[Selector], id: NodeId, scope: NodeId) -> bool {
+        selectors
+            .iter()
+            .any(|selector| matches_in_scope(selector, self, id, Some(scope)))
+    }
+}
+
+fn parse(selectors: &str) -> Result<Vec<Selector>, Diagnostic> {
+    CssParser::new(selectors).parse_selector_list()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::Parser;
+
+    const DOCUMENT: &str = r#"<!DOCTYPE html>
+<div id="outer" lang="en-US" class="box wide" title="Hello World">
+  <a id="pdf" href="https://example.com/file.pdf">PDF</a>
+  <a id="page" href="http://example.org/page.html" rel="">Page</a>
+  <section id="inner" lang="en" class="box">
+    <p id="first" class="note">One</p>
+    <p id="second">Two</p>
+  </section>
+</div>
+<input id="field" type="Text">"#;
+
+    fn document() -> Dom {
+        let (node, _) = Parser::new(DOCUMENT.to_string()).parse();
+        Dom::from(&node)
+    }
+
+    fn id_of(dom: &Dom, node: NodeId) -> &str {
+        let element = dom.element(node).expect("an element");
+        element
+            .attributes
+            .iter()
+            .find(|attribute| attribute.name == "id")
+            .map_or("", |attribute| attribute.value.as_str())
+    }
+
+    fn by_id(dom: &Dom, id: &str) -> NodeId {
+        dom.descendants(dom.root())
+            .find(|&node| dom.element(node).is_some() && id_of(dom, node) == id)
+            .expect("an element with the id")
+    }
+
+    // The ids of the elements in the document that match, in tree order.
+    fn select(dom: &Dom, selectors: &str) -> Vec<String> {
+        dom.query_selector_all(dom.root(), selectors)
+            .expect("a valid selector")
+            .into_iter()
+            .map(|node| id_of(dom, node).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn attribute_operators() {
+        let dom = document();
+        assert_eq!(select(&dom, "[title]"), ["outer"]);
+        assert_eq!(select(&dom, "[lang=en]"), ["inner"]);
+        assert_eq!(select(&dom, "[class~=box]"), ["outer", "inner"]);
+        assert_eq!(select(&dom, "[title~='Hello World']"), Vec::<String>::new());
+        assert_eq!(select(&dom, "[lang|=en]"), ["outer", "inner"]);
+        assert_eq!(select(&dom, "[lang|=en-U]"), Vec::<String>::new());
+        assert_eq!(select(&dom, "[href^=https]"), ["pdf"]);
+        assert_eq!(select(&dom, "[href$='.html']"), ["page"]);
+        assert_eq!(select(&dom, "[href*=example]"), ["pdf", "page"]);
+        // An empty value never matches with ^=, $= or *=.
+        assert_eq!(select(&dom, "[rel^='']"), Vec::<String>::new());
+        assert_eq!(select(&dom, "[rel='']"), ["page"]);
+    }
+
+    #[test]
+    fn attribute_case_flags() {
+        let dom = document();
+        assert_eq!(select(&dom, "[type=text]"), Vec::<String>::new());
+        assert_eq!(select(&dom, "[type=text i]"), ["field"]);
+        assert_eq!(select(&dom, "[type=text s]"), Vec::<String>::new());
+        assert_eq!(select(&dom, "[type=Text s]"), ["field"]);
+        assert_eq!(select(&dom, "[href$='.PDF' I]"), ["pdf"]);
+        assert_eq!(select(&dom, "[title*='hello' i]"), ["outer"]);
+    }
+
+    #[test]
+    fn query_selector() {
+        let dom = document();
+        let outer = by_id(&dom, "outer");
+        let first = dom.query_selector(outer, "p, a").unwrap();
+        assert_eq!(first, Some(by_id(&dom, "pdf")));
+        assert_eq!(dom.query_selector(outer, "input").unwrap(), None);
+        // Only descendants of the scope are searched, not the scope itself.
+        assert_eq!(dom.query_selector(outer, "div").unwrap(), None);
+    }
+
+    #[test]
+    fn scope() {
+        let dom = document();
+        let outer = by_id(&dom, "outer");
+        let inner = by_id(&dom, "inner");
+        let ids = |nodes: Vec<NodeId>| -> Vec<String> {
+            nodes
+                .into_iter()
+                .map(|node| id_of(&dom, node).to_string())
+                .collect()
+        };
+
+        assert_eq!(
+            ids(dom.query_selector_all(outer, ":scope > *").unwrap()),
+            ["pdf", "page", "inner"]
+        );
+        assert_eq!(
+            ids(dom.query_selector_all(inner, ":scope > p").unwrap()),
+            ["first", "second"]
+        );
+        // The rest of the selector may match outside the scope.
+        assert_eq!(
+            ids(dom.query_selector_all(inner, "div p").unwrap()),
+            ["first", "second"]
+        );
+        assert_eq!(
+            ids(dom.query_selector_all(inner, "div > :scope p").unwrap()),
+            ["first", "second"]
+        );
+        assert_eq!(
+            ids(dom.query_selector_all(inner, "section > :scope p").unwrap()),
+            Vec::<String>::new()
+        );
+        assert!(dom.matches(inner, ":scope").unwrap());
+        assert!(!dom.matches(inner, "div :scope > :scope").unwrap());
+    }
+
+    #[test]
+    fn matches() {
+        let dom = document();
+        let first = by_id(&dom, "first");
+        assert!(dom.matches(first, "section > p.note").unwrap());
+        assert!(dom.matches(first, "a, p:first-child").unwrap());
+        assert!(!dom.matches(first, "p:last-child").unwrap());
+        // Only elements match.
+        let text = dom.first_child(first).unwrap();
+        assert!(!dom.matches(text, "*").unwrap());
+    }
+
+    #[test]
+    fn closest() {
+        let dom = document();
+        let first = by_id(&dom, "first");
+        assert_eq!(dom.closest(first, "p").unwrap(), Some(first));
+        assert_eq!(
+            dom.closest(first, ".box").unwrap(),
+            Some(by_id(&dom, "inner"))
+        );
+        assert_eq!(
+            dom.closest(first, "div.box").unwrap(),
+            Some(by_id(&dom, "outer"))
+        );
+        assert_eq!(dom.closest(first, "a").unwrap(), None);
+        // `:scope` is the element closest was called on.
+        assert_eq!(dom.closest(first, ":scope").unwrap(), Some(first));
+        assert_eq!(
+            dom.closest(first, ".box:has(> :scope)").unwrap(),
+            Some(by_id(&dom, "inner"))
+        );
+    }
+
+    #[test]
+    fn invalid_selectors() {
+        let dom = document();
+        let root = dom.root();
+        assert!(dom.query_selector(root, "p >").is_err());
+        assert!(dom.query_selector_all(root, "").is_err());
+        assert!(dom.matches(by_id(&dom, "first"), "[type=").is_err());
+        assert!(dom.closest(by_id(&dom, "first"), "p:unknown").is_err());
+        // No @namespace rule declares a prefix for a query.
+        assert!(dom.query_selector(root, "svg|a").is_err());
+        assert!(dom.query_selector(root, "[xlink|href]").is_err());
+    }
+
+    #[test]
+    fn namespaces() {
+        let dom = document();
+        assert_eq!(select(&dom, "*|p"), ["first", "second"]);
+        // HTML elements are in the XHTML namespace, not in none.
+        assert_eq!(select(&dom, "|p"), Vec::<String>::new());
+        assert_eq!(select(&dom, "[*|lang]"), ["outer", "inner"]);
+        assert_eq!(select(&dom, "[|lang|=en]"), ["outer", "inner"]);
+    }
+}
@@ -1,244 +1,1714 @@
-use crate::html::dom;
+use crate::diagnostics::{Diagnostic, DiagnosticCode};
+use crate::html::dom::{self, Attribute};
+use crate::html::entities;
+use crate::span::{Locator, Span};
+use std::collections::VecDeque;
 
 #[derive(Debug, PartialEq)]
 pub enum Token {
     Doctype,
-    StartTag(String, Vec<(String, String)>),
+    StartTag(String, Vec<Attribute>),
     EndTag(String),
     Comment(String),
     Text(String),
     EOF,
-    SelfClosingTag(String, Vec<(String, String)>),
+    SelfClosingTag(String, Vec<Attribute>),
+}
+
+/// Tokenizer states from the WHATWG HTML tokenization algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Data,
+    Rcdata,
+    Rawtext,
+    ScriptData,
+    Plaintext,
+    TagOpen,
+    EndTagOpen,
+    TagName,
+    RcdataLessThanSign,
+    RcdataEndTagOpen,
+    RcdataEndTagName,
+    RawtextLessThanSign,
+    RawtextEndTagOpen,
+    RawtextEndTagName,
+    ScriptDataLessThanSign,
+    ScriptDataEndTagOpen,
+    ScriptDataEndTagName,
+    ScriptDataEscapeStart,
+    ScriptDataEscapeStartDash,
+    ScriptDataEscaped,
+    ScriptDataEscapedDash,
+    ScriptDataEscapedDashDash,
+    ScriptDataEscapedLessThanSign,
+    ScriptDataEscapedEndTagOpen,
+    ScriptDataEscapedEndTagName,
+    ScriptDataDoubleEscapeStart,
+    ScriptDataDoubleEscaped,
+    ScriptDataDoubleEscapedDash,
+    ScriptDataDoubleEscapedDashDash,
+    ScriptDataDoubleEscapedLessThanSign,
+    ScriptDataDoubleEscapeEnd,
+    BeforeAttributeName,
+    AttributeName,
+    AfterAttributeName,
+    BeforeAttributeValue,
+    AttributeValueDoubleQuoted,
+    AttributeValueSingleQuoted,
+    AttributeValueUnquoted,
+    AfterAttributeValueQuoted,
+    SelfClosingStartTag,
+    BogusComment,
+    MarkupDeclarationOpen,
+    CommentStart,
+    CommentStartDash,
+    Comment,
+    CommentLessThanSign,
+    CommentLessThanSignBang,
+    CommentLessThanSignBangDash,
+    CommentLessThanSignBangDashDash,
+    CommentEndDash,
+    CommentEnd,
+    CommentEndBang,
+    Doctype,
+    BeforeDoctypeName,
+    DoctypeName,
+    AfterDoctypeName,
+    AfterDoctypePublicKeyword,
+    BeforeDoctypePublicIdentifier,
+    DoctypePublicIdentifierDoubleQuoted,
+    DoctypePublicIdentifierSingleQuoted,
+    AfterDoctypePublicIdentifier,
+    BetweenDoctypePublicAndSystemIdentifiers,
+    AfterDoctypeSystemKeyword,
+    BeforeDoctypeSystemIdentifier,
+    DoctypeSystemIdentifierDoubleQuoted,
+    DoctypeSystemIdentifierSingleQuoted,
+    AfterDoctypeSystemIdentifier,
+    BogusDoctype,
+    CdataSection,
+    CdataSectionBracket,
+    CdataSectionEnd,
+    CharacterReference,
+    NamedCharacterReference,
+    AmbiguousAmpersand,
+    NumericCharacterReference,
+    HexadecimalCharacterReferenceStart,
+    DecimalCharacterReferenceStart,
+    HexadecimalCharacterReference,
+    DecimalCharacterReference,
+    NumericCharacterReferenceEnd,
+}
+
+// The tag currently being built by the tag states.
+#[derive(Default)]
+struct TagBuilder {
+    name: String,
+    attributes: Vec<AttributeBuilder>,
+    self_closing: bool,
+    is_end: bool,
+}
+
+// Attribute spans are kept as byte offsets until the tag is emitted, so that
+// line/column lookups happen in source order.
+struct AttributeBuilder {
+    name: String,
+    value: String,
+    start: usize,
+    end: usize,
 }
 
 pub struct Tokenizer {
     input: String,
     position: usize,
+    // Byte length of the last consumed character, so "\r\n" can be undone.
+    last_len: usize,
+    locator: Locator,
+    // Where the '<' of the markup currently being tokenized started, and
+    // where the previous token ended; text tokens span the gap between them.
+    markup_start: usize,
+    last_token_end: usize,
+    state: State,
+    return_state: State,
+    pending: VecDeque<(Token, Span)>,
+    text: String,
+    tag: TagBuilder,
+    attribute: Option<AttributeBuilder>,
+    comment: String,
+    temp_buffer: String,
+    char_ref_code: u32,
+    last_start_tag: Option<String>,
+    allow_cdata: bool,
+    eof_emitted: bool,
+    diagnostics: Vec<Diagnostic>,
 }
 
+const REPLACEMENT_CHAR: char = '\u{FFFD}';
+
 impl Tokenizer {
     pub fn new(input: String) -> Self {
-        Tokenizer { input, position: 0 }
+        Tokenizer {
+            input,
+            position: 0,
+            last_len: 0,
+            locator: Locator::new(),
+            markup_start: 0,
+            last_token_end: 0,
+            state: State::Data,
+            return_state: State::Data,
+            pending: VecDeque::new(),
+            text: String::new(),
+            tag: TagBuilder::default(),
+            attribute: None,
+            comment: String::new(),
+            temp_buffer: String::new(),
+            char_ref_code: 0,
+            last_start_tag: None,
+            allow_cdata: false,
+            eof_emitted: false,
+            diagnostics: Vec::new(),
+        }
     }
 
-    pub fn next_token(&mut self) -> Token {
-        self.skip_whitespace();
+    /// Switches the tokenizer state; the tree builder does this after
+    /// inserting elements like `<script>`, `<style>` or `<textarea>`.
+    pub fn set_state(&mut self, state: State) {
+        self.state = state;
+    }
+
+    /// CDATA sections are only recognized when the adjusted current node is
+    /// in foreign (SVG or MathML) content; elsewhere they are bogus comments.
+    pub fn set_allow_cdata(&mut self, allow: bool) {
+        self.allow_cdata = allow;
+    }
 
-        if self.position >= self.input.len() {
-            return Token::EOF;
+    /// Returns the next token together with the span of source it covers.
+    pub fn next_token(&mut self) -> (Token, Span) {
+        loop {
+            if let Some(token) = self.pending.pop_front() {
+                return token;
+            }
+            if self.eof_emitted {
+                let end = self.input.len();
+                return (Token::EOF, self.locator.span(&self.input, end, end));
+            }
+            self.step();
         }
+    }
 
-        let c = self.input.chars().nth(self.position).unwrap();
+    /// Returns the parse errors found since the last call.
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
 
-        match c {
-            '<' => {
-                self.position += 1;
-                if self.position >= self.input.len() {
-                    return Token::Text("<".to_string());
-                }
+    fn step(&mut self) {
+        let c = self.consume();
+        if c == Some('\0') && reports_null_character(self.state) {
+            self.error(
+                DiagnosticCode::UnexpectedNullCharacter,
+                "unexpected NULL character",
+            );
+        }
 
-                let next_char = self.input.chars().nth(self.position).unwrap();
-                match next_char {
-                    '!' => self.parse_doctype_or_comment(),
-                    '/' => self.parse_end_tag(),
-                    _ => self.parse_start_tag(),
+        match self.state {
+            State::Data => match c {
+                Some('&') => self.begin_character_reference(State::Data),
+                Some('<') => {
+                    self.markup_start = self.position - 1;
+                    self.state = State::TagOpen;
+                }
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::Rcdata => match c {
+                Some('&') => self.begin_character_reference(State::Rcdata),
+                Some('<') => {
+                    self.markup_start = self.position - 1;
+                    self.state = State::RcdataLessThanSign;
+                }
+                Some('\0') => self.emit_char(REPLACEMENT_CHAR),
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::Rawtext => match c {
+                Some('<') => {
+                    self.markup_start = self.position - 1;
+                    self.state = State::RawtextLessThanSign;
+                }
+                Some('\0') => self.emit_char(REPLACEMENT_CHAR),
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::ScriptData => match c {
+                Some('<') => {
+                    self.markup_start = self.position - 1;
+                    self.state = State::ScriptDataLessThanSign;
+                }
+                Some('\0') => self.emit_char(REPLACEMENT_CHAR),
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::Plaintext => match c {
+                Some('\0') => self.emit_char(REPLACEMENT_CHAR),
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::TagOpen => match c {
+                Some('!') => self.state = State::MarkupDeclarationOpen,
+                Some('/') => self.state = State::EndTagOpen,
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.tag = TagBuilder::default();
+                    self.reconsume(Some(c), State::TagName);
+                }
+                Some('?') => {
+                    self.error(
+                        DiagnosticCode::UnexpectedQuestionMarkInsteadOfTagName,
+                        "'<?' starts a bogus comment",
+                    );
+                    self.comment.clear();
+                    self.reconsume(c, State::BogusComment);
+                }
+                None => {
+                    self.error(
+                        DiagnosticCode::EofBeforeTagName,
+                        "unexpected end of file after '<'",
+                    );
+                    self.emit_char('<');
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.error(
+                        DiagnosticCode::InvalidFirstCharacterOfTagName,
+                        "'<' is not followed by a tag name",
+                    );
+                    self.emit_char('<');
+                    self.reconsume(c, State::Data);
+                }
+            },
+            State::EndTagOpen => match c {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.tag = TagBuilder {
+                        is_end: true,
+                        ..TagBuilder::default()
+                    };
+                    self.reconsume(Some(c), State::TagName);
+                }
+                Some('>') => {
+                    self.error(DiagnosticCode::MissingEndTagName, "'</>' has no tag name");
+                    self.state = State::Data;
+                }
+                None => {
+                    self.error(
+                        DiagnosticCode::EofBeforeTagName,
+                        "unexpected end of file after '</'",
+                    );
+                    self.emit_str("</");
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.error(
+                        DiagnosticCode::InvalidFirstCharacterOfTagName,
+                        "'</' is not followed by a tag name",
+                    );
+                    self.comment.clear();
+                    self.reconsume(c, State::BogusComment);
+                }
+            },
+            State::TagName => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                Some('\0') => self.tag.name.push(REPLACEMENT_CHAR),
+                Some(c) => self.tag.name.push(c.to_ascii_lowercase()),
+                None => {
+                    self.error(DiagnosticCode::EofInTag, "unexpected end of file in tag");
+                    self.emit_eof();
+                }
+            },
+            State::RcdataLessThanSign => match c {
+                Some('/') => {
+                    self.temp_buffer.clear();
+                    self.state = State::RcdataEndTagOpen;
+                }
+                _ => {
+                    self.emit_char('<');
+                    self.reconsume(c, State::Rcdata);
+                }
+            },
+            State::RcdataEndTagOpen => {
+                self.raw_end_tag_open(c, State::RcdataEndTagName, State::Rcdata)
+            }
+            State::RcdataEndTagName => self.raw_end_tag_name(c, State::Rcdata),
+            State::RawtextLessThanSign => match c {
+                Some('/') => {
+                    self.temp_buffer.clear();
+                    self.state = State::RawtextEndTagOpen;
+                }
+                _ => {
+                    self.emit_char('<');
+                    self.reconsume(c, State::Rawtext);
+                }
+            },
+            State::RawtextEndTagOpen => {
+                self.raw_end_tag_open(c, State::RawtextEndTagName, State::Rawtext)
+            }
+            State::RawtextEndTagName => self.raw_end_tag_name(c, State::Rawtext),
+            State::ScriptDataLessThanSign => match c {
+                Some('/') => {
+                    self.temp_buffer.clear();
+                    self.state = State::ScriptDataEndTagOpen;
+                }
+                Some('!') => {
+                    self.state = State::ScriptDataEscapeStart;
+                    self.emit_str("<!");
+                }
+                _ => {
+                    self.emit_char('<');
+                    self.reconsume(c, State::ScriptData);
+                }
+            },
+            State::ScriptDataEndTagOpen => {
+                self.raw_end_tag_open(c, State::ScriptDataEndTagName, State::ScriptData)
+            }
+            State::ScriptDataEndTagName => self.raw_end_tag_name(c, State::ScriptData),
+            State::ScriptDataEscapeStart => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapeStartDash;
+                    self.emit_char('-');
+                }
+                _ => self.reconsume(c, State::ScriptData),
+            },
+            State::ScriptDataEscapeStartDash => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapedDashDash;
+                    self.emit_char('-');
+                }
+                _ => self.reconsume(c, State::ScriptData),
+            },
+            State::ScriptDataEscaped => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapedDash;
+                    self.emit_char('-');
+                }
+                Some('<') => {
+                    self.markup_start = self.position - 1;
+                    self.state = State::ScriptDataEscapedLessThanSign;
+                }
+                Some('\0') => self.emit_char(REPLACEMENT_CHAR),
+                Some(c) => self.emit_char(c),
+                None => {
+                    self.error(
+                        DiagnosticCode::EofInScriptHtmlCommentLikeText,
+                        "unexpected end of file in '<!--' inside a script",
+                    );
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataEscapedDash => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapedDashDash;
+                    self.emit_char('-');
+                }
+                Some('<') => {
+                    self.markup_start = self.position - 1;
+                    self.state = State::ScriptDataEscapedLessThanSign;
+                }
+                Some('\0') => {
+                    self.state = State::ScriptDataEscaped;
+                    self.emit_char(REPLACEMENT_CHAR);
+                }
+                Some(c) => {
+                    self.state = State::ScriptDataEscaped;
+                    self.emit_char(c);
+                }
+                None => {
+                    self.error(
+                        DiagnosticCode::EofInScriptHtmlCommentLikeText,
+                        "unexpected end of file in '<!--' inside a script",
+                    );
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataEscapedDashDash => match c {
+                Some('-') => self.emit_char('-'),
+                Some('<') => {
+                    self.markup_start = self.position - 1;
+                    self.state = State::ScriptDataEscapedLessThanSign;
+                }
+                Some('>') => {
+                    self.state = State::ScriptData;
+                    self.emit_char('>');
+                }
+                Some('\0') => {
+                    self.state = State::ScriptDataEscaped;
+                    self.emit_char(REPLACEMENT_CHAR);
+                }
+                Some(c) => {
+                    self.state = State::ScriptDataEscaped;
+                    self.emit_char(c);
+                }
+                None => {
+                    self.error(
+                        DiagnosticCode::EofInScriptHtmlCommentLikeText,
+                        "unexpected end of file in '<!--' inside a script",
+                    );
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataEscapedLessThanSign => match c {
+                Some('/') => {
+                    self.temp_buffer.clear();
+                    self.state = State::ScriptDataEscapedEndTagOpen;
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.temp_buffer.clear();
+                    self.emit_char('<');
+                    self.reconsume(Some(c), State::ScriptDataDoubleEscapeStart);
+                }
+                _ => {
+                    self.emit_char('<');
+                    self.reconsume(c, State::ScriptDataEscaped);
+                }
+            },
+            State::ScriptDataEscapedEndTagOpen => self.raw_end_tag_open(
+                c,
+                State::ScriptDataEscapedEndTagName,
+                State::ScriptDataEscaped,
+            ),
+            State::ScriptDataEscapedEndTagName => {
+                self.raw_end_tag_name(c, State::ScriptDataEscaped)
+            }
+            State::ScriptDataDoubleEscapeStart => match c {
+                Some(c @ ('\t' | '\n' | '\x0C' | ' ' | '/' | '>')) => {
+                    self.state = if self.temp_buffer == "script" {
+                        State::ScriptDataDoubleEscaped
+                    } else {
+                        State::ScriptDataEscaped
+                    };
+                    self.emit_char(c);
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.temp_buffer.push(c.to_ascii_lowercase());
+                    self.emit_char(c);
+                }
+                _ => self.reconsume(c, State::ScriptDataEscaped),
+            },
+            State::ScriptDataDoubleEscaped => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataDoubleEscapedDash;
+                    self.emit_char('-');
+                }
+                Some('<') => {
+                    self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                    self.emit_char('<');
+                }
+                Some('\0') => self.emit_char(REPLACEMENT_CHAR),
+                Some(c) => self.emit_char(c),
+                None => {
+                    self.error(
+                        DiagnosticCode::EofInScriptHtmlCommentLikeText,
+                        "unexpected end of file in '<!--' inside a script",
+                    );
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataDoubleEscapedDash => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataDoubleEscapedDashDash;
+                    self.emit_char('-');
+                }
+                Some('<') => {
+                    self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                    self.emit_char('<');
+                }
+                Some('\0') => {
+                    self.state = State::ScriptDataDoubleEscaped;
+                    self.emit_char(REPLACEMENT_CHAR);
+                }
+                Some(c) => {
+                    self.state = State::ScriptDataDoubleEscaped;
+                    self.emit_char(c);
+                }
+                None => {
+                    self.error(
+                        DiagnosticCode::EofInScriptHtmlCommentLikeText,
+                        "unexpected end of file in '<!--' inside a script",
+                    );
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataDoubleEscapedDashDash => match c {
+                Some('-') => self.emit_char('-'),
+                Some('<') => {
+                    self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                    self.emit_char('<');
+                }
+                Some('>') => {
+                    self.state = State::ScriptData;
+                    self.emit_char('>');
+                }
+                Some('\0') => {
+                    self.state = State::ScriptDataDoubleEscaped;
+                    self.emit_char(REPLACEMENT_CHAR);
+                }
+                Some(c) => {
+                    self.state = State::ScriptDataDoubleEscaped;
+                    self.emit_char(c);
+                }
+                None => {
+                    self.error(
+                        DiagnosticCode::EofInScriptHtmlCommentLikeText,
+                        "unexpected end of file in '<!--' inside a script",
+                    );
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataDoubleEscapedLessThanSign => match c {
+                Some('/') => {
+                    self.temp_buffer.clear();
+                    self.state = State::ScriptDataDoubleEscapeEnd;
+                    self.emit_char('/');
+                }
+                _ => self.reconsume(c, State::ScriptDataDoubleEscaped),
+            },
+            State::ScriptDataDoubleEscapeEnd => match c {
+                Some(c @ ('\t' | '\n' | '\x0C' | ' ' | '/' | '>')) => {
+                    self.state = if self.temp_buffer == "script" {
+                        State::ScriptDataEscaped
+                    } else {
+                        State::ScriptDataDoubleEscaped
+                    };
+                    self.emit_char(c);
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.temp_buffer.push(c.to_ascii_lowercase());
+                    self.emit_char(c);
+                }
+                _ => self.reconsume(c, State::ScriptDataDoubleEscaped),
+            },
+            State::BeforeAttributeName => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('/' | '>') | None => self.reconsume(c, State::AfterAttributeName),
+                Some('=') => {
+                    self.error(
+                        DiagnosticCode::UnexpectedEqualsSignBeforeAttributeName,
+                        "attribute name starts with '='",
+                    );
+                    self.start_attribute();
+                    self.push_attribute_name('=');
+                    self.state = State::AttributeName;
+                }
+                Some(_) => {
+                    self.start_attribute();
+                    self.reconsume(c, State::AttributeName);
+                }
+            },
+            State::AttributeName => match c {
+                Some('\t' | '\n' | '\x0C' | ' ' | '/' | '>') | None => {
+                    self.reconsume(c, State::AfterAttributeName)
+                }
+                Some('=') => {
+                    self.extend_attribute();
+                    self.state = State::BeforeAttributeValue;
+                }
+                Some('\0') => self.push_attribute_name(REPLACEMENT_CHAR),
+                Some(c) => {
+                    if matches!(c, '"' | '\'' | '<') {
+                        self.error(
+                            DiagnosticCode::UnexpectedCharacterInAttributeName,
+                            format!("unexpected {:?} in attribute name", c),
+                        );
+                    }
+                    self.push_attribute_name(c.to_ascii_lowercase());
+                }
+            },
+            State::AfterAttributeName => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('=') => {
+                    self.extend_attribute();
+                    self.state = State::BeforeAttributeValue;
+                }
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                None => {
+                    self.error(DiagnosticCode::EofInTag, "unexpected end of file in tag");
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.start_attribute();
+                    self.reconsume(c, State::AttributeName);
+                }
+            },
+            State::BeforeAttributeValue => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('"') => self.state = State::AttributeValueDoubleQuoted,
+                Some('\'') => self.state = State::AttributeValueSingleQuoted,
+                Some('>') => {
+                    self.error(
+                        DiagnosticCode::MissingAttributeValue,
+                        "missing attribute value after '='",
+                    );
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                _ => self.reconsume(c, State::AttributeValueUnquoted),
+            },
+            State::AttributeValueDoubleQuoted => match c {
+                Some('"') => {
+                    self.extend_attribute();
+                    self.state = State::AfterAttributeValueQuoted;
+                }
+                Some('&') => self.begin_character_reference(State::AttributeValueDoubleQuoted),
+                Some('\0') => self.push_attribute_value(REPLACEMENT_CHAR),
+                Some(c) => self.push_attribute_value(c),
+                None => {
+                    self.error(DiagnosticCode::EofInTag, "unexpected end of file in tag");
+                    self.emit_eof();
+                }
+            },
+            State::AttributeValueSingleQuoted => match c {
+                Some('\'') => {
+                    self.extend_attribute();
+                    self.state = State::AfterAttributeValueQuoted;
+                }
+                Some('&') => self.begin_character_reference(State::AttributeValueSingleQuoted),
+                Some('\0') => self.push_attribute_value(REPLACEMENT_CHAR),
+                Some(c) => self.push_attribute_value(c),
+                None => {
+                    self.error(DiagnosticCode::EofInTag, "unexpected end of file in tag");
+                    self.emit_eof();
+                }
+            },
+            State::AttributeValueUnquoted => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::BeforeAttributeName,
+                Some('&') => self.begin_character_reference(State::AttributeValueUnquoted),
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                Some('\0') => self.push_attribute_value(REPLACEMENT_CHAR),
+                Some(c) => {
+                    if matches!(c, '"' | '\'' | '<' | '=' | '`') {
+                        self.error(
+                            DiagnosticCode::UnexpectedCharacterInUnquotedAttributeValue,
+                            format!("unexpected {:?} in unquoted attribute value", c),
+                        );
+                    }
+                    self.push_attribute_value(c);
+                }
+                None => {
+                    self.error(DiagnosticCode::EofInTag, "unexpected end of file in tag");
+                    self.emit_eof();
+                }
+            },
+            State::AfterAttributeValueQuoted => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                None => {
+                    self.error(DiagnosticCode::EofInTag, "unexpected end of file in tag");
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.error(
+                        DiagnosticCode::MissingWhitespaceBetweenAttributes,
+                        "missing whitespace between attributes",
+                    );
+                    self.reconsume(c, State::BeforeAttributeName);
+                }
+            },
+            State::SelfClosingStartTag => match c {
+                Some('>') => {
+                    self.tag.self_closing = true;
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                None => {
+                    self.error(DiagnosticCode::EofInTag, "unexpected end of file in tag");
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.error(
+                        DiagnosticCode::UnexpectedSolidusInTag,
+                        "unexpected '/' in tag",
+                    );
+                    self.reconsume(c, State::BeforeAttributeName);
+                }
+            },
+            State::BogusComment => match c {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                None => {
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+                Some('\0') => self.comment.push(REPLACEMENT_CHAR),
+                Some(c) => self.comment.push(c),
+            },
+            State::MarkupDeclarationOpen => {
+                self.reconsume(c, State::MarkupDeclarationOpen);
+                if self.lookahead("--", false) {
+                    self.position += 2;
+                    self.comment.clear();
+                    self.state = State::CommentStart;
+                } else if self.lookahead("doctype", true) {
+                    self.position += 7;
+                    self.state = State::Doctype;
+                } else if self.lookahead("[CDATA[", false) {
+                    self.position += 7;
+                    if self.allow_cdata {
+                        self.state = State::CdataSection;
+                    } else {
+                        self.error(
+                            DiagnosticCode::CdataInHtmlContent,
+                            "CDATA sections are only allowed in SVG and MathML",
+                        );
+                        self.comment.clear();
+                        self.comment.push_str("[CDATA[");
+                        self.state = State::BogusComment;
+                    }
+                } else {
+                    self.error(
+                        DiagnosticCode::IncorrectlyOpenedComment,
+                        "'<!' does not start a comment",
+                    );
+                    self.comment.clear();
+                    self.state = State::BogusComment;
+                }
+            }
+            State::CommentStart => match c {
+                Some('-') => self.state = State::CommentStartDash,
+                Some('>') => {
+                    self.error(
+                        DiagnosticCode::AbruptClosingOfEmptyComment,
+                        "comment closed by '<!-->'",
+                    );
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                _ => self.reconsume(c, State::Comment),
+            },
+            State::CommentStartDash => match c {
+                Some('-') => self.state = State::CommentEnd,
+                Some('>') => {
+                    self.error(
+                        DiagnosticCode::AbruptClosingOfEmptyComment,
+                        "comment closed by '<!--->'",
+                    );
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                None => {
+                    self.error(
+                        DiagnosticCode::EofInComment,
+                        "unexpected end of file in comment",
+                    );
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.comment.push('-');
+                    self.reconsume(c, State::Comment);
+                }
+            },
+            State::Comment => match c {
+                Some('<') => {
+                    self.comment.push('<');
+                    self.state = State::CommentLessThanSign;
+                }
+                Some('-') => self.state = State::CommentEndDash,
+                Some('\0') => self.comment.push(REPLACEMENT_CHAR),
+                Some(c) => self.comment.push(c),
+                None => {
+                    self.error(
+                        DiagnosticCode::EofInComment,
+                        "unexpected end of file in comment",
+                    );
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+            },
+            State::CommentLessThanSign => match c {
+                Some('!') => {
+                    self.comment.push('!');
+                    self.state = State::CommentLessThanSignBang;
+                }
+                Some('<') => self.comment.push('<'),
+                _ => self.reconsume(c, State::Comment),
+            },
+            State::CommentLessThanSignBang => match c {
+                Some('-') => self.state = State::CommentLessThanSignBangDash,
+                _ => self.reconsume(c, State::Comment),
+            },
+            State::CommentLessThanSignBangDash => match c {
+                Some('-') => self.state = State::CommentLessThanSignBangDashDash,
+                _ => self.reconsume(c, State::CommentEndDash),
+            },
+            State::CommentLessThanSignBangDashDash => {
+                // Both branches continue in the comment-end state.
+                if !matches!(c, Some('>') | None) {
+                    self.error(DiagnosticCode::NestedComment, "'<!--' inside a comment");
+                }
+                self.reconsume(c, State::CommentEnd);
+            }
+            State::CommentEndDash => match c {
+                Some('-') => self.state = State::CommentEnd,
+                None => {
+                    self.error(
+                        DiagnosticCode::EofInComment,
+                        "unexpected end of file in comment",
+                    );
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.comment.push('-');
+                    self.reconsume(c, State::Comment);
+                }
+            },
+            State::CommentEnd => match c {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                Some('!') => self.state = State::CommentEndBang,
+                Some('-') => self.comment.push('-'),
+                None => {
+                    self.error(
+                        DiagnosticCode::EofInComment,
+                        "unexpected end of file in comment",
+                    );
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.comment.push_str("--");
+                    self.reconsume(c, State::Comment);
+                }
+            },
+            State::CommentEndBang => match c {
+                Some('-') => {
+                    self.comment.push_str("--!");
+                    self.state = State::CommentEndDash;
+                }
+                Some('>') => {
+                    self.error(
+                        DiagnosticCode::IncorrectlyClosedComment,
+                        "comment closed by '--!>'",
+                    );
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                None => {
+                    self.error(
+                        DiagnosticCode::EofInComment,
+                        "unexpected end of file in comment",
+                    );
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.comment.push_str("--!");
+                    self.reconsume(c, State::Comment);
+                }
+            },
+            State::Doctype => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::BeforeDoctypeName,
+                Some('>') => self.reconsume(c, State::BeforeDoctypeName),
+                None => {
+                    self.error(
+                        DiagnosticCode::EofInDoctype,
+                        "unexpected end of file in doctype",
+                    );
+                    self.emit(Token::Doctype);
+                    self.emit_eof();
                 }
+                Some(_) => self.reconsume(c, State::BeforeDoctypeName),
+            },
+            State::BeforeDoctypeName => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('>') => {
+                    self.error(DiagnosticCode::MissingDoctypeName, "doctype has no name");
+                    self.state = State::Data;
+                    self.emit(Token::Doctype);
+                }
+                None => {
+                    self.error(
+                        DiagnosticCode::EofInDoctype,
+                        "unexpected end of file in doctype",
+                    );
+                    self.emit(Token::Doctype);
+                    self.emit_eof();
+                }
+                Some(_) => self.state = State::DoctypeName,
+            },
+            State::DoctypeName => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::AfterDoctypeName,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit(Token::Doctype);
+                }
+                None => {
+                    self.error(
+                        DiagnosticCode::EofInDoctype,
+                        "unexpected end of file in doctype",
+                    );
+                    self.emit(Token::Doctype);
+                    self.emit_eof();
+                }
+                Some(_) => {}
+            },
+            State::AfterDoctypeName => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit(Token::Doctype);
+                }
+                None => {
+                    self.error(
+                        DiagnosticCode::EofInDoctype,
+                        "unexpected end of file in doctype",
+                    );
+                    self.emit(Token::Doctype);
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.reconsume(c, State::AfterDoctypeName);
+                    if self.lookahead("public", true) {
+                        self.position += 6;
+                        self.state = State::AfterDoctypePublicKeyword;
+                    } else if self.lookahead("system", true) {
+                        self.position += 6;
+                        self.state = State::AfterDoctypeSystemKeyword;
+                    } else {
+                        self.error(
+                            DiagnosticCode::InvalidCharacterSequenceAfterDoctypeName,
+                            "expected 'PUBLIC' or 'SYSTEM' after doctype name",
+                        );
+                        self.state = State::BogusDoctype;
+                    }
+                }
+            },
+            State::AfterDoctypePublicKeyword => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {
+                    self.state = State::BeforeDoctypePublicIdentifier
+                }
+                Some('"') => self.state = State::DoctypePublicIdentifierDoubleQuoted,
+                Some('\'') => self.state = State::DoctypePublicIdentifierSingleQuoted,
+                _ => self.doctype_anything_else(c),
+            },
+            State::BeforeDoctypePublicIdentifier => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('"') => self.state = State::DoctypePublicIdentifierDoubleQuoted,
+                Some('\'') => self.state = State::DoctypePublicIdentifierSingleQuoted,
+                _ => self.doctype_anything_else(c),
+            },
+            State::DoctypePublicIdentifierDoubleQuoted => {
+                self.doctype_identifier(c, '"', State::AfterDoctypePublicIdentifier)
+            }
+            State::DoctypePublicIdentifierSingleQuoted => {
+                self.doctype_identifier(c, '\'', State::AfterDoctypePublicIdentifier)
+            }
+            State::AfterDoctypePublicIdentifier => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {
+                    self.state = State::BetweenDoctypePublicAndSystemIdentifiers
+                }
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit(Token::Doctype);
+                }
+                Some('"') => self.state = State::DoctypeSystemIdentifierDoubleQuoted,
+                Some('\'') => self.state = State::DoctypeSystemIdentifierSingleQuoted,
+                _ => self.doctype_anything_else(c),
+            },
+            State::BetweenDoctypePublicAndSystemIdentifiers => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit(Token::Doctype);
+                }
+                Some('"') => self.state = State::DoctypeSystemIdentifierDoubleQuoted,
+                Some('\'') => self.state = State::DoctypeSystemIdentifierSingleQuoted,
+                _ => self.doctype_anything_else(c),
+            },
+            State::AfterDoctypeSystemKeyword => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {
+                    self.state = State::BeforeDoctypeSystemIdentifier
+                }
+                Some('"') => self.state = State::DoctypeSystemIdentifierDoubleQuoted,
+                Some('\'') => self.state = State::DoctypeSystemIdentifierSingleQuoted,
+                _ => self.doctype_anything_else(c),
+            },
+            State::BeforeDoctypeSystemIdentifier => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('"') => self.state = State::DoctypeSystemIdentifierDoubleQuoted,
+                Some('\'') => self.state = State::DoctypeSystemIdentifierSingleQuoted,
+                _ => self.doctype_anything_else(c),
+            },
+            State::DoctypeSystemIdentifierDoubleQuoted => {
+                self.doctype_identifier(c, '"', State::AfterDoctypeSystemIdentifier)
+            }
+            State::DoctypeSystemIdentifierSingleQuoted => {
+                self.doctype_identifier(c, '\'', State::AfterDoctypeSystemIdentifier)
+            }
+            State::AfterDoctypeSystemIdentifier => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit(Token::Doctype);
+                }
+                None => {
+                    self.error(
+                        DiagnosticCode::EofInDoctype,
+                        "unexpected end of file in doctype",
+                    );
+                    self.emit(Token::Doctype);
+                    self.emit_eof();
+                }
+                Some(_) => self.state = State::BogusDoctype,
+            },
+            State::BogusDoctype => match c {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit(Token::Doctype);
+                }
+                None => {
+                    self.emit(Token::Doctype);
+                    self.emit_eof();
+                }
+                Some(_) => {}
+            },
+            State::CdataSection => match c {
+                Some(']') => self.state = State::CdataSectionBracket,
+                Some(c) => self.emit_char(c),
+                None => {
+                    self.error(
+                        DiagnosticCode::EofInCdata,
+                        "unexpected end of file in CDATA section",
+                    );
+                    self.emit_eof();
+                }
+            },
+            State::CdataSectionBracket => match c {
+                Some(']') => self.state = State::CdataSectionEnd,
+                _ => {
+                    self.emit_char(']');
+                    self.reconsume(c, State::CdataSection);
+                }
+            },
+            State::CdataSectionEnd => match c {
+                Some(']') => self.emit_char(']'),
+                Some('>') => self.state = State::Data,
+                _ => {
+                    self.emit_str("]]");
+                    self.reconsume(c, State::CdataSection);
+                }
+            },
+            State::CharacterReference => {
+                self.temp_buffer.clear();
+                self.temp_buffer.push('&');
+                match c {
+                    Some(c) if c.is_ascii_alphanumeric() => {
+                        self.reconsume(Some(c), State::NamedCharacterReference)
+                    }
+                    Some('#') => {
+                        self.temp_buffer.push('#');
+                        self.state = State::NumericCharacterReference;
+                    }
+                    _ => {
+                        self.flush_character_reference();
+                        self.reconsume(c, self.return_state);
+                    }
+                }
+            }
+            State::NamedCharacterReference => {
+                self.reconsume(c, State::NamedCharacterReference);
+                self.consume_named_character_reference();
+            }
+            State::AmbiguousAmpersand => match c {
+                Some(c) if c.is_ascii_alphanumeric() => {
+                    if self.in_attribute_value() {
+                        self.push_attribute_value(c);
+                    } else {
+                        self.emit_char(c);
+                    }
+                }
+                Some(';') => {
+                    self.error(
+                        DiagnosticCode::UnknownNamedCharacterReference,
+                        "unknown named character reference",
+                    );
+                    self.reconsume(c, self.return_state);
+                }
+                _ => self.reconsume(c, self.return_state),
+            },
+            State::NumericCharacterReference => {
+                self.char_ref_code = 0;
+                match c {
+                    Some(c @ ('x' | 'X')) => {
+                        self.temp_buffer.push(c);
+                        self.state = State::HexadecimalCharacterReferenceStart;
+                    }
+                    _ => self.reconsume(c, State::DecimalCharacterReferenceStart),
+                }
+            }
+            State::HexadecimalCharacterReferenceStart => match c {
+                Some(c) if c.is_ascii_hexdigit() => {
+                    self.reconsume(Some(c), State::HexadecimalCharacterReference)
+                }
+                _ => {
+                    self.error(
+                        DiagnosticCode::AbsenceOfDigitsInNumericCharacterReference,
+                        "numeric character reference has no digits",
+                    );
+                    self.flush_character_reference();
+                    self.reconsume(c, self.return_state);
+                }
+            },
+            State::DecimalCharacterReferenceStart => match c {
+                Some(c) if c.is_ascii_digit() => {
+                    self.reconsume(Some(c), State::DecimalCharacterReference)
+                }
+                _ => {
+                    self.error(
+                        DiagnosticCode::AbsenceOfDigitsInNumericCharacterReference,
+                        "numeric character reference has no digits",
+                    );
+                    self.flush_character_reference();
+                    self.reconsume(c, self.return_state);
+                }
+            },
+            State::HexadecimalCharacterReference => match c {
+                Some(c) if c.is_ascii_hexdigit() => {
+                    self.push_char_ref_digit(16, c.to_digit(16).unwrap_or(0))
+                }
+                Some(';') => self.state = State::NumericCharacterReferenceEnd,
+                _ => {
+                    self.error(
+                        DiagnosticCode::MissingSemicolonAfterCharacterReference,
+                        "character reference is missing a ';'",
+                    );
+                    self.reconsume(c, State::NumericCharacterReferenceEnd);
+                }
+            },
+            State::DecimalCharacterReference => match c {
+                Some(c) if c.is_ascii_digit() => {
+                    self.push_char_ref_digit(10, c.to_digit(10).unwrap_or(0))
+                }
+                Some(';') => self.state = State::NumericCharacterReferenceEnd,
+                _ => {
+                    self.error(
+                        DiagnosticCode::MissingSemicolonAfterCharacterReference,
+                        "character reference is missing a ';'",
+                    );
+                    self.reconsume(c, State::NumericCharacterReferenceEnd);
+                }
+            },
+            State::NumericCharacterReferenceEnd => {
+                self.check_numeric_reference();
+                self.temp_buffer.clear();
+                self.temp_buffer
+                    .push(numeric_reference_char(self.char_ref_code));
+                self.flush_character_reference();
+                self.reconsume(c, self.return_state);
             }
-            _ => self.parse_text(),
         }
     }
 
-    fn parse_start_tag(&mut self) -> Token {
-        let tag_name = self.parse_tag_name();
-        let attributes = self.parse_attributes();
+    fn begin_character_reference(&mut self, return_state: State) {
+        self.return_state = return_state;
+        self.state = State::CharacterReference;
+    }
+
+    // Matches the longest entity name at the cursor, so `&notit;` decodes as
+    // `¬it;` the way browsers do.
+    fn consume_named_character_reference(&mut self) {
+        let rest = &self.input[self.position..];
+        let name_len = rest
+            .bytes()
+            .take(entities::MAX_ENTITY_LENGTH)
+            .take_while(|b| b.is_ascii_alphanumeric())
+            .count();
+        let max_len = name_len + usize::from(rest.as_bytes().get(name_len) == Some(&b';'));
 
-        // Check for self-closing
-        let is_self_closing = if self.position < self.input.len() {
-            let c = self.input.chars().nth(self.position).unwrap();
-            if c == '/' {
-                self.position += 1;
-                true
-            } else {
-                false
+        let matched = (1..=max_len)
+            .rev()
+            .find_map(|len| entities::lookup(&rest[..len]).map(|value| (len, value)));
+
+        match matched {
+            Some((len, value)) => {
+                let has_semicolon = rest.as_bytes()[len - 1] == b';';
+                let next = rest.as_bytes().get(len).copied();
+
+                // Legacy references without `;` are left alone in attribute
+                // values when followed by `=` or an alphanumeric, so query
+                // strings like `?a=1&copy=2` survive.
+                let literal = self.in_attribute_value()
+                    && !has_semicolon
+                    && matches!(next, Some(b) if b == b'=' || b.is_ascii_alphanumeric());
+
+                if literal {
+                    self.temp_buffer.push_str(&rest[..len]);
+                } else {
+                    self.temp_buffer.clear();
+                    self.temp_buffer.push_str(value);
+                }
+                self.position += len;
+                if !literal && !has_semicolon {
+                    self.error(
+                        DiagnosticCode::MissingSemicolonAfterCharacterReference,
+                        "character reference is missing a ';'",
+                    );
+                }
+                self.flush_character_reference();
+                self.state = self.return_state;
             }
-        } else {
-            false
+            None => {
+                self.flush_character_reference();
+                self.state = State::AmbiguousAmpersand;
+            }
+        }
+    }
+
+    fn push_char_ref_digit(&mut self, radix: u32, digit: u32) {
+        self.char_ref_code = self
+            .char_ref_code
+            .saturating_mul(radix)
+            .saturating_add(digit)
+            .min(0x110000);
+    }
+
+    // Reports the code points a numeric character reference may not produce,
+    // before `numeric_reference_char` replaces them.
+    fn check_numeric_reference(&mut self) {
+        let code = self.char_ref_code;
+        let problem = match code {
+            0 => Some((
+                DiagnosticCode::NullCharacterReference,
+                "&#0; is not allowed",
+            )),
+            0x110000.. => Some((
+                DiagnosticCode::CharacterReferenceOutsideUnicodeRange,
+                "character reference is outside the Unicode range",
+            )),
+            0xD800..=0xDFFF => Some((
+                DiagnosticCode::SurrogateCharacterReference,
+                "character reference is a surrogate",
+            )),
+            0xFDD0..=0xFDEF => Some((
+                DiagnosticCode::NoncharacterCharacterReference,
+                "character reference is a noncharacter",
+            )),
+            _ if code & 0xFFFE == 0xFFFE => Some((
+                DiagnosticCode::NoncharacterCharacterReference,
+                "character reference is a noncharacter",
+            )),
+            0x0D | 0x01..=0x08 | 0x0B | 0x0E..=0x1F | 0x7F..=0x9F => Some((
+                DiagnosticCode::ControlCharacterReference,
+                "character reference is a control character",
+            )),
+            _ => None,
         };
+        if let Some((code, message)) = problem {
+            self.error(code, message);
+        }
+    }
 
-        self.skip_until('>');
+    fn in_attribute_value(&self) -> bool {
+        matches!(
+            self.return_state,
+            State::AttributeValueDoubleQuoted
+                | State::AttributeValueSingleQuoted
+                | State::AttributeValueUnquoted
+        )
+    }
 
-        if is_self_closing || dom::is_void_element(&tag_name) {
-            Token::SelfClosingTag(tag_name, attributes)
+    // "Flush code points consumed as a character reference": the temporary
+    // buffer goes to the attribute value or out as character tokens.
+    fn flush_character_reference(&mut self) {
+        let buffer = std::mem::take(&mut self.temp_buffer);
+        if self.in_attribute_value() {
+            if let Some(attribute) = self.attribute.as_mut() {
+                attribute.value.push_str(&buffer);
+                attribute.end = self.position;
+            }
         } else {
-            Token::StartTag(tag_name, attributes)
+            self.emit_str(&buffer);
         }
     }
 
-    fn parse_attributes(&mut self) -> Vec<(String, String)> {
-        let mut attributes = Vec::new();
-
-        while self.position < self.input.len() {
-            self.skip_whitespace();
-            let c = self.input.chars().nth(self.position).unwrap();
+    // Shared "end tag open" handling for RCDATA, RAWTEXT and script data.
+    fn raw_end_tag_open(&mut self, c: Option<char>, name_state: State, text_state: State) {
+        match c {
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.tag = TagBuilder {
+                    is_end: true,
+                    ..TagBuilder::default()
+                };
+                self.reconsume(Some(c), name_state);
+            }
+            _ => {
+                self.emit_str("</");
+                self.reconsume(c, text_state);
+            }
+        }
+    }
 
-            if c == '>' || c == '/' {
-                break;
+    // Shared "end tag name" handling: only an appropriate end tag (one that
+    // matches the last start tag) leaves the raw text state.
+    fn raw_end_tag_name(&mut self, c: Option<char>, text_state: State) {
+        let appropriate = self.last_start_tag.as_deref() == Some(self.tag.name.as_str());
+        match c {
+            Some('\t' | '\n' | '\x0C' | ' ') if appropriate => {
+                self.state = State::BeforeAttributeName;
+            }
+            Some('/') if appropriate => self.state = State::SelfClosingStartTag,
+            Some('>') if appropriate => {
+                self.state = State::Data;
+                self.emit_tag();
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.tag.name.push(c.to_ascii_lowercase());
+                self.temp_buffer.push(c);
             }
+            _ => {
+                let buffer = std::mem::take(&mut self.temp_buffer);
+                self.emit_str("</");
+                self.emit_str(&buffer);
+                self.reconsume(c, text_state);
+            }
+        }
+    }
 
-            // Parse attribute name
-            let name_start = self.position;
-            while self.position < self.input.len() {
-                let c = self.input.chars().nth(self.position).unwrap();
-                if c.is_whitespace() || c == '=' || c == '>' || c == '/' {
-                    break;
+    fn doctype_identifier(&mut self, c: Option<char>, quote: char, after: State) {
+        match c {
+            Some(c) if c == quote => self.state = after,
+            Some('>') => {
+                if after == State::AfterDoctypePublicIdentifier {
+                    self.error(
+                        DiagnosticCode::AbruptDoctypePublicIdentifier,
+                        "doctype public identifier is not closed",
+                    );
+                } else {
+                    self.error(
+                        DiagnosticCode::AbruptDoctypeSystemIdentifier,
+                        "doctype system identifier is not closed",
+                    );
                 }
-                self.position += 1;
+                self.state = State::Data;
+                self.emit(Token::Doctype);
             }
-            let name = self.input[name_start..self.position].to_string();
-
-            // Skip whitespace after name
-            self.skip_whitespace();
-
-            // Check for attribute value
-            if self.position < self.input.len()
-                && self.input.chars().nth(self.position).unwrap() == '='
-            {
-                self.position += 1;
-                self.skip_whitespace();
+            None => {
+                self.error(
+                    DiagnosticCode::EofInDoctype,
+                    "unexpected end of file in doctype",
+                );
+                self.emit(Token::Doctype);
+                self.emit_eof();
+            }
+            Some(_) => {}
+        }
+    }
 
-                // Parse attribute value
-                let quote = self.input.chars().nth(self.position).unwrap();
-                if quote == '"' || quote == '\'' {
-                    self.position += 1;
-                    let value_start = self.position;
+    fn doctype_anything_else(&mut self, c: Option<char>) {
+        match c {
+            Some('>') => {
+                self.state = State::Data;
+                self.emit(Token::Doctype);
+            }
+            None => {
+                self.error(
+                    DiagnosticCode::EofInDoctype,
+                    "unexpected end of file in doctype",
+                );
+                self.emit(Token::Doctype);
+                self.emit_eof();
+            }
+            Some(_) => self.state = State::BogusDoctype,
+        }
+    }
 
-                    while self.position < self.input.len() {
-                        if self.input.chars().nth(self.position).unwrap() == quote {
-                            break;
-                        }
-                        self.position += 1;
-                    }
+    // Called with the attribute's first character already consumed.
+    fn start_attribute(&mut self) {
+        self.finish_attribute();
+        let start = self.position - self.last_len;
+        self.attribute = Some(AttributeBuilder {
+            name: String::new(),
+            value: String::new(),
+            start,
+            end: self.position,
+        });
+    }
 
-                    let value = self.input[value_start..self.position].to_string();
-                    self.position += 1; // Skip closing quote
-                    attributes.push((name, value));
-                } else {
-                    // Unquoted attribute value
-                    let value_start = self.position;
-                    while self.position < self.input.len() {
-                        let c = self.input.chars().nth(self.position).unwrap();
-                        if c.is_whitespace() || c == '>' || c == '/' {
-                            break;
-                        }
-                        self.position += 1;
-                    }
-                    let value = self.input[value_start..self.position].to_string();
-                    attributes.push((name, value));
-                }
+    fn finish_attribute(&mut self) {
+        if let Some(attribute) = self.attribute.take() {
+            // Duplicate attributes are dropped, keeping the first occurrence.
+            if self.tag.attributes.iter().any(|a| a.name == attribute.name) {
+                let message = format!("duplicate attribute '{}'", attribute.name);
+                self.error_at(
+                    DiagnosticCode::DuplicateAttribute,
+                    message,
+                    attribute.start,
+                    attribute.end,
+                );
             } else {
-                // Boolean attribute (no value)
-                attributes.push((name, "".to_string()));
+                self.tag.attributes.push(attribute);
             }
         }
+    }
+
+    fn extend_attribute(&mut self) {
+        if let Some(attribute) = self.attribute.as_mut() {
+            attribute.end = self.position;
+        }
+    }
 
-        attributes
+    fn push_attribute_name(&mut self, c: char) {
+        if let Some(attribute) = self.attribute.as_mut() {
+            attribute.name.push(c);
+            attribute.end = self.position;
+        }
     }
 
-    fn parse_end_tag(&mut self) -> Token {
-        self.position += 1; // Skip '/'
-        let tag_name = self.parse_tag_name();
-        self.skip_until('>');
-        Token::EndTag(tag_name)
+    fn push_attribute_value(&mut self, c: char) {
+        if let Some(attribute) = self.attribute.as_mut() {
+            attribute.value.push(c);
+            attribute.end = self.position;
+        }
     }
 
-    fn parse_doctype_or_comment(&mut self) -> Token {
-        self.position += 1; // Skip '!'
+    fn emit_tag(&mut self) {
+        self.finish_attribute();
+        let tag = std::mem::take(&mut self.tag);
 
-        if self.position + 2 >= self.input.len() {
-            return Token::Text("<!".to_string());
+        if tag.is_end {
+            if !tag.attributes.is_empty() {
+                self.error_at(
+                    DiagnosticCode::EndTagWithAttributes,
+                    format!("end tag '{}' has attributes", tag.name),
+                    self.markup_start,
+                    self.position,
+                );
+            }
+            if tag.self_closing {
+                self.error_at(
+                    DiagnosticCode::EndTagWithTrailingSolidus,
+                    format!("end tag '{}' ends with '/>'", tag.name),
+                    self.markup_start,
+                    self.position,
+                );
+            }
+            self.emit(Token::EndTag(tag.name));
+            return;
         }
 
-        if &self.input[self.position..self.position + 2] == "--" {
-            self.position += 2;
-            self.parse_comment()
+        // Flush text first so spans are resolved in source order.
+        self.flush_text();
+        let start = self.locator.position(&self.input, self.markup_start);
+        let attributes = tag
+            .attributes
+            .into_iter()
+            .map(|attribute| Attribute {
+                name: attribute.name,
+                value: attribute.value,
+                span: Some(
+                    self.locator
+                        .span(&self.input, attribute.start, attribute.end),
+                ),
+            })
+            .collect();
+
+        let span = Span::new(start, self.locator.position(&self.input, self.position));
+
+        self.last_start_tag = Some(tag.name.clone());
+        if tag.self_closing || dom::is_void_element(&tag.name) {
+            self.push_token(Token::SelfClosingTag(tag.name, attributes), span);
         } else {
-            self.parse_doctype()
+            self.push_token(Token::StartTag(tag.name, attributes), span);
         }
     }
 
-    fn parse_comment(&mut self) -> Token {
-        let start = self.position;
-        while self.position + 2 < self.input.len()
-            && &self.input[self.position..self.position + 2] != "--"
-        {
-            self.position += 1;
-        }
+    fn emit_comment(&mut self) {
+        let comment = std::mem::take(&mut self.comment);
+        self.emit(Token::Comment(comment));
+    }
 
-        let comment = self.input[start..self.position].to_string();
-        self.position += 3; // Skip '-->'
-        Token::Comment(comment)
+    fn emit_eof(&mut self) {
+        self.markup_start = self.position;
+        self.emit(Token::EOF);
+        self.eof_emitted = true;
     }
 
-    fn parse_doctype(&mut self) -> Token {
-        // Skip until '>'
-        while self.position < self.input.len()
-            && self.input.chars().nth(self.position).unwrap() != '>'
-        {
-            self.position += 1;
-        }
-        self.position += 1; // Skip '>'
-        Token::Doctype
+    fn emit(&mut self, token: Token) {
+        self.flush_text();
+        let span = self
+            .locator
+            .span(&self.input, self.markup_start, self.position);
+        self.push_token(token, span);
     }
 
-    fn parse_text(&mut self) -> Token {
-        let start = self.position;
-        while self.position < self.input.len() {
-            let c = self.input.chars().nth(self.position).unwrap();
-            if c == '<' {
-                break;
-            }
-            self.position += 1;
-        }
-        let text = self.input[start..self.position].trim().to_string();
-        if text.is_empty() {
-            self.next_token()
-        } else {
-            Token::Text(text)
+    fn push_token(&mut self, token: Token, span: Span) {
+        self.last_token_end = self.position;
+        self.pending.push_back((token, span));
+    }
+
+    // Reports an error at the character just consumed (or at the end of the
+    // input).
+    fn error(&mut self, code: DiagnosticCode, message: impl Into<String>) {
+        self.error_at(code, message, self.position - self.last_len, self.position);
+    }
+
+    fn error_at(
+        &mut self,
+        code: DiagnosticCode,
+        message: impl Into<String>,
+        start: usize,
+        end: usize,
+    ) {
+        let span = self.locator.span(&self.input, start, end);
+        self.diagnostics
+            .push(Diagnostic::error(code, message, span));
+    }
+
+    fn emit_char(&mut self, c: char) {
+        self.text.push(c);
+    }
+
+    fn emit_str(&mut self, s: &str) {
+        self.text.push_str(s);
+    }
+
+    fn flush_text(&mut self) {
+        if self.text.is_empty() {
+            return;
         }
+        let text = std::mem::take(&mut self.text);
+        let span = self
+            .locator
+            .span(&self.input, self.last_token_end, self.markup_start);
+        self.last_token_end = self.markup_start;
+        self.pending.push_back((Token::Text(text), span));
     }
 
-    fn parse_tag_name(&mut self) -> String {
-        let start = self.position;
-        while self.position < self.input.len() {
-            let c = self.input.chars().nth(self.position).unwrap();
-            if !c.is_alphanumeric() && c != '-' && c != ':' {
-                break;
+    // Input stream preprocessing happens here: "\r\n" and lone '\r' are
+    // read as '\n' while positions keep pointing into the original input.
+    fn consume(&mut self) -> Option<char> {
+        let Some(c) = self.input[self.position..].chars().next() else {
+            self.last_len = 0;
+            return None;
+        };
+        self.last_len = c.len_utf8();
+        if c == '\r' {
+            if self.input.as_bytes().get(self.position + 1) == Some(&b'\n') {
+                self.last_len = 2;
             }
-            self.position += 1;
+            self.position += self.last_len;
+            return Some('\n');
         }
-        self.input[start..self.position].to_ascii_lowercase()
+        self.position += self.last_len;
+        Some(c)
     }
 
-    fn skip_until(&mut self, c: char) {
-        while self.position < self.input.len() {
-            if self.input.chars().nth(self.position).unwrap() == c {
-                self.position += 1;
-                break;
-            }
-            self.position += 1;
+    fn reconsume(&mut self, c: Option<char>, state: State) {
+        if c.is_some() {
+            self.position -= self.last_len;
         }
+        self.state = state;
     }
 
-    fn skip_whitespace(&mut self) {
-        while self.position < self.input.len()
-            && self
-                .input
-                .chars()
-                .nth(self.position)
-                .unwrap()
-                .is_whitespace()
+    fn lookahead(&self, expected: &str, ignore_case: bool) -> bool {
+        match self
+            .input
+            .as_bytes()
+            .get(self.position..self.position + expected.len())
         {
-            self.position += 1;
+            Some(bytes) if ignore_case => bytes.eq_ignore_ascii_case(expected.as_bytes()),
+            Some(bytes) => bytes == expected.as_bytes(),
+            None => false,
         }
     }
 }
+
+// States where a NULL character is a parse error; the state itself decides
+// whether it is replaced, kept or ignored.
+fn reports_null_character(state: State) -> bool {
+    matches!(
+        state,
+        State::Data
+            | State::Rcdata
+            | State::Rawtext
+            | State::ScriptData
+            | State::Plaintext
+            | State::TagName
+            | State::ScriptDataEscaped
+            | State::ScriptDataEscapedDash
+            | State::ScriptDataEscapedDashDash
+            | State::ScriptDataDoubleEscaped
+            | State::ScriptDataDoubleEscapedDash
+            | State::ScriptDataDoubleEscapedDashDash
+            | State::AttributeName
+            | State::AttributeValueDoubleQuoted
+            | State::AttributeValueSingleQuoted
+            | State::AttributeValueUnquoted
+            | State::BogusComment
+            | State::Comment
+            | State::BeforeDoctypeName
+            | State::DoctypeName
+            | State::DoctypePublicIdentifierDoubleQuoted
+            | State::DoctypePublicIdentifierSingleQuoted
+            | State::DoctypeSystemIdentifierDoubleQuoted
+            | State::DoctypeSystemIdentifierSingleQuoted
+            | State::BogusDoctype
+    )
+}
+
+// Resolves a numeric character reference code point, applying the
+// replacements browsers use for null, out-of-range, surrogate and C1 values.
+fn numeric_reference_char(code: u32) -> char {
+    let code = match code {
+        0x80 => 0x20AC,
+        0x82 => 0x201A,
+        0x83 => 0x0192,
+        0x84 => 0x201E,
+        0x85 => 0x2026,
+        0x86 => 0x2020,
+        0x87 => 0x2021,
+        0x88 => 0x02C6,
+        0x89 => 0x2030,
+        0x8A => 0x0160,
+        0x8B => 0x2039,
+        0x8C => 0x0152,
+        0x8E => 0x017D,
+        0x91 => 0x2018,
+        0x92 => 0x2019,
+        0x93 => 0x201C,
+        0x94 => 0x201D,
+        0x95 => 0x2022,
+        0x96 => 0x2013,
+        0x97 => 0x2014,
+        0x98 => 0x02DC,
+        0x99 => 0x2122,
+        0x9A => 0x0161,
+        0x9B => 0x203A,
+        0x9C => 0x0153,
+        0x9E => 0x017E,
+        0x9F => 0x0178,
+        code => code,
+    };
+
+    match code {
+        0 => REPLACEMENT_CHAR,
+        code => char::from_u32(code).unwrap_or(REPLACEMENT_CHAR),
+    }
+}
@@ -1,16 +1,35 @@
+use crate::css::calc::Calc;
+use crate::css::cascade::StyledNode;
+use crate::css::media::{MediaCondition, MediaFeature, MediaQuery, Qualifier};
 use crate::css::rules::{
-    AttributeOperator, AttributeSelector, Declaration, Keyframe, Rule, Selector, SimpleSelector,
-    StyleRule, Stylesheet,
+    AttributeOperator, AttributeSelector, CompoundSelector, Declaration, Keyframe,
+    NamespaceSelector, PseudoClass, Rule, Selector, Specificity, Stylesheet,
 };
+use crate::css::supports::SupportsCondition;
 use crate::css::values::Value as CssValue;
-use crate::html::dom::{ElementData, Node, NodeType};
-use serde_json::{json, Value};
+use crate::html::arena::Dom;
+use crate::html::dom::{Node, NodeType};
+use crate::span::{Position, Span};
+use serde_json::{json, Map, Value};
 
-pub fn node_to_json(node: &Node) -> Value {
-    match &node.node_type {
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonOptions {
+    // Include source spans on nodes, attributes, rules and declarations.
+    pub spans: bool,
+}
+
+pub fn node_to_json(node: &Node, options: JsonOptions) -> Value {
+    let children = || {
+        node.children
+            .iter()
+            .map(|child| node_to_json(child, options))
+            .collect::<Vec<_>>()
+    };
+
+    let mut json_node = match &node.node_type {
         NodeType::Document => json!({
             "type": "document",
-            "children": node.children.iter().map(node_to_json).collect::<Vec<_>>()
+            "children": children()
         }),
         NodeType::Doctype => json!({
             "type": "doctype"
@@ -19,14 +38,22 @@ pub fn node_to_json(node: &Node) -> Value {
             let mut json_elem = json!({
                 "type": "element",
                 "tag": elem.tag_name,
-                "attributes": elem.attributes.iter().map(|(k, v)| json!({k: v})).collect::<Vec<_>>(),
-                "children": node.children.iter().map(node_to_json).collect::<Vec<_>>()
+                "attributes": elem.attributes.iter().map(|a| json!({&a.name: a.value})).collect::<Vec<_>>(),
+                "children": children()
             });
 
             if elem.is_self_closing {
                 json_elem["selfClosing"] = Value::Bool(true);
             }
 
+            if options.spans {
+                json_elem["attributeSpans"] = elem
+                    .attributes
+                    .iter()
+                    .map(|a| optional_span_to_json(a.span))
+                    .collect();
+            }
+
             json_elem
         }
         NodeType::Text(text) => json!({
@@ -37,53 +64,251 @@ pub fn node_to_json(node: &Node) -> Value {
             "type": "comment",
             "value": text
         }),
+    };
+
+    if options.spans {
+        json_node["span"] = optional_span_to_json(node.span);
     }
+
+    json_node
 }
 
-pub fn stylesheet_to_json(stylesheet: &Stylesheet) -> Value {
+pub fn stylesheet_to_json(stylesheet: &Stylesheet, options: JsonOptions) -> Value {
     json!({
         "type": "stylesheet",
-        "rules": stylesheet.rules.iter().map(rule_to_json).collect::<Vec<_>>()
+        "rules": stylesheet.rules.iter().map(|rule| rule_to_json(rule, options)).collect::<Vec<_>>()
     })
 }
 
-fn rule_to_json(rule: &Rule) -> Value {
-    match rule {
+/// The styled tree with each element's computed properties. Only the
+/// properties set by the cascade or inherited are listed.
+pub fn styled_node_to_json(dom: &Dom, node: &StyledNode) -> Value {
+    let children = || {
+        node.children
+            .iter()
+            .map(|child| styled_node_to_json(dom, child))
+            .collect::<Vec<_>>()
+    };
+
+    match &dom[node.node].node_type {
+        NodeType::Element(elem) => {
+            let mut style: Map<String, Value> = node
+                .style
+                .properties()
+                .map(|property| {
+                    (
+                        property.name().to_string(),
+                        css_value_to_json(property.value()),
+                    )
+                })
+                .collect();
+            for (name, value) in node.style.custom_properties() {
+                style.insert(name.to_string(), css_value_to_json(value));
+            }
+            json!({
+                "type": "element",
+                "tag": elem.tag_name,
+                "style": style,
+                "children": children()
+            })
+        }
+        NodeType::Text(text) => json!({
+            "type": "text",
+            "value": text
+        }),
+        _ => json!({
+            "type": "document",
+            "children": children()
+        }),
+    }
+}
+
+fn rule_to_json(rule: &Rule, options: JsonOptions) -> Value {
+    let declarations = |declarations: &[Declaration]| {
+        declarations
+            .iter()
+            .map(|declaration| declaration_to_json(declaration, options))
+            .collect::<Vec<_>>()
+    };
+
+    let rules = |rules: &[Rule]| {
+        rules
+            .iter()
+            .map(|rule| rule_to_json(rule, options))
+            .collect::<Vec<_>>()
+    };
+
+    let mut json_rule = match rule {
         Rule::Style(style_rule) => json!({
             "type": "style_rule",
             "selectors": style_rule.selectors.iter().map(selector_to_json).collect::<Vec<_>>(),
-            "declarations": style_rule.declarations.iter().map(declaration_to_json).collect::<Vec<_>>()
+            "declarations": declarations(&style_rule.declarations),
+            "rules": rules(&style_rule.rules)
         }),
-        Rule::Media { query, rules } => json!({
+        Rule::Media {
+            query,
+            rules: nested,
+            ..
+        } => json!({
             "type": "media_rule",
-            "query": query,
-            "rules": rules.iter().map(rule_to_json).collect::<Vec<_>>()
+            "query": query.to_string(),
+            "queries": query.queries.iter().map(media_query_to_json).collect::<Vec<_>>(),
+            "rules": rules(nested)
         }),
-        Rule::Keyframes { name, frames } => json!({
+        Rule::Keyframes { name, frames, .. } => json!({
             "type": "keyframes_rule",
             "name": name,
-            "frames": frames.iter().map(keyframe_to_json).collect::<Vec<_>>()
+            "frames": frames.iter().map(|frame| keyframe_to_json(frame, options)).collect::<Vec<_>>()
+        }),
+        Rule::Import {
+            url,
+            layer,
+            supports,
+            media,
+            ..
+        } => json!({
+            "type": "import_rule",
+            "url": url,
+            "layer": layer.as_ref().map(|name| json!({ "name": name })),
+            "supports": supports.as_ref().map(|condition| condition.to_string()),
+            "media": media.to_string()
         }),
+        Rule::FontFace { descriptors, .. } => json!({
+            "type": "font_face_rule",
+            "descriptors": declarations(descriptors)
+        }),
+        Rule::Supports {
+            condition,
+            rules: nested,
+            ..
+        } => json!({
+            "type": "supports_rule",
+            "condition": condition.to_string(),
+            "expression": supports_condition_to_json(condition),
+            "supported": condition.evaluate(),
+            "rules": rules(nested)
+        }),
+        Rule::Layer {
+            name,
+            rules: nested,
+            ..
+        } => json!({
+            "type": "layer_block_rule",
+            "name": name,
+            "rules": rules(nested)
+        }),
+        Rule::LayerStatement { names, .. } => json!({
+            "type": "layer_statement_rule",
+            "names": names
+        }),
+        Rule::Page {
+            selector,
+            declarations: page_declarations,
+            ..
+        } => json!({
+            "type": "page_rule",
+            "selector": selector,
+            "declarations": declarations(page_declarations)
+        }),
+        Rule::Container {
+            name,
+            condition,
+            rules: nested,
+            ..
+        } => json!({
+            "type": "container_rule",
+            "name": name,
+            "condition": condition.as_ref().map(|condition| condition.to_string()),
+            "expression": condition.as_ref().map(media_condition_to_json),
+            "rules": rules(nested)
+        }),
+        Rule::Namespace { prefix, url, .. } => json!({
+            "type": "namespace_rule",
+            "prefix": prefix,
+            "url": url
+        }),
+    };
+
+    if options.spans {
+        json_rule["span"] = span_to_json(rule.span());
     }
+
+    json_rule
 }
 
 fn selector_to_json(selector: &Selector) -> Value {
-    match selector {
-        Selector::Simple(simple) => {
-            json!({
-                "type": "simple_selector",
-                "tag_name": simple.tag_name,
-                "id": simple.id,
-                "classes": simple.classes,
-                "universal": simple.universal,
-                "attributes": simple.attributes.iter().map(attribute_selector_to_json).collect::<Vec<_>>()
-            })
+    json!({
+        "type": "complex_selector",
+        "specificity": specificity_to_json(selector.specificity()),
+        "compounds": selector.compounds.iter().map(compound_selector_to_json).collect::<Vec<_>>()
+    })
+}
+
+fn specificity_to_json(specificity: Specificity) -> Value {
+    let Specificity(ids, classes, types) = specificity;
+    json!([ids, classes, types])
+}
+
+fn selectors_to_json(selectors: &[Selector]) -> Vec<Value> {
+    selectors.iter().map(selector_to_json).collect()
+}
+
+fn compound_selector_to_json(compound: &CompoundSelector) -> Value {
+    json!({
+        "type": "compound_selector",
+        "combinator": compound.combinator.map(|combinator| combinator.as_str()),
+        "namespace": compound.namespace.as_ref().map(namespace_selector_to_json),
+        "tag_name": compound.tag_name,
+        "universal": compound.universal,
+        "nesting": compound.nesting,
+        "ids": compound.ids,
+        "classes": compound.classes,
+        "attributes": compound.attributes.iter().map(attribute_selector_to_json).collect::<Vec<_>>(),
+        "pseudo_classes": compound.pseudo_classes.iter().map(pseudo_class_to_json).collect::<Vec<_>>(),
+        "pseudo_element": compound.pseudo_element.as_ref().map(|element| element.name())
+    })
+}
+
+fn pseudo_class_to_json(pseudo_class: &PseudoClass) -> Value {
+    let mut json = json!({ "name": pseudo_class.name() });
+    match pseudo_class {
+        PseudoClass::NthChild(nth, of) | PseudoClass::NthLastChild(nth, of) => {
+            json["a"] = json!(nth.a);
+            json["b"] = json!(nth.b);
+            if !of.is_empty() {
+                json["of"] = json!(selectors_to_json(of));
+            }
+        }
+        PseudoClass::NthOfType(nth) | PseudoClass::NthLastOfType(nth) => {
+            json["a"] = json!(nth.a);
+            json["b"] = json!(nth.b);
+        }
+        PseudoClass::Not(selectors)
+        | PseudoClass::Is(selectors)
+        | PseudoClass::Where(selectors)
+        | PseudoClass::Has(selectors) => {
+            json["selectors"] = json!(selectors_to_json(selectors));
+        }
+        PseudoClass::Lang(ranges) => json["ranges"] = json!(ranges),
+        PseudoClass::Dir(direction) => json["direction"] = json!(direction),
+        _ => {}
+    }
+    json
+}
+
+fn namespace_selector_to_json(namespace: &NamespaceSelector) -> Value {
+    match namespace {
+        NamespaceSelector::None => json!({ "type": "none" }),
+        NamespaceSelector::Any => json!({ "type": "any" }),
+        NamespaceSelector::Named { prefix, url } => {
+            json!({ "type": "named", "prefix": prefix, "url": url })
         }
     }
 }
 
 fn attribute_selector_to_json(attr: &AttributeSelector) -> Value {
     json!({
+        "namespace": attr.namespace.as_ref().map(namespace_selector_to_json),
         "name": attr.name,
         "operator": match attr.op {
             Some(AttributeOperator::Equal) => "=",
@@ -94,25 +319,37 @@ fn attribute_selector_to_json(attr: &AttributeSelector) -> Value {
             Some(AttributeOperator::Substring) => "*=",
             None => "",
         },
-        "value": attr.value
+        "value": attr.value,
+        "case_insensitive": attr.case_insensitive
     })
 }
 
-fn declaration_to_json(declaration: &Declaration) -> Value {
-    json!({
+fn declaration_to_json(declaration: &Declaration, options: JsonOptions) -> Value {
+    let mut json_declaration = json!({
         "property": declaration.name,
         "value": css_value_to_json(&declaration.value),
         "important": declaration.important
-    })
+    });
+
+    if options.spans {
+        json_declaration["span"] = span_to_json(declaration.span);
+    }
+
+    json_declaration
 }
 
 fn css_value_to_json(value: &CssValue) -> Value {
     match value {
         CssValue::Keyword(s) => json!(s),
+        CssValue::Number(n) => json!(n),
         CssValue::Length(n, unit) => json!({
             "value": n,
             "unit": format!("{:?}", unit).to_lowercase()
         }),
+        CssValue::Dimension(n, unit) => json!({
+            "value": n,
+            "unit": unit
+        }),
         CssValue::Percentage(p) => json!({
             "value": p,
             "unit": "%"
@@ -131,6 +368,11 @@ fn css_value_to_json(value: &CssValue) -> Value {
             "name": name,
             "args": args.iter().map(css_value_to_json).collect::<Vec<_>>()
         }),
+        CssValue::Calc(calc) => json!({
+            "type": "calc",
+            "text": value.to_string(),
+            "expression": calc_to_json(calc)
+        }),
         CssValue::Rect(rect) => json!({
             "type": "rect",
             "top": css_value_to_json(&rect.top),
@@ -153,12 +395,128 @@ fn css_value_to_json(value: &CssValue) -> Value {
             })).collect::<Vec<_>>()
         }),
         CssValue::List(values) => json!(values.iter().map(css_value_to_json).collect::<Vec<_>>()),
+        CssValue::Unparsed(_) => json!({
+            "type": "unparsed",
+            "text": value.to_string()
+        }),
     }
 }
 
-fn keyframe_to_json(keyframe: &Keyframe) -> Value {
+fn calc_to_json(calc: &Calc) -> Value {
+    let all = |calcs: &[Calc]| calcs.iter().map(calc_to_json).collect::<Vec<_>>();
+    match calc {
+        Calc::Number(n) => css_value_to_json(&CssValue::Number(*n)),
+        Calc::Percentage(p) => css_value_to_json(&CssValue::Percentage(*p)),
+        Calc::Dimension(n, unit) => css_value_to_json(&CssValue::Length(*n, unit.clone())),
+        Calc::Sum(terms) => json!({ "sum": all(terms) }),
+        Calc::Product(factors) => json!({ "product": all(factors) }),
+        Calc::Negate(calc) => json!({ "negate": calc_to_json(calc) }),
+        Calc::Invert(calc) => json!({ "invert": calc_to_json(calc) }),
+        Calc::Min(calcs) => json!({ "min": all(calcs) }),
+        Calc::Max(calcs) => json!({ "max": all(calcs) }),
+        Calc::Clamp(min, value, max) => {
+            json!({ "clamp": [calc_to_json(min), calc_to_json(value), calc_to_json(max)] })
+        }
+    }
+}
+
+fn media_query_to_json(query: &MediaQuery) -> Value {
     json!({
+        "qualifier": query.qualifier.map(|qualifier| match qualifier {
+            Qualifier::Not => "not",
+            Qualifier::Only => "only",
+        }),
+        "media_type": query.media_type,
+        "condition": query.condition.as_ref().map(media_condition_to_json)
+    })
+}
+
+fn media_condition_to_json(condition: &MediaCondition) -> Value {
+    let all = |conditions: &[MediaCondition]| {
+        conditions
+            .iter()
+            .map(media_condition_to_json)
+            .collect::<Vec<_>>()
+    };
+    match condition {
+        MediaCondition::Feature(MediaFeature::Boolean(name)) => {
+            json!({ "type": "feature", "name": name })
+        }
+        MediaCondition::Feature(MediaFeature::Plain(name, value)) => json!({
+            "type": "feature",
+            "name": name,
+            "value": css_value_to_json(value)
+        }),
+        MediaCondition::Feature(MediaFeature::Range(name, comparisons)) => json!({
+            "type": "range",
+            "name": name,
+            "comparisons": comparisons.iter().map(|(comparison, value)| json!({
+                "operator": comparison.as_str(),
+                "value": css_value_to_json(value)
+            })).collect::<Vec<_>>()
+        }),
+        MediaCondition::Not(condition) => {
+            json!({ "type": "not", "condition": media_condition_to_json(condition) })
+        }
+        MediaCondition::And(conditions) => json!({ "type": "and", "conditions": all(conditions) }),
+        MediaCondition::Or(conditions) => json!({ "type": "or", "conditions": all(conditions) }),
+        MediaCondition::GeneralEnclosed(text) => {
+            json!({ "type": "general_enclosed", "text": text })
+        }
+    }
+}
+
+fn supports_condition_to_json(condition: &SupportsCondition) -> Value {
+    let all = |conditions: &[SupportsCondition]| {
+        conditions
+            .iter()
+            .map(supports_condition_to_json)
+            .collect::<Vec<_>>()
+    };
+    match condition {
+        SupportsCondition::Not(condition) => {
+            json!({ "type": "not", "condition": supports_condition_to_json(condition) })
+        }
+        SupportsCondition::And(conditions) => {
+            json!({ "type": "and", "conditions": all(conditions) })
+        }
+        SupportsCondition::Or(conditions) => json!({ "type": "or", "conditions": all(conditions) }),
+        SupportsCondition::Declaration(text) => json!({ "type": "declaration", "text": text }),
+        SupportsCondition::Selector(text) => json!({ "type": "selector", "text": text }),
+        SupportsCondition::GeneralEnclosed(text) => {
+            json!({ "type": "general_enclosed", "text": text })
+        }
+    }
+}
+
+fn keyframe_to_json(keyframe: &Keyframe, options: JsonOptions) -> Value {
+    let mut json_keyframe = json!({
         "selectors": keyframe.selectors,
-        "declarations": keyframe.declarations.iter().map(declaration_to_json).collect::<Vec<_>>()
+        "declarations": keyframe.declarations.iter().map(|declaration| declaration_to_json(declaration, options)).collect::<Vec<_>>()
+    });
+
+    if options.spans {
+        json_keyframe["span"] = span_to_json(keyframe.span);
+    }
+
+    json_keyframe
+}
+
+fn span_to_json(span: Span) -> Value {
+    json!({
+        "start": position_to_json(span.start),
+        "end": position_to_json(span.end)
+    })
+}
+
+fn optional_span_to_json(span: Option<Span>) -> Value {
+    span.map_or(Value::Null, span_to_json)
+}
+
+fn position_to_json(position: Position) -> Value {
+    json!({
+        "offset": position.offset,
+        "line": position.line,
+        "column": position.column
     })
 }
@@ -0,0 +1,6 @@
+pub mod css;
+pub mod diagnostics;
+pub mod encoding;
+pub mod html;
+pub mod json;
+pub mod span;
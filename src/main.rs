@@ -1,12 +1,14 @@
-mod css;
-mod html;
-mod json;
-
-use crate::html::dom::NodeType;
-use crate::json::{node_to_json, stylesheet_to_json};
+use axolotl::css::cascade::{Cascade, Origin, StyledNode};
+use axolotl::css::media::{ColorScheme, MediaEnvironment};
+use axolotl::diagnostics::Diagnostic;
+use axolotl::encoding;
+use axolotl::html::arena::Dom;
+use axolotl::html::dom::NodeType;
+use axolotl::json::{node_to_json, styled_node_to_json, stylesheet_to_json, JsonOptions};
+use axolotl::{css, html};
+use encoding_rs::Encoding;
 use html::dom::Node;
 use html::Parser;
-use serde_json;
 use std::env;
 use std::fs;
 use std::path::Path;
@@ -74,11 +76,15 @@ fn pretty_print(node: &Node, indent: usize, mode: PrintMode) {
 
             // Attributes
             if mode != PrintMode::Json {
-                for (name, value) in &elem.attributes {
-                    if value.is_empty() {
-                        print!(" {}", name);
+                for attribute in &elem.attributes {
+                    if attribute.value.is_empty() {
+                        print!(" {}", attribute.name);
                     } else {
-                        print!(" {}=\"{}\"", name, escape_html_attribute(value));
+                        print!(
+                            " {}=\"{}\"",
+                            attribute.name,
+                            escape_html_attribute(&attribute.value)
+                        );
                     }
                 }
             }
@@ -97,8 +103,33 @@ fn pretty_print(node: &Node, indent: usize, mode: PrintMode) {
             }
 
             // Children
-            for child in &node.children {
-                pretty_print(child, indent + 2, mode);
+            let preformatted = html::dom::is_preformatted_element(&elem.tag_name);
+            for (index, child) in node.children.iter().enumerate() {
+                match &child.node_type {
+                    // <plaintext> is raw text whose whitespace is also
+                    // significant, so it is printed unescaped and untrimmed.
+                    NodeType::Text(text) if html::dom::is_raw_text_element(&elem.tag_name) => {
+                        match mode {
+                            PrintMode::Pretty if preformatted => println!("{}", text),
+                            PrintMode::Pretty => println!("{}  {}", spaces, text.trim()),
+                            PrintMode::Compact => print!("{}", text),
+                            PrintMode::Json => {}
+                        }
+                    }
+                    // Whitespace inside <pre> and friends is printed exactly; a
+                    // leading newline is doubled because the parser drops one.
+                    NodeType::Text(text) if preformatted => match mode {
+                        PrintMode::Pretty => println!("{}", escape_html_text(text)),
+                        PrintMode::Compact => {
+                            if index == 0 && text.starts_with('\n') {
+                                println!();
+                            }
+                            print!("{}", escape_html_text(text));
+                        }
+                        PrintMode::Json => {}
+                    },
+                    _ => pretty_print(child, indent + 2, mode),
+                }
             }
 
             // Closing tag
@@ -145,9 +176,8 @@ fn parse_inline_styles(node: &Node, stylesheet: &mut css::rules::Stylesheet) {
             if let Some(text_node) = node.children.first() {
                 if let NodeType::Text(css_text) = &text_node.node_type {
                     let mut css_parser = css::parser::CssParser::new(css_text.as_str());
-                    if let Ok(parsed) = css_parser.parse_stylesheet() {
-                        stylesheet.rules.extend(parsed.rules);
-                    }
+                    let (parsed, _) = css_parser.parse_stylesheet();
+                    stylesheet.rules.extend(parsed.rules);
                 }
             }
         }
@@ -159,100 +189,298 @@ fn parse_inline_styles(node: &Node, stylesheet: &mut css::rules::Stylesheet) {
     }
 }
 
+// Lists each element with the properties the cascade set or it inherited.
+fn print_styled(dom: &Dom, node: &StyledNode, indent: usize) {
+    let mut indent = indent;
+    if let NodeType::Element(elem) = &dom[node.node].node_type {
+        let spaces = " ".repeat(indent);
+        println!("{}<{}>", spaces, elem.tag_name);
+        for property in node.style.properties() {
+            println!("{}  {}: {:?}", spaces, property.name(), property.value());
+        }
+        for (name, value) in node.style.custom_properties() {
+            println!("{}  {}: {}", spaces, name, value);
+        }
+        indent += 2;
+    }
+    for child in &node.children {
+        print_styled(dom, child, indent);
+    }
+}
+
+fn print_rule(rule: &css::rules::Rule, indent: usize) {
+    use css::rules::Rule;
+    use css::supports::SupportsCondition;
+
+    let pad = "  ".repeat(indent);
+    let print_declarations = |declarations: &[css::rules::Declaration]| {
+        for decl in declarations {
+            println!("{}  {}: {:?}", pad, decl.name, decl.value);
+        }
+    };
+    let print_group = |prelude: String, rules: &[Rule]| {
+        println!("{}{} {{", pad, prelude);
+        for nested_rule in rules {
+            print_rule(nested_rule, indent + 1);
+        }
+        println!("{}}}", pad);
+    };
+
+    match rule {
+        Rule::Style(style_rule) => {
+            if indent == 0 {
+                println!("Style Rule:");
+            } else {
+                println!("{}Nested Rule:", pad);
+            }
+            println!("{}  Selectors: {:?}", pad, style_rule.selectors);
+            for decl in &style_rule.declarations {
+                println!("{}    {}: {:?}", pad, decl.name, decl.value);
+            }
+            for rule in &style_rule.rules {
+                print_rule(rule, indent + 1);
+            }
+        }
+        Rule::Media { query, rules, .. } => print_group(format!("@media {}", query), rules),
+        Rule::Keyframes { name, frames, .. } => {
+            println!("{}@keyframes {} {{", pad, name);
+            for frame in frames {
+                println!("{}  Keyframe Selectors: {:?}", pad, frame.selectors);
+                for decl in &frame.declarations {
+                    println!("{}    {}: {:?}", pad, decl.name, decl.value);
+                }
+            }
+            println!("{}}}", pad);
+        }
+        Rule::Import {
+            url,
+            layer,
+            supports,
+            media,
+            ..
+        } => {
+            let mut prelude = format!("{:?}", url);
+            match layer {
+                Some(Some(name)) => prelude.push_str(&format!(" layer({})", name)),
+                Some(None) => prelude.push_str(" layer"),
+                None => {}
+            }
+            match supports {
+                // A declaration brings its own parentheses.
+                Some(condition @ SupportsCondition::Declaration(_)) => {
+                    prelude.push_str(&format!(" supports{}", condition))
+                }
+                Some(condition) => prelude.push_str(&format!(" supports({})", condition)),
+                None => {}
+            }
+            if !media.queries.is_empty() {
+                prelude.push_str(&format!(" {}", media));
+            }
+            println!("{}@import {};", pad, prelude);
+        }
+        Rule::FontFace { descriptors, .. } => {
+            println!("{}@font-face {{", pad);
+            print_declarations(descriptors);
+            println!("{}}}", pad);
+        }
+        Rule::Supports {
+            condition, rules, ..
+        } => print_group(format!("@supports {}", condition), rules),
+        Rule::Layer {
+            name: Some(name),
+            rules,
+            ..
+        } => print_group(format!("@layer {}", name), rules),
+        Rule::Layer {
+            name: None, rules, ..
+        } => print_group("@layer".to_string(), rules),
+        Rule::LayerStatement { names, .. } => println!("{}@layer {};", pad, names.join(", ")),
+        Rule::Page {
+            selector,
+            declarations,
+            ..
+        } => {
+            if selector.is_empty() {
+                println!("{}@page {{", pad);
+            } else {
+                println!("{}@page {} {{", pad, selector);
+            }
+            print_declarations(declarations);
+            println!("{}}}", pad);
+        }
+        Rule::Container {
+            name,
+            condition,
+            rules,
+            ..
+        } => {
+            let mut prelude = "@container".to_string();
+            if let Some(name) = name {
+                prelude.push_str(&format!(" {}", name));
+            }
+            if let Some(condition) = condition {
+                prelude.push_str(&format!(" {}", condition));
+            }
+            print_group(prelude, rules)
+        }
+        Rule::Namespace { prefix, url, .. } => match prefix {
+            Some(prefix) => println!("{}@namespace {} {:?};", pad, prefix, url),
+            None => println!("{}@namespace {:?};", pad, url),
+        },
+    }
+}
+
+// Diagnostics go to stderr as `file:line:column: severity[code]: message`.
+fn print_diagnostics(file_path: &str, diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        eprintln!(
+            "{}:{}:{}: {}[{}]: {}",
+            file_path,
+            diagnostic.span.start.line,
+            diagnostic.span.start.column,
+            diagnostic.severity.name(),
+            diagnostic.code.name(),
+            diagnostic.message
+        );
+    }
+}
+
 fn main() -> std::io::Result<()> {
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
-        eprintln!("Usage: {} [--format=pretty|compact|json] <file>", args[0]);
+        eprintln!(
+            "Usage: {} [--format=pretty|compact|json] [--spans] [--styles] [--flatten] [--viewport=<width>x<height>] [--color-scheme=light|dark] [--encoding=<label>] <file>",
+            args[0]
+        );
         std::process::exit(1);
     }
 
     let mut format = PrintMode::Compact;
-    let mut file_index = 1;
+    let mut json_options = JsonOptions::default();
+    let mut transport = None;
+    let mut styles = false;
+    let mut flatten = false;
+    let mut media = MediaEnvironment::default();
+    let mut file_path = None;
 
-    if args.len() > 1 && args[1].starts_with("--format=") {
-        match args[1].split('=').nth(1) {
-            Some("pretty") => format = PrintMode::Pretty,
-            Some("compact") => format = PrintMode::Compact,
-            Some("json") => format = PrintMode::Json,
-            _ => {
-                eprintln!("Invalid format. Use 'pretty', 'compact' or 'json'");
-                std::process::exit(1);
+    for arg in &args[1..] {
+        if let Some(value) = arg.strip_prefix("--format=") {
+            match value {
+                "pretty" => format = PrintMode::Pretty,
+                "compact" => format = PrintMode::Compact,
+                "json" => format = PrintMode::Json,
+                _ => {
+                    eprintln!("Invalid format. Use 'pretty', 'compact' or 'json'");
+                    std::process::exit(1);
+                }
+            }
+        } else if arg == "--spans" {
+            // Source spans are only part of the JSON output.
+            json_options.spans = true;
+        } else if arg == "--styles" {
+            // Print the computed styles of an HTML document instead of it.
+            styles = true;
+        } else if arg == "--flatten" {
+            // Print a stylesheet with its nested rules flattened.
+            flatten = true;
+        } else if let Some(value) = arg.strip_prefix("--viewport=") {
+            // The viewport @media rules are evaluated against with --styles.
+            let size = value
+                .split_once('x')
+                .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+            match size {
+                Some((width, height)) => {
+                    media.width = width;
+                    media.height = height;
+                }
+                None => {
+                    eprintln!("Invalid viewport. Use e.g. '1024x768'");
+                    std::process::exit(1);
+                }
+            }
+        } else if let Some(value) = arg.strip_prefix("--color-scheme=") {
+            match value {
+                "light" => media.color_scheme = ColorScheme::Light,
+                "dark" => media.color_scheme = ColorScheme::Dark,
+                _ => {
+                    eprintln!("Invalid color scheme. Use 'light' or 'dark'");
+                    std::process::exit(1);
+                }
+            }
+        } else if let Some(label) = arg.strip_prefix("--encoding=") {
+            // Stands in for the charset a server would send with the file.
+            match Encoding::for_label(label.as_bytes()) {
+                Some(encoding) => transport = Some(encoding),
+                None => {
+                    eprintln!("Unknown encoding label '{}'", label);
+                    std::process::exit(1);
+                }
             }
+        } else {
+            file_path = Some(arg);
         }
-        file_index = 2;
     }
 
-    if args.len() <= file_index {
+    let Some(file_path) = file_path else {
         eprintln!("Missing file argument");
         std::process::exit(1);
-    }
-
-    let file_path = &args[file_index];
-    let content = fs::read_to_string(file_path)?;
+    };
+    let bytes = fs::read(file_path)?;
 
     let path = Path::new(file_path);
     match path.extension().and_then(|ext| ext.to_str()) {
         Some("html") => {
+            let (content, _) = encoding::decode_html(&bytes, transport);
             let mut html_parser = Parser::new(content);
-            let dom = html_parser.parse();
+            let (dom, diagnostics) = html_parser.parse();
+            print_diagnostics(file_path, &diagnostics);
+
+            if styles {
+                let mut stylesheet = css::rules::Stylesheet { rules: Vec::new() };
+                parse_inline_styles(&dom, &mut stylesheet);
+                let mut cascade = Cascade::new();
+                cascade.set_media_environment(media);
+                cascade.add_stylesheet(Origin::Author, stylesheet);
+
+                let dom = Dom::from(&dom);
+                let styled = cascade.style_tree(&dom);
+                match format {
+                    PrintMode::Json => {
+                        let json = styled_node_to_json(&dom, &styled);
+                        println!("{}", serde_json::to_string_pretty(&json)?);
+                    }
+                    _ => print_styled(&dom, &styled, 0),
+                }
+                return Ok(());
+            }
 
             match format {
                 PrintMode::Json => {
-                    let json = node_to_json(&dom);
+                    let json = node_to_json(&dom, json_options);
                     println!("{}", serde_json::to_string_pretty(&json)?);
                 }
                 _ => pretty_print(&dom, 0, format),
             }
         }
         Some("css") => {
+            let (content, _) = encoding::decode_css(&bytes, transport, None);
             let mut css_parser = css::parser::CssParser::new(&content);
-            match css_parser.parse_stylesheet() {
-                Ok(stylesheet) => match format {
-                    PrintMode::Json => {
-                        let json = stylesheet_to_json(&stylesheet);
-                        println!("{}", serde_json::to_string_pretty(&json)?);
-                    }
-                    _ => {
-                        println!("Parsed CSS Rules:");
-                        for rule in stylesheet.rules {
-                            match rule {
-                                css::rules::Rule::Style(style_rule) => {
-                                    println!("Style Rule:");
-                                    println!("  Selectors: {:?}", style_rule.selectors);
-                                    for decl in style_rule.declarations {
-                                        println!("    {}: {:?}", decl.name, decl.value);
-                                    }
-                                }
-                                css::rules::Rule::Media { query, rules } => {
-                                    println!("@media {} {{", query);
-                                    for nested_rule in rules {
-                                        if let css::rules::Rule::Style(sr) = nested_rule {
-                                            println!("  Nested Rule:");
-                                            println!("    Selectors: {:?}", sr.selectors);
-                                            for decl in sr.declarations {
-                                                println!("      {}: {:?}", decl.name, decl.value);
-                                            }
-                                        }
-                                    }
-                                    println!("}}");
-                                }
-                                css::rules::Rule::Keyframes { name, frames } => {
-                                    println!("@keyframes {} {{", name);
-                                    for frame in frames {
-                                        println!("  Keyframe Selectors: {:?}", frame.selectors);
-                                        for decl in frame.declarations {
-                                            println!("    {}: {:?}", decl.name, decl.value);
-                                        }
-                                    }
-                                    println!("}}");
-                                }
-                            }
-                        }
+            let (mut stylesheet, diagnostics) = css_parser.parse_stylesheet();
+            print_diagnostics(file_path, &diagnostics);
+            if flatten {
+                stylesheet = stylesheet.flatten();
+            }
+
+            match format {
+                PrintMode::Json => {
+                    let json = stylesheet_to_json(&stylesheet, json_options);
+                    println!("{}", serde_json::to_string_pretty(&json)?);
+                }
+                _ => {
+                    println!("Parsed CSS Rules:");
+                    for rule in &stylesheet.rules {
+                        print_rule(rule, 0);
                     }
-                },
-                Err(e) => {
-                    eprintln!("Error parsing CSS: {}", e);
-                    std::process::exit(1);
                 }
             }
         }
@@ -0,0 +1,109 @@
+/// A location in the source text. `offset` is a byte offset; `line` and
+/// `column` are 1-based, with columns counted in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    pub fn start() -> Self {
+        Position {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+/// The half-open source range `start..end` something was parsed from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn new(start: Position, end: Position) -> Self {
+        Span { start, end }
+    }
+}
+
+/// Resolves byte offsets to line/column positions. Parsers ask for offsets in
+/// mostly increasing order, so scanning resumes from the furthest position
+/// resolved so far, which keeps the total cost linear in the input size.
+/// Earlier offsets are found through the line starts seen on the way.
+pub struct Locator {
+    last: Position,
+    line_starts: Vec<usize>,
+}
+
+impl Default for Locator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Locator {
+    pub fn new() -> Self {
+        Locator {
+            last: Position::start(),
+            line_starts: vec![0],
+        }
+    }
+
+    pub fn position(&mut self, input: &str, offset: usize) -> Position {
+        if offset < self.last.offset {
+            return self.position_before(input, offset);
+        }
+
+        let mut position = self.last;
+        let bytes = input.as_bytes();
+        for (index, c) in input[position.offset..offset].char_indices() {
+            let index = position.offset + index;
+            // "\r\n" counts as a single line break, at the '\n'.
+            let line_break = c == '\n' || (c == '\r' && bytes.get(index + 1) != Some(&b'\n'));
+            if line_break {
+                position.line += 1;
+                position.column = 1;
+                self.line_starts.push(index + 1);
+            } else if c != '\r' {
+                position.column += 1;
+            }
+        }
+        position.offset = offset;
+
+        self.last = position;
+        position
+    }
+
+    pub fn span(&mut self, input: &str, start: usize, end: usize) -> Span {
+        let start = self.position(input, start);
+        let end = self.position(input, end);
+        Span::new(start, end)
+    }
+
+    fn position_before(&self, input: &str, offset: usize) -> Position {
+        let skipped = &input[offset..self.last.offset];
+        if !skipped.contains(['\n', '\r']) {
+            // Stepping back within a line only moves the column.
+            return Position {
+                offset,
+                line: self.last.line,
+                column: self.last.column - skipped.chars().filter(|&c| c != '\r').count(),
+            };
+        }
+
+        let line = self.line_starts.partition_point(|&start| start <= offset);
+        let line_start = self.line_starts[line - 1];
+        Position {
+            offset,
+            line,
+            column: 1 + input[line_start..offset]
+                .chars()
+                .filter(|&c| c != '\r')
+                .count(),
+        }
+    }
+}